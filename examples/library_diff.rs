@@ -0,0 +1,22 @@
+//! This example compares the current library with the snapshot saved by its previous run
+//!
+//! It must be built with the `--all-features` Cargo flag
+
+#![allow(non_snake_case)]
+
+use itunes_com::wrappers::library::{LibrarySnapshot, TrackField};
+use itunes_com::wrappers::diff::diff_ignoring;
+
+
+fn main() {
+    let snapshot_path = "library_snapshot.json";
+    let iTunes = itunes_com::wrappers::iTunes::new().unwrap();
+
+    let new = LibrarySnapshot::from_iTunes(&iTunes).unwrap();
+    if let Ok(old) = LibrarySnapshot::load_json(snapshot_path) {
+        let diff = diff_ignoring(&old, &new, &[TrackField::PlayedCount, TrackField::PlayedDate, TrackField::SkippedCount, TrackField::SkippedDate]);
+        print!("{}", diff);
+    }
+
+    new.save_json(snapshot_path).unwrap();
+}
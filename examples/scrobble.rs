@@ -0,0 +1,27 @@
+//! This example writes a `.scrobbler.log` file as iTunes plays
+//!
+//! It must be built with the `--all-features` Cargo flag
+
+#![allow(non_snake_case)]
+
+use std::time::Duration;
+
+use itunes_com::wrappers::scrobble::{Scrobbler, ScrobblerLog, JsonLinesJournal, ScrobbleJournal, watch};
+
+
+fn main() {
+    let iTunes = itunes_com::wrappers::iTunes::new().unwrap();
+
+    let mut journals: Vec<Box<dyn ScrobbleJournal>> = vec![
+        Box::new(ScrobblerLog::open(".scrobbler.log", "itunes-com").unwrap()),
+        Box::new(JsonLinesJournal::open("scrobbles.jsonl").unwrap()),
+    ];
+    let mut scrobbler = Scrobbler::default();
+
+    // Let's watch for one hour
+    let mut remaining_polls = 3600;
+    watch(&iTunes, &mut scrobbler, &mut journals, Duration::from_secs(1), false, || {
+        remaining_polls -= 1;
+        remaining_polls > 0
+    }).unwrap();
+}
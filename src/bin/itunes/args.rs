@@ -0,0 +1,223 @@
+//! Command-line arguments
+//!
+//! This module does not talk to iTunes, so that argument parsing can be checked without a running instance.
+
+use clap::{Parser, Subcommand};
+
+use itunes_com::wrappers::library::{TrackField, FieldValue, parse_persistent_id};
+use itunes_com::wrappers::query_language::{field_from_query_name, parse_duration, parse_field_value};
+use itunes_com::wrappers::types::PersistentId;
+
+/// Control a local iTunes instance
+#[derive(Debug, Parser)]
+#[command(name = "itunes", version)]
+pub struct Cli {
+    /// Print results as JSON rather than as tables
+    #[arg(long, global = true)]
+    pub json: bool,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, PartialEq, Subcommand)]
+pub enum Command {
+    /// Start playing
+    Play,
+    /// Pause playback
+    Pause,
+    /// Toggle between playing and paused
+    PlayPause,
+    /// Stop playback
+    Stop,
+    /// Skip to the next track
+    Next,
+    /// Go back to the previous track
+    Previous,
+    /// Set the sound volume
+    Volume {
+        /// From 0 to 100
+        #[arg(value_parser = clap::value_parser!(u8).range(0..=100))]
+        level: u8,
+    },
+    /// Move within the current track
+    Seek {
+        /// The position to seek to, in seconds, or as `m:ss`
+        #[arg(value_parser = parse_position)]
+        position: i32,
+    },
+    /// Show the current track
+    NowPlaying,
+    /// Search the library
+    Search {
+        /// A query, e.g. `artist:beatles year:1965..1969 sort:name` (several arguments are joined)
+        #[arg(required = true)]
+        query: Vec<String>,
+    },
+    /// Commands over all playlists
+    Playlists {
+        #[command(subcommand)]
+        command: PlaylistsCommand,
+    },
+    /// Commands over a single playlist
+    Playlist {
+        #[command(subcommand)]
+        command: PlaylistCommand,
+    },
+    /// Commands over a single track
+    Track {
+        #[command(subcommand)]
+        command: TrackCommand,
+    },
+    /// Commands over the equalizer
+    Eq {
+        #[command(subcommand)]
+        command: EqCommand,
+    },
+}
+
+#[derive(Debug, PartialEq, Subcommand)]
+pub enum PlaylistsCommand {
+    /// Show the playlists, nested in their folders
+    Tree,
+}
+
+#[derive(Debug, PartialEq, Subcommand)]
+pub enum PlaylistCommand {
+    /// Print the tracks of a playlist, as an M3U playlist (or as JSON)
+    Export {
+        /// The name or the persistent ID of the playlist
+        playlist: String,
+        /// Write to this file rather than to the standard output
+        #[arg(short, long)]
+        output: Option<std::path::PathBuf>,
+    },
+}
+
+#[derive(Debug, PartialEq, Subcommand)]
+pub enum TrackCommand {
+    /// Change fields of a track
+    Set {
+        /// The persistent ID of the track
+        #[arg(value_parser = parse_track_id)]
+        track: PersistentId,
+        /// Values to set, e.g. `rating=4` or `genre=Jazz` (with the value syntax of queries)
+        #[arg(required = true, value_parser = parse_assignment)]
+        values: Vec<(TrackField, FieldValue)>,
+    },
+}
+
+#[derive(Debug, PartialEq, Subcommand)]
+pub enum EqCommand {
+    /// List the equalizer presets
+    List,
+    /// Enable the equalizer, with a preset
+    Use {
+        /// The name of the preset
+        preset: String,
+    },
+}
+
+/// Parse a player position (in seconds, or as `m:ss`)
+pub fn parse_position(text: &str) -> Result<i32, String> {
+    parse_duration(text)
+        .and_then(|seconds| i32::try_from(seconds).ok())
+        .filter(|seconds| *seconds >= 0)
+        .ok_or_else(|| format!("invalid position `{}` (expected seconds, or m:ss)", text))
+}
+
+/// Parse a persistent ID, as printed by this tool
+pub fn parse_track_id(text: &str) -> Result<PersistentId, String> {
+    parse_persistent_id(text).ok_or_else(|| format!("invalid persistent ID `{}` (expected up to 16 hexadecimal digits)", text))
+}
+
+/// Parse a `field=value` argument
+pub fn parse_assignment(text: &str) -> Result<(TrackField, FieldValue), String> {
+    let (name, value) = text.split_once('=').ok_or_else(|| format!("expected `field=value`, got `{}`", text))?;
+    let field = field_from_query_name(name.trim()).ok_or_else(|| format!("unknown field `{}`", name.trim()))?;
+    let value = parse_field_value(field, value).map_err(|err| format!("invalid value for {}: {}", field, err.message))?;
+    Ok((field, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(std::iter::once("itunes").chain(args.iter().copied()))
+    }
+
+    fn command(args: &[&str]) -> Command {
+        parse(args).unwrap_or_else(|err| panic!("{:?}: {}", args, err)).command
+    }
+
+    #[test]
+    fn transport() {
+        assert_eq!(command(&["play"]), Command::Play);
+        assert_eq!(command(&["play-pause"]), Command::PlayPause);
+        assert_eq!(command(&["now-playing"]), Command::NowPlaying);
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["rewind"]).is_err());
+    }
+
+    #[test]
+    fn json_is_global() {
+        assert!(!parse(&["now-playing"]).unwrap().json);
+        assert!(parse(&["--json", "now-playing"]).unwrap().json);
+        assert!(parse(&["now-playing", "--json"]).unwrap().json);
+        assert!(parse(&["eq", "list", "--json"]).unwrap().json);
+    }
+
+    #[test]
+    fn volume_and_seek() {
+        assert_eq!(command(&["volume", "0"]), Command::Volume { level: 0 });
+        assert_eq!(command(&["volume", "100"]), Command::Volume { level: 100 });
+        assert!(parse(&["volume", "101"]).is_err());
+        assert!(parse(&["volume"]).is_err());
+
+        assert_eq!(command(&["seek", "75"]), Command::Seek { position: 75 });
+        assert_eq!(command(&["seek", "1:15"]), Command::Seek { position: 75 });
+        assert_eq!(command(&["seek", "1:00:00"]), Command::Seek { position: 3600 });
+        assert!(parse(&["seek", "1:xx"]).is_err());
+        assert!(parse(&["seek", "--", "-5"]).is_err());
+    }
+
+    #[test]
+    fn search_and_playlists() {
+        assert_eq!(command(&["search", "artist:beatles", "year:1965"]), Command::Search { query: vec!["artist:beatles".to_string(), "year:1965".to_string()] });
+        assert!(parse(&["search"]).is_err());
+
+        assert_eq!(command(&["playlists", "tree"]), Command::Playlists { command: PlaylistsCommand::Tree });
+        assert_eq!(command(&["playlist", "export", "Favorites"]), Command::Playlist { command: PlaylistCommand::Export { playlist: "Favorites".to_string(), output: None } });
+        assert_eq!(command(&["playlist", "export", "Favorites", "-o", "out.m3u"]), Command::Playlist {
+            command: PlaylistCommand::Export { playlist: "Favorites".to_string(), output: Some("out.m3u".into()) },
+        });
+        assert_eq!(command(&["eq", "use", "Rock"]), Command::Eq { command: EqCommand::Use { preset: "Rock".to_string() } });
+    }
+
+    #[test]
+    fn track_set() {
+        assert_eq!(command(&["track", "set", "0123456789ABCDEF", "rating=4", "genre=Free Jazz"]), Command::Track {
+            command: TrackCommand::Set {
+                track: 0x0123456789ABCDEF,
+                values: vec![(TrackField::Rating, FieldValue::Integer(80)), (TrackField::Genre, FieldValue::Text("Free Jazz".to_string()))],
+            },
+        });
+        assert_eq!(command(&["track", "set", "ff", "time=1:15"]), Command::Track {
+            command: TrackCommand::Set { track: 0xFF, values: vec![(TrackField::Duration, FieldValue::Integer(75))] },
+        });
+
+        assert!(parse(&["track", "set", "0123456789ABCDEF"]).is_err());
+        assert!(parse(&["track", "set", "not-an-id", "rating=4"]).is_err());
+        assert!(parse(&["track", "set", "123456789ABCDEF01", "rating=4"]).is_err());
+    }
+
+    #[test]
+    fn assignments() {
+        assert_eq!(parse_assignment(" year=1965"), Ok((TrackField::Year, FieldValue::Integer(1965))));
+        assert_eq!(parse_assignment("comments=a=b"), Ok((TrackField::Comment, FieldValue::Text("a=b".to_string()))));
+        assert_eq!(parse_assignment("rating"), Err("expected `field=value`, got `rating`".to_string()));
+        assert_eq!(parse_assignment("colour=red"), Err("unknown field `colour`".to_string()));
+        assert_eq!(parse_assignment("rating=6"), Err("invalid value for Rating: expected a number of stars (0 to 5)".to_string()));
+    }
+}
@@ -0,0 +1,126 @@
+//! Execution of commands on a live iTunes instance
+
+use std::error::Error;
+
+use itunes_com::sys::{ITPlaylistKind, ITUserPlaylistSpecialKind};
+use itunes_com::wrappers::{iTunes, Playlist, Iterable, IITObjectWrapper, IITPlaylistWrapper};
+use itunes_com::wrappers::edit::TrackEdit;
+use itunes_com::wrappers::library::{TrackSnapshot, TrackField, FieldValue, snapshot_tracks, parse_persistent_id, format_persistent_id};
+use itunes_com::wrappers::player::PlayerSample;
+use itunes_com::wrappers::query::Query;
+use itunes_com::wrappers::types::PersistentId;
+
+use crate::args::{Command, PlaylistsCommand, PlaylistCommand, TrackCommand, EqCommand};
+use crate::output::{Output, PlaylistEntry, Preset, playlist_tree};
+
+pub fn run(iTunes: &iTunes, command: &Command) -> Result<Output, Box<dyn Error>> {
+    match command {
+        Command::Play => iTunes.Play()?,
+        Command::Pause => iTunes.Pause()?,
+        Command::PlayPause => iTunes.PlayPause()?,
+        Command::Stop => iTunes.Stop()?,
+        Command::Next => iTunes.NextTrack()?,
+        Command::Previous => iTunes.PreviousTrack()?,
+        Command::Volume { level } => iTunes.set_SoundVolume((*level).into())?,
+        Command::Seek { position } => iTunes.set_PlayerPosition(*position)?,
+        Command::NowPlaying => return Ok(Output::NowPlaying((&PlayerSample::from_iTunes(iTunes)?).into())),
+        Command::Search { query } => return search(iTunes, &query.join(" ")),
+        Command::Playlists { command: PlaylistsCommand::Tree } => return playlists(iTunes),
+        Command::Playlist { command: PlaylistCommand::Export { playlist, .. } } => {
+            let playlist = find_playlist(iTunes, playlist)?;
+            return Ok(Output::Export(snapshot_tracks(&playlist.Tracks()?)?));
+        },
+        Command::Track { command: TrackCommand::Set { track, values } } => return set_track(iTunes, *track, values),
+        Command::Eq { command: EqCommand::List } => return presets(iTunes),
+        Command::Eq { command: EqCommand::Use { preset } } => {
+            let preset = iTunes.EQPresets()?.ItemByName(preset)
+                .map_err(|_| format!("No equalizer preset named {:?}", preset))?;
+            iTunes.set_CurrentEQPreset(preset)?;
+            iTunes.set_EQEnabled(true)?;
+        },
+    }
+    Ok(Output::Nothing)
+}
+
+fn search(iTunes: &iTunes, query: &str) -> Result<Output, Box<dyn Error>> {
+    let parsed = query.parse::<Query>().map_err(|err| err.render(query))?;
+    Ok(Output::Tracks(parsed.run_on_playlist(&iTunes.LibraryPlaylist()?)?))
+}
+
+fn playlists(iTunes: &iTunes) -> Result<Output, Box<dyn Error>> {
+    let mut entries = Vec::new();
+    for playlist in iTunes.LibrarySource()?.Playlists()?.iter()? {
+        if playlist.Kind()? == ITPlaylistKind::ITPlaylistKindLibrary {
+            continue;
+        }
+        let (parent, kind) = match playlist.as_user_playlist() {
+            None => (None, "playlist"),
+            Some(user_playlist) => {
+                let parent = match user_playlist.Parent() {
+                    Ok(parent) => Some(parent.persistent_id()?),
+                    Err(_) => None,
+                };
+                let kind = if user_playlist.SpecialKind()? == ITUserPlaylistSpecialKind::ITUserPlaylistSpecialKindFolder {
+                    "folder"
+                } else if user_playlist.is_Smart()? {
+                    "smart"
+                } else {
+                    "playlist"
+                };
+                (parent, kind)
+            },
+        };
+        // Only the number of tracks is needed, so that they are not read one by one
+        let track_count = match kind {
+            "folder" => 0,
+            _ => playlist.Tracks()?.Count()? as usize,
+        };
+        entries.push(PlaylistEntry { persistent_id: playlist.persistent_id()?, name: playlist.Name()?, parent, kind, track_count });
+    }
+    Ok(Output::Playlists(playlist_tree(&entries)))
+}
+
+/// Find a playlist of the library by persistent ID, or by name
+fn find_playlist(iTunes: &iTunes, name_or_id: &str) -> Result<Playlist, Box<dyn Error>> {
+    let playlists = iTunes.LibrarySource()?.Playlists()?;
+    if let Some(id) = parse_persistent_id(name_or_id).filter(|_| name_or_id.len() == 16) {
+        if let Ok(playlist) = playlists.ItemByPersistentID(id) {
+            return Ok(playlist);
+        }
+    }
+    playlists.ItemByName(name_or_id)
+        .map_err(|_| format!("No playlist named {:?}", name_or_id).into())
+}
+
+fn set_track(iTunes: &iTunes, track_id: PersistentId, values: &[(TrackField, FieldValue)]) -> Result<Output, Box<dyn Error>> {
+    let track = iTunes.LibraryPlaylist()?.Tracks()?.ItemByPersistentID(track_id)
+        .map_err(|_| format!("No track with persistent ID {}", format_persistent_id(track_id)))?;
+    let snapshot = TrackSnapshot::from_track(&track)?;
+
+    let edit = values.iter().fold(TrackEdit::new(), |edit, (field, value)| edit.set(*field, value.clone()));
+    let plan = edit.plan([&snapshot]);
+    if let Some((_, errors)) = plan.invalid.first() {
+        let errors: Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+        return Err(errors.join(", ").into());
+    }
+
+    let mut written = 0;
+    for result in plan.apply(iTunes)? {
+        written += result.written.len();
+        if let Some((field, err)) = result.failures.into_iter().next() {
+            let field = field.map(|f| f.to_string()).unwrap_or_else(|| "track".to_string());
+            return Err(format!("Unable to write {}: {}", field, err).into());
+        }
+    }
+    Ok(Output::Message(format!("{} field(s) changed", written)))
+}
+
+fn presets(iTunes: &iTunes) -> Result<Output, Box<dyn Error>> {
+    let current = iTunes.CurrentEQPreset().and_then(|preset| preset.Name()).ok();
+    let mut presets = Vec::new();
+    for preset in iTunes.EQPresets()?.iter()? {
+        let name = preset.Name()?;
+        presets.push(Preset { current: current.as_ref() == Some(&name), name });
+    }
+    Ok(Output::Presets(presets))
+}
@@ -0,0 +1,36 @@
+//! `itunes`, a command-line tool to control a local iTunes instance
+//!
+//! It must be built with the `cli` Cargo feature, e.g. `cargo run --features cli -- now-playing`
+
+#![allow(non_snake_case)]
+
+mod args;
+mod commands;
+mod output;
+
+use clap::Parser;
+
+use args::{Cli, Command, PlaylistCommand};
+use output::OutputFormat;
+
+
+fn main() {
+    let cli = Cli::parse();
+    let format = if cli.json { OutputFormat::Json } else { OutputFormat::Table };
+
+    if let Err(err) = run(&cli, format) {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn run(cli: &Cli, format: OutputFormat) -> Result<(), Box<dyn std::error::Error>> {
+    let iTunes = itunes_com::wrappers::iTunes::new()?;
+    let text = commands::run(&iTunes, &cli.command)?.render(format)?;
+
+    match &cli.command {
+        Command::Playlist { command: PlaylistCommand::Export { output: Some(path), .. } } => std::fs::write(path, text)?,
+        _ => print!("{}", text),
+    }
+    Ok(())
+}
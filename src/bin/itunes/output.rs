@@ -0,0 +1,430 @@
+//! Formatting of command results
+//!
+//! Results are plain data, built from snapshots. This module does not talk to iTunes, so that the output can be checked without a running instance.
+
+use std::collections::HashSet;
+
+use serde::Serialize;
+
+use itunes_com::wrappers::library::{TrackSnapshot, format_persistent_id};
+use itunes_com::wrappers::player::PlayerSample;
+use itunes_com::wrappers::types::PersistentId;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OutputFormat {
+    Table,
+    Json,
+}
+
+/// The result of a command
+#[derive(Debug)]
+pub enum Output {
+    /// Commands that have nothing to report (e.g. transport commands)
+    Nothing,
+    Message(String),
+    NowPlaying(NowPlaying),
+    Tracks(Vec<TrackSnapshot>),
+    /// The tracks of an exported playlist. Tables are M3U playlists in this case
+    Export(Vec<TrackSnapshot>),
+    Playlists(Vec<PlaylistNode>),
+    Presets(Vec<Preset>),
+}
+
+/// What the player is playing
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct NowPlaying {
+    pub playing: bool,
+    /// The position within the current track (in seconds)
+    pub position: i32,
+    pub track: Option<NowPlayingTrack>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct NowPlayingTrack {
+    pub persistent_id: String,
+    pub name: String,
+    pub artist: String,
+    pub album: String,
+    /// In seconds
+    pub duration: i32,
+}
+
+impl From<&PlayerSample> for NowPlaying {
+    fn from(sample: &PlayerSample) -> Self {
+        Self {
+            playing: sample.playing,
+            position: sample.position,
+            track: sample.track.as_ref().map(|track| NowPlayingTrack {
+                persistent_id: format_persistent_id(track.persistent_id),
+                name: track.name.clone(),
+                artist: track.artist.clone(),
+                album: track.album.clone(),
+                duration: track.duration,
+            }),
+        }
+    }
+}
+
+/// A playlist, and the playlists of the folder it may be
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct PlaylistNode {
+    pub persistent_id: String,
+    pub name: String,
+    /// `folder`, `smart` or `playlist`
+    pub kind: &'static str,
+    /// This is always 0 for folders
+    pub track_count: usize,
+    pub children: Vec<PlaylistNode>,
+}
+
+/// What the tree of playlists needs to know about a playlist
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PlaylistEntry {
+    pub persistent_id: PersistentId,
+    pub name: String,
+    /// The folder that contains this playlist, if any
+    pub parent: Option<PersistentId>,
+    /// `folder`, `smart` or `playlist`
+    pub kind: &'static str,
+    /// This is always 0 for folders
+    pub track_count: usize,
+}
+
+/// An equalizer preset
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct Preset {
+    pub name: String,
+    /// Whether this is the current preset
+    pub current: bool,
+}
+
+/// Nest playlists in their folders. Playlists keep their relative order, and those whose folder is unknown are put at the top level
+pub fn playlist_tree(playlists: &[PlaylistEntry]) -> Vec<PlaylistNode> {
+    let known: HashSet<PersistentId> = playlists.iter().map(|p| p.persistent_id).collect();
+    let roots: Vec<&PlaylistEntry> = playlists.iter()
+        .filter(|p| !p.parent.is_some_and(|parent| known.contains(&parent)))
+        .collect();
+    roots.into_iter().map(|root| playlist_node(root, playlists, &mut HashSet::new())).collect()
+}
+
+fn playlist_node(playlist: &PlaylistEntry, playlists: &[PlaylistEntry], ancestors: &mut HashSet<PersistentId>) -> PlaylistNode {
+    ancestors.insert(playlist.persistent_id);
+    // Ancestors are skipped, in case a corrupted library has a cycle of folders
+    let children: Vec<&PlaylistEntry> = playlists.iter()
+        .filter(|p| p.parent == Some(playlist.persistent_id) && !ancestors.contains(&p.persistent_id))
+        .collect();
+    let children = children.into_iter().map(|child| playlist_node(child, playlists, ancestors)).collect();
+    ancestors.remove(&playlist.persistent_id);
+
+    PlaylistNode {
+        persistent_id: format_persistent_id(playlist.persistent_id),
+        name: playlist.name.clone(),
+        kind: playlist.kind,
+        track_count: playlist.track_count,
+        children,
+    }
+}
+
+/// Format a duration (in seconds) as `m:ss` or `h:mm:ss`
+pub fn format_duration(seconds: i64) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    match hours {
+        0 => format!("{}:{:02}", minutes, seconds),
+        _ => format!("{}:{:02}:{:02}", hours, minutes, seconds),
+    }
+}
+
+/// Format a rating (0 to 100) as stars
+pub fn format_rating(rating: i32) -> String {
+    let stars = (rating.clamp(0, 100) / 20) as usize;
+    format!("{}{}", "*".repeat(stars), ".".repeat(5 - stars))
+}
+
+/// An extended M3U playlist of the tracks. Tracks that are not files, and tracks whose file is missing, are skipped
+pub fn m3u(tracks: &[TrackSnapshot]) -> String {
+    let mut text = String::from("#EXTM3U\n");
+    for track in tracks {
+        // iTunes reports an empty location for missing files
+        if let Some(location) = track.location.as_ref().filter(|location| !location.is_empty()) {
+            text.push_str(&format!("#EXTINF:{},{} - {}\n{}\n", track.duration, track.artist, track.name, location));
+        }
+    }
+    text
+}
+
+/// A text table, with aligned columns
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Table {
+    pub headers: Vec<&'static str>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(headers: Vec<&'static str>) -> Self {
+        Self { headers, rows: Vec::new() }
+    }
+
+    pub fn push(&mut self, row: Vec<String>) {
+        self.rows.push(row);
+    }
+}
+
+impl std::fmt::Display for Table {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut widths: Vec<usize> = self.headers.iter().map(|h| h.chars().count()).collect();
+        for row in &self.rows {
+            for (index, cell) in row.iter().enumerate() {
+                if let Some(width) = widths.get_mut(index) {
+                    *width = (*width).max(cell.chars().count());
+                }
+            }
+        }
+
+        let headers: Vec<String> = self.headers.iter().map(|h| h.to_string()).collect();
+        for row in std::iter::once(&headers).chain(&self.rows) {
+            let mut line = String::new();
+            for (index, cell) in row.iter().enumerate() {
+                if index > 0 {
+                    line.push_str("  ");
+                }
+                line.push_str(cell);
+                // The last column is not padded, so that lines have no trailing spaces
+                if index + 1 < row.len() {
+                    let width = widths.get(index).copied().unwrap_or(0);
+                    line.push_str(&" ".repeat(width.saturating_sub(cell.chars().count())));
+                }
+            }
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+fn tracks_table(tracks: &[TrackSnapshot]) -> Table {
+    let mut table = Table::new(vec!["ID", "Name", "Artist", "Album", "Time", "Rating"]);
+    for track in tracks {
+        table.push(vec![
+            format_persistent_id(track.persistent_id),
+            track.name.clone(),
+            track.artist.clone(),
+            track.album.clone(),
+            format_duration(track.duration.into()),
+            format_rating(track.rating),
+        ]);
+    }
+    table
+}
+
+fn playlists_table(playlists: &[PlaylistNode]) -> Table {
+    fn push_nodes(table: &mut Table, nodes: &[PlaylistNode], depth: usize) {
+        for node in nodes {
+            let track_count = match node.kind {
+                "folder" => String::new(),
+                _ => node.track_count.to_string(),
+            };
+            table.push(vec![node.persistent_id.clone(), format!("{}{}", "  ".repeat(depth), node.name), node.kind.to_string(), track_count]);
+            push_nodes(table, &node.children, depth + 1);
+        }
+    }
+
+    let mut table = Table::new(vec!["ID", "Name", "Kind", "Tracks"]);
+    push_nodes(&mut table, playlists, 0);
+    table
+}
+
+fn presets_table(presets: &[Preset]) -> Table {
+    let mut table = Table::new(vec!["", "Preset"]);
+    for preset in presets {
+        let marker = if preset.current { "*" } else { "" };
+        table.push(vec![marker.to_string(), preset.name.clone()]);
+    }
+    table
+}
+
+impl std::fmt::Display for NowPlaying {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = if self.playing { "Playing" } else { "Stopped" };
+        match &self.track {
+            None => writeln!(f, "{} (no current track)", state),
+            Some(track) => {
+                writeln!(f, "{}: {} - {} ({})", state, track.artist, track.name, track.album)?;
+                writeln!(f, "{} / {}  [{}]", format_duration(self.position.into()), format_duration(track.duration.into()), track.persistent_id)
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonMessage<'a> {
+    message: &'a str,
+}
+
+impl Output {
+    /// Render this result, for the standard output
+    pub fn render(&self, format: OutputFormat) -> serde_json::Result<String> {
+        match format {
+            OutputFormat::Table => Ok(match self {
+                Output::Nothing => String::new(),
+                Output::Message(message) => format!("{}\n", message),
+                Output::NowPlaying(now_playing) => now_playing.to_string(),
+                Output::Tracks(tracks) => tracks_table(tracks).to_string(),
+                Output::Export(tracks) => m3u(tracks),
+                Output::Playlists(playlists) => playlists_table(playlists).to_string(),
+                Output::Presets(presets) => presets_table(presets).to_string(),
+            }),
+            OutputFormat::Json => {
+                let json = match self {
+                    Output::Nothing => return Ok(String::new()),
+                    Output::Message(message) => serde_json::to_string_pretty(&JsonMessage { message })?,
+                    Output::NowPlaying(now_playing) => serde_json::to_string_pretty(now_playing)?,
+                    Output::Tracks(tracks) | Output::Export(tracks) => serde_json::to_string_pretty(tracks)?,
+                    Output::Playlists(playlists) => serde_json::to_string_pretty(playlists)?,
+                    Output::Presets(presets) => serde_json::to_string_pretty(presets)?,
+                };
+                Ok(format!("{}\n", json))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(persistent_id: PersistentId, name: &str, parent: Option<PersistentId>, kind: &'static str, track_count: usize) -> PlaylistEntry {
+        PlaylistEntry { persistent_id, name: name.to_string(), parent, kind, track_count }
+    }
+
+    fn track(persistent_id: PersistentId, name: &str, location: Option<&str>) -> TrackSnapshot {
+        TrackSnapshot {
+            persistent_id,
+            name: name.to_string(),
+            artist: "The Beatles".to_string(),
+            album: "Help!".to_string(),
+            duration: 125,
+            rating: 80,
+            location: location.map(str::to_string),
+            ..TrackSnapshot::default()
+        }
+    }
+
+    #[test]
+    fn playlists_are_nested_in_their_folders() {
+        let tree = playlist_tree(&[
+            entry(0x3, "Jazz", Some(0x1), "smart", 12),
+            entry(0x1, "Genres", None, "folder", 0),
+            entry(0x2, "Rock", Some(0x1), "playlist", 3),
+            entry(0x4, "Favorites", None, "playlist", 2),
+            // Its folder is not in the library
+            entry(0x5, "Orphan", Some(0x99), "playlist", 1),
+        ]);
+        assert_eq!(tree.iter().map(|node| node.name.as_str()).collect::<Vec<_>>(), ["Genres", "Favorites", "Orphan"]);
+        assert_eq!(tree[0].children.iter().map(|node| node.name.as_str()).collect::<Vec<_>>(), ["Jazz", "Rock"]);
+        assert_eq!(tree[0].children[0], PlaylistNode {
+            persistent_id: "0000000000000003".to_string(),
+            name: "Jazz".to_string(),
+            kind: "smart",
+            track_count: 12,
+            children: Vec::new(),
+        });
+
+        assert_eq!(Output::Playlists(tree).render(OutputFormat::Table).unwrap(), concat!(
+            "ID                Name       Kind      Tracks\n",
+            "0000000000000001  Genres     folder\n",
+            "0000000000000003    Jazz     smart     12\n",
+            "0000000000000002    Rock     playlist  3\n",
+            "0000000000000004  Favorites  playlist  2\n",
+            "0000000000000005  Orphan     playlist  1\n",
+        ));
+    }
+
+    #[test]
+    fn folder_cycles_are_broken() {
+        // Neither folder is at the top level, so neither is shown
+        let tree = playlist_tree(&[entry(0x1, "A", Some(0x2), "folder", 0), entry(0x2, "B", Some(0x1), "folder", 0)]);
+        assert!(tree.is_empty());
+
+        let tree = playlist_tree(&[entry(0x1, "A", None, "folder", 0), entry(0x2, "B", Some(0x1), "folder", 0), entry(0x1, "A again", Some(0x2), "folder", 0)]);
+        assert_eq!(tree.len(), 1);
+        assert_eq!(tree[0].children[0].name, "B");
+        assert!(tree[0].children[0].children.is_empty());
+    }
+
+    #[test]
+    fn durations_and_ratings() {
+        assert_eq!(format_duration(0), "0:00");
+        assert_eq!(format_duration(125), "2:05");
+        assert_eq!(format_duration(3599), "59:59");
+        assert_eq!(format_duration(3661), "1:01:01");
+
+        assert_eq!(format_rating(0), ".....");
+        assert_eq!(format_rating(59), "**...");
+        assert_eq!(format_rating(100), "*****");
+        assert_eq!(format_rating(-20), ".....");
+        assert_eq!(format_rating(120), "*****");
+    }
+
+    #[test]
+    fn m3u_skips_streams_and_dead_tracks() {
+        let tracks = [
+            track(0x1, "Yesterday", Some("C:\\Music\\Yesterday.mp3")),
+            track(0x2, "Stream", None),
+            track(0x3, "Missing", Some("")),
+            track(0x4, "Help!", Some("C:\\Music\\Help!.mp3")),
+        ];
+        assert_eq!(m3u(&tracks), concat!(
+            "#EXTM3U\n",
+            "#EXTINF:125,The Beatles - Yesterday\nC:\\Music\\Yesterday.mp3\n",
+            "#EXTINF:125,The Beatles - Help!\nC:\\Music\\Help!.mp3\n",
+        ));
+        assert_eq!(Output::Export(tracks.to_vec()).render(OutputFormat::Table).unwrap(), m3u(&tracks));
+        assert_eq!(m3u(&[]), "#EXTM3U\n");
+    }
+
+    #[test]
+    fn tables_are_aligned() {
+        let tracks = vec![track(0xAB, "Yesterday", None), track(0xCD, "Help!", None)];
+        assert_eq!(Output::Tracks(tracks).render(OutputFormat::Table).unwrap(), concat!(
+            "ID                Name       Artist       Album  Time  Rating\n",
+            "00000000000000AB  Yesterday  The Beatles  Help!  2:05  ****.\n",
+            "00000000000000CD  Help!      The Beatles  Help!  2:05  ****.\n",
+        ));
+
+        let presets = vec![Preset { name: "Flat".to_string(), current: false }, Preset { name: "Rock".to_string(), current: true }];
+        assert_eq!(Output::Presets(presets).render(OutputFormat::Table).unwrap(), "   Preset\n   Flat\n*  Rock\n");
+    }
+
+    #[test]
+    fn now_playing() {
+        let now_playing = NowPlaying {
+            playing: true,
+            position: 62,
+            track: Some(NowPlayingTrack {
+                persistent_id: "0000000000000001".to_string(),
+                name: "Yesterday".to_string(),
+                artist: "The Beatles".to_string(),
+                album: "Help!".to_string(),
+                duration: 125,
+            }),
+        };
+        assert_eq!(Output::NowPlaying(now_playing.clone()).render(OutputFormat::Table).unwrap(), "Playing: The Beatles - Yesterday (Help!)\n1:02 / 2:05  [0000000000000001]\n");
+
+        let json: serde_json::Value = serde_json::from_str(&Output::NowPlaying(now_playing).render(OutputFormat::Json).unwrap()).unwrap();
+        assert_eq!(json, serde_json::json!({
+            "playing": true,
+            "position": 62,
+            "track": { "persistent_id": "0000000000000001", "name": "Yesterday", "artist": "The Beatles", "album": "Help!", "duration": 125 },
+        }));
+
+        let stopped = NowPlaying { playing: false, position: 0, track: None };
+        assert_eq!(Output::NowPlaying(stopped).render(OutputFormat::Table).unwrap(), "Stopped (no current track)\n");
+    }
+
+    #[test]
+    fn messages() {
+        assert_eq!(Output::Nothing.render(OutputFormat::Table).unwrap(), "");
+        assert_eq!(Output::Nothing.render(OutputFormat::Json).unwrap(), "");
+        assert_eq!(Output::Message("2 field(s) changed".to_string()).render(OutputFormat::Table).unwrap(), "2 field(s) changed\n");
+        assert_eq!(Output::Message("2 field(s) changed".to_string()).render(OutputFormat::Json).unwrap(), "{\n  \"message\": \"2 field(s) changed\"\n}\n");
+    }
+}
@@ -30,6 +30,11 @@
 //!
 //! Examples are available in the `examples/` folder. Run them with `cargo run --example ... --all-features`.
 //!
+//! ## Command-line tool
+//!
+//! In case it is built with the `cli` Cargo feature, this crate also provides an `itunes` binary, that controls the player, searches the library, exports playlists, etc.
+//! Run `cargo run --features cli -- help` for the list of commands.
+//!
 //! ## Notes
 //!
 //! This crate probably does not correctly work on 32-bit machines (run `cargo clippy --all-features` and see the Clippy errors).
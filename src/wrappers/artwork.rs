@@ -0,0 +1,190 @@
+//! Artwork images as bytes
+//!
+//! iTunes only reads and writes artwork through image files. This module hides the temporary files, and finds out the actual format of images
+//! from their contents (see [`ImageFormat::sniff`]), since the [`ITArtworkFormat`] iTunes reports is not always right.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::sys::ITArtworkFormat;
+use super::Artwork;
+
+/// Distinguishes the temporary files of a process
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// The image formats iTunes supports for artwork
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ImageFormat {
+    Jpeg,
+    Png,
+    Bmp,
+}
+
+impl ImageFormat {
+    /// Find out the format of an image from its magic number
+    pub fn sniff(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(ImageFormat::Jpeg)
+        } else if bytes.starts_with(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]) {
+            Some(ImageFormat::Png)
+        } else if bytes.starts_with(b"BM") && bytes.len() >= 14 {
+            Some(ImageFormat::Bmp)
+        } else {
+            None
+        }
+    }
+
+    /// The format iTunes reports, if it is known
+    pub fn from_artwork_format(format: ITArtworkFormat) -> Option<Self> {
+        match format {
+            ITArtworkFormat::ITArtworkFormatJPEG => Some(ImageFormat::Jpeg),
+            ITArtworkFormat::ITArtworkFormatPNG => Some(ImageFormat::Png),
+            ITArtworkFormat::ITArtworkFormatBMP => Some(ImageFormat::Bmp),
+            ITArtworkFormat::ITArtworkFormatUnknown => None,
+        }
+    }
+
+    /// The format of a file, according to its extension (case-insensitively)
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "jpg" | "jpeg" => Some(ImageFormat::Jpeg),
+            "png" => Some(ImageFormat::Png),
+            "bmp" => Some(ImageFormat::Bmp),
+            _ => None,
+        }
+    }
+
+    /// The usual file extension (without the dot)
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Png => "png",
+            ImageFormat::Bmp => "bmp",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::Png => "image/png",
+            ImageFormat::Bmp => "image/bmp",
+        }
+    }
+}
+
+/// The contents of an image, and its format
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ArtworkImage {
+    pub format: ImageFormat,
+    pub bytes: Vec<u8>,
+}
+
+impl ArtworkImage {
+    /// Check that `bytes` are an image in a supported format
+    pub fn from_bytes(bytes: Vec<u8>) -> std::io::Result<Self> {
+        match ImageFormat::sniff(&bytes) {
+            Some(format) => Ok(Self { format, bytes }),
+            None => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Not a JPEG, PNG or BMP image")),
+        }
+    }
+
+    /// The width and height of the image (in pixels), read from its header. This is `None` for truncated or corrupted images
+    pub fn dimensions(&self) -> Option<(u32, u32)> {
+        let bytes = &self.bytes;
+        let u16_be = |at: usize| Some(u16::from_be_bytes(bytes.get(at..at + 2)?.try_into().ok()?));
+        let u32_be = |at: usize| Some(u32::from_be_bytes(bytes.get(at..at + 4)?.try_into().ok()?));
+        let i32_le = |at: usize| Some(i32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?));
+
+        match self.format {
+            // The IHDR chunk comes first
+            ImageFormat::Png => Some((u32_be(16)?, u32_be(20)?)),
+            // Heights are negative for top-down bitmaps
+            ImageFormat::Bmp => Some((i32_le(18)?.unsigned_abs(), i32_le(22)?.unsigned_abs())),
+            ImageFormat::Jpeg => {
+                // Walk the segments, up to the start of a frame (SOF0 to SOF15, apart from DHT, JPG and DAC)
+                let mut at = 2;
+                loop {
+                    if *bytes.get(at)? != 0xFF {
+                        return None;
+                    }
+                    let marker = *bytes.get(at + 1)?;
+                    match marker {
+                        // Fill bytes
+                        0xFF => at += 1,
+                        0xC0..=0xCF if marker != 0xC4 && marker != 0xC8 && marker != 0xCC => {
+                            return Some((u16_be(at + 7)?.into(), u16_be(at + 5)?.into()));
+                        },
+                        _ => at += 2 + usize::from(u16_be(at + 2)?),
+                    }
+                }
+            },
+        }
+    }
+}
+
+/// A path for a temporary image file, that no other thread of this process uses
+fn temp_path(format: Option<ImageFormat>) -> PathBuf {
+    let counter = TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+    // iTunes looks at the extension to decide how to write or read the file
+    let extension = format.map_or("tmp", |f| f.extension());
+    std::env::temp_dir().join(format!("itunes-com-artwork-{}-{}.{}", std::process::id(), counter, extension))
+}
+
+/// A temporary copy of an image, for the COM methods that read images from files. It is deleted when dropped
+pub(crate) struct TempImageFile {
+    path: PathBuf,
+}
+
+impl TempImageFile {
+    pub(crate) fn create(image: &ArtworkImage) -> std::io::Result<Self> {
+        let path = temp_path(Some(image.format));
+        std::fs::write(&path, &image.bytes)?;
+        Ok(Self { path })
+    }
+
+    pub(crate) fn path(&self) -> String {
+        self.path.to_string_lossy().into_owned()
+    }
+}
+
+impl Drop for TempImageFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+impl Artwork {
+    /// Read the image data of this artwork (through a temporary file)
+    pub fn to_bytes(&self) -> std::io::Result<Vec<u8>> {
+        let path = temp_path(ImageFormat::from_artwork_format(self.Format()?));
+        let result = self.SaveArtworkToFile(&path.to_string_lossy())
+            .map_err(std::io::Error::from)
+            .and_then(|_| std::fs::read(&path));
+        let _ = std::fs::remove_file(&path);
+        result
+    }
+
+    /// Read the image data of this artwork, and check its format.
+    ///
+    /// The format is the actual format of the data, which may differ from the [`Format`](Artwork::Format) iTunes reports.
+    pub fn to_image(&self) -> std::io::Result<ArtworkImage> {
+        ArtworkImage::from_bytes(self.to_bytes()?)
+    }
+
+    /// Save this artwork to `path`, with the extension of its actual format (e.g. `covers/abbey_road` becomes `covers/abbey_road.jpg`).
+    ///
+    /// This returns the path of the file.
+    pub fn save_as<P: AsRef<Path>>(&self, path: P) -> std::io::Result<PathBuf> {
+        let image = self.to_image()?;
+        let path = path.as_ref().with_extension(image.format.extension());
+        std::fs::write(&path, &image.bytes)?;
+        Ok(path)
+    }
+
+    /// Replace this artwork with an image (in any [`ImageFormat`])
+    pub fn set_from_bytes(&self, bytes: &[u8]) -> std::io::Result<()> {
+        let file = TempImageFile::create(&ArtworkImage::from_bytes(bytes.to_vec())?)?;
+        Ok(self.SetArtworkFromFile(&file.path())?)
+    }
+}
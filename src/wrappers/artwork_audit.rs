@@ -0,0 +1,446 @@
+//! Artwork audit and repair
+//!
+//! An audit groups the tracks of a library by album, and reports the albums whose artwork needs some care:
+//! tracks without artwork, tracks whose artwork differs from the rest of the album (different images, or downloaded artwork mixed with embedded artwork),
+//! tracks with several pieces of artwork, artwork that cannot be read, and oversized images.
+//!
+//! A [`RepairPlan`] then propagates the image of each album (the one most of its tracks have) to the tracks that lack it.
+//! Plans are plain data, that can be printed as a dry-run report before being applied.
+//!
+//! ```no_run
+//! use itunes_com::wrappers::iTunes;
+//! use itunes_com::wrappers::artwork_audit::{audit_library, AuditOptions, RepairOptions, RepairPlan};
+//!
+//! let iTunes = iTunes::new().unwrap();
+//! let audit = audit_library(&iTunes, &AuditOptions::default(), |_, _| ()).unwrap();
+//! let plan = RepairPlan::new(&audit, &RepairOptions::default());
+//! print!("{}", plan);  // The dry-run report
+//! let failures = plan.apply(&iTunes).unwrap();
+//! ```
+
+use std::collections::BTreeMap;
+
+use super::{iTunes, IITObjectWrapper, IITPlaylistWrapper, IITTrackWrapper, Iterable};
+use super::artwork::{ImageFormat, TempImageFile};
+use super::artwork_cache::content_hash;
+use super::library::{TrackSnapshot, format_persistent_id};
+use super::types::PersistentId;
+
+/// A piece of artwork, as seen by an audit
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ArtworkSample {
+    /// See [`content_hash`]
+    pub hash: String,
+    pub format: ImageFormat,
+    /// The size of the image (in bytes)
+    pub size: usize,
+    /// The width and height of the image (in pixels), if they could be read
+    pub dimensions: Option<(u32, u32)>,
+    /// Whether iTunes downloaded this artwork (rather than it being embedded in the file)
+    pub is_downloaded: bool,
+}
+
+/// The artwork of a track, as seen by an audit
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TrackArtworks {
+    pub track: PersistentId,
+    pub name: String,
+    pub album: AlbumKey,
+    /// Every piece of artwork of the track that could be read. The first one is the one iTunes displays, unless it could not be read
+    pub artworks: Vec<ArtworkSample>,
+    /// The positions (from 1, as in `Artwork`) of the pieces of artwork that could not be read, or that are not JPEG, PNG or BMP images
+    pub unreadable: Vec<usize>,
+}
+
+impl TrackArtworks {
+    pub fn new(track: &TrackSnapshot, artworks: Vec<ArtworkSample>) -> Self {
+        Self { track: track.persistent_id, name: track.name.clone(), album: AlbumKey::of(track), artworks, unreadable: Vec::new() }
+    }
+
+    /// The artwork iTunes displays, if it could be read
+    fn primary(&self) -> Option<&ArtworkSample> {
+        match self.unreadable.first() {
+            Some(1) => None,
+            _ => self.artworks.first(),
+        }
+    }
+
+    fn artwork_count(&self) -> usize {
+        self.artworks.len() + self.unreadable.len()
+    }
+}
+
+/// What identifies an album
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct AlbumKey {
+    /// The album artist, or the artist of the tracks that have no album artist
+    pub album_artist: String,
+    pub album: String,
+}
+
+impl AlbumKey {
+    pub fn of(track: &TrackSnapshot) -> Self {
+        let album_artist = match track.album_artist.is_empty() {
+            true => track.artist.clone(),
+            false => track.album_artist.clone(),
+        };
+        Self { album_artist, album: track.album.clone() }
+    }
+}
+
+impl std::fmt::Display for AlbumKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} - {}", self.album_artist, self.album)
+    }
+}
+
+/// What is considered oversized
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AuditOptions {
+    /// The largest acceptable image (in bytes)
+    pub max_size: usize,
+    /// The largest acceptable width or height (in pixels)
+    pub max_dimension: u32,
+}
+
+impl Default for AuditOptions {
+    fn default() -> Self {
+        Self { max_size: 1024 * 1024, max_dimension: 1500 }
+    }
+}
+
+/// Something wrong with the artwork of an album
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AlbumIssue {
+    /// None of the tracks has artwork
+    NoArtwork,
+    /// Some tracks have no artwork
+    Missing(Vec<PersistentId>),
+    /// The tracks do not all show the same image. These are the tracks of each image (by hash), most common image first
+    DifferingImages(Vec<(String, Vec<PersistentId>)>),
+    /// Some tracks show downloaded artwork, others show artwork embedded in their files. These are the tracks with downloaded artwork
+    MixedDownloaded(Vec<PersistentId>),
+    /// Some tracks have several pieces of artwork
+    SeveralPieces(Vec<PersistentId>),
+    /// Some tracks have artwork that cannot be read, or that is not a JPEG, PNG or BMP image
+    Unreadable(Vec<PersistentId>),
+    /// Some tracks have images larger than [`AuditOptions`] allow
+    Oversized(Vec<PersistentId>),
+}
+
+impl std::fmt::Display for AlbumIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AlbumIssue::NoArtwork => write!(f, "no artwork"),
+            AlbumIssue::Missing(tracks) => write!(f, "{} track(s) without artwork", tracks.len()),
+            AlbumIssue::DifferingImages(images) => write!(f, "{} different images", images.len()),
+            AlbumIssue::MixedDownloaded(tracks) => write!(f, "{} track(s) with downloaded artwork, among embedded artwork", tracks.len()),
+            AlbumIssue::SeveralPieces(tracks) => write!(f, "{} track(s) with several pieces of artwork", tracks.len()),
+            AlbumIssue::Unreadable(tracks) => write!(f, "{} track(s) with unreadable artwork", tracks.len()),
+            AlbumIssue::Oversized(tracks) => write!(f, "{} track(s) with oversized artwork", tracks.len()),
+        }
+    }
+}
+
+/// The audit of an album
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AlbumAudit {
+    pub album: AlbumKey,
+    pub tracks: Vec<TrackArtworks>,
+    /// The hash of the image most tracks show (the first one found, in case of a tie), if any
+    pub chosen: Option<String>,
+    pub issues: Vec<AlbumIssue>,
+}
+
+impl AlbumAudit {
+    /// Audit the tracks of an album
+    pub fn new(album: AlbumKey, tracks: Vec<TrackArtworks>, options: &AuditOptions) -> Self {
+        let mut issues = Vec::new();
+
+        // Tracks by primary image, in the order images are found
+        let mut images: Vec<(String, Vec<PersistentId>)> = Vec::new();
+        for track in &tracks {
+            if let Some(primary) = track.primary() {
+                match images.iter_mut().find(|(hash, _)| *hash == primary.hash) {
+                    Some((_, ids)) => ids.push(track.track),
+                    None => images.push((primary.hash.clone(), vec![track.track])),
+                }
+            }
+        }
+        // This sort is stable, so that ties keep the first image first
+        images.sort_by_key(|(_, ids)| std::cmp::Reverse(ids.len()));
+        let chosen = images.first().map(|(hash, _)| hash.clone());
+
+        let select = |predicate: &dyn Fn(&TrackArtworks) -> bool| -> Vec<PersistentId> {
+            tracks.iter().filter(|t| predicate(t)).map(|t| t.track).collect()
+        };
+
+        let missing = select(&|t| t.artwork_count() == 0);
+        if missing.len() == tracks.len() {
+            issues.push(AlbumIssue::NoArtwork);
+        } else if !missing.is_empty() {
+            issues.push(AlbumIssue::Missing(missing));
+        }
+        if images.len() > 1 {
+            issues.push(AlbumIssue::DifferingImages(images));
+        }
+        let downloaded = select(&|t| t.primary().is_some_and(|a| a.is_downloaded));
+        let embedded = select(&|t| t.primary().is_some_and(|a| !a.is_downloaded));
+        if !downloaded.is_empty() && !embedded.is_empty() {
+            issues.push(AlbumIssue::MixedDownloaded(downloaded));
+        }
+        let several = select(&|t| t.artwork_count() > 1);
+        if !several.is_empty() {
+            issues.push(AlbumIssue::SeveralPieces(several));
+        }
+        let unreadable = select(&|t| !t.unreadable.is_empty());
+        if !unreadable.is_empty() {
+            issues.push(AlbumIssue::Unreadable(unreadable));
+        }
+        let oversized = select(&|t| t.artworks.iter().any(|a| {
+            a.size > options.max_size || a.dimensions.is_some_and(|(w, h)| w.max(h) > options.max_dimension)
+        }));
+        if !oversized.is_empty() {
+            issues.push(AlbumIssue::Oversized(oversized));
+        }
+
+        Self { album, tracks, chosen, issues }
+    }
+}
+
+/// Audit tracks, grouped by album. Only albums with issues are returned, sorted by album artist, then album.
+///
+/// Tracks that have no album are skipped.
+pub fn audit_tracks<I: IntoIterator<Item = TrackArtworks>>(tracks: I, options: &AuditOptions) -> Vec<AlbumAudit> {
+    let mut albums: BTreeMap<AlbumKey, Vec<TrackArtworks>> = BTreeMap::new();
+    for track in tracks {
+        if !track.album.album.is_empty() {
+            albums.entry(track.album.clone()).or_default().push(track);
+        }
+    }
+    albums.into_iter()
+        .map(|(album, tracks)| AlbumAudit::new(album, tracks, options))
+        .filter(|audit| !audit.issues.is_empty())
+        .collect()
+}
+
+/// The result of [`audit_library`]
+#[derive(Debug)]
+pub struct LibraryAudit {
+    /// The albums with issues
+    pub albums: Vec<AlbumAudit>,
+    /// The tracks that could not be read, or whose artwork could not be listed (with a persistent ID of 0 if even it could not be read)
+    pub failures: Vec<(PersistentId, std::io::Error)>,
+}
+
+/// Read the artwork of a live track.
+///
+/// Pieces of artwork that cannot be read are skipped, and their positions (from 1) are returned along with the others
+fn read_artworks<T: IITTrackWrapper>(track: &T) -> std::io::Result<(Vec<ArtworkSample>, Vec<usize>)> {
+    let mut samples = Vec::new();
+    let mut unreadable = Vec::new();
+    for (index, artwork) in track.Artwork()?.iter()?.enumerate() {
+        let sample = artwork.to_image().and_then(|image| Ok(ArtworkSample {
+            hash: content_hash(&image.bytes),
+            format: image.format,
+            size: image.bytes.len(),
+            dimensions: image.dimensions(),
+            is_downloaded: artwork.is_IsDownloadedArtwork()?,
+        }));
+        match sample {
+            Ok(sample) => samples.push(sample),
+            Err(_) => unreadable.push(index + 1),
+        }
+    }
+    Ok((samples, unreadable))
+}
+
+/// Audit the artwork of the main library of a live iTunes instance.
+///
+/// `progress` is called after every track, with the number of processed tracks and the total.
+/// This reads every piece of artwork of every track, which takes a while on large libraries.
+pub fn audit_library<F: FnMut(usize, usize)>(iTunes: &iTunes, options: &AuditOptions, mut progress: F) -> std::io::Result<LibraryAudit> {
+    let tracks = iTunes.LibraryPlaylist()?.Tracks()?;
+    let total = tracks.Count()? as usize;
+    let mut audited = Vec::new();
+    let mut failures = Vec::new();
+
+    for (done, track) in tracks.iter()?.enumerate() {
+        let result = TrackSnapshot::from_track(&track)
+            .map_err(std::io::Error::from)
+            .and_then(|snapshot| Ok((snapshot, read_artworks(&track)?)));
+        match result {
+            Ok((snapshot, (artworks, unreadable))) => audited.push(TrackArtworks { unreadable, ..TrackArtworks::new(&snapshot, artworks) }),
+            Err(err) => failures.push((track.persistent_id().unwrap_or_default(), err)),
+        }
+        progress(done + 1, total);
+    }
+
+    Ok(LibraryAudit { albums: audit_tracks(audited, options), failures })
+}
+
+/// What a [`RepairPlan`] fixes
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RepairOptions {
+    /// Add the image of the album to the tracks without artwork
+    pub add_missing: bool,
+    /// Replace the images of the tracks that show another image than the rest of their album
+    pub replace_differing: bool,
+}
+
+impl Default for RepairOptions {
+    fn default() -> Self {
+        Self { add_missing: true, replace_differing: true }
+    }
+}
+
+/// A change to the artwork of a track
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RepairKind {
+    /// Add the image, with `AddArtworkFromFile`
+    Add,
+    /// Replace the image the track shows, with `SetArtworkFromFile`
+    Replace,
+}
+
+/// The changes to the tracks of an album
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AlbumRepair {
+    pub album: AlbumKey,
+    /// The hash of the image to propagate
+    pub image: String,
+    /// A track that shows this image, to read it from
+    pub source: PersistentId,
+    pub changes: Vec<(PersistentId, RepairKind)>,
+}
+
+/// The changes that propagate the image of albums to all their tracks
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RepairPlan {
+    pub albums: Vec<AlbumRepair>,
+}
+
+impl RepairPlan {
+    pub fn new(audit: &LibraryAudit, options: &RepairOptions) -> Self {
+        Self::from_albums(&audit.albums, options)
+    }
+
+    /// Plan the repair of audited albums
+    pub fn from_albums(albums: &[AlbumAudit], options: &RepairOptions) -> Self {
+        let mut repairs = Vec::new();
+        for album in albums {
+            let image = match &album.chosen {
+                Some(image) => image,
+                None => continue,
+            };
+            let source = album.tracks.iter()
+                .find(|t| t.primary().is_some_and(|a| &a.hash == image))
+                .map(|t| t.track);
+            let source = match source {
+                Some(source) => source,
+                None => continue,
+            };
+
+            // Tracks whose artwork cannot be read are left alone
+            let changes: Vec<(PersistentId, RepairKind)> = album.tracks.iter()
+                .filter_map(|t| match t.primary() {
+                    None if options.add_missing && t.artwork_count() == 0 => Some((t.track, RepairKind::Add)),
+                    Some(primary) if options.replace_differing && &primary.hash != image => Some((t.track, RepairKind::Replace)),
+                    _ => None,
+                })
+                .collect();
+            if !changes.is_empty() {
+                repairs.push(AlbumRepair { album: album.album.clone(), image: image.clone(), source, changes });
+            }
+        }
+        Self { albums: repairs }
+    }
+
+    /// The number of tracks to change
+    pub fn len(&self) -> usize {
+        self.albums.iter().map(|a| a.changes.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.albums.is_empty()
+    }
+
+    /// Apply this plan to the main library of a live iTunes instance.
+    ///
+    /// The image of each album is read again from its source track, and is checked to be the one that has been audited.
+    /// This does not stop at the first failure (e.g. a read-only file), but returns every track that could not be changed.
+    pub fn apply(&self, iTunes: &iTunes) -> std::io::Result<Vec<(PersistentId, std::io::Error)>> {
+        let library_tracks = iTunes.LibraryPlaylist()?.Tracks()?;
+        let mut failures = Vec::new();
+
+        for album in &self.albums {
+            let file = library_tracks.ItemByPersistentID(album.source)
+                .and_then(|source| source.primary_artwork())
+                .map_err(std::io::Error::from)
+                .and_then(|artwork| artwork.ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "The source track has no artwork anymore")))
+                .and_then(|artwork| artwork.to_image())
+                .and_then(|image| match content_hash(&image.bytes) == album.image {
+                    true => TempImageFile::create(&image),
+                    false => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "The artwork of the source track has changed since the audit")),
+                });
+            let file = match file {
+                Ok(file) => file,
+                Err(err) => {
+                    // The whole album fails. `io::Error` cannot be cloned
+                    for (track, _) in &album.changes {
+                        failures.push((*track, std::io::Error::new(err.kind(), err.to_string())));
+                    }
+                    continue;
+                },
+            };
+
+            for (track_id, kind) in &album.changes {
+                let result = library_tracks.ItemByPersistentID(*track_id).and_then(|track| match kind {
+                    RepairKind::Add => track.AddArtworkFromFile(&file.path()).map(|_| ()),
+                    RepairKind::Replace => match track.primary_artwork()? {
+                        Some(artwork) => artwork.SetArtworkFromFile(&file.path()),
+                        None => track.AddArtworkFromFile(&file.path()).map(|_| ()),
+                    },
+                });
+                if let Err(err) = result {
+                    failures.push((*track_id, err.into()));
+                }
+            }
+        }
+
+        Ok(failures)
+    }
+}
+
+/// The dry-run report
+impl std::fmt::Display for RepairPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} track(s) to change in {} album(s)", self.len(), self.albums.len())?;
+        for album in &self.albums {
+            writeln!(f, "  {} (image {}, from [{}])", album.album, &album.image[..album.image.len().min(12)], format_persistent_id(album.source))?;
+            for (track, kind) in &album.changes {
+                let action = match kind {
+                    RepairKind::Add => "add",
+                    RepairKind::Replace => "replace",
+                };
+                writeln!(f, "      {:<7} [{}]", action, format_persistent_id(*track))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A summary of the audit, album by album
+impl std::fmt::Display for LibraryAudit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} album(s) with artwork issues", self.albums.len())?;
+        for album in &self.albums {
+            let issues: Vec<String> = album.issues.iter().map(|issue| issue.to_string()).collect();
+            writeln!(f, "  {} ({} tracks): {}", album.album, album.tracks.len(), issues.join(", "))?;
+        }
+        if !self.failures.is_empty() {
+            writeln!(f, "{} track(s) whose artwork could not be read", self.failures.len())?;
+        }
+        Ok(())
+    }
+}
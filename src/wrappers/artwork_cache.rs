@@ -0,0 +1,296 @@
+//! Deduplicated artwork cache
+//!
+//! An [`ArtworkCache`] is a directory where artwork is stored by content: every unique image is written once, to `images/<hash>.<extension>`,
+//! where `hash` is the SHA-256 of its bytes. Thumbnails (with the `thumbnails` Cargo feature) go to `thumbnails/<size>/<hash>.jpg`.
+//!
+//! [`ArtworkCache::export_library`] dumps the artwork of a whole library, and writes an [`ArtworkIndex`] to `index.json`,
+//! that maps albums and tracks to their images. Front-ends can then load covers without talking to iTunes.
+//!
+//! ```no_run
+//! use itunes_com::wrappers::iTunes;
+//! use itunes_com::wrappers::artwork_cache::ArtworkCache;
+//!
+//! let iTunes = iTunes::new().unwrap();
+//! let cache = ArtworkCache::new("C:\\covers");
+//! let report = cache.export_library(&iTunes, |done, total| println!("{}/{}", done, total)).unwrap();
+//! println!("{} unique images for {} albums", report.index.images.len(), report.index.albums.len());
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{iTunes, Track, IITObjectWrapper, IITPlaylistWrapper, IITTrackWrapper, Iterable};
+use super::artwork::{ArtworkImage, ImageFormat};
+use super::artwork_audit::AlbumKey;
+use super::library::{TrackSnapshot, format_persistent_id};
+use super::types::PersistentId;
+
+/// The name of the index file, at the root of the cache
+pub const INDEX_FILE_NAME: &str = "index.json";
+
+/// The SHA-256 of some bytes, as lowercase hexadecimal digits
+pub fn content_hash(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// An image of the cache
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CachedImage {
+    /// See [`content_hash`]
+    pub hash: String,
+    pub format: ImageFormat,
+    /// The path of the image, relative to the root of the cache (with forward slashes)
+    pub file: String,
+    /// The size of the image (in bytes)
+    pub size: u64,
+    /// The paths of the thumbnails, relative to the root of the cache, by size (in pixels)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub thumbnails: BTreeMap<u32, String>,
+}
+
+/// The image of an album
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct AlbumArtwork {
+    /// The album artist, or the artist of the tracks that have no album artist
+    pub album_artist: String,
+    pub album: String,
+    /// The hash of the image
+    pub image: String,
+}
+
+/// The artwork of a track, as found during an export
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TrackArtwork {
+    pub track: PersistentId,
+    pub album: AlbumKey,
+    pub image: CachedImage,
+}
+
+impl TrackArtwork {
+    pub fn new(track: &TrackSnapshot, image: CachedImage) -> Self {
+        Self { track: track.persistent_id, album: AlbumKey::of(track), image }
+    }
+}
+
+/// What a cache contains, as stored in its `index.json`
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ArtworkIndex {
+    /// The images, by hash
+    pub images: BTreeMap<String, CachedImage>,
+    /// The images of albums, sorted by album artist, then album
+    pub albums: Vec<AlbumArtwork>,
+    /// The hashes of the images of tracks, by persistent ID (formatted by [`format_persistent_id`])
+    pub tracks: BTreeMap<String, String>,
+}
+
+impl ArtworkIndex {
+    /// Index the artwork of tracks.
+    ///
+    /// The image of an album is the one most of its tracks have (or the first one found, in case of a tie). Tracks without an album are only indexed as tracks.
+    pub fn build(artworks: &[TrackArtwork]) -> Self {
+        let mut index = ArtworkIndex::default();
+        // Counts of images by album, in the order they have been found
+        let mut album_images: BTreeMap<&AlbumKey, Vec<(&str, usize)>> = BTreeMap::new();
+
+        for artwork in artworks {
+            index.images.entry(artwork.image.hash.clone()).or_insert_with(|| artwork.image.clone());
+            index.tracks.insert(format_persistent_id(artwork.track), artwork.image.hash.clone());
+
+            if artwork.album.album.is_empty() {
+                continue;
+            }
+            let counts = album_images.entry(&artwork.album).or_default();
+            match counts.iter_mut().find(|(hash, _)| *hash == artwork.image.hash) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((&artwork.image.hash, 1)),
+            }
+        }
+
+        for (album, counts) in album_images {
+            // `max_by_key` returns the last maximum, hence the reversal
+            if let Some((hash, _)) = counts.iter().rev().max_by_key(|(_, count)| *count) {
+                index.albums.push(AlbumArtwork { album_artist: album.album_artist.clone(), album: album.album.clone(), image: hash.to_string() });
+            }
+        }
+        index
+    }
+
+    /// The image of an album, if any
+    pub fn album_image(&self, album_artist: &str, album: &str) -> Option<&CachedImage> {
+        self.albums.iter()
+            .find(|a| a.album_artist == album_artist && a.album == album)
+            .and_then(|a| self.images.get(&a.image))
+    }
+
+    /// The image of a track, if any
+    pub fn track_image(&self, track: PersistentId) -> Option<&CachedImage> {
+        self.tracks.get(&format_persistent_id(track)).and_then(|hash| self.images.get(hash))
+    }
+}
+
+/// The result of [`ArtworkCache::export_library`]
+#[derive(Debug)]
+pub struct ExportReport {
+    /// The index, as written to the cache
+    pub index: ArtworkIndex,
+    pub tracks_without_artwork: usize,
+    /// The tracks whose artwork could not be read or stored (with a persistent ID of 0 if even it could not be read)
+    pub failures: Vec<(PersistentId, std::io::Error)>,
+    /// The thumbnails that could not be made, by track and size. The image itself has been stored, and indexed
+    pub thumbnail_failures: Vec<(PersistentId, u32, std::io::Error)>,
+}
+
+/// A directory of images, stored by content
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ArtworkCache {
+    root: PathBuf,
+    thumbnail_sizes: Vec<u32>,
+}
+
+impl ArtworkCache {
+    /// A cache in this directory (which is created when the first image is stored)
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into(), thumbnail_sizes: Vec::new() }
+    }
+
+    /// Also write thumbnails that fit in squares of these sizes (in pixels), for every stored image
+    #[cfg(feature = "thumbnails")]
+    pub fn with_thumbnails(mut self, sizes: &[u32]) -> Self {
+        self.thumbnail_sizes = sizes.to_vec();
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The path of an image, relative to the root of the cache
+    pub fn image_file(hash: &str, format: ImageFormat) -> String {
+        format!("images/{}.{}", hash, format.extension())
+    }
+
+    /// The path of a thumbnail, relative to the root of the cache
+    pub fn thumbnail_file(hash: &str, size: u32) -> String {
+        format!("thumbnails/{}/{}.jpg", size, hash)
+    }
+
+    /// Write a file of the cache, unless it already exists (its content can only be the same)
+    fn write_once(&self, file: &str, bytes: impl FnOnce() -> std::io::Result<Vec<u8>>) -> std::io::Result<()> {
+        let path = self.root.join(file);
+        if path.is_file() {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Readers never see partial files
+        let partial = path.with_extension("part");
+        std::fs::write(&partial, bytes()?)?;
+        std::fs::rename(&partial, &path)
+    }
+
+    /// Store an image (and its thumbnails), if the cache does not have it yet.
+    ///
+    /// Thumbnails that cannot be made (e.g. because the image cannot be decoded) are left out of the result, and returned with their size.
+    /// The image itself has been stored in this case.
+    pub fn store(&self, image: &ArtworkImage) -> std::io::Result<(CachedImage, Vec<(u32, std::io::Error)>)> {
+        let hash = content_hash(&image.bytes);
+        let file = Self::image_file(&hash, image.format);
+        self.write_once(&file, || Ok(image.bytes.clone()))?;
+
+        let mut thumbnails = BTreeMap::new();
+        let mut thumbnail_failures = Vec::new();
+        for size in &self.thumbnail_sizes {
+            let thumbnail_file = Self::thumbnail_file(&hash, *size);
+            match self.write_once(&thumbnail_file, || make_thumbnail(image, *size)) {
+                Ok(()) => { thumbnails.insert(*size, thumbnail_file); },
+                Err(err) => thumbnail_failures.push((*size, err)),
+            }
+        }
+
+        Ok((CachedImage { hash, format: image.format, file, size: image.bytes.len() as u64, thumbnails }, thumbnail_failures))
+    }
+
+    /// The path of the index file
+    pub fn index_path(&self) -> PathBuf {
+        self.root.join(INDEX_FILE_NAME)
+    }
+
+    pub fn save_index(&self, index: &ArtworkIndex) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.root)?;
+        // Readers never see a partial index, and the previous one is kept if writing fails
+        let path = self.index_path();
+        let partial = path.with_extension("part");
+        std::fs::write(&partial, serde_json::to_vec_pretty(index)?)?;
+        std::fs::rename(&partial, &path)
+    }
+
+    pub fn load_index(&self) -> std::io::Result<ArtworkIndex> {
+        let file = std::fs::File::open(self.index_path())?;
+        Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+    }
+
+    /// Store the primary artwork of every track of the library, and write the index of the cache.
+    ///
+    /// `progress` is called after every track, with the number of processed tracks and the total.
+    /// This does not stop at the first failure, but reports the tracks whose artwork could not be stored.
+    pub fn export_library<F: FnMut(usize, usize)>(&self, iTunes: &iTunes, mut progress: F) -> std::io::Result<ExportReport> {
+        let tracks = iTunes.LibraryPlaylist()?.Tracks()?;
+        let total = tracks.Count()? as usize;
+        let mut artworks = Vec::new();
+        let mut tracks_without_artwork = 0;
+        let mut failures = Vec::new();
+        let mut thumbnail_failures = Vec::new();
+
+        for (done, track) in tracks.iter()?.enumerate() {
+            match self.export_track(&track, &mut thumbnail_failures) {
+                Ok(Some(artwork)) => artworks.push(artwork),
+                Ok(None) => tracks_without_artwork += 1,
+                Err(err) => failures.push((track.persistent_id().unwrap_or_default(), err)),
+            }
+            progress(done + 1, total);
+        }
+
+        let index = ArtworkIndex::build(&artworks);
+        self.save_index(&index)?;
+        Ok(ExportReport { index, tracks_without_artwork, failures, thumbnail_failures })
+    }
+
+    /// Store the primary artwork of a track, if it has some. Thumbnails that cannot be made are added to `thumbnail_failures`
+    fn export_track(&self, track: &Track, thumbnail_failures: &mut Vec<(PersistentId, u32, std::io::Error)>) -> std::io::Result<Option<TrackArtwork>> {
+        let snapshot = TrackSnapshot::from_track(track)?;
+        let image = match track.primary_artwork()? {
+            None => return Ok(None),
+            Some(artwork) => artwork.to_image()?,
+        };
+        let (image, failures) = self.store(&image)?;
+        thumbnail_failures.extend(failures.into_iter().map(|(size, err)| (snapshot.persistent_id, size, err)));
+        Ok(Some(TrackArtwork::new(&snapshot, image)))
+    }
+}
+
+/// A JPEG version of an image, that fits in a square of `size` pixels
+#[cfg(feature = "thumbnails")]
+fn make_thumbnail(image: &ArtworkImage, size: u32) -> std::io::Result<Vec<u8>> {
+    let format = match image.format {
+        ImageFormat::Jpeg => image::ImageFormat::Jpeg,
+        ImageFormat::Png => image::ImageFormat::Png,
+        ImageFormat::Bmp => image::ImageFormat::Bmp,
+    };
+    let decoded = image::load_from_memory_with_format(&image.bytes, format).map_err(std::io::Error::other)?;
+    // JPEG has no alpha channel
+    let thumbnail = image::DynamicImage::ImageRgb8(decoded.thumbnail(size, size).to_rgb8());
+    let mut bytes = std::io::Cursor::new(Vec::new());
+    thumbnail.write_to(&mut bytes, image::ImageFormat::Jpeg).map_err(std::io::Error::other)?;
+    Ok(bytes.into_inner())
+}
+
+/// Thumbnails need the `thumbnails` Cargo feature
+#[cfg(not(feature = "thumbnails"))]
+fn make_thumbnail(_image: &ArtworkImage, _size: u32) -> std::io::Result<Vec<u8>> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "Thumbnails need the `thumbnails` feature"))
+}
+
@@ -0,0 +1,175 @@
+//! Cleanup of user playlists
+//!
+//! Playlists accumulate repeated entries, and entries for tracks that have been disabled or whose file is missing.
+//! [`plan_cleanup`] (or [`UserPlaylist::plan_cleanup`] for a live playlist) lists the entries to remove, and [`CleanupPlan::apply`] removes them.
+//! Not applying the plan is a dry run.
+
+use std::collections::{HashMap, HashSet};
+
+use super::{UserPlaylist, IITObjectWrapper, IITPlaylistWrapper, IITTrackWrapper};
+use super::library::{PlaylistSnapshot, TrackSnapshot, format_persistent_id};
+use super::relocate::is_dead;
+use super::types::PersistentId;
+
+/// Which occurrence of a repeated track to keep
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KeepOccurrence {
+    First,
+    Last,
+}
+
+/// What to remove from a playlist
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CleanupOptions {
+    /// Remove repeated entries (`None` to keep them)
+    pub duplicates: Option<KeepOccurrence>,
+    /// Remove tracks that are disabled (i.e. unchecked)
+    pub disabled: bool,
+    /// Remove tracks whose file is missing
+    pub dead: bool,
+}
+
+impl Default for CleanupOptions {
+    fn default() -> Self {
+        Self { duplicates: Some(KeepOccurrence::First), disabled: false, dead: false }
+    }
+}
+
+/// Why an entry would be removed
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RemovalReason {
+    Duplicate,
+    Disabled,
+    Dead,
+}
+
+impl std::fmt::Display for RemovalReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RemovalReason::Duplicate => f.write_str("duplicate"),
+            RemovalReason::Disabled => f.write_str("disabled"),
+            RemovalReason::Dead => f.write_str("missing file"),
+        }
+    }
+}
+
+/// An entry of a playlist to remove
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Removal {
+    /// The (zero-based) index of the entry in the playlist
+    pub index: usize,
+    pub track: PersistentId,
+    pub reason: RemovalReason,
+}
+
+/// The entries to remove from a playlist
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CleanupPlan {
+    pub playlist: PersistentId,
+    /// Sorted by index
+    pub removals: Vec<Removal>,
+}
+
+/// List the entries to remove from a playlist.
+///
+/// `lookup` gives the info of tracks, which is needed to find disabled and dead tracks (entries it does not know about are only checked for duplicates).
+/// Disabled and dead tracks are removed entirely, so they are not reported as duplicates as well.
+pub fn plan_cleanup<'a, F>(playlist: &PlaylistSnapshot, lookup: F, options: &CleanupOptions) -> CleanupPlan
+where F: Fn(PersistentId) -> Option<&'a TrackSnapshot>
+{
+    let mut removals = Vec::new();
+    let mut kept = Vec::new();
+    for (index, track) in playlist.tracks.iter().enumerate() {
+        let info = lookup(*track);
+        let reason = match info {
+            Some(t) if options.disabled && !t.enabled => Some(RemovalReason::Disabled),
+            Some(t) if options.dead && is_dead(t) => Some(RemovalReason::Dead),
+            _ => None,
+        };
+        match reason {
+            Some(reason) => removals.push(Removal { index, track: *track, reason }),
+            None => kept.push((index, *track)),
+        }
+    }
+
+    if let Some(keep) = options.duplicates {
+        let mut seen = HashSet::new();
+        let mut check = |&(index, track): &(usize, PersistentId)| {
+            if !seen.insert(track) {
+                removals.push(Removal { index, track, reason: RemovalReason::Duplicate });
+            }
+        };
+        match keep {
+            KeepOccurrence::First => kept.iter().for_each(&mut check),
+            KeepOccurrence::Last => kept.iter().rev().for_each(&mut check),
+        }
+    }
+
+    removals.sort_by_key(|r| r.index);
+    CleanupPlan { playlist: playlist.persistent_id, removals }
+}
+
+impl UserPlaylist {
+    /// List the entries to remove from this playlist (see [`plan_cleanup`])
+    pub fn plan_cleanup(&self, options: &CleanupOptions) -> windows::core::Result<CleanupPlan> {
+        let mut snapshot = PlaylistSnapshot { persistent_id: self.persistent_id()?, ..PlaylistSnapshot::default() };
+        let mut infos = HashMap::new();
+        for track in self.Tracks()?.iter()? {
+            let id = track.persistent_id()?;
+            snapshot.tracks.push(id);
+
+            // Only the fields `plan_cleanup` needs are read
+            if options.disabled || options.dead {
+                let location = match track.as_file_or_cd_track() {
+                    Some(file_track) if options.dead => Some(file_track.Location()?),
+                    _ => None,
+                };
+                infos.insert(id, TrackSnapshot { persistent_id: id, enabled: track.is_Enabled()?, location, ..TrackSnapshot::default() });
+            }
+        }
+
+        Ok(plan_cleanup(&snapshot, |id| infos.get(&id), options))
+    }
+}
+
+impl CleanupPlan {
+    pub fn is_empty(&self) -> bool {
+        self.removals.is_empty()
+    }
+
+    /// Remove the planned entries from a live playlist.
+    ///
+    /// Entries that are not the expected track anymore (e.g. because the playlist has changed since the plan has been computed) are not removed, but reported as failures.
+    /// So are entries whose track cannot be read: the other removals go on.
+    pub fn apply(&self, playlist: &UserPlaylist) -> windows::core::Result<Vec<(Removal, windows::core::Error)>> {
+        // Track objects are collected before removing anything, as removals shift the indices of the next tracks
+        let tracks: Vec<_> = playlist.Tracks()?.iter()?.collect();
+        let mut failures = Vec::new();
+        let changed = || windows::core::Error::new(windows::Win32::Foundation::E_UNEXPECTED, windows::h!("The playlist has changed").clone());
+
+        for removal in &self.removals {
+            let result = match tracks.get(removal.index) {
+                Some(track) => track.persistent_id().and_then(|id| match id == removal.track {
+                    true => track.Delete(),
+                    false => Err(changed()),
+                }),
+                None => Err(changed()),
+            };
+            if let Err(err) = result {
+                failures.push((*removal, err));
+            }
+        }
+
+        Ok(failures)
+    }
+}
+
+impl std::fmt::Display for CleanupPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} entry(ies) to remove from playlist {}", self.removals.len(), format_persistent_id(self.playlist))?;
+        for removal in &self.removals {
+            writeln!(f, "  #{} [{}] {}", removal.index + 1, format_persistent_id(removal.track), removal.reason)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,325 @@
+//! Differences between two snapshots of a library
+//!
+//! [`diff`] compares two [`LibrarySnapshot`]s (e.g. one taken yesterday and one taken today), keyed by persistent IDs.
+//! The resulting [`LibraryDiff`] can be inspected, displayed as human-readable text (with `Display`), or serialized as JSON (with the `json` Cargo feature).
+
+use std::collections::{HashMap, HashSet};
+
+use super::library::{LibrarySnapshot, TrackSnapshot, PlaylistSnapshot, TrackField, FieldChange, format_persistent_id};
+use super::types::PersistentId;
+
+/// A short description of a track
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TrackRef {
+    pub persistent_id: PersistentId,
+    pub artist: String,
+    pub name: String,
+}
+
+impl TrackRef {
+    pub fn of(track: &TrackSnapshot) -> Self {
+        Self {
+            persistent_id: track.persistent_id,
+            artist: track.artist.clone(),
+            name: track.name.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for TrackRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} - {} [{}]", self.artist, self.name, format_persistent_id(self.persistent_id))
+    }
+}
+
+/// A track whose file has moved
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TrackMove {
+    pub track: TrackRef,
+    pub old_location: Option<String>,
+    pub new_location: Option<String>,
+}
+
+/// A track whose fields have changed (apart from its location, see [`TrackMove`])
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TrackDiff {
+    pub track: TrackRef,
+    pub changes: Vec<FieldChange>,
+}
+
+/// A short description of a playlist
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PlaylistRef {
+    pub persistent_id: PersistentId,
+    pub name: String,
+}
+
+impl PlaylistRef {
+    pub fn of(playlist: &PlaylistSnapshot) -> Self {
+        Self {
+            persistent_id: playlist.persistent_id,
+            name: playlist.name.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for PlaylistRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} [{}]", self.name, format_persistent_id(self.persistent_id))
+    }
+}
+
+/// A playlist that has been renamed
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PlaylistRename {
+    pub persistent_id: PersistentId,
+    pub old_name: String,
+    pub new_name: String,
+}
+
+/// A playlist that has been moved to another folder
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PlaylistMove {
+    pub playlist: PlaylistRef,
+    pub old_parent: Option<PlaylistRef>,
+    pub new_parent: Option<PlaylistRef>,
+}
+
+/// Tracks that have been added to or removed from a playlist
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MembershipChange {
+    pub playlist: PlaylistRef,
+    pub added: Vec<TrackRef>,
+    pub removed: Vec<TrackRef>,
+    /// Whether the tracks that are in both versions of the playlist have been reordered
+    pub reordered: bool,
+}
+
+/// Every difference between two snapshots
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LibraryDiff {
+    pub added_tracks: Vec<TrackRef>,
+    pub removed_tracks: Vec<TrackRef>,
+    pub moved_tracks: Vec<TrackMove>,
+    pub changed_tracks: Vec<TrackDiff>,
+    pub added_playlists: Vec<PlaylistRef>,
+    pub removed_playlists: Vec<PlaylistRef>,
+    pub renamed_playlists: Vec<PlaylistRename>,
+    pub moved_playlists: Vec<PlaylistMove>,
+    pub membership_changes: Vec<MembershipChange>,
+}
+
+/// Compare two snapshots
+pub fn diff(old: &LibrarySnapshot, new: &LibrarySnapshot) -> LibraryDiff {
+    diff_ignoring(old, new, &[])
+}
+
+/// Compare two snapshots, without reporting changes of some fields (e.g. `PlayedCount` and `PlayedDate`, which change all the time)
+pub fn diff_ignoring(old: &LibrarySnapshot, new: &LibrarySnapshot, ignored_fields: &[TrackField]) -> LibraryDiff {
+    let mut diff = LibraryDiff::default();
+    diff_tracks(&mut diff, old, new, ignored_fields);
+    diff_playlists(&mut diff, old, new);
+    diff
+}
+
+fn diff_tracks(diff: &mut LibraryDiff, old: &LibrarySnapshot, new: &LibrarySnapshot, ignored_fields: &[TrackField]) {
+    for (id, old_track) in &old.tracks {
+        let new_track = match new.tracks.get(id) {
+            None => {
+                diff.removed_tracks.push(TrackRef::of(old_track));
+                continue;
+            },
+            Some(t) => t,
+        };
+
+        if old_track.location != new_track.location && !ignored_fields.contains(&TrackField::Location) {
+            diff.moved_tracks.push(TrackMove {
+                track: TrackRef::of(new_track),
+                old_location: old_track.location.clone(),
+                new_location: new_track.location.clone(),
+            });
+        }
+
+        let changes: Vec<FieldChange> = TrackField::ALL.iter()
+            .filter(|field| **field != TrackField::Location && !ignored_fields.contains(field))
+            .filter_map(|field| {
+                let old_value = old_track.get(*field);
+                let new_value = new_track.get(*field);
+                match old_value == new_value {
+                    true => None,
+                    false => Some(FieldChange { field: *field, old: old_value, new: new_value }),
+                }
+            })
+            .collect();
+        if !changes.is_empty() {
+            diff.changed_tracks.push(TrackDiff { track: TrackRef::of(new_track), changes });
+        }
+    }
+
+    for (id, new_track) in &new.tracks {
+        if !old.tracks.contains_key(id) {
+            diff.added_tracks.push(TrackRef::of(new_track));
+        }
+    }
+}
+
+fn diff_playlists(diff: &mut LibraryDiff, old: &LibrarySnapshot, new: &LibrarySnapshot) {
+    let parent_ref = |snapshot: &LibrarySnapshot, parent: Option<PersistentId>| {
+        parent.map(|id| match snapshot.playlist(id) {
+            Some(p) => PlaylistRef::of(p),
+            None => PlaylistRef { persistent_id: id, name: String::new() },
+        })
+    };
+    let track_ref = |id: &PersistentId| {
+        match new.track(*id).or_else(|| old.track(*id)) {
+            Some(t) => TrackRef::of(t),
+            None => TrackRef { persistent_id: *id, artist: String::new(), name: String::new() },
+        }
+    };
+
+    let new_playlists: HashMap<PersistentId, &PlaylistSnapshot> = new.playlists.iter().map(|p| (p.persistent_id, p)).collect();
+    let old_ids: HashSet<PersistentId> = old.playlists.iter().map(|p| p.persistent_id).collect();
+
+    for old_playlist in &old.playlists {
+        let new_playlist = match new_playlists.get(&old_playlist.persistent_id) {
+            None => {
+                diff.removed_playlists.push(PlaylistRef::of(old_playlist));
+                continue;
+            },
+            Some(p) => p,
+        };
+
+        if old_playlist.name != new_playlist.name {
+            diff.renamed_playlists.push(PlaylistRename {
+                persistent_id: new_playlist.persistent_id,
+                old_name: old_playlist.name.clone(),
+                new_name: new_playlist.name.clone(),
+            });
+        }
+
+        if old_playlist.parent != new_playlist.parent {
+            diff.moved_playlists.push(PlaylistMove {
+                playlist: PlaylistRef::of(new_playlist),
+                old_parent: parent_ref(old, old_playlist.parent),
+                new_parent: parent_ref(new, new_playlist.parent),
+            });
+        }
+
+        let old_tracks: HashSet<PersistentId> = old_playlist.tracks.iter().copied().collect();
+        let new_tracks: HashSet<PersistentId> = new_playlist.tracks.iter().copied().collect();
+        let added: Vec<TrackRef> = unique(&new_playlist.tracks).into_iter().filter(|id| !old_tracks.contains(id)).map(|id| track_ref(&id)).collect();
+        let removed: Vec<TrackRef> = unique(&old_playlist.tracks).into_iter().filter(|id| !new_tracks.contains(id)).map(|id| track_ref(&id)).collect();
+
+        let common_old: Vec<PersistentId> = old_playlist.tracks.iter().copied().filter(|id| new_tracks.contains(id)).collect();
+        let common_new: Vec<PersistentId> = new_playlist.tracks.iter().copied().filter(|id| old_tracks.contains(id)).collect();
+        let reordered = common_old != common_new;
+
+        if !added.is_empty() || !removed.is_empty() || reordered {
+            diff.membership_changes.push(MembershipChange {
+                playlist: PlaylistRef::of(new_playlist),
+                added,
+                removed,
+                reordered,
+            });
+        }
+    }
+
+    for new_playlist in &new.playlists {
+        if !old_ids.contains(&new_playlist.persistent_id) {
+            diff.added_playlists.push(PlaylistRef::of(new_playlist));
+        }
+    }
+}
+
+/// The items of a list, without duplicates, in their order of first appearance
+fn unique(ids: &[PersistentId]) -> Vec<PersistentId> {
+    let mut seen = HashSet::new();
+    ids.iter().copied().filter(|id| seen.insert(*id)).collect()
+}
+
+impl LibraryDiff {
+    /// Whether both snapshots are identical
+    pub fn is_empty(&self) -> bool {
+        self.added_tracks.is_empty()
+            && self.removed_tracks.is_empty()
+            && self.moved_tracks.is_empty()
+            && self.changed_tracks.is_empty()
+            && self.added_playlists.is_empty()
+            && self.removed_playlists.is_empty()
+            && self.renamed_playlists.is_empty()
+            && self.moved_playlists.is_empty()
+            && self.membership_changes.is_empty()
+    }
+
+    /// Serialize this diff as pretty-printed JSON
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+impl std::fmt::Display for LibraryDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn folder(parent: &Option<PlaylistRef>) -> String {
+            match parent {
+                None => String::from("<top level>"),
+                Some(p) => p.to_string(),
+            }
+        }
+
+        if self.is_empty() {
+            return writeln!(f, "No changes");
+        }
+
+        for track in &self.added_tracks {
+            writeln!(f, "+ track {}", track)?;
+        }
+        for track in &self.removed_tracks {
+            writeln!(f, "- track {}", track)?;
+        }
+        for moved in &self.moved_tracks {
+            writeln!(f, "> track {}: {} -> {}", moved.track,
+                moved.old_location.as_deref().unwrap_or("<none>"),
+                moved.new_location.as_deref().unwrap_or("<none>"))?;
+        }
+        for changed in &self.changed_tracks {
+            writeln!(f, "~ track {}", changed.track)?;
+            for change in &changed.changes {
+                writeln!(f, "    {}: {} -> {}", change.field, change.old, change.new)?;
+            }
+        }
+        for playlist in &self.added_playlists {
+            writeln!(f, "+ playlist {}", playlist)?;
+        }
+        for playlist in &self.removed_playlists {
+            writeln!(f, "- playlist {}", playlist)?;
+        }
+        for renamed in &self.renamed_playlists {
+            writeln!(f, "~ playlist [{}] renamed: {:?} -> {:?}", format_persistent_id(renamed.persistent_id), renamed.old_name, renamed.new_name)?;
+        }
+        for moved in &self.moved_playlists {
+            writeln!(f, "> playlist {}: {} -> {}", moved.playlist, folder(&moved.old_parent), folder(&moved.new_parent))?;
+        }
+        for change in &self.membership_changes {
+            writeln!(f, "~ playlist {}", change.playlist)?;
+            for track in &change.added {
+                writeln!(f, "    + {}", track)?;
+            }
+            for track in &change.removed {
+                writeln!(f, "    - {}", track)?;
+            }
+            if change.reordered {
+                writeln!(f, "    (reordered)")?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,341 @@
+//! Detection and merging of duplicate tracks
+//!
+//! [`find_duplicates`] groups tracks according to configurable [`DuplicateCriteria`].<br/>
+//! [`plan_merge`] then chooses which copy of each group to keep (see [`KeeperCriterion`]), and computes a [`MergePlan`] that
+//! moves the play counts, ratings and playlist memberships of the other copies onto the keeper, before deleting them.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+
+use super::{iTunes, IITObjectWrapper, IITPlaylistWrapper, IITTrackWrapper};
+use super::files::windows_path_key;
+use super::library::{LibrarySnapshot, TrackSnapshot, TrackField, FieldValue, FieldChange, set_track_field, normalize_text};
+use super::types::PersistentId;
+use super::LONG;
+
+/// What makes two tracks duplicates of each other
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DuplicateCriteria {
+    /// Compare the normalized artists and names
+    pub artist_and_name: bool,
+    /// Compare the normalized albums
+    pub album: bool,
+    /// Maximum difference between durations (in seconds). `None` to ignore durations
+    pub duration_tolerance: Option<LONG>,
+    /// Compare the file sizes
+    pub size: bool,
+    /// Compare the bit rates
+    pub bit_rate: bool,
+    /// Compare the file locations (the way Windows does, see [`windows_path_key`])
+    pub location: bool,
+}
+
+impl Default for DuplicateCriteria {
+    fn default() -> Self {
+        Self {
+            artist_and_name: true,
+            album: false,
+            duration_tolerance: Some(2),
+            size: false,
+            bit_rate: false,
+            location: false,
+        }
+    }
+}
+
+/// The part of the criteria that must be strictly equal
+#[derive(Debug, Eq, PartialEq, Hash)]
+struct ExactKey {
+    artist: Option<String>,
+    name: Option<String>,
+    album: Option<String>,
+    size: Option<i64>,
+    bit_rate: Option<LONG>,
+    location: Option<String>,
+}
+
+impl ExactKey {
+    fn new(track: &TrackSnapshot, criteria: &DuplicateCriteria) -> Self {
+        Self {
+            artist: criteria.artist_and_name.then(|| normalize_text(&track.artist)),
+            name: criteria.artist_and_name.then(|| normalize_text(&track.name)),
+            album: criteria.album.then(|| normalize_text(&track.album)),
+            size: criteria.size.then_some(track.size),
+            bit_rate: criteria.bit_rate.then_some(track.bit_rate),
+            location: criteria.location.then(|| windows_path_key(track.location.as_deref().unwrap_or_default())),
+        }
+    }
+}
+
+/// Group duplicate tracks.
+///
+/// Only groups of at least two tracks are returned. Tracks that have no file are not considered duplicates when comparing locations.
+/// When a duration tolerance is set, tracks whose durations are "chained" within the tolerance belong to the same group.
+pub fn find_duplicates<'a, I>(tracks: I, criteria: &DuplicateCriteria) -> Vec<Vec<PersistentId>>
+where I: IntoIterator<Item = &'a TrackSnapshot>
+{
+    let mut buckets: HashMap<ExactKey, Vec<&TrackSnapshot>> = HashMap::new();
+    for track in tracks {
+        if criteria.location && track.location.as_deref().unwrap_or_default().is_empty() {
+            continue;
+        }
+        buckets.entry(ExactKey::new(track, criteria)).or_default().push(track);
+    }
+
+    let mut groups = Vec::new();
+    for mut bucket in buckets.into_values() {
+        match criteria.duration_tolerance {
+            None => groups.push(bucket),
+            Some(tolerance) => {
+                bucket.sort_by_key(|t| t.duration);
+                let mut current: Vec<&TrackSnapshot> = Vec::new();
+                for track in bucket {
+                    if let Some(last) = current.last() {
+                        if track.duration - last.duration > tolerance {
+                            groups.push(std::mem::take(&mut current));
+                        }
+                    }
+                    current.push(track);
+                }
+                groups.push(current);
+            },
+        }
+    }
+
+    let mut groups: Vec<Vec<PersistentId>> = groups.into_iter()
+        .filter(|g| g.len() > 1)
+        .map(|g| {
+            let mut ids: Vec<PersistentId> = g.iter().map(|t| t.persistent_id).collect();
+            ids.sort_unstable();
+            ids
+        })
+        .collect();
+    groups.sort();
+    groups
+}
+
+/// What makes a copy better than another one, when choosing which one to keep
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KeeperCriterion {
+    /// Prefer tracks whose file exists (i.e. whose location is not empty)
+    ExistingFile,
+    /// Prefer higher bit rates
+    BitRate,
+    /// Prefer tracks that have been played more
+    PlayedCount,
+    /// Prefer tracks that have artwork
+    Artwork,
+    /// Prefer tracks with a higher rating
+    Rating,
+    /// Prefer tracks that have been added to the library first
+    OldestAdded,
+}
+
+/// The default preferences: existing file, then bit rate, then play count, then artwork
+pub const DEFAULT_KEEPER_CRITERIA: [KeeperCriterion; 4] = [
+    KeeperCriterion::ExistingFile,
+    KeeperCriterion::BitRate,
+    KeeperCriterion::PlayedCount,
+    KeeperCriterion::Artwork,
+];
+
+impl KeeperCriterion {
+    /// `Ordering::Greater` means `a` is a better keeper than `b`
+    fn compare(&self, a: &TrackSnapshot, b: &TrackSnapshot) -> Ordering {
+        let has_file = |t: &TrackSnapshot| !t.location.as_deref().unwrap_or_default().is_empty();
+        match self {
+            KeeperCriterion::ExistingFile => has_file(a).cmp(&has_file(b)),
+            KeeperCriterion::BitRate => a.bit_rate.cmp(&b.bit_rate),
+            KeeperCriterion::PlayedCount => a.played_count.cmp(&b.played_count),
+            KeeperCriterion::Artwork => (a.artwork_count > 0).cmp(&(b.artwork_count > 0)),
+            KeeperCriterion::Rating => a.rating.cmp(&b.rating),
+            KeeperCriterion::OldestAdded => b.date_added.cmp(&a.date_added),
+        }
+    }
+}
+
+/// Choose the best copy among duplicates.
+///
+/// Criteria are considered in order, the next one being only used to break ties. Remaining ties keep the first track.
+pub fn choose_keeper<'a>(tracks: &[&'a TrackSnapshot], criteria: &[KeeperCriterion]) -> Option<&'a TrackSnapshot> {
+    let mut best: Option<&TrackSnapshot> = None;
+    for track in tracks {
+        best = match best {
+            None => Some(track),
+            Some(current) => {
+                let ordering = criteria.iter()
+                    .map(|c| c.compare(track, current))
+                    .find(|o| *o != Ordering::Equal)
+                    .unwrap_or(Ordering::Equal);
+                match ordering {
+                    Ordering::Greater => Some(track),
+                    _ => Some(current),
+                }
+            },
+        };
+    }
+    best
+}
+
+/// What to do with a group of duplicates
+#[derive(Clone, Debug, PartialEq)]
+pub struct GroupMerge {
+    pub keeper: PersistentId,
+    /// The copies that will be deleted
+    pub duplicates: Vec<PersistentId>,
+    /// The fields of the keeper that will change
+    pub keeper_changes: Vec<FieldChange>,
+    /// The playlists the keeper will be added to (because they contain a duplicate, but not the keeper)
+    pub playlist_additions: Vec<PersistentId>,
+}
+
+/// Every change a merge of duplicates would make
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MergePlan {
+    pub groups: Vec<GroupMerge>,
+}
+
+/// Compute how to merge groups of duplicates (as returned by [`find_duplicates`]).
+///
+/// The keeper gets the sum of the play and skip counts, the latest played and skipped dates, and the highest ratings of its group.
+/// Smart playlists and folders are not considered, since tracks cannot be added to them.
+pub fn plan_merge(library: &LibrarySnapshot, groups: &[Vec<PersistentId>], criteria: &[KeeperCriterion]) -> MergePlan {
+    let mut plan = MergePlan::default();
+
+    for group in groups {
+        let tracks: Vec<&TrackSnapshot> = group.iter().filter_map(|id| library.track(*id)).collect();
+        let keeper = match choose_keeper(&tracks, criteria) {
+            Some(k) if tracks.len() > 1 => k,
+            _ => continue,
+        };
+        let duplicates: Vec<PersistentId> = tracks.iter().map(|t| t.persistent_id).filter(|id| *id != keeper.persistent_id).collect();
+
+        let merged = merged_values(&tracks);
+        let keeper_changes = merged.into_iter()
+            .filter_map(|(field, new)| {
+                let old = keeper.get(field);
+                (old != new).then_some(FieldChange { field, old, new })
+            })
+            .collect();
+
+        let playlist_additions = library.playlists.iter()
+            .filter(|p| !p.is_smart && !p.is_folder)
+            .filter(|p| !p.tracks.contains(&keeper.persistent_id) && p.tracks.iter().any(|t| duplicates.contains(t)))
+            .map(|p| p.persistent_id)
+            .collect();
+
+        plan.groups.push(GroupMerge {
+            keeper: keeper.persistent_id,
+            duplicates,
+            keeper_changes,
+            playlist_additions,
+        });
+    }
+
+    plan
+}
+
+/// The values the keeper of a group should end up with
+fn merged_values(tracks: &[&TrackSnapshot]) -> Vec<(TrackField, FieldValue)> {
+    let sum = |f: fn(&TrackSnapshot) -> LONG| tracks.iter().map(|t| i64::from(f(t))).sum::<i64>();
+    let max = |f: fn(&TrackSnapshot) -> LONG| tracks.iter().map(|t| i64::from(f(t))).max().unwrap_or(0);
+
+    vec![
+        (TrackField::PlayedCount, FieldValue::Integer(sum(|t| t.played_count))),
+        (TrackField::SkippedCount, FieldValue::Integer(sum(|t| t.skipped_count))),
+        (TrackField::Rating, FieldValue::Integer(max(|t| t.rating))),
+        (TrackField::AlbumRating, FieldValue::Integer(max(|t| t.album_rating))),
+        (TrackField::PlayedDate, FieldValue::Date(tracks.iter().filter_map(|t| t.played_date).max())),
+        (TrackField::SkippedDate, FieldValue::Date(tracks.iter().filter_map(|t| t.skipped_date).max())),
+    ]
+}
+
+/// A step of a merge that failed
+#[derive(Debug)]
+pub enum MergeFailure {
+    /// A field of the keeper could not be changed
+    SetField { keeper: PersistentId, field: TrackField, error: windows::core::Error },
+    /// The keeper could not be added to a playlist
+    AddToPlaylist { keeper: PersistentId, playlist: PersistentId, error: windows::core::Error },
+    /// A duplicate could not be deleted
+    Delete { track: PersistentId, error: windows::core::Error },
+    /// The keeper could not be found. Nothing has been done for its group
+    MissingKeeper { keeper: PersistentId, error: windows::core::Error },
+}
+
+impl MergePlan {
+    /// The count of tracks that would be deleted
+    pub fn deletion_count(&self) -> usize {
+        self.groups.iter().map(|g| g.duplicates.len()).sum()
+    }
+
+    /// Apply this plan to a snapshot
+    pub fn apply_to_snapshot(&self, library: &mut LibrarySnapshot) {
+        for group in &self.groups {
+            if let Some(keeper) = library.track_mut(group.keeper) {
+                for change in &group.keeper_changes {
+                    keeper.set(change.field, &change.new);
+                }
+            }
+            for playlist_id in &group.playlist_additions {
+                if let Some(playlist) = library.playlist_mut(*playlist_id) {
+                    playlist.tracks.push(group.keeper);
+                }
+            }
+            for duplicate in &group.duplicates {
+                library.tracks.remove(duplicate);
+                for playlist in library.playlists.iter_mut() {
+                    playlist.tracks.retain(|t| t != duplicate);
+                }
+            }
+        }
+    }
+
+    /// Apply this plan to the main library of a live iTunes instance.
+    ///
+    /// Duplicates are only deleted once their group has been merged into the keeper without any failure.
+    pub fn apply(&self, iTunes: &iTunes) -> windows::core::Result<Vec<MergeFailure>> {
+        let library_tracks = iTunes.LibraryPlaylist()?.Tracks()?;
+        let playlists = iTunes.LibrarySource()?.Playlists()?;
+        let mut failures = Vec::new();
+
+        for group in &self.groups {
+            let keeper = match library_tracks.ItemByPersistentID(group.keeper) {
+                Ok(k) => k,
+                Err(error) => {
+                    failures.push(MergeFailure::MissingKeeper { keeper: group.keeper, error });
+                    continue;
+                },
+            };
+            let failure_count = failures.len();
+
+            for change in &group.keeper_changes {
+                if let Err(error) = set_track_field(&keeper, change.field, &change.new) {
+                    failures.push(MergeFailure::SetField { keeper: group.keeper, field: change.field, error });
+                }
+            }
+
+            let affected_playlists: BTreeSet<PersistentId> = group.playlist_additions.iter().copied().collect();
+            for playlist_id in affected_playlists {
+                let added = playlists.ItemByPersistentID(playlist_id)
+                    .and_then(|p| p.as_user_playlist().ok_or_else(|| windows::core::Error::from(windows::Win32::Foundation::E_NOINTERFACE)))
+                    .and_then(|p| p.AddTrack(&keeper.as_variant()));
+                if let Err(error) = added {
+                    failures.push(MergeFailure::AddToPlaylist { keeper: group.keeper, playlist: playlist_id, error });
+                }
+            }
+
+            if failures.len() != failure_count {
+                continue;
+            }
+            for duplicate in &group.duplicates {
+                let deleted = library_tracks.ItemByPersistentID(*duplicate).and_then(|t| t.Delete());
+                if let Err(error) = deleted {
+                    failures.push(MergeFailure::Delete { track: *duplicate, error });
+                }
+            }
+        }
+
+        Ok(failures)
+    }
+}
@@ -0,0 +1,317 @@
+//! Batch edits of track metadata
+//!
+//! A [`TrackEdit`] is a set of field values to write on many tracks at once. It is validated before anything is written,
+//! [`TrackEdit::plan`] computes the actual changes of every track (a dry run, that skips values that would not change),
+//! and [`EditPlan::apply`] writes them, reporting failures per track instead of stopping at the first locked track.
+
+use std::collections::BTreeMap;
+
+use crate::sys::ITErrors;
+use super::{iTunes, IITPlaylistWrapper};
+use super::library::{LibrarySnapshot, TrackSnapshot, TrackField, FieldValue, FieldChange, set_track_field, format_persistent_id};
+use super::types::{PersistentId, Rating};
+use super::LONG;
+
+/// Whether an error is `ITUNES_E_OBJECTLOCKED`, e.g. for tracks that are being played, or that are in a read-only location
+pub fn is_object_locked(error: &windows::core::Error) -> bool {
+    error.code().0 as u32 == ITErrors::ITUNES_E_OBJECTLOCKED as u32
+}
+
+/// Why a value cannot be written
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ValidationError {
+    /// This field cannot be written (see [`TrackField::is_writable`])
+    ReadOnly(TrackField),
+    /// The value does not have the type of this field (e.g. a text for `Year`)
+    WrongType(TrackField),
+    OutOfRange { field: TrackField, value: i64 },
+    /// E.g. a track number that is greater than the track count
+    NumberExceedsCount { number: TrackField, count: TrackField },
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationError::ReadOnly(field) => write!(f, "{} is read-only", field),
+            ValidationError::WrongType(field) => write!(f, "wrong value type for {}", field),
+            ValidationError::OutOfRange { field, value } => write!(f, "{} is out of range for {}", value, field),
+            ValidationError::NumberExceedsCount { number, count } => write!(f, "{} is greater than {}", number, count),
+        }
+    }
+}
+
+/// The valid values of integer fields (`None` for fields that are not integers)
+fn valid_range(field: TrackField) -> Option<std::ops::RangeInclusive<i64>> {
+    match field {
+        TrackField::Rating | TrackField::AlbumRating => Some(0..=100),
+        // Zero means "no year"
+        TrackField::Year => Some(0..=9999),
+        TrackField::TrackNumber | TrackField::TrackCount | TrackField::DiscNumber | TrackField::DiscCount
+        | TrackField::BPM | TrackField::PlayedCount | TrackField::SkippedCount => Some(0..=i64::from(LONG::MAX)),
+        _ => None,
+    }
+}
+
+/// The value iTunes would store: ratings are rounded down to whole stars (multiples of 20)
+fn stored_value(field: TrackField, value: &FieldValue) -> FieldValue {
+    match (field, value) {
+        (TrackField::Rating | TrackField::AlbumRating, FieldValue::Integer(rating)) => {
+            let rating = LONG::try_from(*rating).unwrap_or(0);
+            FieldValue::Integer(LONG::from(Rating::from(rating)).into())
+        },
+        _ => value.clone(),
+    }
+}
+
+/// Fields are written in this order, so that counts are set before the numbers they bound
+fn write_order(field: TrackField) -> (u8, TrackField) {
+    match field {
+        TrackField::TrackCount | TrackField::DiscCount => (0, field),
+        _ => (1, field),
+    }
+}
+
+/// Values to write on many tracks
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrackEdit {
+    values: BTreeMap<TrackField, FieldValue>,
+}
+
+macro_rules! text_setter {
+    ($(#[$attr:meta])* $fn_name:ident, $field:ident) => {
+        $(#[$attr])*
+        pub fn $fn_name(self, value: &str) -> Self {
+            self.set(TrackField::$field, FieldValue::Text(value.to_string()))
+        }
+    };
+}
+
+macro_rules! integer_setter {
+    ($(#[$attr:meta])* $fn_name:ident, $field:ident) => {
+        $(#[$attr])*
+        pub fn $fn_name(self, value: LONG) -> Self {
+            self.set(TrackField::$field, FieldValue::Integer(value.into()))
+        }
+    };
+}
+
+impl TrackEdit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set any field. Setting the same field twice keeps the last value
+    pub fn set(mut self, field: TrackField, value: FieldValue) -> Self {
+        self.values.insert(field, value);
+        self
+    }
+
+    text_setter!(name, Name);
+    text_setter!(artist, Artist);
+    text_setter!(album_artist, AlbumArtist);
+    text_setter!(album, Album);
+    text_setter!(composer, Composer);
+    text_setter!(genre, Genre);
+    text_setter!(grouping, Grouping);
+    text_setter!(comment, Comment);
+    text_setter!(sort_name, SortName);
+    text_setter!(sort_artist, SortArtist);
+    text_setter!(sort_album_artist, SortAlbumArtist);
+    text_setter!(sort_album, SortAlbum);
+    text_setter!(sort_composer, SortComposer);
+    integer_setter!(
+        /// Zero removes the year
+        year, Year);
+    integer_setter!(track_number, TrackNumber);
+    integer_setter!(track_count, TrackCount);
+    integer_setter!(disc_number, DiscNumber);
+    integer_setter!(disc_count, DiscCount);
+    integer_setter!(bpm, BPM);
+    integer_setter!(
+        /// From 0 to 100 (20 per star, rounded down to whole stars)
+        rating, Rating);
+    integer_setter!(
+        /// From 0 to 100 (20 per star, rounded down to whole stars)
+        album_rating, AlbumRating);
+
+    pub fn enabled(self, value: bool) -> Self {
+        self.set(TrackField::Enabled, FieldValue::Bool(value))
+    }
+
+    pub fn compilation(self, value: bool) -> Self {
+        self.set(TrackField::Compilation, FieldValue::Bool(value))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// The values of this edit
+    pub fn values(&self) -> impl Iterator<Item = (TrackField, &FieldValue)> {
+        self.values.iter().map(|(field, value)| (*field, value))
+    }
+
+    /// Check the values themselves, regardless of the tracks they would be written to
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let default = TrackSnapshot::default();
+        let mut errors = Vec::new();
+
+        for (field, value) in self.values() {
+            if !field.is_writable() {
+                errors.push(ValidationError::ReadOnly(field));
+            } else if std::mem::discriminant(value) != std::mem::discriminant(&default.get(field)) {
+                errors.push(ValidationError::WrongType(field));
+            } else if let (Some(range), Some(i)) = (valid_range(field), value.as_integer()) {
+                if !range.contains(&i) {
+                    errors.push(ValidationError::OutOfRange { field, value: i });
+                }
+            }
+        }
+
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors),
+        }
+    }
+
+    /// The changes this edit would make to a track, once validated against its current values (e.g. a track number cannot exceed its track count).
+    ///
+    /// Values that would not change are skipped. Ratings are rounded down to whole stars, as iTunes does.
+    pub fn changes_for(&self, track: &TrackSnapshot) -> Result<Vec<FieldChange>, Vec<ValidationError>> {
+        self.validate()?;
+
+        let mut edited = track.clone();
+        for (field, value) in self.values() {
+            edited.set(field, &stored_value(field, value));
+        }
+        let mut errors = Vec::new();
+        for (number, count) in [(TrackField::TrackNumber, TrackField::TrackCount), (TrackField::DiscNumber, TrackField::DiscCount)] {
+            let touched = self.values.contains_key(&number) || self.values.contains_key(&count);
+            let (n, c) = (edited.get(number).as_integer().unwrap_or(0), edited.get(count).as_integer().unwrap_or(0));
+            // A zero count means "unknown"
+            if touched && c > 0 && n > c {
+                errors.push(ValidationError::NumberExceedsCount { number, count });
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let mut changes: Vec<FieldChange> = self.values()
+            .map(|(field, new)| FieldChange { field, old: track.get(field), new: stored_value(field, new) })
+            .filter(|change| change.old != change.new)
+            .collect();
+        changes.sort_by_key(|change| write_order(change.field));
+        Ok(changes)
+    }
+
+    /// Compute the changes this edit would make to every track (this is a dry run)
+    pub fn plan<'a, I>(&self, tracks: I) -> EditPlan
+    where I: IntoIterator<Item = &'a TrackSnapshot>
+    {
+        let mut plan = EditPlan::default();
+        for track in tracks {
+            match self.changes_for(track) {
+                Ok(changes) if changes.is_empty() => plan.unchanged.push(track.persistent_id),
+                Ok(changes) => plan.tracks.push(TrackEditChange { track: track.persistent_id, name: track.name.clone(), changes }),
+                Err(errors) => plan.invalid.push((track.persistent_id, errors)),
+            }
+        }
+        plan
+    }
+}
+
+/// The changes to write on a track
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrackEditChange {
+    pub track: PersistentId,
+    pub name: String,
+    pub changes: Vec<FieldChange>,
+}
+
+/// The changes an edit would make
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EditPlan {
+    pub tracks: Vec<TrackEditChange>,
+    /// Tracks that already have the values of the edit
+    pub unchanged: Vec<PersistentId>,
+    /// Tracks the edit cannot be applied to. Nothing is written to these
+    pub invalid: Vec<(PersistentId, Vec<ValidationError>)>,
+}
+
+/// What happened to a track when applying an [`EditPlan`]
+#[derive(Debug)]
+pub struct TrackEditResult {
+    pub track: PersistentId,
+    /// The fields that have been written
+    pub written: Vec<TrackField>,
+    /// The fields that could not be written (or `None` if the track could not be found)
+    pub failures: Vec<(Option<TrackField>, windows::core::Error)>,
+}
+
+impl TrackEditResult {
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// Whether some fields could not be written because the track is locked
+    pub fn is_locked(&self) -> bool {
+        self.failures.iter().any(|(_, err)| is_object_locked(err))
+    }
+}
+
+impl EditPlan {
+    /// Apply this plan to a snapshot
+    pub fn apply_to_snapshot(&self, library: &mut LibrarySnapshot) {
+        for track_change in &self.tracks {
+            if let Some(track) = library.track_mut(track_change.track) {
+                for change in &track_change.changes {
+                    track.set(change.field, &change.new);
+                }
+            }
+        }
+    }
+
+    /// Apply this plan to the main library of a live iTunes instance.
+    ///
+    /// This does not stop at the first failure (e.g. a locked track), but returns the result of every track.
+    pub fn apply(&self, iTunes: &iTunes) -> windows::core::Result<Vec<TrackEditResult>> {
+        let library_tracks = iTunes.LibraryPlaylist()?.Tracks()?;
+        let mut results = Vec::with_capacity(self.tracks.len());
+
+        for track_change in &self.tracks {
+            let mut result = TrackEditResult { track: track_change.track, written: Vec::new(), failures: Vec::new() };
+            match library_tracks.ItemByPersistentID(track_change.track) {
+                Err(err) => result.failures.push((None, err)),
+                Ok(track) => {
+                    for change in &track_change.changes {
+                        match set_track_field(&track, change.field, &change.new) {
+                            Ok(()) => result.written.push(change.field),
+                            Err(err) => result.failures.push((Some(change.field), err)),
+                        }
+                    }
+                },
+            }
+            results.push(result);
+        }
+
+        Ok(results)
+    }
+}
+
+impl std::fmt::Display for EditPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} track(s) to change, {} unchanged, {} invalid", self.tracks.len(), self.unchanged.len(), self.invalid.len())?;
+        for track_change in &self.tracks {
+            writeln!(f, "  [{}] {}", format_persistent_id(track_change.track), track_change.name)?;
+            for change in &track_change.changes {
+                writeln!(f, "      {}: {} -> {}", change.field, change.old, change.new)?;
+            }
+        }
+        for (track, errors) in &self.invalid {
+            let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+            writeln!(f, "  [{}] invalid: {}", format_persistent_id(*track), errors.join(", "))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+//! Equalizer settings as values
+//!
+//! An [`EqCurve`] holds the preamp and the ten bands of an [`EQPreset`], so that they can be read, written, and computed on at once.
+//! Curves can also be built from arbitrary frequency/gain points (e.g. a parametric curve measured for headphones), see [`EqCurve::interpolate`].
+//!
+//! [`EqByGenre`] assigns presets to the tracks of a library according to their genre.
+
+use std::collections::BTreeMap;
+
+use windows::Win32::Foundation::E_INVALIDARG;
+
+use super::{iTunes, EQPreset, IITObjectWrapper, IITPlaylistWrapper, IITTrackWrapper};
+use super::types::PersistentId;
+
+/// The center frequencies of the bands of the iTunes equalizer (in Hz)
+pub const BAND_FREQUENCIES: [f64; 10] = [32.0, 64.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0];
+
+/// The largest gain iTunes accepts, either way (in dB)
+pub const MAX_GAIN: f64 = 12.0;
+
+/// The preamp and band gains of an equalizer (in dB)
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EqCurve {
+    pub preamp: f64,
+    /// In the order of [`BAND_FREQUENCIES`]
+    pub bands: [f64; 10],
+}
+
+impl EqCurve {
+    /// No gain at all
+    pub const FLAT: EqCurve = EqCurve { preamp: 0.0, bands: [0.0; 10] };
+
+    /// Read the settings of a live preset
+    pub fn from_preset(preset: &EQPreset) -> windows::core::Result<Self> {
+        Ok(Self {
+            preamp: preset.Preamp()?,
+            bands: [
+                preset.Band1()?, preset.Band2()?, preset.Band3()?, preset.Band4()?, preset.Band5()?,
+                preset.Band6()?, preset.Band7()?, preset.Band8()?, preset.Band9()?, preset.Band10()?,
+            ],
+        })
+    }
+
+    /// Write these gains to a live preset. iTunes rejects gains out of the ±12 dB range (see [`EqCurve::clamp`])
+    pub fn write_to(&self, preset: &EQPreset) -> windows::core::Result<()> {
+        preset.set_Preamp(self.preamp)?;
+        preset.set_Band1(self.bands[0])?;
+        preset.set_Band2(self.bands[1])?;
+        preset.set_Band3(self.bands[2])?;
+        preset.set_Band4(self.bands[3])?;
+        preset.set_Band5(self.bands[4])?;
+        preset.set_Band6(self.bands[5])?;
+        preset.set_Band7(self.bands[6])?;
+        preset.set_Band8(self.bands[7])?;
+        preset.set_Band9(self.bands[8])?;
+        preset.set_Band10(self.bands[9])
+    }
+
+    /// Create a preset with these gains
+    pub fn create_preset(&self, iTunes: &iTunes, name: &str) -> windows::core::Result<EQPreset> {
+        let preset = iTunes.CreateEQPreset(name)?;
+        self.write_to(&preset)?;
+        Ok(preset)
+    }
+
+    /// A curve that goes through frequency/gain points (frequencies in Hz, in any order), with no preamp.
+    ///
+    /// Gains are interpolated linearly on a logarithmic frequency scale, and are flat beyond the first and last points.
+    /// Points with non-positive or non-finite values are ignored. Without points, the curve is flat.
+    pub fn interpolate(points: &[(f64, f64)]) -> Self {
+        let mut points: Vec<(f64, f64)> = points.iter()
+            .copied()
+            .filter(|(frequency, gain)| frequency.is_finite() && *frequency > 0.0 && gain.is_finite())
+            .collect();
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let bands = BAND_FREQUENCIES.map(|frequency| {
+            let after = points.partition_point(|(f, _)| *f < frequency);
+            match (after.checked_sub(1).map(|index| points[index]), points.get(after).copied()) {
+                (None, None) => 0.0,
+                (None, Some((_, gain))) | (Some((_, gain)), None) => gain,
+                (Some((f1, g1)), Some((f2, g2))) if f2 > f1 => {
+                    let ratio = (frequency.ln() - f1.ln()) / (f2.ln() - f1.ln());
+                    g1 + (g2 - g1) * ratio
+                },
+                // The point is exactly at the frequency of the band
+                (Some(_), Some((_, gain))) => gain,
+            }
+        });
+        Self { preamp: 0.0, bands }
+    }
+
+    /// The band frequencies and their gains
+    pub fn points(&self) -> impl Iterator<Item = (f64, f64)> + '_ {
+        BAND_FREQUENCIES.iter().copied().zip(self.bands.iter().copied())
+    }
+
+    /// The gain-by-gain average of curves, or `None` if there are none
+    pub fn average(curves: &[EqCurve]) -> Option<Self> {
+        if curves.is_empty() {
+            return None;
+        }
+        let count = curves.len() as f64;
+        let mut average = Self::FLAT;
+        for curve in curves {
+            average.preamp += curve.preamp / count;
+            for (band, gain) in average.bands.iter_mut().zip(&curve.bands) {
+                *band += gain / count;
+            }
+        }
+        Some(average)
+    }
+
+    /// Multiply every gain (including the preamp) by `factor`, e.g. `0.5` for a curve half as pronounced
+    pub fn scale(&self, factor: f64) -> Self {
+        Self { preamp: self.preamp * factor, bands: self.bands.map(|gain| gain * factor) }
+    }
+
+    /// Bring every gain within the ±12 dB iTunes accepts
+    pub fn clamp(&self) -> Self {
+        let clamp = |gain: f64| gain.clamp(-MAX_GAIN, MAX_GAIN);
+        Self { preamp: clamp(self.preamp), bands: self.bands.map(clamp) }
+    }
+
+    /// Whether iTunes accepts every gain
+    pub fn is_within_range(&self) -> bool {
+        std::iter::once(&self.preamp).chain(&self.bands).all(|gain| (-MAX_GAIN..=MAX_GAIN).contains(gain))
+    }
+}
+
+/// The result of [`EqByGenre::apply`]
+#[derive(Debug, Default)]
+pub struct EqAssignmentReport {
+    /// The tracks whose preset has been changed
+    pub assigned: Vec<PersistentId>,
+    /// Tracks that already had the right preset
+    pub unchanged: usize,
+    /// Tracks whose genre has no preset (and there is no default one)
+    pub unmatched: usize,
+    pub failures: Vec<(PersistentId, windows::core::Error)>,
+}
+
+/// Which EQ preset tracks should use, according to their genre
+///
+/// ```no_run
+/// use itunes_com::wrappers::iTunes;
+/// use itunes_com::wrappers::eq_curve::EqByGenre;
+///
+/// let iTunes = iTunes::new().unwrap();
+/// let rules = EqByGenre::new()
+///     .with_genre("Classical", "Classical")
+///     .with_genre("Hip-Hop", "Hip Hop")
+///     .with_default("Flat");
+/// let report = rules.apply(&iTunes).unwrap();
+/// println!("{} tracks changed", report.assigned.len());
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct EqByGenre {
+    /// Preset names, by lowercase genre
+    presets: BTreeMap<String, String>,
+    default: Option<String>,
+}
+
+impl EqByGenre {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use `preset` for the tracks of `genre` (compared case-insensitively, and regardless of surrounding spaces)
+    pub fn with_genre(mut self, genre: &str, preset: &str) -> Self {
+        self.presets.insert(genre.trim().to_lowercase(), preset.to_string());
+        self
+    }
+
+    /// Use `preset` for the tracks whose genre has no preset. Otherwise, their preset is left as is
+    pub fn with_default(mut self, preset: &str) -> Self {
+        self.default = Some(preset.to_string());
+        self
+    }
+
+    /// The preset for a genre, if any
+    pub fn preset_for(&self, genre: &str) -> Option<&str> {
+        self.presets.get(&genre.trim().to_lowercase()).or(self.default.as_ref()).map(String::as_str)
+    }
+
+    /// The presets these rules refer to, that do not exist in a live iTunes instance
+    pub fn missing_presets(&self, iTunes: &iTunes) -> windows::core::Result<Vec<String>> {
+        let mut existing = Vec::new();
+        for preset in iTunes.EQPresets()?.iter()? {
+            existing.push(preset.Name()?);
+        }
+        let mut missing: Vec<String> = self.presets.values().chain(&self.default)
+            .filter(|name| !existing.contains(name))
+            .cloned()
+            .collect();
+        missing.sort();
+        missing.dedup();
+        Ok(missing)
+    }
+
+    /// Set the EQ preset of every track of the main library.
+    ///
+    /// This fails with `E_INVALIDARG` before changing any track if a preset does not exist (see [`Self::missing_presets`]).
+    /// Otherwise, this does not stop at the first failure (e.g. a locked track), but reports the tracks that could not be changed.
+    pub fn apply(&self, iTunes: &iTunes) -> windows::core::Result<EqAssignmentReport> {
+        let missing = self.missing_presets(iTunes)?;
+        if !missing.is_empty() {
+            let message = format!("No EQ preset named {}", missing.join(", "));
+            return Err(windows::core::Error::new(E_INVALIDARG, message.as_str().into()));
+        }
+
+        let mut report = EqAssignmentReport::default();
+        for track in iTunes.LibraryPlaylist()?.Tracks()?.iter()? {
+            let preset = match self.preset_for(&track.Genre()?) {
+                Some(preset) => preset,
+                None => {
+                    report.unmatched += 1;
+                    continue;
+                },
+            };
+            if track.EQ()? == preset {
+                report.unchanged += 1;
+                continue;
+            }
+            let id = track.persistent_id()?;
+            match track.set_EQ(preset) {
+                Ok(()) => report.assigned.push(id),
+                Err(err) => report.failures.push((id, err)),
+            }
+        }
+        Ok(report)
+    }
+}
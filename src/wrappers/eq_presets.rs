@@ -0,0 +1,369 @@
+//! Backup and restore of equalizer presets
+//!
+//! [`export_presets`] reads the presets that can be modified (the ones users created), and [`import_presets`] creates them again,
+//! possibly in another iTunes instance. In between, presets can be saved to files in several [`PresetFormat`]s:
+//!
+//! * JSON: `{ "presets": [ { "name": "Late night", "preamp": -2.0, "bands": [3.0, 2.5, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 2.0, 2.5] } ] }`
+//! * TOML: the same structure, i.e. a `[[presets]]` table per preset, with `name`, `preamp` and `bands` keys
+//! * `.eq` files: one preset per file, as an XML property list in the style of Apple's preference files: a dictionary with a `Name` string,
+//!   a `Preamp` real, and a `Bands` array of ten reals
+//! * [Equalizer APO](https://sourceforge.net/p/equalizerapo/wiki/Configuration%20reference/) text: one preset per file (named after the file),
+//!   with an optional `Preamp: <gain> dB` line, and a `GraphicEQ: <frequency> <gain>; ...` line. Curves whose points are not at the
+//!   frequencies of iTunes are interpolated (see [`EqCurve::interpolate`])
+//!
+//! In every format, gains are in dB, and bands are in the order of [`BAND_FREQUENCIES`]. Every gain must be within ±12 dB (see [`EqPresetData::validate`]).
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::sys::ITErrors;
+use super::{iTunes, EQPreset};
+use super::eq_curve::EqCurve;
+pub use super::eq_curve::{BAND_FREQUENCIES, MAX_GAIN};
+
+/// Whether an error is `ITUNES_E_OBJECTEXISTS`, e.g. when creating a preset with the name of an existing one
+pub fn is_object_exists(error: &windows::core::Error) -> bool {
+    error.code().0 as u32 == ITErrors::ITUNES_E_OBJECTEXISTS as u32
+}
+
+/// Why presets cannot be read or imported
+#[derive(Debug)]
+pub enum EqPresetError {
+    Io(std::io::Error),
+    /// A file is not in the expected format
+    Parse(String),
+    /// A gain is out of the ±12 dB range. `band` is `None` for the preamp, or the index of the band
+    OutOfRange { preset: String, band: Option<usize>, gain: f64 },
+    /// Presets must have a name
+    EmptyName,
+    /// A preset with this name already exists, and cannot be modified (e.g. a built-in preset)
+    NotModifiable(String),
+    Com(windows::core::Error),
+}
+
+impl std::fmt::Display for EqPresetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EqPresetError::Io(err) => write!(f, "{}", err),
+            EqPresetError::Parse(message) => write!(f, "Invalid preset file: {}", message),
+            EqPresetError::OutOfRange { preset, band: None, gain } => write!(f, "The preamp of {:?} is out of range ({} dB)", preset, gain),
+            EqPresetError::OutOfRange { preset, band: Some(band), gain } => {
+                write!(f, "The {} Hz band of {:?} is out of range ({} dB)", BAND_FREQUENCIES[*band], preset, gain)
+            },
+            EqPresetError::EmptyName => write!(f, "Presets must have a name"),
+            EqPresetError::NotModifiable(name) => write!(f, "The existing preset {:?} cannot be modified", name),
+            EqPresetError::Com(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for EqPresetError {}
+
+impl From<std::io::Error> for EqPresetError {
+    fn from(err: std::io::Error) -> Self {
+        EqPresetError::Io(err)
+    }
+}
+
+impl From<windows::core::Error> for EqPresetError {
+    fn from(err: windows::core::Error) -> Self {
+        EqPresetError::Com(err)
+    }
+}
+
+/// The settings of an equalizer preset
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct EqPresetData {
+    pub name: String,
+    /// In dB
+    pub preamp: f64,
+    /// In dB, in the order of [`BAND_FREQUENCIES`]
+    pub bands: [f64; 10],
+}
+
+impl EqPresetData {
+    pub fn from_curve(name: &str, curve: &EqCurve) -> Self {
+        Self { name: name.to_string(), preamp: curve.preamp, bands: curve.bands }
+    }
+
+    /// The gains of this preset
+    pub fn curve(&self) -> EqCurve {
+        EqCurve { preamp: self.preamp, bands: self.bands }
+    }
+
+    /// Read the settings of a live preset
+    pub fn from_preset(preset: &EQPreset) -> windows::core::Result<Self> {
+        Ok(Self::from_curve(&preset.Name()?, &EqCurve::from_preset(preset)?))
+    }
+
+    /// Write these gains to a live preset (its name is not changed)
+    pub fn write_to(&self, preset: &EQPreset) -> windows::core::Result<()> {
+        self.curve().write_to(preset)
+    }
+
+    /// Check that this preset has a name, and that every gain is within ±12 dB
+    pub fn validate(&self) -> Result<(), EqPresetError> {
+        if self.name.trim().is_empty() {
+            return Err(EqPresetError::EmptyName);
+        }
+        let gains = std::iter::once((None, self.preamp)).chain(self.bands.iter().enumerate().map(|(index, gain)| (Some(index), *gain)));
+        for (band, gain) in gains {
+            if !(-MAX_GAIN..=MAX_GAIN).contains(&gain) {
+                return Err(EqPresetError::OutOfRange { preset: self.name.clone(), band, gain });
+            }
+        }
+        Ok(())
+    }
+
+    /// An Equalizer APO configuration, with a `Preamp` and a `GraphicEQ` line
+    pub fn to_graphic_eq(&self) -> String {
+        let points: Vec<String> = BAND_FREQUENCIES.iter().zip(&self.bands)
+            .map(|(frequency, gain)| format!("{} {}", frequency, gain))
+            .collect();
+        format!("Preamp: {} dB\nGraphicEQ: {}\n", self.preamp, points.join("; "))
+    }
+
+    /// Read an Equalizer APO configuration. Other lines than `Preamp` and `GraphicEQ` are ignored
+    pub fn from_graphic_eq(name: &str, text: &str) -> Result<Self, EqPresetError> {
+        let mut preamp = 0.0;
+        let mut points = None;
+        for line in text.lines().map(str::trim) {
+            if let Some(value) = strip_prefix_ignore_case(line, "Preamp:") {
+                let value = value.trim();
+                let value = value.strip_suffix("dB").or_else(|| value.strip_suffix("db")).unwrap_or(value);
+                preamp = parse_gain(value)?;
+            } else if let Some(value) = strip_prefix_ignore_case(line, "GraphicEQ:") {
+                points = Some(parse_graphic_eq_points(value)?);
+            }
+        }
+        let points = points.ok_or_else(|| EqPresetError::Parse("no GraphicEQ line".to_string()))?;
+        let curve = EqCurve { preamp, ..EqCurve::interpolate(&points) };
+        Ok(Self::from_curve(name, &curve))
+    }
+
+    /// An `.eq` file (see the [module documentation](self))
+    pub fn to_apple_eq(&self) -> Result<Vec<u8>, EqPresetError> {
+        let mut bytes = Vec::new();
+        plist::to_writer_xml(&mut bytes, &AppleEqFile::from(self)).map_err(|err| EqPresetError::Parse(err.to_string()))?;
+        Ok(bytes)
+    }
+
+    /// Read an `.eq` file (see the [module documentation](self))
+    pub fn from_apple_eq(bytes: &[u8]) -> Result<Self, EqPresetError> {
+        let file: AppleEqFile = plist::from_bytes(bytes).map_err(|err| EqPresetError::Parse(err.to_string()))?;
+        Ok(Self { name: file.name, preamp: file.preamp, bands: file.bands })
+    }
+}
+
+/// The layout of `.eq` files
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AppleEqFile {
+    name: String,
+    preamp: f64,
+    bands: [f64; 10],
+}
+
+impl From<&EqPresetData> for AppleEqFile {
+    fn from(preset: &EqPresetData) -> Self {
+        Self { name: preset.name.clone(), preamp: preset.preamp, bands: preset.bands }
+    }
+}
+
+/// The layout of JSON and TOML files
+#[derive(Serialize, Deserialize)]
+struct PresetFile {
+    presets: Vec<EqPresetData>,
+}
+
+fn strip_prefix_ignore_case<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    match text.get(..prefix.len()) {
+        Some(start) if start.eq_ignore_ascii_case(prefix) => Some(&text[prefix.len()..]),
+        _ => None,
+    }
+}
+
+fn parse_gain(text: &str) -> Result<f64, EqPresetError> {
+    text.trim().parse().map_err(|_| EqPresetError::Parse(format!("invalid gain {:?}", text.trim())))
+}
+
+/// Parse `frequency gain` pairs, separated by semicolons
+fn parse_graphic_eq_points(text: &str) -> Result<Vec<(f64, f64)>, EqPresetError> {
+    let mut points = Vec::new();
+    for point in text.split(';').map(str::trim).filter(|p| !p.is_empty()) {
+        let mut parts = point.split_whitespace();
+        let (frequency, gain) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(frequency), Some(gain), None) => (frequency, gain),
+            _ => return Err(EqPresetError::Parse(format!("invalid GraphicEQ point {:?}", point))),
+        };
+        let frequency: f64 = frequency.parse().ok()
+            .filter(|f: &f64| *f > 0.0)
+            .ok_or_else(|| EqPresetError::Parse(format!("invalid frequency {:?}", frequency)))?;
+        points.push((frequency, parse_gain(gain)?));
+    }
+    if points.is_empty() {
+        return Err(EqPresetError::Parse("empty GraphicEQ line".to_string()));
+    }
+    Ok(points)
+}
+
+/// How presets are stored in files
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PresetFormat {
+    /// Many presets per file
+    Json,
+    /// Many presets per file
+    Toml,
+    /// One preset per file
+    AppleEq,
+    /// One preset per file
+    GraphicEq,
+}
+
+impl PresetFormat {
+    /// The format of a file, according to its extension (`.json`, `.toml`, `.eq`, or `.txt` for Equalizer APO)
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "json" => Some(PresetFormat::Json),
+            "toml" => Some(PresetFormat::Toml),
+            "eq" => Some(PresetFormat::AppleEq),
+            "txt" => Some(PresetFormat::GraphicEq),
+            _ => None,
+        }
+    }
+
+    /// Whether a file of this format can hold several presets
+    pub fn holds_many(&self) -> bool {
+        matches!(self, PresetFormat::Json | PresetFormat::Toml)
+    }
+
+    /// Serialize presets. Formats that hold a single preset need exactly one
+    pub fn write(&self, presets: &[EqPresetData]) -> Result<Vec<u8>, EqPresetError> {
+        let single = || match presets {
+            [preset] => Ok(preset),
+            _ => Err(EqPresetError::Parse(format!("{:?} files hold a single preset", self))),
+        };
+        match self {
+            PresetFormat::Json => serde_json::to_vec_pretty(&PresetFile { presets: presets.to_vec() })
+                .map_err(|err| EqPresetError::Parse(err.to_string())),
+            PresetFormat::Toml => toml::to_string(&PresetFile { presets: presets.to_vec() })
+                .map(String::into_bytes)
+                .map_err(|err| EqPresetError::Parse(err.to_string())),
+            PresetFormat::AppleEq => single()?.to_apple_eq(),
+            PresetFormat::GraphicEq => Ok(single()?.to_graphic_eq().into_bytes()),
+        }
+    }
+
+    /// Deserialize presets. `name` is the name of Equalizer APO presets, which do not hold one
+    pub fn read(&self, bytes: &[u8], name: &str) -> Result<Vec<EqPresetData>, EqPresetError> {
+        let text = || std::str::from_utf8(bytes).map_err(|err| EqPresetError::Parse(err.to_string()));
+        let presets = match self {
+            PresetFormat::Json => serde_json::from_slice::<PresetFile>(bytes).map_err(|err| EqPresetError::Parse(err.to_string()))?.presets,
+            PresetFormat::Toml => toml::from_str::<PresetFile>(text()?).map_err(|err| EqPresetError::Parse(err.to_string()))?.presets,
+            PresetFormat::AppleEq => vec![EqPresetData::from_apple_eq(bytes)?],
+            PresetFormat::GraphicEq => vec![EqPresetData::from_graphic_eq(name, text()?)?],
+        };
+        presets.iter().try_for_each(EqPresetData::validate)?;
+        Ok(presets)
+    }
+}
+
+/// Save presets to a file, in the format of its extension (see [`PresetFormat::from_path`])
+pub fn save_presets<P: AsRef<Path>>(path: P, presets: &[EqPresetData]) -> Result<(), EqPresetError> {
+    let format = PresetFormat::from_path(&path).ok_or_else(|| EqPresetError::Parse("unknown file extension".to_string()))?;
+    std::fs::write(path, format.write(presets)?)?;
+    Ok(())
+}
+
+/// Load and validate the presets of a file, in the format of its extension (see [`PresetFormat::from_path`])
+pub fn load_presets<P: AsRef<Path>>(path: P) -> Result<Vec<EqPresetData>, EqPresetError> {
+    let path = path.as_ref();
+    let format = PresetFormat::from_path(path).ok_or_else(|| EqPresetError::Parse("unknown file extension".to_string()))?;
+    let name = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    format.read(&std::fs::read(path)?, &name)
+}
+
+/// Read the presets that can be modified (i.e. not the built-in ones)
+pub fn export_presets(iTunes: &iTunes) -> windows::core::Result<Vec<EqPresetData>> {
+    let mut presets = Vec::new();
+    for preset in iTunes.EQPresets()?.iter()? {
+        if preset.is_Modifiable()? {
+            presets.push(EqPresetData::from_preset(&preset)?);
+        }
+    }
+    Ok(presets)
+}
+
+/// What to do when importing a preset whose name is taken
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OnCollision {
+    /// Keep the existing preset
+    Skip,
+    /// Overwrite the gains of the existing preset (built-in presets cannot be overwritten)
+    Overwrite,
+    /// Create the preset under another name, e.g. `Late night (2)`
+    Rename,
+}
+
+/// What happened to an imported preset
+#[derive(Debug)]
+pub enum ImportOutcome {
+    Created,
+    /// The preset has been created with this other name
+    Renamed(String),
+    Overwritten,
+    Skipped,
+    Failed(EqPresetError),
+}
+
+/// Create presets in a live iTunes instance.
+///
+/// Presets are validated first. This does not stop at the first failure, but returns the outcome of every preset, in order.
+pub fn import_presets(iTunes: &iTunes, presets: &[EqPresetData], on_collision: OnCollision) -> Vec<(String, ImportOutcome)> {
+    presets.iter()
+        .map(|preset| {
+            let outcome = import_preset(iTunes, preset, on_collision).unwrap_or_else(ImportOutcome::Failed);
+            (preset.name.clone(), outcome)
+        })
+        .collect()
+}
+
+fn import_preset(iTunes: &iTunes, preset: &EqPresetData, on_collision: OnCollision) -> Result<ImportOutcome, EqPresetError> {
+    preset.validate()?;
+
+    let existing = match iTunes.CreateEQPreset(&preset.name) {
+        Ok(created) => {
+            preset.write_to(&created)?;
+            return Ok(ImportOutcome::Created);
+        },
+        Err(err) if is_object_exists(&err) => iTunes.EQPresets()?.ItemByName(&preset.name)?,
+        Err(err) => return Err(err.into()),
+    };
+
+    match on_collision {
+        OnCollision::Skip => Ok(ImportOutcome::Skipped),
+        OnCollision::Overwrite => {
+            if !existing.is_Modifiable()? {
+                return Err(EqPresetError::NotModifiable(preset.name.clone()));
+            }
+            preset.write_to(&existing)?;
+            Ok(ImportOutcome::Overwritten)
+        },
+        OnCollision::Rename => {
+            for suffix in 2.. {
+                let name = format!("{} ({})", preset.name, suffix);
+                match iTunes.CreateEQPreset(&name) {
+                    Ok(created) => {
+                        preset.write_to(&created)?;
+                        return Ok(ImportOutcome::Renamed(name));
+                    },
+                    Err(err) if is_object_exists(&err) => continue,
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            unreachable!("every suffix is taken")
+        },
+    }
+}
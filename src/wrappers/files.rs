@@ -0,0 +1,114 @@
+//! Media files on disk
+//!
+//! This module does not talk to iTunes at all. It is used by the tools that compare the library with the actual files.
+
+use std::path::{Path, PathBuf};
+
+use unicode_normalization::UnicodeNormalization;
+
+/// The file extensions iTunes is able to import (lowercase, without the dot)
+pub const MEDIA_EXTENSIONS: [&str; 12] = [
+    "mp3", "m4a", "m4b", "m4p", "m4v", "mp4", "aac", "aif", "aiff", "wav", "mov", "m4r",
+];
+
+/// A file found on disk
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct MediaFile {
+    pub path: PathBuf,
+    /// The size of the file (in bytes)
+    pub size: u64,
+}
+
+/// Whether this path has one of the given extensions (case-insensitively)
+pub fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    match path.extension().and_then(|e| e.to_str()) {
+        None => false,
+        Some(ext) => extensions.iter().any(|e| e.eq_ignore_ascii_case(ext)),
+    }
+}
+
+/// Recursively list the files under `root` that have one of the given extensions (e.g. [`MEDIA_EXTENSIONS`]).
+///
+/// Symbolic links are not followed. Sub-folders that cannot be read are skipped, but an error is returned if `root` itself cannot be read.
+/// Files are sorted by path.
+pub fn walk_media_files<P: AsRef<Path>>(root: P, extensions: &[&str]) -> std::io::Result<Vec<MediaFile>> {
+    let mut files = Vec::new();
+    let mut folders = vec![root.as_ref().to_path_buf()];
+    let mut is_root = true;
+
+    while let Some(folder) = folders.pop() {
+        let entries = match std::fs::read_dir(&folder) {
+            Ok(entries) => entries,
+            Err(err) if is_root => return Err(err),
+            Err(_) => continue,
+        };
+        is_root = false;
+
+        for entry in entries.flatten() {
+            let file_type = match entry.file_type() {
+                Ok(t) => t,
+                Err(_) => continue,
+            };
+            let path = entry.path();
+            if file_type.is_dir() {
+                folders.push(path);
+            } else if file_type.is_file() && has_extension(&path, extensions) {
+                let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+                files.push(MediaFile { path, size });
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Clean a Windows path up, so that paths can be compared: `/` become `\`, `\\?\` prefixes are removed, repeated separators are collapsed,
+/// drive letters are uppercased, and trailing separators are removed (apart from drive roots, e.g. `C:\`).
+///
+/// This preserves the case of the path (apart from the drive letter), see [`windows_path_key`] for case-insensitive comparisons.
+pub fn canonical_windows_path(path: &str) -> String {
+    let path = path.replace('/', "\\");
+    let path = if let Some(unc) = path.strip_prefix("\\\\?\\UNC\\") {
+        format!("\\\\{}", unc)
+    } else if let Some(local) = path.strip_prefix("\\\\?\\") {
+        local.to_string()
+    } else {
+        path
+    };
+
+    let is_unc = path.starts_with("\\\\");
+    let mut canonical = String::with_capacity(path.len());
+    if is_unc {
+        canonical.push_str("\\\\");
+    }
+    for c in path.trim_start_matches('\\').chars() {
+        if c == '\\' && canonical.ends_with('\\') {
+            continue;
+        }
+        canonical.push(c);
+    }
+    if !is_unc && path.starts_with('\\') {
+        canonical.insert(0, '\\');
+    }
+
+    let bytes = canonical.as_bytes();
+    if bytes.len() >= 2 && bytes[1] == b':' && bytes[0].is_ascii_alphabetic() {
+        canonical[..1].make_ascii_uppercase();
+    }
+
+    let is_drive_root = canonical.len() == 3 && canonical.ends_with(":\\");
+    if !is_drive_root && canonical.len() > 2 {
+        while canonical.ends_with('\\') {
+            canonical.pop();
+        }
+    }
+    canonical
+}
+
+/// A key to compare Windows paths case-insensitively (the way NTFS does, by default).
+///
+/// Keys are also in Unicode normalization form C, so that e.g. paths written by macOS (that uses decomposed forms) match their Windows equivalent.
+pub fn windows_path_key(path: &str) -> String {
+    canonical_windows_path(path).nfc().collect::<String>().to_lowercase()
+}
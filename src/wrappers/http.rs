@@ -0,0 +1,635 @@
+//! HTTP API and now-playing page
+//!
+//! A [`Server`] exposes the player and the playlists of iTunes over HTTP, so that web pages and other services can show what is playing:
+//!
+//! | Request                              | Response                                                         |
+//! |--------------------------------------|------------------------------------------------------------------|
+//! | `GET /`                              | a minimal HTML page that shows the current track, and updates itself |
+//! | `GET /now-playing`                   | a [`NowPlaying`] JSON object                                     |
+//! | `GET /now-playing/artwork`           | the artwork of the current track (`404` if it has none)          |
+//! | `POST /player/{action}`              | `204`. Actions are `play`, `pause`, `play-pause`, `stop`, `next` and `previous` |
+//! | `GET /playlists`                     | a JSON array of [`PlaylistInfo`]                                 |
+//! | `GET /playlists/{persistentId}/tracks` | a JSON array of [`TrackInfo`]                                  |
+//! | `GET /events`                        | [server-sent events](https://html.spec.whatwg.org/multipage/server-sent-events.html): a `player` event, with a [`NowPlaying`] object, whenever the player is started or stopped, or changes tracks |
+//!
+//! Errors are JSON objects with an `error` message. Persistent IDs are formatted as 16 hexadecimal digits (see [`format_persistent_id`]).<br/>
+//! `GET` requests can be made from pages served elsewhere, but player actions cannot: `POST` requests whose `Origin` is not this
+//! server are rejected (`403`), so that any page the user visits cannot control the player. Clients that are not browsers send no `Origin`.
+//!
+//! Requests are served by a [`Backend`]. It is implemented for [`iTunes`], and by [`SnapshotBackend`], an in-memory stand-in
+//! that makes it possible to run a server without iTunes (e.g. in tests).<br/>
+//! Connections are handled by background threads, but the backend is only called by the thread that runs [`Server::run`]
+//! (COM objects must stay on the thread that created them).
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::sys::ITPlayerState;
+use super::{iTunes, IITPlaylistWrapper, IITTrackWrapper};
+use super::library::{LibrarySnapshot, TrackSnapshot, snapshot_tracks, format_persistent_id, parse_persistent_id};
+use super::server::{Acceptor, library_playlists, server_stopped, skip};
+pub use super::server::{PlaylistInfo, TrackInfo};
+use super::types::PersistentId;
+use super::LONG;
+
+/// How often event streams send a comment, to find out about disconnected clients
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Requests with longer bodies are rejected
+const MAX_BODY_LENGTH: usize = 64 * 1024;
+
+/// The page served at `/`
+const NOW_PLAYING_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Now playing</title>
+<style>
+  body { margin: 0; height: 100vh; display: flex; align-items: center; justify-content: center; gap: 4vw; background: #111; color: #eee; font-family: sans-serif; }
+  img { width: 40vmin; height: 40vmin; object-fit: cover; border-radius: 1vmin; background: #333; }
+  #name { font-size: 5vmin; font-weight: bold; }
+  #artist, #album { font-size: 3.5vmin; color: #aaa; }
+  progress { width: 100%; margin-top: 2vmin; }
+  button { font-size: 3vmin; margin: 2vmin 1vmin 0 0; }
+</style>
+</head>
+<body>
+<img id="artwork" alt="">
+<div>
+  <div id="name">Nothing is playing</div>
+  <div id="artist"></div>
+  <div id="album"></div>
+  <progress id="progress" value="0" max="1"></progress>
+  <div>
+    <button onclick="control('previous')">&#9198;</button>
+    <button onclick="control('play-pause')">&#9199;</button>
+    <button onclick="control('next')">&#9197;</button>
+  </div>
+</div>
+<script>
+  let state = null, receivedAt = 0;
+  function show(nowPlaying) {
+    state = nowPlaying;
+    receivedAt = Date.now();
+    const track = nowPlaying.track;
+    document.getElementById('name').textContent = track ? track.name : 'Nothing is playing';
+    document.getElementById('artist').textContent = track ? track.artist : '';
+    document.getElementById('album').textContent = track ? track.album : '';
+    const artwork = document.getElementById('artwork');
+    if (nowPlaying.artwork) { artwork.src = nowPlaying.artwork; } else { artwork.removeAttribute('src'); }
+    tick();
+  }
+  function tick() {
+    const progress = document.getElementById('progress');
+    if (!state || !state.track) { progress.value = 0; return; }
+    const elapsed = state.state === 'playing' ? Date.now() - receivedAt : 0;
+    progress.max = Math.max(state.track.duration * 1000, 1);
+    progress.value = Math.min(state.positionMs + elapsed, progress.max);
+  }
+  function control(action) { fetch('/player/' + action, { method: 'POST' }); }
+  fetch('/now-playing').then(response => response.json()).then(show);
+  new EventSource('/events').addEventListener('player', event => show(JSON.parse(event.data)));
+  setInterval(tick, 1000);
+</script>
+</body>
+</html>
+"#;
+
+/// The state of the player (see [`ITPlayerState`]).<br/>
+/// iTunes reports paused tracks as stopped.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PlaybackState {
+    Stopped,
+    Playing,
+    FastForward,
+    Rewind,
+}
+
+impl From<ITPlayerState> for PlaybackState {
+    fn from(state: ITPlayerState) -> Self {
+        match state {
+            ITPlayerState::ITPlayerStateStopped => PlaybackState::Stopped,
+            ITPlayerState::ITPlayerStatePlaying => PlaybackState::Playing,
+            ITPlayerState::ITPlayerStateFastForward => PlaybackState::FastForward,
+            ITPlayerState::ITPlayerStateRewind => PlaybackState::Rewind,
+        }
+    }
+}
+
+/// What the player is playing
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NowPlaying {
+    pub state: PlaybackState,
+    /// The position within the current track (in milliseconds)
+    pub position_ms: LONG,
+    pub track: Option<TrackInfo>,
+    /// The URL of the artwork of the current track, if it has some
+    pub artwork: Option<String>,
+}
+
+impl NowPlaying {
+    /// Build the state of a player, with the URL this server uses for the artwork of `track`
+    pub fn new(state: PlaybackState, position_ms: LONG, track: Option<TrackInfo>, has_artwork: bool) -> Self {
+        // The track ID makes the URL change with the track, so that browsers do not show a cached image
+        let artwork = match &track {
+            Some(track) if has_artwork => Some(format!("/now-playing/artwork?track={}", format_persistent_id(track.persistent_id))),
+            _ => None,
+        };
+        Self { state, position_ms, track, artwork }
+    }
+
+    /// Whether `other` differs from this state in a way that is worth an event (i.e. not only by the position within the track)
+    pub fn differs_from(&self, other: &NowPlaying) -> bool {
+        self.state != other.state || self.track != other.track || self.artwork != other.artwork
+    }
+}
+
+/// An image, with its MIME type
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Image {
+    pub content_type: &'static str,
+    pub bytes: Vec<u8>,
+}
+
+/// What `POST /player/{action}` does
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PlayerAction {
+    Play,
+    Pause,
+    PlayPause,
+    Stop,
+    Next,
+    Previous,
+}
+
+impl PlayerAction {
+    /// The action designated by a URL segment, e.g. `play-pause`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "play" => Some(PlayerAction::Play),
+            "pause" => Some(PlayerAction::Pause),
+            "play-pause" => Some(PlayerAction::PlayPause),
+            "stop" => Some(PlayerAction::Stop),
+            "next" => Some(PlayerAction::Next),
+            "previous" => Some(PlayerAction::Previous),
+            _ => None,
+        }
+    }
+}
+
+/// An error, sent with an HTTP status code
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HttpError {
+    pub status: u16,
+    pub message: String,
+}
+
+impl HttpError {
+    pub fn new<S: Into<String>>(status: u16, message: S) -> Self {
+        Self { status, message: message.into() }
+    }
+
+    pub fn not_found<S: Into<String>>(message: S) -> Self {
+        Self::new(404, message)
+    }
+}
+
+impl From<windows::core::Error> for HttpError {
+    fn from(err: windows::core::Error) -> Self {
+        Self::new(500, err.message().to_string())
+    }
+}
+
+impl std::fmt::Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (status {})", self.message, self.status)
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+/// What serves the requests of a [`Server`]
+pub trait Backend {
+    fn now_playing(&mut self) -> Result<NowPlaying, HttpError>;
+    /// The artwork of the current track, if any
+    fn artwork(&mut self) -> Result<Option<Image>, HttpError>;
+    fn control(&mut self, action: PlayerAction) -> Result<(), HttpError>;
+    /// The playlists of the library (apart from the main library playlist)
+    fn playlists(&mut self) -> Result<Vec<PlaylistInfo>, HttpError>;
+    fn playlist_tracks(&mut self, playlist: PersistentId) -> Result<Vec<TrackInfo>, HttpError>;
+}
+
+impl Backend for iTunes {
+    fn now_playing(&mut self) -> Result<NowPlaying, HttpError> {
+        let state = PlaybackState::from(self.PlayerState()?);
+        // There is no current track when iTunes has nothing to play
+        let current = match self.CurrentTrack() {
+            Ok(track) => track,
+            Err(_) => return Ok(NowPlaying::new(state, 0, None, false)),
+        };
+        let track = TrackInfo::from(&TrackSnapshot::from_track(&current)?);
+        let has_artwork = current.primary_artwork()?.is_some();
+        Ok(NowPlaying::new(state, self.PlayerPositionMS()?, Some(track), has_artwork))
+    }
+
+    fn artwork(&mut self) -> Result<Option<Image>, HttpError> {
+        let artwork = match self.CurrentTrack() {
+            Ok(track) => track.primary_artwork()?,
+            Err(_) => return Ok(None),
+        };
+        match artwork {
+            None => Ok(None),
+            Some(artwork) => {
+                let image = artwork.to_image().map_err(|err| HttpError::new(500, err.to_string()))?;
+                Ok(Some(Image { content_type: image.format.mime_type(), bytes: image.bytes }))
+            },
+        }
+    }
+
+    fn control(&mut self, action: PlayerAction) -> Result<(), HttpError> {
+        let result = match action {
+            PlayerAction::Play => self.Play(),
+            PlayerAction::Pause => self.Pause(),
+            PlayerAction::PlayPause => self.PlayPause(),
+            PlayerAction::Stop => self.Stop(),
+            PlayerAction::Next => self.NextTrack(),
+            PlayerAction::Previous => self.PreviousTrack(),
+        };
+        Ok(result?)
+    }
+
+    fn playlists(&mut self) -> Result<Vec<PlaylistInfo>, HttpError> {
+        Ok(library_playlists(self)?)
+    }
+
+    fn playlist_tracks(&mut self, playlist: PersistentId) -> Result<Vec<TrackInfo>, HttpError> {
+        let playlist = self.LibrarySource()?.Playlists()?.ItemByPersistentID(playlist)
+            .map_err(|_| HttpError::not_found(format!("No playlist with persistent ID {}", format_persistent_id(playlist))))?;
+        let tracks = snapshot_tracks(&playlist.Tracks()?)?;
+        Ok(tracks.iter().map(TrackInfo::from).collect())
+    }
+}
+
+/// A [`Backend`] that plays a library snapshot, without actually playing anything.
+///
+/// The player goes through the tracks of the library in order of persistent IDs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SnapshotBackend {
+    pub library: LibrarySnapshot,
+    pub state: PlaybackState,
+    /// The current track, if any
+    pub current: Option<PersistentId>,
+    /// The position within the current track (in milliseconds)
+    pub position_ms: LONG,
+    /// The artwork of tracks (tracks that are not in this map have none)
+    pub artwork: BTreeMap<PersistentId, Image>,
+}
+
+impl SnapshotBackend {
+    pub fn new(library: LibrarySnapshot) -> Self {
+        Self { library, state: PlaybackState::Stopped, current: None, position_ms: 0, artwork: BTreeMap::new() }
+    }
+
+    /// Go to another track of the library. Going past the last track stops the player
+    fn skip(&mut self, forward: bool) {
+        self.current = skip(&self.library, self.current, forward);
+        self.position_ms = 0;
+        if self.current.is_none() {
+            self.state = PlaybackState::Stopped;
+        }
+    }
+}
+
+impl Backend for SnapshotBackend {
+    fn now_playing(&mut self) -> Result<NowPlaying, HttpError> {
+        let track = self.current.and_then(|id| self.library.track(id)).map(TrackInfo::from);
+        let has_artwork = self.current.is_some_and(|id| self.artwork.contains_key(&id));
+        Ok(NowPlaying::new(self.state, self.position_ms, track, has_artwork))
+    }
+
+    fn artwork(&mut self) -> Result<Option<Image>, HttpError> {
+        Ok(self.current.and_then(|id| self.artwork.get(&id)).cloned())
+    }
+
+    fn control(&mut self, action: PlayerAction) -> Result<(), HttpError> {
+        let playing = self.state != PlaybackState::Stopped;
+        match action {
+            PlayerAction::Play => {
+                if self.current.is_none() {
+                    self.skip(true);
+                }
+                if self.current.is_some() {
+                    self.state = PlaybackState::Playing;
+                }
+            },
+            PlayerAction::PlayPause if playing => self.state = PlaybackState::Stopped,
+            PlayerAction::PlayPause => return self.control(PlayerAction::Play),
+            PlayerAction::Pause => self.state = PlaybackState::Stopped,
+            PlayerAction::Stop => {
+                self.state = PlaybackState::Stopped;
+                self.position_ms = 0;
+            },
+            PlayerAction::Next => self.skip(true),
+            PlayerAction::Previous => self.skip(false),
+        }
+        Ok(())
+    }
+
+    fn playlists(&mut self) -> Result<Vec<PlaylistInfo>, HttpError> {
+        Ok(self.library.playlists.iter().map(PlaylistInfo::from).collect())
+    }
+
+    fn playlist_tracks(&mut self, playlist: PersistentId) -> Result<Vec<TrackInfo>, HttpError> {
+        let snapshot = self.library.playlist(playlist)
+            .ok_or_else(|| HttpError::not_found(format!("No playlist with persistent ID {}", format_persistent_id(playlist))))?;
+        Ok(snapshot.tracks.iter().filter_map(|id| self.library.track(*id)).map(TrackInfo::from).collect())
+    }
+}
+
+/// An HTTP request (only what the server needs of it)
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Request {
+    pub method: String,
+    /// The path of the URL, without its query string
+    pub path: String,
+    /// The `Host` header, if any
+    pub host: Option<String>,
+    /// The `Origin` header, if any (browsers send it with cross-origin requests, and with `POST` requests)
+    pub origin: Option<String>,
+}
+
+impl Request {
+    /// Whether this request has been sent by a page served elsewhere
+    pub fn is_cross_origin(&self) -> bool {
+        // e.g. `http://localhost:8080`, for a host of `localhost:8080`
+        let origin_host = self.origin.as_deref().map(|origin| origin.split_once("://").map_or(origin, |(_, host)| host));
+        match (origin_host, self.host.as_deref()) {
+            (None, _) => false,
+            (Some(origin_host), Some(host)) => !origin_host.eq_ignore_ascii_case(host),
+            (Some(_), None) => true,
+        }
+    }
+}
+
+/// An HTTP response
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    fn json<T: Serialize>(value: &T) -> Self {
+        match serde_json::to_vec(value) {
+            Ok(body) => Self { status: 200, content_type: "application/json", body },
+            Err(err) => Self::error(&HttpError::new(500, err.to_string())),
+        }
+    }
+
+    fn error(err: &HttpError) -> Self {
+        let body = serde_json::json!({ "error": err.message }).to_string().into_bytes();
+        Self { status: err.status, content_type: "application/json", body }
+    }
+
+    fn no_content() -> Self {
+        Self { status: 204, content_type: "text/plain", body: Vec::new() }
+    }
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        _ => "Internal Server Error",
+    }
+}
+
+fn route<B: Backend + ?Sized>(backend: &mut B, request: &Request) -> Result<Response, HttpError> {
+    let segments: Vec<&str> = request.path.split('/').filter(|s| !s.is_empty()).collect();
+    let allowed = match segments.as_slice() {
+        [] | ["now-playing"] | ["now-playing", "artwork"] | ["playlists"] | ["playlists", _, "tracks"] | ["events"] => "GET",
+        ["player", _] => "POST",
+        _ => return Err(HttpError::not_found(format!("No resource at {}", request.path))),
+    };
+    if request.method != allowed && !(allowed == "GET" && request.method == "HEAD") {
+        return Err(HttpError::new(405, format!("{} only accepts {} requests", request.path, allowed)));
+    }
+
+    match segments.as_slice() {
+        [] => Ok(Response { status: 200, content_type: "text/html; charset=utf-8", body: NOW_PLAYING_PAGE.as_bytes().to_vec() }),
+        ["now-playing"] => Ok(Response::json(&backend.now_playing()?)),
+        ["now-playing", "artwork"] => match backend.artwork()? {
+            Some(image) => Ok(Response { status: 200, content_type: image.content_type, body: image.bytes }),
+            None => Err(HttpError::not_found("The current track has no artwork")),
+        },
+        ["player", action] => {
+            if request.is_cross_origin() {
+                return Err(HttpError::new(403, "Player actions cannot be sent from other origins"));
+            }
+            let action = PlayerAction::from_name(action).ok_or_else(|| HttpError::not_found(format!("Unknown player action `{}`", action)))?;
+            backend.control(action)?;
+            Ok(Response::no_content())
+        },
+        ["playlists"] => Ok(Response::json(&backend.playlists()?)),
+        ["playlists", id, "tracks"] => {
+            let id = parse_persistent_id(id).ok_or_else(|| HttpError::new(400, format!("Invalid persistent ID {:?}", id)))?;
+            Ok(Response::json(&backend.playlist_tracks(id)?))
+        },
+        // Event streams are handled by connection threads
+        _ => Err(HttpError::new(400, "Event streams are served by Server")),
+    }
+}
+
+/// Serve a request (apart from `/events`).
+///
+/// This is what a [`Server`] does with every request it receives.
+pub fn handle_request<B: Backend + ?Sized>(backend: &mut B, request: &Request) -> Response {
+    route(backend, request).unwrap_or_else(|err| Response::error(&err))
+}
+
+/// A server-sent event that carries the state of the player
+pub fn player_event(now_playing: &NowPlaying) -> String {
+    // JSON has no raw line breaks, so the data fits on a single line
+    format!("event: player\ndata: {}\n\n", serde_json::to_string(now_playing).unwrap_or_default())
+}
+
+/// What connection threads send to the thread that runs [`Server::run`]
+enum ServerMessage {
+    Request { request: Request, response: Sender<Response> },
+    /// A new event stream
+    Subscribe { events: Sender<String> },
+}
+
+/// An HTTP server.
+///
+/// Connections are handled by background threads, that hand every request over to the thread that calls [`Server::run`].
+pub struct Server {
+    sender: Sender<ServerMessage>,
+    receiver: Receiver<ServerMessage>,
+    acceptor: Acceptor,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Server {
+    pub fn new() -> Self {
+        let (sender, receiver) = channel();
+        Self { sender, receiver, acceptor: Acceptor::new() }
+    }
+
+    /// Accept connections on an address, until this server is dropped.
+    ///
+    /// This returns the actual address, which is useful when binding port 0.
+    pub fn listen<A: ToSocketAddrs>(&self, address: A) -> std::io::Result<SocketAddr> {
+        let sender = self.sender.clone();
+        self.acceptor.listen(address, move |stream, _| serve_connection(stream, &sender))
+    }
+
+    /// Serve requests with `backend` on the current thread, until `keep_running` returns `false`.
+    ///
+    /// `keep_running` is called after every request, and at least every `poll_interval`.
+    /// While event streams are open, the player is checked every `poll_interval` too.
+    pub fn run<B, F>(&self, backend: &mut B, poll_interval: Duration, mut keep_running: F)
+    where B: Backend + ?Sized, F: FnMut() -> bool
+    {
+        let mut subscribers: Vec<Sender<String>> = Vec::new();
+        let mut new_subscribers: Vec<Sender<String>> = Vec::new();
+        let mut last_state: Option<NowPlaying> = None;
+
+        while keep_running() {
+            match self.receiver.recv_timeout(poll_interval) {
+                Ok(ServerMessage::Request { request, response }) => {
+                    let _ = response.send(handle_request(backend, &request));
+                },
+                Ok(ServerMessage::Subscribe { events }) => new_subscribers.push(events),
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+
+            if subscribers.is_empty() && new_subscribers.is_empty() {
+                last_state = None;
+                continue;
+            }
+            let now_playing = match backend.now_playing() {
+                Ok(now_playing) => now_playing,
+                Err(_) => continue,
+            };
+            let changed = match &last_state {
+                Some(last) => now_playing.differs_from(last),
+                None => true,
+            };
+            let event = player_event(&now_playing);
+            // Streams whose client has gone are dropped
+            if changed {
+                subscribers.retain(|events| events.send(event.clone()).is_ok());
+            }
+            // New streams start with the current state
+            subscribers.extend(new_subscribers.drain(..).filter(|events| events.send(event.clone()).is_ok()));
+            last_state = Some(now_playing);
+        }
+    }
+}
+
+/// Read the request line and the headers of a request, and skip its body
+fn read_request(reader: &mut BufReader<TcpStream>) -> std::io::Result<Result<Request, HttpError>> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target.to_string()),
+        _ => return Ok(Err(HttpError::new(400, "Invalid request line"))),
+    };
+
+    let mut content_length = 0;
+    let (mut host, mut origin) = (None, None);
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let (name, value) = (name.trim(), value.trim());
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.parse().unwrap_or(usize::MAX);
+            } else if name.eq_ignore_ascii_case("host") {
+                host = Some(value.to_string());
+            } else if name.eq_ignore_ascii_case("origin") {
+                origin = Some(value.to_string());
+            }
+        }
+    }
+    if content_length > MAX_BODY_LENGTH {
+        return Ok(Err(HttpError::new(413, "The request body is too long")));
+    }
+    std::io::copy(&mut reader.by_ref().take(content_length as u64), &mut std::io::sink())?;
+
+    let path = target.split('?').next().unwrap_or_default().to_string();
+    Ok(Ok(Request { method, path, host, origin }))
+}
+
+/// `method` is that of the request, if it could be read
+fn write_response(stream: &mut TcpStream, response: &Response, method: Option<&str>) -> std::io::Result<()> {
+    // Pages served elsewhere may read the API, but not the result of player actions
+    let allow_origin = match method {
+        Some("POST") => "",
+        _ => "Access-Control-Allow-Origin: *\r\n",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n{}Cache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        response.status, reason_phrase(response.status), response.content_type, response.body.len(), allow_origin,
+    )?;
+    if method != Some("HEAD") {
+        stream.write_all(&response.body)?;
+    }
+    stream.flush()
+}
+
+/// Serve a single request (connections are not kept alive), or an event stream
+fn serve_connection(stream: TcpStream, sender: &Sender<ServerMessage>) -> std::io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+
+    let request = match read_request(&mut reader)? {
+        Ok(request) => request,
+        Err(err) => return write_response(&mut writer, &Response::error(&err), None),
+    };
+
+    if request.method == "GET" && request.path.trim_end_matches('/') == "/events" {
+        let (events_sender, events) = channel();
+        sender.send(ServerMessage::Subscribe { events: events_sender }).map_err(|_| server_stopped())?;
+        writer.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nAccess-Control-Allow-Origin: *\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n")?;
+        loop {
+            match events.recv_timeout(KEEP_ALIVE_INTERVAL) {
+                Ok(event) => writer.write_all(event.as_bytes())?,
+                // Writing fails once the client has disconnected
+                Err(RecvTimeoutError::Timeout) => writer.write_all(b": keep-alive\n\n")?,
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+            writer.flush()?;
+        }
+    }
+
+    let (response_sender, response) = channel();
+    sender.send(ServerMessage::Request { request: request.clone(), response: response_sender }).map_err(|_| server_stopped())?;
+    let response = response.recv().map_err(|_| server_stopped())?;
+    write_response(&mut writer, &response, Some(&request.method))
+}
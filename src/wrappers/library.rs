@@ -0,0 +1,864 @@
+//! Offline snapshots of a library
+//!
+//! Reading many properties from COM objects is slow, and COM objects are only valid as long as iTunes is running.
+//! A [`LibrarySnapshot`] is a plain-data copy of the tracks and playlists of a library, that can either be read from a live iTunes instance,
+//! or parsed from an `iTunes Library.xml` file (with the `library_xml` Cargo feature).
+//!
+//! Tracks and playlists are keyed by their persistent IDs, which are stable across sessions (and are the same in the XML file).
+
+use std::collections::BTreeMap;
+use std::time::SystemTime;
+
+use windows::Win32::Foundation::E_INVALIDARG;
+
+use crate::sys::{ITPlaylistKind, ITRatingKind, ITUserPlaylistSpecialKind};
+use super::{iTunes, Track, TrackCollection, Playlist, Iterable, IITObjectWrapper, IITTrackWrapper, IITPlaylistWrapper};
+use super::types::{PersistentId, Rating, date_to_system_time, system_time_to_date, format_system_time};
+use super::LONG;
+
+/// The fields of a track that are stored in a [`TrackSnapshot`]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TrackField {
+    Name,
+    Artist,
+    AlbumArtist,
+    Album,
+    Composer,
+    Genre,
+    Grouping,
+    Comment,
+    SortName,
+    SortArtist,
+    SortAlbumArtist,
+    SortAlbum,
+    SortComposer,
+    Year,
+    TrackNumber,
+    TrackCount,
+    DiscNumber,
+    DiscCount,
+    BPM,
+    Duration,
+    Size,
+    BitRate,
+    SampleRate,
+    PlayedCount,
+    PlayedDate,
+    SkippedCount,
+    SkippedDate,
+    Rating,
+    AlbumRating,
+    DateAdded,
+    Enabled,
+    Compilation,
+    Location,
+}
+
+impl TrackField {
+    /// All the fields, in display order
+    pub const ALL: [TrackField; 33] = [
+        TrackField::Name, TrackField::Artist, TrackField::AlbumArtist, TrackField::Album, TrackField::Composer,
+        TrackField::Genre, TrackField::Grouping, TrackField::Comment, TrackField::SortName, TrackField::SortArtist,
+        TrackField::SortAlbumArtist, TrackField::SortAlbum, TrackField::SortComposer, TrackField::Year, TrackField::TrackNumber,
+        TrackField::TrackCount, TrackField::DiscNumber, TrackField::DiscCount, TrackField::BPM, TrackField::Duration,
+        TrackField::Size, TrackField::BitRate, TrackField::SampleRate, TrackField::PlayedCount, TrackField::PlayedDate,
+        TrackField::SkippedCount, TrackField::SkippedDate, TrackField::Rating, TrackField::AlbumRating, TrackField::DateAdded,
+        TrackField::Enabled, TrackField::Compilation, TrackField::Location,
+    ];
+
+    /// The name of the matching COM property
+    pub fn name(&self) -> &'static str {
+        match self {
+            TrackField::Name => "Name",
+            TrackField::Artist => "Artist",
+            TrackField::AlbumArtist => "AlbumArtist",
+            TrackField::Album => "Album",
+            TrackField::Composer => "Composer",
+            TrackField::Genre => "Genre",
+            TrackField::Grouping => "Grouping",
+            TrackField::Comment => "Comment",
+            TrackField::SortName => "SortName",
+            TrackField::SortArtist => "SortArtist",
+            TrackField::SortAlbumArtist => "SortAlbumArtist",
+            TrackField::SortAlbum => "SortAlbum",
+            TrackField::SortComposer => "SortComposer",
+            TrackField::Year => "Year",
+            TrackField::TrackNumber => "TrackNumber",
+            TrackField::TrackCount => "TrackCount",
+            TrackField::DiscNumber => "DiscNumber",
+            TrackField::DiscCount => "DiscCount",
+            TrackField::BPM => "BPM",
+            TrackField::Duration => "Duration",
+            TrackField::Size => "Size",
+            TrackField::BitRate => "BitRate",
+            TrackField::SampleRate => "SampleRate",
+            TrackField::PlayedCount => "PlayedCount",
+            TrackField::PlayedDate => "PlayedDate",
+            TrackField::SkippedCount => "SkippedCount",
+            TrackField::SkippedDate => "SkippedDate",
+            TrackField::Rating => "Rating",
+            TrackField::AlbumRating => "AlbumRating",
+            TrackField::DateAdded => "DateAdded",
+            TrackField::Enabled => "Enabled",
+            TrackField::Compilation => "Compilation",
+            TrackField::Location => "Location",
+        }
+    }
+
+    /// Retrieve a field from its COM property name (case-insensitive)
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|f| f.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Whether iTunes lets us change this field
+    pub fn is_writable(&self) -> bool {
+        !matches!(self,
+            TrackField::Duration | TrackField::Size | TrackField::BitRate | TrackField::SampleRate | TrackField::DateAdded
+        )
+    }
+}
+
+impl std::fmt::Display for TrackField {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The value of a [`TrackField`]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FieldValue {
+    Text(String),
+    Integer(i64),
+    Bool(bool),
+    Date(Option<SystemTime>),
+}
+
+impl FieldValue {
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            FieldValue::Text(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            FieldValue::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            FieldValue::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_date(&self) -> Option<Option<SystemTime>> {
+        match self {
+            FieldValue::Date(d) => Some(*d),
+            _ => None,
+        }
+    }
+}
+
+impl From<&str> for FieldValue {
+    fn from(value: &str) -> Self {
+        FieldValue::Text(value.to_string())
+    }
+}
+
+impl From<String> for FieldValue {
+    fn from(value: String) -> Self {
+        FieldValue::Text(value)
+    }
+}
+
+impl From<LONG> for FieldValue {
+    fn from(value: LONG) -> Self {
+        FieldValue::Integer(value.into())
+    }
+}
+
+impl From<i64> for FieldValue {
+    fn from(value: i64) -> Self {
+        FieldValue::Integer(value)
+    }
+}
+
+impl From<bool> for FieldValue {
+    fn from(value: bool) -> Self {
+        FieldValue::Bool(value)
+    }
+}
+
+impl From<SystemTime> for FieldValue {
+    fn from(value: SystemTime) -> Self {
+        FieldValue::Date(Some(value))
+    }
+}
+
+impl std::fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FieldValue::Text(s) => write!(f, "{:?}", s),
+            FieldValue::Integer(i) => write!(f, "{}", i),
+            FieldValue::Bool(b) => write!(f, "{}", b),
+            FieldValue::Date(None) => f.write_str("none"),
+            FieldValue::Date(Some(d)) => f.write_str(&format_system_time(*d)),
+        }
+    }
+}
+
+/// A change of a field value
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FieldChange {
+    pub field: TrackField,
+    pub old: FieldValue,
+    pub new: FieldValue,
+}
+
+/// An offline copy of the info of a track
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TrackSnapshot {
+    pub persistent_id: PersistentId,
+    pub name: String,
+    pub artist: String,
+    pub album_artist: String,
+    pub album: String,
+    pub composer: String,
+    pub genre: String,
+    pub grouping: String,
+    pub comment: String,
+    pub sort_name: String,
+    pub sort_artist: String,
+    pub sort_album_artist: String,
+    pub sort_album: String,
+    pub sort_composer: String,
+    pub year: LONG,
+    pub track_number: LONG,
+    pub track_count: LONG,
+    pub disc_number: LONG,
+    pub disc_count: LONG,
+    pub bpm: LONG,
+    /// The length of the track (in seconds)
+    pub duration: LONG,
+    /// The size of the track (in bytes)
+    pub size: i64,
+    /// The bit rate of the track (in kbps)
+    pub bit_rate: LONG,
+    /// The sample rate of the track (in Hz)
+    pub sample_rate: LONG,
+    pub played_count: LONG,
+    pub played_date: Option<SystemTime>,
+    pub skipped_count: LONG,
+    pub skipped_date: Option<SystemTime>,
+    /// The user rating of the track (0 to 100). Computed ratings are ignored.
+    pub rating: LONG,
+    /// The user rating of the album of the track (0 to 100). Computed ratings are ignored.
+    pub album_rating: LONG,
+    pub date_added: Option<SystemTime>,
+    pub enabled: bool,
+    pub compilation: bool,
+    /// The full path to the file of this track. This is `None` for tracks that are not files (e.g. streams)
+    pub location: Option<String>,
+    /// The number of pieces of artwork of this track
+    pub artwork_count: LONG,
+}
+
+impl TrackSnapshot {
+    /// Read the info of a live track
+    pub fn from_track(track: &Track) -> windows::core::Result<Self> {
+        let mut snapshot = Self {
+            persistent_id: track.persistent_id()?,
+            name: track.Name()?,
+            artist: track.Artist()?,
+            album_artist: String::new(),
+            album: track.Album()?,
+            composer: track.Composer()?,
+            genre: track.Genre()?,
+            grouping: track.Grouping()?,
+            comment: track.Comment()?,
+            sort_name: String::new(),
+            sort_artist: String::new(),
+            sort_album_artist: String::new(),
+            sort_album: String::new(),
+            sort_composer: String::new(),
+            year: track.Year()?,
+            track_number: track.TrackNumber()?,
+            track_count: track.TrackCount()?,
+            disc_number: track.DiscNumber()?,
+            disc_count: track.DiscCount()?,
+            bpm: track.BPM()?,
+            duration: track.Duration()?,
+            size: i64::from(track.Size()?),
+            bit_rate: track.BitRate()?,
+            sample_rate: track.SampleRate()?,
+            played_count: track.PlayedCount()?,
+            played_date: date_to_system_time(track.PlayedDate()?),
+            skipped_count: 0,
+            skipped_date: None,
+            rating: LONG::from(track.Rating()?),
+            album_rating: 0,
+            date_added: date_to_system_time(track.DateAdded()?),
+            enabled: track.is_Enabled()?,
+            compilation: track.is_Compilation()?,
+            location: None,
+            artwork_count: track.Artwork()?.Count()?,
+        };
+
+        if let Some(file_track) = track.as_file_or_cd_track() {
+            snapshot.album_artist = file_track.AlbumArtist()?;
+            snapshot.sort_name = file_track.SortName()?;
+            snapshot.sort_artist = file_track.SortArtist()?;
+            snapshot.sort_album_artist = file_track.SortAlbumArtist()?;
+            snapshot.sort_album = file_track.SortAlbum()?;
+            snapshot.sort_composer = file_track.SortComposer()?;
+            snapshot.size = file_track.Size()?;
+            snapshot.skipped_count = file_track.SkippedCount()?;
+            snapshot.skipped_date = date_to_system_time(file_track.SkippedDate()?);
+            if file_track.ratingKind()? == ITRatingKind::ITRatingKindComputed {
+                snapshot.rating = 0;
+            }
+            if file_track.AlbumRatingKind()? == ITRatingKind::ITRatingKindUser {
+                snapshot.album_rating = LONG::from(file_track.AlbumRating()?);
+            }
+            snapshot.location = Some(file_track.Location()?);
+        }
+
+        Ok(snapshot)
+    }
+
+    /// Get the value of a field
+    pub fn get(&self, field: TrackField) -> FieldValue {
+        match field {
+            TrackField::Name => FieldValue::Text(self.name.clone()),
+            TrackField::Artist => FieldValue::Text(self.artist.clone()),
+            TrackField::AlbumArtist => FieldValue::Text(self.album_artist.clone()),
+            TrackField::Album => FieldValue::Text(self.album.clone()),
+            TrackField::Composer => FieldValue::Text(self.composer.clone()),
+            TrackField::Genre => FieldValue::Text(self.genre.clone()),
+            TrackField::Grouping => FieldValue::Text(self.grouping.clone()),
+            TrackField::Comment => FieldValue::Text(self.comment.clone()),
+            TrackField::SortName => FieldValue::Text(self.sort_name.clone()),
+            TrackField::SortArtist => FieldValue::Text(self.sort_artist.clone()),
+            TrackField::SortAlbumArtist => FieldValue::Text(self.sort_album_artist.clone()),
+            TrackField::SortAlbum => FieldValue::Text(self.sort_album.clone()),
+            TrackField::SortComposer => FieldValue::Text(self.sort_composer.clone()),
+            TrackField::Year => FieldValue::Integer(self.year.into()),
+            TrackField::TrackNumber => FieldValue::Integer(self.track_number.into()),
+            TrackField::TrackCount => FieldValue::Integer(self.track_count.into()),
+            TrackField::DiscNumber => FieldValue::Integer(self.disc_number.into()),
+            TrackField::DiscCount => FieldValue::Integer(self.disc_count.into()),
+            TrackField::BPM => FieldValue::Integer(self.bpm.into()),
+            TrackField::Duration => FieldValue::Integer(self.duration.into()),
+            TrackField::Size => FieldValue::Integer(self.size),
+            TrackField::BitRate => FieldValue::Integer(self.bit_rate.into()),
+            TrackField::SampleRate => FieldValue::Integer(self.sample_rate.into()),
+            TrackField::PlayedCount => FieldValue::Integer(self.played_count.into()),
+            TrackField::PlayedDate => FieldValue::Date(self.played_date),
+            TrackField::SkippedCount => FieldValue::Integer(self.skipped_count.into()),
+            TrackField::SkippedDate => FieldValue::Date(self.skipped_date),
+            TrackField::Rating => FieldValue::Integer(self.rating.into()),
+            TrackField::AlbumRating => FieldValue::Integer(self.album_rating.into()),
+            TrackField::DateAdded => FieldValue::Date(self.date_added),
+            TrackField::Enabled => FieldValue::Bool(self.enabled),
+            TrackField::Compilation => FieldValue::Bool(self.compilation),
+            TrackField::Location => FieldValue::Text(self.location.clone().unwrap_or_default()),
+        }
+    }
+
+    /// Set the value of a field.
+    ///
+    /// Values of the wrong type are ignored
+    pub fn set(&mut self, field: TrackField, value: &FieldValue) {
+        fn text(target: &mut String, value: &FieldValue) {
+            if let Some(s) = value.as_text() { *target = s.to_string(); }
+        }
+        fn long(target: &mut LONG, value: &FieldValue) {
+            if let Some(i) = value.as_integer() { *target = i as LONG; }
+        }
+        fn date(target: &mut Option<SystemTime>, value: &FieldValue) {
+            if let Some(d) = value.as_date() { *target = d; }
+        }
+        fn boolean(target: &mut bool, value: &FieldValue) {
+            if let Some(b) = value.as_bool() { *target = b; }
+        }
+
+        match field {
+            TrackField::Name => text(&mut self.name, value),
+            TrackField::Artist => text(&mut self.artist, value),
+            TrackField::AlbumArtist => text(&mut self.album_artist, value),
+            TrackField::Album => text(&mut self.album, value),
+            TrackField::Composer => text(&mut self.composer, value),
+            TrackField::Genre => text(&mut self.genre, value),
+            TrackField::Grouping => text(&mut self.grouping, value),
+            TrackField::Comment => text(&mut self.comment, value),
+            TrackField::SortName => text(&mut self.sort_name, value),
+            TrackField::SortArtist => text(&mut self.sort_artist, value),
+            TrackField::SortAlbumArtist => text(&mut self.sort_album_artist, value),
+            TrackField::SortAlbum => text(&mut self.sort_album, value),
+            TrackField::SortComposer => text(&mut self.sort_composer, value),
+            TrackField::Year => long(&mut self.year, value),
+            TrackField::TrackNumber => long(&mut self.track_number, value),
+            TrackField::TrackCount => long(&mut self.track_count, value),
+            TrackField::DiscNumber => long(&mut self.disc_number, value),
+            TrackField::DiscCount => long(&mut self.disc_count, value),
+            TrackField::BPM => long(&mut self.bpm, value),
+            TrackField::Duration => long(&mut self.duration, value),
+            TrackField::Size => if let Some(i) = value.as_integer() { self.size = i },
+            TrackField::BitRate => long(&mut self.bit_rate, value),
+            TrackField::SampleRate => long(&mut self.sample_rate, value),
+            TrackField::PlayedCount => long(&mut self.played_count, value),
+            TrackField::PlayedDate => date(&mut self.played_date, value),
+            TrackField::SkippedCount => long(&mut self.skipped_count, value),
+            TrackField::SkippedDate => date(&mut self.skipped_date, value),
+            TrackField::Rating => long(&mut self.rating, value),
+            TrackField::AlbumRating => long(&mut self.album_rating, value),
+            TrackField::DateAdded => date(&mut self.date_added, value),
+            TrackField::Enabled => boolean(&mut self.enabled, value),
+            TrackField::Compilation => boolean(&mut self.compilation, value),
+            TrackField::Location => if let Some(s) = value.as_text() { self.location = Some(s.to_string()) },
+        }
+    }
+}
+
+/// Read the info of every track of a live collection (e.g. the result of a search)
+pub fn snapshot_tracks(collection: &TrackCollection) -> windows::core::Result<Vec<TrackSnapshot>> {
+    let mut tracks = Vec::new();
+    for track in collection.iter()? {
+        tracks.push(TrackSnapshot::from_track(&track)?);
+    }
+    Ok(tracks)
+}
+
+/// Write a field on a live track
+///
+/// This fails for read-only fields (see [`TrackField::is_writable`]), for values of the wrong type,
+/// and for fields that only exist on file tracks (e.g. `AlbumArtist`) when `track` is not a file track.
+pub fn set_track_field(track: &Track, field: TrackField, value: &FieldValue) -> windows::core::Result<()> {
+    fn invalid() -> windows::core::Error {
+        windows::core::Error::new(E_INVALIDARG, windows::h!("Invalid field or value").clone())
+    }
+
+    let text = || value.as_text().ok_or_else(invalid);
+    let long = || value.as_integer().map(|i| i as LONG).ok_or_else(invalid);
+    let date = || value.as_date().map(system_time_to_date).ok_or_else(invalid);
+    let boolean = || value.as_bool().ok_or_else(invalid);
+    let file_track = || track.as_file_or_cd_track().ok_or_else(invalid);
+
+    match field {
+        TrackField::Name => track.set_Name(text()?),
+        TrackField::Artist => track.set_Artist(text()?),
+        TrackField::AlbumArtist => file_track()?.set_AlbumArtist(text()?),
+        TrackField::Album => track.set_Album(text()?),
+        TrackField::Composer => track.set_Composer(text()?),
+        TrackField::Genre => track.set_Genre(text()?),
+        TrackField::Grouping => track.set_Grouping(text()?),
+        TrackField::Comment => track.set_Comment(text()?),
+        TrackField::SortName => file_track()?.set_SortName(text()?),
+        TrackField::SortArtist => file_track()?.set_SortArtist(text()?),
+        TrackField::SortAlbumArtist => file_track()?.set_SortAlbumArtist(text()?),
+        TrackField::SortAlbum => file_track()?.set_SortAlbum(text()?),
+        TrackField::SortComposer => file_track()?.set_SortComposer(text()?),
+        TrackField::Year => track.set_Year(long()?),
+        TrackField::TrackNumber => track.set_TrackNumber(long()?),
+        TrackField::TrackCount => track.set_TrackCount(long()?),
+        TrackField::DiscNumber => track.set_DiscNumber(long()?),
+        TrackField::DiscCount => track.set_DiscCount(long()?),
+        TrackField::BPM => track.set_BPM(long()?),
+        TrackField::PlayedCount => track.set_PlayedCount(long()?),
+        TrackField::PlayedDate => track.set_PlayedDate(date()?),
+        TrackField::SkippedCount => file_track()?.set_SkippedCount(long()?),
+        TrackField::SkippedDate => file_track()?.set_SkippedDate(date()?),
+        TrackField::Rating => track.set_Rating(Rating::from(long()?)),
+        TrackField::AlbumRating => file_track()?.set_AlbumRating(Rating::from(long()?)),
+        TrackField::Enabled => track.set_Enabled(boolean()?),
+        TrackField::Compilation => track.set_Compilation(boolean()?),
+        TrackField::Location => file_track()?.set_Location(text()?),
+        TrackField::Duration | TrackField::Size | TrackField::BitRate | TrackField::SampleRate | TrackField::DateAdded => Err(invalid()),
+    }
+}
+
+/// Read a single field of a live track
+///
+/// Values are the same as the ones of [`TrackSnapshot::get`] (e.g. computed ratings are read as zero), without reading every field of the track.
+pub fn get_track_field(track: &Track, field: TrackField) -> windows::core::Result<FieldValue> {
+    let text = |s: String| Ok(FieldValue::Text(s));
+    let long = |i: LONG| Ok(FieldValue::Integer(i.into()));
+    let date = |d: f64| Ok(FieldValue::Date(date_to_system_time(d)));
+    let boolean = |b: bool| Ok(FieldValue::Bool(b));
+    let file_track = track.as_file_or_cd_track();
+
+    match field {
+        TrackField::Name => text(track.Name()?),
+        TrackField::Artist => text(track.Artist()?),
+        TrackField::AlbumArtist => text(match &file_track { Some(f) => f.AlbumArtist()?, None => String::new() }),
+        TrackField::Album => text(track.Album()?),
+        TrackField::Composer => text(track.Composer()?),
+        TrackField::Genre => text(track.Genre()?),
+        TrackField::Grouping => text(track.Grouping()?),
+        TrackField::Comment => text(track.Comment()?),
+        TrackField::SortName => text(match &file_track { Some(f) => f.SortName()?, None => String::new() }),
+        TrackField::SortArtist => text(match &file_track { Some(f) => f.SortArtist()?, None => String::new() }),
+        TrackField::SortAlbumArtist => text(match &file_track { Some(f) => f.SortAlbumArtist()?, None => String::new() }),
+        TrackField::SortAlbum => text(match &file_track { Some(f) => f.SortAlbum()?, None => String::new() }),
+        TrackField::SortComposer => text(match &file_track { Some(f) => f.SortComposer()?, None => String::new() }),
+        TrackField::Year => long(track.Year()?),
+        TrackField::TrackNumber => long(track.TrackNumber()?),
+        TrackField::TrackCount => long(track.TrackCount()?),
+        TrackField::DiscNumber => long(track.DiscNumber()?),
+        TrackField::DiscCount => long(track.DiscCount()?),
+        TrackField::BPM => long(track.BPM()?),
+        TrackField::Duration => long(track.Duration()?),
+        TrackField::Size => Ok(FieldValue::Integer(match &file_track { Some(f) => f.Size()?, None => i64::from(track.Size()?) })),
+        TrackField::BitRate => long(track.BitRate()?),
+        TrackField::SampleRate => long(track.SampleRate()?),
+        TrackField::PlayedCount => long(track.PlayedCount()?),
+        TrackField::PlayedDate => date(track.PlayedDate()?),
+        TrackField::SkippedCount => long(match &file_track { Some(f) => f.SkippedCount()?, None => 0 }),
+        TrackField::SkippedDate => date(match &file_track { Some(f) => f.SkippedDate()?, None => 0.0 }),
+        TrackField::Rating => match &file_track {
+            Some(f) if f.ratingKind()? == ITRatingKind::ITRatingKindComputed => long(0),
+            _ => long(LONG::from(track.Rating()?)),
+        },
+        TrackField::AlbumRating => match &file_track {
+            Some(f) if f.AlbumRatingKind()? == ITRatingKind::ITRatingKindUser => long(LONG::from(f.AlbumRating()?)),
+            _ => long(0),
+        },
+        TrackField::DateAdded => date(track.DateAdded()?),
+        TrackField::Enabled => boolean(track.is_Enabled()?),
+        TrackField::Compilation => boolean(track.is_Compilation()?),
+        TrackField::Location => text(match &file_track { Some(f) => f.Location()?, None => String::new() }),
+    }
+}
+
+/// An offline copy of the info of a playlist
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PlaylistSnapshot {
+    pub persistent_id: PersistentId,
+    pub name: String,
+    /// The folder that contains this playlist, if any
+    pub parent: Option<PersistentId>,
+    pub is_folder: bool,
+    pub is_smart: bool,
+    /// The tracks of this playlist, in play order.<br/>
+    /// This is always empty for folders (even though iTunes reports the tracks of their children).
+    pub tracks: Vec<PersistentId>,
+}
+
+impl PlaylistSnapshot {
+    /// Read the info of a live playlist
+    pub fn from_playlist(playlist: &Playlist) -> windows::core::Result<Self> {
+        let (parent, is_folder, is_smart) = playlist_kind(playlist)?;
+
+        let mut tracks = Vec::new();
+        if !is_folder {
+            for track in playlist.Tracks()?.iter()? {
+                tracks.push(track.persistent_id()?);
+            }
+        }
+
+        Ok(Self {
+            persistent_id: playlist.persistent_id()?,
+            name: playlist.Name()?,
+            parent,
+            is_folder,
+            is_smart,
+            tracks,
+        })
+    }
+}
+
+/// The folder that contains a live playlist (if any), whether it is a folder, and whether it is a smart playlist
+pub(crate) fn playlist_kind(playlist: &Playlist) -> windows::core::Result<(Option<PersistentId>, bool, bool)> {
+    match playlist.as_user_playlist() {
+        None => Ok((None, false, false)),
+        Some(user_playlist) => {
+            let parent = match user_playlist.Parent() {
+                Ok(parent) => Some(parent.persistent_id()?),
+                Err(_) => None,
+            };
+            let is_folder = user_playlist.SpecialKind()? == ITUserPlaylistSpecialKind::ITUserPlaylistSpecialKindFolder;
+            Ok((parent, is_folder, user_playlist.is_Smart()?))
+        },
+    }
+}
+
+/// An offline copy of a library
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LibrarySnapshot {
+    /// All the tracks of the library
+    pub tracks: BTreeMap<PersistentId, TrackSnapshot>,
+    /// All the playlists of the library (apart from the main library playlist), in the iTunes order
+    pub playlists: Vec<PlaylistSnapshot>,
+}
+
+impl LibrarySnapshot {
+    /// Read the main library of a live iTunes instance
+    ///
+    /// This reads every property of every track, and may take a while for large libraries.
+    pub fn from_iTunes(iTunes: &iTunes) -> windows::core::Result<Self> {
+        let tracks = snapshot_tracks(&iTunes.LibraryPlaylist()?.Tracks()?)?
+            .into_iter()
+            .map(|track| (track.persistent_id, track))
+            .collect();
+
+        let mut playlists = Vec::new();
+        for playlist in iTunes.LibrarySource()?.Playlists()?.iter()? {
+            if playlist.Kind()? == ITPlaylistKind::ITPlaylistKindLibrary {
+                continue;
+            }
+            playlists.push(PlaylistSnapshot::from_playlist(&playlist)?);
+        }
+
+        Ok(Self { tracks, playlists })
+    }
+
+    pub fn track(&self, id: PersistentId) -> Option<&TrackSnapshot> {
+        self.tracks.get(&id)
+    }
+
+    pub fn track_mut(&mut self, id: PersistentId) -> Option<&mut TrackSnapshot> {
+        self.tracks.get_mut(&id)
+    }
+
+    pub fn playlist(&self, id: PersistentId) -> Option<&PlaylistSnapshot> {
+        self.playlists.iter().find(|p| p.persistent_id == id)
+    }
+
+    pub fn playlist_mut(&mut self, id: PersistentId) -> Option<&mut PlaylistSnapshot> {
+        self.playlists.iter_mut().find(|p| p.persistent_id == id)
+    }
+
+    /// Save this snapshot as a JSON file, e.g. to compare it with a later snapshot
+    #[cfg(feature = "json")]
+    pub fn save_json<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        serde_json::to_writer(file, self)?;
+        Ok(())
+    }
+
+    /// Load a snapshot saved by [`Self::save_json`]
+    #[cfg(feature = "json")]
+    pub fn load_json<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Self> {
+        let file = std::io::BufReader::new(std::fs::File::open(path)?);
+        Ok(serde_json::from_reader(file)?)
+    }
+}
+
+/// Normalize a text for loose comparisons: lowercase, trimmed, with whitespaces collapsed
+pub fn normalize_text(text: &str) -> String {
+    text.split_whitespace()
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Format a persistent ID the way iTunes does (e.g. in its XML file)
+pub fn format_persistent_id(id: PersistentId) -> String {
+    format!("{:016X}", id)
+}
+
+/// Parse a persistent ID formatted by [`format_persistent_id`]
+pub fn parse_persistent_id(text: &str) -> Option<PersistentId> {
+    PersistentId::from_str_radix(text.trim(), 16).ok()
+}
+
+
+#[cfg(feature = "library_xml")]
+mod xml {
+    //! Parser for `iTunes Library.xml` files
+    //!
+    //! These files are property lists, which are described [here](https://developer.apple.com/library/archive/documentation/Cocoa/Conceptual/PropertyLists/Introduction/Introduction.html)
+
+    use std::collections::HashMap;
+    use std::io::{Read, Seek};
+    use std::path::Path;
+    use std::time::SystemTime;
+
+    use plist::{Dictionary, Value};
+
+    use super::{LibrarySnapshot, TrackSnapshot, PlaylistSnapshot, parse_persistent_id};
+    use super::LONG;
+
+    fn string(dict: &Dictionary, key: &str) -> String {
+        dict.get(key).and_then(Value::as_string).unwrap_or_default().to_string()
+    }
+
+    fn integer(dict: &Dictionary, key: &str) -> i64 {
+        dict.get(key).and_then(Value::as_signed_integer).unwrap_or(0)
+    }
+
+    fn long(dict: &Dictionary, key: &str) -> LONG {
+        integer(dict, key) as LONG
+    }
+
+    fn boolean(dict: &Dictionary, key: &str) -> bool {
+        dict.get(key).and_then(Value::as_boolean).unwrap_or(false)
+    }
+
+    fn date(dict: &Dictionary, key: &str) -> Option<SystemTime> {
+        dict.get(key).and_then(Value::as_date).map(SystemTime::from)
+    }
+
+    fn persistent_id(dict: &Dictionary, key: &str) -> Option<u64> {
+        dict.get(key).and_then(Value::as_string).and_then(parse_persistent_id)
+    }
+
+    /// Turn a `file://` URL from the XML file into a Windows path.
+    ///
+    /// Returns `None` for non-file URLs (e.g. streams)
+    pub(crate) fn location_from_url(url: &str) -> Option<String> {
+        let path = url.strip_prefix("file://")?;
+        let path = path.strip_prefix("localhost").unwrap_or(path);
+        let path = percent_decode(path);
+
+        // "/C:/Music/..." is a drive path, "//server/share/..." is a UNC path
+        let bytes = path.as_bytes();
+        let path = if bytes.len() >= 3 && bytes[0] == b'/' && bytes[2] == b':' {
+            &path[1..]
+        } else {
+            &path[..]
+        };
+
+        Some(path.replace('/', "\\"))
+    }
+
+    fn percent_decode(text: &str) -> String {
+        let bytes = text.as_bytes();
+        let mut decoded = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            if bytes[i] == b'%' && i + 2 < bytes.len() {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+                if let Some(byte) = hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                    decoded.push(byte);
+                    i += 3;
+                    continue;
+                }
+            }
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+        String::from_utf8_lossy(&decoded).into_owned()
+    }
+
+    impl TrackSnapshot {
+        fn from_xml_dict(dict: &Dictionary) -> Option<Self> {
+            Some(Self {
+                persistent_id: persistent_id(dict, "Persistent ID")?,
+                name: string(dict, "Name"),
+                artist: string(dict, "Artist"),
+                album_artist: string(dict, "Album Artist"),
+                album: string(dict, "Album"),
+                composer: string(dict, "Composer"),
+                genre: string(dict, "Genre"),
+                grouping: string(dict, "Grouping"),
+                comment: string(dict, "Comments"),
+                sort_name: string(dict, "Sort Name"),
+                sort_artist: string(dict, "Sort Artist"),
+                sort_album_artist: string(dict, "Sort Album Artist"),
+                sort_album: string(dict, "Sort Album"),
+                sort_composer: string(dict, "Sort Composer"),
+                year: long(dict, "Year"),
+                track_number: long(dict, "Track Number"),
+                track_count: long(dict, "Track Count"),
+                disc_number: long(dict, "Disc Number"),
+                disc_count: long(dict, "Disc Count"),
+                bpm: long(dict, "BPM"),
+                // The XML file stores milliseconds
+                duration: ((integer(dict, "Total Time") + 500) / 1000) as LONG,
+                size: integer(dict, "Size"),
+                bit_rate: long(dict, "Bit Rate"),
+                sample_rate: long(dict, "Sample Rate"),
+                played_count: long(dict, "Play Count"),
+                played_date: date(dict, "Play Date UTC"),
+                skipped_count: long(dict, "Skip Count"),
+                skipped_date: date(dict, "Skip Date"),
+                rating: if boolean(dict, "Rating Computed") { 0 } else { long(dict, "Rating") },
+                album_rating: if boolean(dict, "Album Rating Computed") { 0 } else { long(dict, "Album Rating") },
+                date_added: date(dict, "Date Added"),
+                enabled: !boolean(dict, "Disabled"),
+                compilation: boolean(dict, "Compilation"),
+                location: dict.get("Location").and_then(Value::as_string).and_then(location_from_url),
+                artwork_count: long(dict, "Artwork Count"),
+            })
+        }
+    }
+
+    impl LibrarySnapshot {
+        /// Parse an `iTunes Library.xml` file.
+        ///
+        /// Its path is given by [`crate::wrappers::iTunes::LibraryXMLPath`]. Note that iTunes only updates this file from time to time.
+        pub fn from_xml_file<P: AsRef<Path>>(path: P) -> Result<Self, plist::Error> {
+            let root = Value::from_file(path)?;
+            Ok(Self::from_xml_value(&root))
+        }
+
+        /// Parse the content of an `iTunes Library.xml` file
+        pub fn from_xml_reader<R: Read + Seek>(reader: R) -> Result<Self, plist::Error> {
+            let root = Value::from_reader(reader)?;
+            Ok(Self::from_xml_value(&root))
+        }
+
+        fn from_xml_value(root: &Value) -> Self {
+            let mut snapshot = LibrarySnapshot::default();
+            let root = match root.as_dictionary() {
+                Some(dict) => dict,
+                None => return snapshot,
+            };
+
+            // Playlists refer to tracks by their (non-persistent) "Track ID"
+            let mut ids_by_track_id = HashMap::new();
+            let xml_tracks = root.get("Tracks").and_then(Value::as_dictionary);
+            for xml_track in xml_tracks.iter().flat_map(|d| d.values()).filter_map(Value::as_dictionary) {
+                if let Some(track) = TrackSnapshot::from_xml_dict(xml_track) {
+                    ids_by_track_id.insert(integer(xml_track, "Track ID"), track.persistent_id);
+                    snapshot.tracks.insert(track.persistent_id, track);
+                }
+            }
+
+            let xml_playlists = root.get("Playlists").and_then(Value::as_array);
+            for xml_playlist in xml_playlists.iter().flat_map(|a| a.iter()).filter_map(Value::as_dictionary) {
+                if boolean(xml_playlist, "Master") {
+                    continue;
+                }
+                let playlist_id = match persistent_id(xml_playlist, "Playlist Persistent ID") {
+                    Some(id) => id,
+                    None => continue,
+                };
+
+                let is_folder = boolean(xml_playlist, "Folder");
+                let tracks = match is_folder {
+                    true => Vec::new(),
+                    false => xml_playlist.get("Playlist Items")
+                        .and_then(Value::as_array)
+                        .map(|items| items.iter()
+                            .filter_map(Value::as_dictionary)
+                            .filter_map(|item| ids_by_track_id.get(&integer(item, "Track ID")).copied())
+                            .collect())
+                        .unwrap_or_default(),
+                };
+
+                snapshot.playlists.push(PlaylistSnapshot {
+                    persistent_id: playlist_id,
+                    name: string(xml_playlist, "Name"),
+                    parent: persistent_id(xml_playlist, "Parent Persistent ID"),
+                    is_folder,
+                    is_smart: !is_folder && xml_playlist.contains_key("Smart Info"),
+                    tracks,
+                });
+            }
+
+            snapshot
+        }
+    }
+}
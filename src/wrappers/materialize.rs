@@ -0,0 +1,153 @@
+//! Saving query results as static playlists
+//!
+//! [`materialize`] makes a user playlist contain exactly a list of tracks, in order. The playlist is created if needed,
+//! otherwise it is updated with as few operations as possible (see [`plan_playlist_update`]), so that it keeps its persistent ID, and its settings.
+
+use super::{iTunes, Playlist, UserPlaylist, ITunesRelatedObject, IITObjectWrapper, IITPlaylistWrapper, IITTrackWrapper};
+use super::library::PlaylistSnapshot;
+use super::query::Query;
+use super::types::PersistentId;
+
+/// The operations that turn the track list of a playlist into another one
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PlaylistUpdate {
+    /// The indices of the tracks to remove from the playlist (increasing)
+    pub removals: Vec<usize>,
+    /// The tracks to append, once tracks have been removed
+    pub additions: Vec<PersistentId>,
+}
+
+impl PlaylistUpdate {
+    pub fn is_empty(&self) -> bool {
+        self.removals.is_empty() && self.additions.is_empty()
+    }
+}
+
+/// Compute how to turn the `current` track list of a playlist into the `desired` one.
+///
+/// iTunes can only append tracks to a playlist, and remove them. The tracks that are kept must thus be the start of the desired list,
+/// and they are chosen as the longest such start that appears (in order) in the current list. Every other track is removed, and the missing ones are appended.
+pub fn plan_playlist_update(current: &[PersistentId], desired: &[PersistentId]) -> PlaylistUpdate {
+    let mut kept = 0;
+    let mut removals = Vec::new();
+    for (index, track) in current.iter().enumerate() {
+        if desired.get(kept) == Some(track) {
+            kept += 1;
+        } else {
+            removals.push(index);
+        }
+    }
+
+    PlaylistUpdate {
+        removals,
+        additions: desired[kept..].to_vec(),
+    }
+}
+
+/// What has been done to the track list of a playlist
+#[derive(Debug, Default)]
+pub struct UpdateReport {
+    pub removed: usize,
+    pub added: usize,
+    /// Tracks that could not be removed or added
+    pub failures: Vec<(PersistentId, windows::core::Error)>,
+}
+
+/// What [`materialize`] did
+#[derive(Debug)]
+pub struct MaterializeReport {
+    pub playlist: PersistentId,
+    /// Whether the playlist has been created
+    pub created: bool,
+    pub update: UpdateReport,
+}
+
+/// Find a regular (i.e. not smart, not folder) user playlist by name, in a folder (or at the top level)
+fn find_static_playlist(iTunes: &iTunes, name: &str, folder: Option<PersistentId>) -> windows::core::Result<Option<(UserPlaylist, PlaylistSnapshot)>> {
+    for playlist in iTunes.LibrarySource()?.Playlists()?.iter()? {
+        if playlist.Name()? != name {
+            continue;
+        }
+        let snapshot = PlaylistSnapshot::from_playlist(&playlist)?;
+        if snapshot.is_smart || snapshot.is_folder || snapshot.parent != folder {
+            continue;
+        }
+        if let Some(user_playlist) = playlist.as_user_playlist() {
+            return Ok(Some((user_playlist, snapshot)));
+        }
+    }
+    Ok(None)
+}
+
+fn to_user_playlist(playlist: Playlist) -> windows::core::Result<UserPlaylist> {
+    playlist.as_user_playlist().ok_or_else(|| windows::core::Error::from(windows::Win32::Foundation::E_NOINTERFACE))
+}
+
+/// Make the user playlist called `name` (in `folder`, or at the top level) contain exactly `tracks`, in this order.
+///
+/// The playlist is created if there is no such playlist yet. Smart playlists and folders are never changed.
+pub fn materialize(iTunes: &iTunes, name: &str, folder: Option<&UserPlaylist>, tracks: &[PersistentId]) -> windows::core::Result<MaterializeReport> {
+    let folder_id = folder.map(|f| f.persistent_id()).transpose()?;
+
+    let (playlist, current, created) = match find_static_playlist(iTunes, name, folder_id)? {
+        Some((playlist, snapshot)) => (playlist, snapshot.tracks, false),
+        None => {
+            let playlist = match folder {
+                Some(folder) => folder.CreatePlaylist(name)?,
+                None => iTunes.CreatePlaylist(name)?,
+            };
+            (to_user_playlist(playlist)?, Vec::new(), true)
+        },
+    };
+
+    let update = plan_playlist_update(&current, tracks);
+    Ok(MaterializeReport {
+        playlist: playlist.persistent_id()?,
+        created,
+        update: apply_playlist_update(&playlist, &current, &update)?,
+    })
+}
+
+/// Apply an update to a live playlist, whose track list is `current`.
+///
+/// This does not stop at the first track that cannot be added or removed, but reports them all.
+pub fn apply_playlist_update(playlist: &UserPlaylist, current: &[PersistentId], update: &PlaylistUpdate) -> windows::core::Result<UpdateReport> {
+    let mut report = UpdateReport::default();
+    if update.is_empty() {
+        return Ok(report);
+    }
+
+    // Track objects are collected before removing anything, as removals shift the indices of the next tracks
+    let playlist_tracks: Vec<_> = playlist.Tracks()?.iter()?.collect();
+    for index in &update.removals {
+        let result = match playlist_tracks.get(*index) {
+            Some(track) => track.Delete(),
+            None => Err(windows::core::Error::from(windows::Win32::Foundation::E_BOUNDS)),
+        };
+        match result {
+            Ok(()) => report.removed += 1,
+            Err(err) => report.failures.push((current.get(*index).copied().unwrap_or_default(), err)),
+        }
+    }
+
+    let library_tracks = playlist.iTunes_instance().LibraryPlaylist()?.Tracks()?;
+    for track_id in &update.additions {
+        let result = library_tracks.ItemByPersistentID(*track_id)
+            .and_then(|track| playlist.AddTrack(&track.as_variant()));
+        match result {
+            Ok(_) => report.added += 1,
+            Err(err) => report.failures.push((*track_id, err)),
+        }
+    }
+
+    Ok(report)
+}
+
+/// Run a query on the library, and save its result as a static playlist (see [`materialize`])
+pub fn save_query_as_playlist(iTunes: &iTunes, query: &Query, name: &str, folder: Option<&UserPlaylist>) -> windows::core::Result<MaterializeReport> {
+    let tracks: Vec<PersistentId> = query.run_on_playlist(&iTunes.LibraryPlaylist()?)?
+        .iter()
+        .map(|t| t.persistent_id)
+        .collect();
+    materialize(iTunes, name, folder, &tracks)
+}
@@ -9,6 +9,41 @@ use std::sync::Arc;
 
 pub mod iter;
 pub mod types;
+pub mod player;
+pub mod scrobble;
+pub mod library;
+pub mod sync;
+pub mod diff;
+pub mod duplicates;
+pub mod files;
+pub mod relocate;
+pub mod remap;
+pub mod orphans;
+pub mod undo;
+pub mod edit;
+pub mod query;
+pub mod query_language;
+pub mod materialize;
+pub mod reorder;
+pub mod playlist_sets;
+pub mod cleanup;
+pub mod artwork;
+pub mod eq_curve;
+pub mod schedule;
+#[cfg(any(feature = "rpc", feature = "mpd", feature = "http"))]
+pub mod server;
+#[cfg(feature = "rpc")]
+pub mod rpc;
+#[cfg(feature = "mpd")]
+pub mod mpd;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "artwork_cache")]
+pub mod artwork_cache;
+#[cfg(feature = "artwork_cache")]
+pub mod artwork_audit;
+#[cfg(feature = "eq_presets")]
+pub mod eq_presets;
 use types::*;
 
 // We'd rather use the re-exported versions, so that they are available to our users.
@@ -843,6 +878,15 @@ pub trait IITPlaylistWrapper: private::ComObjectWrapper {
     get_object!(
         /// Returns a collection of tracks in this playlist.
         Tracks -> TrackCollection as IITPlaylist);
+
+    /// The persistent IDs of the tracks of this playlist, in order
+    fn track_ids(&self) -> windows::core::Result<Vec<PersistentId>> {
+        let mut ids = Vec::new();
+        for track in self.Tracks()?.iter()? {
+            ids.push(track.persistent_id()?);
+        }
+        Ok(ids)
+    }
 }
 
 com_wrapper_struct!(
@@ -1103,6 +1147,15 @@ pub trait IITTrackWrapper: private::ComObjectWrapper {
     get_object!(
         /// Returns a collection of artwork.
         Artwork -> ArtworkCollection as IITTrack);
+
+    /// The first piece of artwork of this track (the one iTunes displays), if it has any
+    fn primary_artwork(&self) -> windows::core::Result<Option<Artwork>> {
+        let artworks = self.Artwork()?;
+        match artworks.Count()? {
+            0 => Ok(None),
+            _ => Ok(Some(artworks.item(1)?)),
+        }
+    }
 }
 
 com_wrapper_struct!(
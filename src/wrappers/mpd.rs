@@ -0,0 +1,1058 @@
+//! MPD protocol server
+//!
+//! A [`Server`] lets [MPD](https://mpd.readthedocs.io/en/latest/protocol.html) clients (phone remotes, `mpc`, `ncmpcpp`, etc.) control iTunes.
+//! It implements the subset of the protocol these clients need to show and control the player:
+//! * `status`, `currentsong`, `ping` and `close`
+//! * `play [POS]`, `pause [0|1]`, `stop`, `next`, `previous`, `setvol VOLUME` and `seekcur TIME` (absolute, or relative with a leading `+` or `-`)
+//! * `listplaylists` and `listplaylistinfo NAME`
+//! * `find` and `search`, with `TAG VALUE` pairs or with filter expressions (e.g. `((artist == 'Queen') AND (album contains 'opera'))`).
+//!   With pairs, `find` compares whole values and `search` looks for substrings. In expressions, `==` compares whole values for both.
+//!   Unlike MPD, whose `find` is case-sensitive, both commands ignore case (as iTunes searches do)
+//! * `idle` (for the `player`, `mixer` and `database` subsystems) and `noidle`.
+//!   `database` only reports tracks being added to or removed from the library, not edits of existing tracks
+//! * command lists
+//!
+//! MPD has a play queue, whose role is played by the current iTunes playlist (`song` and `songid` are positions within it).
+//! Songs are designated by their file paths, or by their persistent IDs for tracks that are not files.
+//!
+//! Requests are served by a [`Backend`]. It is implemented for [`iTunes`], and by [`SnapshotBackend`], an in-memory stand-in
+//! that makes it possible to run a server without iTunes (e.g. in tests).<br/>
+//! Connections are handled by background threads, but the backend is only called by the thread that runs [`Server::run`]
+//! (COM objects must stay on the thread that created them).
+
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+use crate::sys::{ITPlayerState, ITPlaylistKind, ITPlaylistRepeatMode};
+use super::{iTunes, IITObjectWrapper, IITPlaylistWrapper, IITTrackWrapper, Iterable};
+use super::library::{LibrarySnapshot, TrackSnapshot, TrackField, FieldValue, snapshot_tracks, format_persistent_id};
+use super::query::{Query, Filter, field};
+use super::server::{Acceptor, server_stopped, skip};
+use super::types::PersistentId;
+use super::LONG;
+
+/// The greeting sent to every client. Filter expressions need version 0.21
+const GREETING: &str = "OK MPD 0.21.0\n";
+
+/// MPD `idle` subsystems that are valid, but that this server never reports
+const SILENT_SUBSYSTEMS: [&str; 11] = ["update", "stored_playlist", "playlist", "output", "options", "partition", "sticker", "subscription", "message", "neighbor", "mount"];
+
+/// The error codes of `ACK` responses
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AckError {
+    NotList = 1,
+    Arg = 2,
+    Password = 3,
+    Permission = 4,
+    Unknown = 5,
+    NoExist = 50,
+    PlaylistMax = 51,
+    System = 52,
+    PlaylistLoad = 53,
+    UpdateAlready = 54,
+    PlayerSync = 55,
+    Exist = 56,
+}
+
+/// An error, sent as an `ACK` response
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MpdError {
+    pub code: AckError,
+    pub message: String,
+}
+
+impl MpdError {
+    pub fn new<S: Into<String>>(code: AckError, message: S) -> Self {
+        Self { code, message: message.into() }
+    }
+
+    fn arg<S: Into<String>>(message: S) -> Self {
+        Self::new(AckError::Arg, message)
+    }
+}
+
+impl From<windows::core::Error> for MpdError {
+    fn from(err: windows::core::Error) -> Self {
+        Self::new(AckError::System, err.message().to_string())
+    }
+}
+
+impl std::fmt::Display for MpdError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (error {})", self.message, self.code as u32)
+    }
+}
+
+impl std::error::Error for MpdError {}
+
+/// The state of the player, as MPD sees it
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PlaybackState {
+    Play,
+    Pause,
+    Stop,
+}
+
+impl PlaybackState {
+    fn name(&self) -> &'static str {
+        match self {
+            PlaybackState::Play => "play",
+            PlaybackState::Pause => "pause",
+            PlaybackState::Stop => "stop",
+        }
+    }
+}
+
+/// The answer to `status`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Status {
+    pub state: PlaybackState,
+    /// From 0 to 100
+    pub volume: LONG,
+    pub repeat: bool,
+    pub random: bool,
+    /// Whether the current song is repeated
+    pub single: bool,
+    /// The number of songs of the queue
+    pub queue_length: usize,
+    /// The position of the current song in the queue (zero-based), if any
+    pub song: Option<usize>,
+    /// The persistent ID of the current song, if any
+    pub current: Option<PersistentId>,
+    /// The position within the current song (in seconds)
+    pub elapsed: f64,
+    /// The duration of the current song (in seconds)
+    pub duration: f64,
+}
+
+/// A song, and its position in the queue (if it is in the queue)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Song {
+    pub track: TrackSnapshot,
+    pub position: Option<usize>,
+}
+
+/// What serves the requests of a [`Server`]
+pub trait Backend {
+    fn status(&mut self) -> Result<Status, MpdError>;
+    fn current_song(&mut self) -> Result<Option<Song>, MpdError>;
+    fn play(&mut self) -> Result<(), MpdError>;
+    /// Play the song at this position of the queue (zero-based)
+    fn play_at(&mut self, position: usize) -> Result<(), MpdError>;
+    fn pause(&mut self) -> Result<(), MpdError>;
+    fn play_pause(&mut self) -> Result<(), MpdError>;
+    fn stop(&mut self) -> Result<(), MpdError>;
+    fn next_track(&mut self) -> Result<(), MpdError>;
+    fn previous_track(&mut self) -> Result<(), MpdError>;
+    /// Set the volume (already checked to be between 0 and 100)
+    fn set_volume(&mut self, volume: LONG) -> Result<(), MpdError>;
+    /// Move within the current song (in seconds from its start)
+    fn seek(&mut self, position: f64) -> Result<(), MpdError>;
+    /// The names of the stored playlists
+    fn playlists(&mut self) -> Result<Vec<String>, MpdError>;
+    fn playlist_tracks(&mut self, name: &str) -> Result<Vec<TrackSnapshot>, MpdError>;
+    /// Run a query on the whole library
+    fn search(&mut self, query: &Query) -> Result<Vec<TrackSnapshot>, MpdError>;
+    /// A number that changes when tracks are added to or removed from the library.
+    ///
+    /// Edits of existing tracks need not change it, and an addition and a removal between two calls may cancel out.
+    fn database_version(&mut self) -> Result<u64, MpdError>;
+}
+
+impl Backend for iTunes {
+    fn status(&mut self) -> Result<Status, MpdError> {
+        let player_state = self.PlayerState()?;
+        let volume = self.SoundVolume()?;
+
+        let (current, song, elapsed, duration) = match self.CurrentTrack() {
+            Err(_) => (None, None, 0.0, 0.0),
+            Ok(track) => {
+                let index = track.PlayOrderIndex()?;
+                let song = usize::try_from(index - 1).ok();
+                (Some(track.persistent_id()?), song, f64::from(self.PlayerPositionMS()?) / 1000.0, f64::from(track.Duration()?))
+            },
+        };
+
+        let (queue_length, random, repeat, single) = match self.CurrentPlaylist() {
+            Err(_) => (0, false, false, false),
+            Ok(playlist) => {
+                let song_repeat = playlist.SongRepeat()?;
+                (
+                    playlist.Tracks()?.Count()? as usize,
+                    playlist.is_Shuffle()?,
+                    song_repeat != ITPlaylistRepeatMode::ITPlaylistRepeatModeOff,
+                    song_repeat == ITPlaylistRepeatMode::ITPlaylistRepeatModeOne,
+                )
+            },
+        };
+
+        // iTunes reports paused tracks as stopped
+        let state = match player_state {
+            ITPlayerState::ITPlayerStateStopped if current.is_some() && elapsed > 0.0 => PlaybackState::Pause,
+            ITPlayerState::ITPlayerStateStopped => PlaybackState::Stop,
+            _ => PlaybackState::Play,
+        };
+
+        Ok(Status { state, volume, repeat, random, single, queue_length, song, current, elapsed, duration })
+    }
+
+    fn current_song(&mut self) -> Result<Option<Song>, MpdError> {
+        let track = match self.CurrentTrack() {
+            Err(_) => return Ok(None),
+            Ok(track) => track,
+        };
+        let position = usize::try_from(track.PlayOrderIndex()? - 1).ok();
+        Ok(Some(Song { track: TrackSnapshot::from_track(&track)?, position }))
+    }
+
+    fn play(&mut self) -> Result<(), MpdError> {
+        Ok(self.Play()?)
+    }
+
+    fn play_at(&mut self, position: usize) -> Result<(), MpdError> {
+        let index = LONG::try_from(position + 1).map_err(|_| MpdError::arg("Bad song index"))?;
+        let tracks = match self.CurrentPlaylist() {
+            Ok(playlist) => playlist.Tracks()?,
+            Err(_) => self.LibraryPlaylist()?.Tracks()?,
+        };
+        let track = tracks.ItemByPlayOrder(index).map_err(|_| MpdError::arg("Bad song index"))?;
+        Ok(track.Play()?)
+    }
+
+    fn pause(&mut self) -> Result<(), MpdError> {
+        Ok(self.Pause()?)
+    }
+
+    fn play_pause(&mut self) -> Result<(), MpdError> {
+        Ok(self.PlayPause()?)
+    }
+
+    fn stop(&mut self) -> Result<(), MpdError> {
+        Ok(self.Stop()?)
+    }
+
+    fn next_track(&mut self) -> Result<(), MpdError> {
+        Ok(self.NextTrack()?)
+    }
+
+    fn previous_track(&mut self) -> Result<(), MpdError> {
+        Ok(self.PreviousTrack()?)
+    }
+
+    fn set_volume(&mut self, volume: LONG) -> Result<(), MpdError> {
+        Ok(self.set_SoundVolume(volume)?)
+    }
+
+    fn seek(&mut self, position: f64) -> Result<(), MpdError> {
+        Ok(self.set_PlayerPositionMS((position * 1000.0).round() as LONG)?)
+    }
+
+    fn playlists(&mut self) -> Result<Vec<String>, MpdError> {
+        let mut names = Vec::new();
+        for playlist in self.LibrarySource()?.Playlists()?.iter()? {
+            if playlist.Kind()? != ITPlaylistKind::ITPlaylistKindLibrary {
+                names.push(playlist.Name()?);
+            }
+        }
+        Ok(names)
+    }
+
+    fn playlist_tracks(&mut self, name: &str) -> Result<Vec<TrackSnapshot>, MpdError> {
+        let playlist = self.LibrarySource()?.Playlists()?.ItemByName(name)
+            .map_err(|_| MpdError::new(AckError::NoExist, "No such playlist"))?;
+        Ok(snapshot_tracks(&playlist.Tracks()?)?)
+    }
+
+    fn search(&mut self, query: &Query) -> Result<Vec<TrackSnapshot>, MpdError> {
+        Ok(query.run_on_playlist(&self.LibraryPlaylist()?)?)
+    }
+
+    /// The number of tracks of the library, which iTunes counts without reading them
+    fn database_version(&mut self) -> Result<u64, MpdError> {
+        Ok(self.LibraryPlaylist()?.Tracks()?.Count()? as u64)
+    }
+}
+
+/// A [`Backend`] that plays a library snapshot, without actually playing anything.
+///
+/// The queue is the whole library, in order of persistent IDs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SnapshotBackend {
+    pub library: LibrarySnapshot,
+    pub state: PlaybackState,
+    /// The current track, if any
+    pub current: Option<PersistentId>,
+    /// The position within the current track (in seconds)
+    pub elapsed: f64,
+    pub volume: LONG,
+}
+
+impl SnapshotBackend {
+    pub fn new(library: LibrarySnapshot) -> Self {
+        Self { library, state: PlaybackState::Stop, current: None, elapsed: 0.0, volume: 100 }
+    }
+
+    fn position_of(&self, id: PersistentId) -> Option<usize> {
+        self.library.tracks.keys().position(|key| *key == id)
+    }
+
+    fn go_to(&mut self, track: Option<PersistentId>) {
+        self.current = track;
+        self.elapsed = 0.0;
+        if self.current.is_none() {
+            self.state = PlaybackState::Stop;
+        }
+    }
+}
+
+impl Backend for SnapshotBackend {
+    fn status(&mut self) -> Result<Status, MpdError> {
+        let duration = self.current.and_then(|id| self.library.track(id)).map_or(0, |track| track.duration);
+        Ok(Status {
+            state: self.state,
+            volume: self.volume,
+            repeat: false,
+            random: false,
+            single: false,
+            queue_length: self.library.tracks.len(),
+            song: self.current.and_then(|id| self.position_of(id)),
+            current: self.current,
+            elapsed: self.elapsed,
+            duration: f64::from(duration),
+        })
+    }
+
+    fn current_song(&mut self) -> Result<Option<Song>, MpdError> {
+        Ok(self.current
+            .and_then(|id| self.library.track(id))
+            .map(|track| Song { track: track.clone(), position: self.position_of(track.persistent_id) }))
+    }
+
+    fn play(&mut self) -> Result<(), MpdError> {
+        if self.current.is_none() {
+            self.go_to(self.library.tracks.keys().next().copied());
+        }
+        if self.current.is_some() {
+            self.state = PlaybackState::Play;
+        }
+        Ok(())
+    }
+
+    fn play_at(&mut self, position: usize) -> Result<(), MpdError> {
+        let track = self.library.tracks.keys().nth(position).copied().ok_or_else(|| MpdError::arg("Bad song index"))?;
+        self.go_to(Some(track));
+        self.state = PlaybackState::Play;
+        Ok(())
+    }
+
+    fn pause(&mut self) -> Result<(), MpdError> {
+        if self.state == PlaybackState::Play {
+            self.state = PlaybackState::Pause;
+        }
+        Ok(())
+    }
+
+    fn play_pause(&mut self) -> Result<(), MpdError> {
+        match self.state {
+            PlaybackState::Play => self.pause(),
+            _ => self.play(),
+        }
+    }
+
+    fn stop(&mut self) -> Result<(), MpdError> {
+        self.state = PlaybackState::Stop;
+        self.elapsed = 0.0;
+        Ok(())
+    }
+
+    fn next_track(&mut self) -> Result<(), MpdError> {
+        self.go_to(skip(&self.library, self.current, true));
+        Ok(())
+    }
+
+    fn previous_track(&mut self) -> Result<(), MpdError> {
+        self.go_to(skip(&self.library, self.current, false));
+        Ok(())
+    }
+
+    fn set_volume(&mut self, volume: LONG) -> Result<(), MpdError> {
+        self.volume = volume;
+        Ok(())
+    }
+
+    fn seek(&mut self, position: f64) -> Result<(), MpdError> {
+        match self.current {
+            None => Err(MpdError::new(AckError::PlayerSync, "Not playing")),
+            Some(_) => {
+                self.elapsed = position;
+                Ok(())
+            },
+        }
+    }
+
+    fn playlists(&mut self) -> Result<Vec<String>, MpdError> {
+        Ok(self.library.playlists.iter().filter(|p| !p.is_folder).map(|p| p.name.clone()).collect())
+    }
+
+    fn playlist_tracks(&mut self, name: &str) -> Result<Vec<TrackSnapshot>, MpdError> {
+        let playlist = self.library.playlists.iter()
+            .find(|p| p.name == name)
+            .ok_or_else(|| MpdError::new(AckError::NoExist, "No such playlist"))?;
+        Ok(playlist.tracks.iter().filter_map(|id| self.library.track(*id)).cloned().collect())
+    }
+
+    fn search(&mut self, query: &Query) -> Result<Vec<TrackSnapshot>, MpdError> {
+        Ok(query.run(self.library.tracks.values()).into_iter().cloned().collect())
+    }
+
+    fn database_version(&mut self) -> Result<u64, MpdError> {
+        Ok(self.library.tracks.len() as u64)
+    }
+}
+
+/// Split a command line into arguments. Arguments may be double-quoted, with backslash escapes
+pub fn tokenize(line: &str) -> Result<Vec<String>, MpdError> {
+    let mut args = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let mut arg = String::new();
+        match chars.peek() {
+            None => return Ok(args),
+            Some('"') => {
+                chars.next();
+                loop {
+                    match chars.next() {
+                        None => return Err(MpdError::arg("Missing closing '\"'")),
+                        Some('"') => break,
+                        Some('\\') => arg.extend(chars.next()),
+                        Some(c) => arg.push(c),
+                    }
+                }
+            },
+            Some(_) => {
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    arg.push(c);
+                }
+            },
+        }
+        args.push(arg);
+    }
+}
+
+/// The field of an MPD tag (`any` is not a field)
+fn tag_field(tag: &str) -> Option<TrackField> {
+    match tag.to_ascii_lowercase().as_str() {
+        "artist" => Some(TrackField::Artist),
+        "artistsort" => Some(TrackField::SortArtist),
+        "albumartist" => Some(TrackField::AlbumArtist),
+        "albumartistsort" => Some(TrackField::SortAlbumArtist),
+        "album" => Some(TrackField::Album),
+        "albumsort" => Some(TrackField::SortAlbum),
+        "title" => Some(TrackField::Name),
+        "titlesort" => Some(TrackField::SortName),
+        "genre" => Some(TrackField::Genre),
+        "composer" => Some(TrackField::Composer),
+        "comment" => Some(TrackField::Comment),
+        "grouping" => Some(TrackField::Grouping),
+        "date" => Some(TrackField::Year),
+        "track" => Some(TrackField::TrackNumber),
+        "disc" => Some(TrackField::DiscNumber),
+        "file" => Some(TrackField::Location),
+        _ => None,
+    }
+}
+
+/// How a tag is compared with a value
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum TagOperator {
+    Equals,
+    Contains,
+    StartsWith,
+}
+
+/// A condition on a tag. Numbers (e.g. dates) are always compared for equality
+fn tag_filter(tag: &str, operator: TagOperator, value: &str) -> Result<Filter, MpdError> {
+    if tag.eq_ignore_ascii_case("any") {
+        let filters = [TrackField::Name, TrackField::Artist, TrackField::Album]
+            .into_iter()
+            .map(|f| tag_filter_on(f, operator, value))
+            .collect::<Result<Vec<_>, _>>()?;
+        return Ok(Filter::Or(filters));
+    }
+    let field = tag_field(tag).ok_or_else(|| MpdError::arg(format!("Unknown tag type: {}", tag)))?;
+    tag_filter_on(field, operator, value)
+}
+
+fn tag_filter_on(track_field: TrackField, operator: TagOperator, value: &str) -> Result<Filter, MpdError> {
+    match TrackSnapshot::default().get(track_field) {
+        FieldValue::Text(_) => Ok(match operator {
+            TagOperator::Equals => field(track_field).eq(value),
+            TagOperator::Contains => field(track_field).contains(value),
+            TagOperator::StartsWith => field(track_field).starts_with(value),
+        }),
+        _ => {
+            // Dates may be full dates (e.g. `1969-09-26`), of which only the year is kept
+            let digits: String = value.trim().chars().take_while(char::is_ascii_digit).collect();
+            let number: i64 = digits.parse().map_err(|_| MpdError::arg(format!("Expected a number for {}", track_field)))?;
+            Ok(field(track_field).eq(number))
+        },
+    }
+}
+
+/// A parser of filter expressions, e.g. `((artist == 'Queen') AND (!(album contains "live")))`
+struct ExpressionParser<'e> {
+    text: &'e str,
+    position: usize,
+}
+
+impl<'e> ExpressionParser<'e> {
+    fn rest(&self) -> &'e str {
+        &self.text[self.position..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), MpdError> {
+        self.skip_whitespace();
+        match self.rest().starts_with(token) {
+            true => {
+                self.position += token.len();
+                Ok(())
+            },
+            false => Err(MpdError::arg(format!("Expected '{}' in filter expression", token))),
+        }
+    }
+
+    fn word(&mut self) -> &'e str {
+        self.skip_whitespace();
+        let rest = self.rest();
+        let length = rest.find(|c: char| c.is_whitespace() || c == '(' || c == ')').unwrap_or(rest.len());
+        self.position += length;
+        &rest[..length]
+    }
+
+    fn quoted(&mut self) -> Result<String, MpdError> {
+        self.skip_whitespace();
+        let mut chars = self.rest().char_indices();
+        let quote = match chars.next() {
+            Some((_, c)) if c == '\'' || c == '"' => c,
+            _ => return Err(MpdError::arg("Expected a quoted value in filter expression")),
+        };
+        let mut value = String::new();
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '\\' => value.extend(chars.next().map(|(_, c)| c)),
+                c if c == quote => {
+                    self.position += index + c.len_utf8();
+                    return Ok(value);
+                },
+                c => value.push(c),
+            }
+        }
+        Err(MpdError::arg("Missing closing quote in filter expression"))
+    }
+
+    fn parse(mut self) -> Result<Filter, MpdError> {
+        let filter = self.expression()?;
+        self.skip_whitespace();
+        match self.rest().is_empty() {
+            true => Ok(filter),
+            false => Err(MpdError::arg("Unexpected text after filter expression")),
+        }
+    }
+
+    fn expression(&mut self) -> Result<Filter, MpdError> {
+        self.expect("(")?;
+        self.skip_whitespace();
+
+        let filter = if self.rest().starts_with('!') {
+            self.position += 1;
+            self.expression()?.not()
+        } else if self.rest().starts_with('(') {
+            let mut filter = self.expression()?;
+            loop {
+                self.skip_whitespace();
+                if !self.rest().starts_with("AND") {
+                    break;
+                }
+                self.position += "AND".len();
+                filter = filter.and(self.expression()?);
+            }
+            filter
+        } else {
+            let tag = self.word();
+            let operator = self.word();
+            let value = self.quoted()?;
+            match operator {
+                "==" => tag_filter(tag, TagOperator::Equals, &value)?,
+                "!=" => tag_filter(tag, TagOperator::Equals, &value)?.not(),
+                "contains" => tag_filter(tag, TagOperator::Contains, &value)?,
+                "starts_with" => tag_filter(tag, TagOperator::StartsWith, &value)?,
+                _ => return Err(MpdError::arg(format!("Unsupported filter operator: {}", operator))),
+            }
+        };
+
+        self.expect(")")?;
+        Ok(filter)
+    }
+}
+
+/// Build the query of a `find` (if `exact`) or `search` command
+pub fn parse_filter(args: &[String], exact: bool) -> Result<Query, MpdError> {
+    let filter = match args {
+        [] => return Err(MpdError::arg("Missing filter")),
+        [expression] if expression.trim_start().starts_with('(') => ExpressionParser { text: expression, position: 0 }.parse()?,
+        pairs if pairs.len() % 2 == 0 => {
+            let operator = if exact { TagOperator::Equals } else { TagOperator::Contains };
+            pairs.chunks(2).try_fold(Filter::All, |filter, pair| Ok::<_, MpdError>(filter.and(tag_filter(&pair[0], operator, &pair[1])?)))?
+        },
+        _ => return Err(MpdError::arg("Incorrect number of filter arguments")),
+    };
+    Ok(Query::new().filter(filter))
+}
+
+/// The `idle` subsystems this server reports
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Subsystem {
+    /// The player has been started, stopped or paused, or the current song has changed
+    Player,
+    /// The volume has changed
+    Mixer,
+    /// Tracks have been added to or removed from the library (see [`Backend::database_version`])
+    Database,
+}
+
+impl Subsystem {
+    pub const ALL: [Subsystem; 3] = [Subsystem::Player, Subsystem::Mixer, Subsystem::Database];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Subsystem::Player => "player",
+            Subsystem::Mixer => "mixer",
+            Subsystem::Database => "database",
+        }
+    }
+}
+
+/// Parse the arguments of `idle`. Valid MPD subsystems this server does not report are ignored
+pub fn parse_subsystems(args: &[String]) -> Result<Vec<Subsystem>, MpdError> {
+    if args.is_empty() {
+        return Ok(Subsystem::ALL.to_vec());
+    }
+    let mut subsystems = Vec::new();
+    for arg in args {
+        match Subsystem::ALL.iter().find(|s| s.name() == arg) {
+            Some(subsystem) => subsystems.push(*subsystem),
+            None if SILENT_SUBSYSTEMS.contains(&arg.as_str()) => (),
+            None => return Err(MpdError::arg(format!("Unrecognized idle event: {}", arg))),
+        }
+    }
+    Ok(subsystems)
+}
+
+/// What the `idle` subsystems watch
+#[derive(Clone, Debug, PartialEq)]
+pub struct IdleState {
+    pub player: (PlaybackState, Option<PersistentId>),
+    pub mixer: LONG,
+    pub database: u64,
+}
+
+impl IdleState {
+    pub fn from_backend<B: Backend + ?Sized>(backend: &mut B) -> Result<Self, MpdError> {
+        let status = backend.status()?;
+        Ok(Self { player: (status.state, status.current), mixer: status.volume, database: backend.database_version()? })
+    }
+
+    /// The subsystems that have changed since `previous`
+    pub fn changes_since(&self, previous: &IdleState) -> Vec<Subsystem> {
+        let mut changes = Vec::new();
+        if self.database != previous.database {
+            changes.push(Subsystem::Database);
+        }
+        if self.player != previous.player {
+            changes.push(Subsystem::Player);
+        }
+        if self.mixer != previous.mixer {
+            changes.push(Subsystem::Mixer);
+        }
+        changes
+    }
+}
+
+fn write_song(response: &mut String, track: &TrackSnapshot, position: Option<usize>) {
+    use std::fmt::Write;
+
+    let file = match &track.location {
+        Some(location) if !location.is_empty() => location.clone(),
+        _ => format_persistent_id(track.persistent_id),
+    };
+    // Writing to a `String` cannot fail
+    let _ = writeln!(response, "file: {}", file);
+    for (tag, value) in [("Title", &track.name), ("Artist", &track.artist), ("AlbumArtist", &track.album_artist), ("Album", &track.album), ("Genre", &track.genre), ("Composer", &track.composer)] {
+        if !value.is_empty() {
+            let _ = writeln!(response, "{}: {}", tag, value);
+        }
+    }
+    for (tag, value) in [("Date", track.year), ("Track", track.track_number), ("Disc", track.disc_number)] {
+        if value > 0 {
+            let _ = writeln!(response, "{}: {}", tag, value);
+        }
+    }
+    let _ = writeln!(response, "Time: {}", track.duration);
+    let _ = writeln!(response, "duration: {:.3}", f64::from(track.duration));
+    if let Some(position) = position {
+        let _ = writeln!(response, "Pos: {}\nId: {}", position, position);
+    }
+}
+
+fn write_status(response: &mut String, status: &Status) {
+    use std::fmt::Write;
+
+    let _ = writeln!(response, "volume: {}", status.volume);
+    let _ = writeln!(response, "repeat: {}", status.repeat as u8);
+    let _ = writeln!(response, "random: {}", status.random as u8);
+    let _ = writeln!(response, "single: {}", status.single as u8);
+    let _ = writeln!(response, "consume: 0");
+    let _ = writeln!(response, "playlist: 1");
+    let _ = writeln!(response, "playlistlength: {}", status.queue_length);
+    let _ = writeln!(response, "state: {}", status.state.name());
+    if let Some(song) = status.song {
+        let _ = writeln!(response, "song: {}\nsongid: {}", song, song);
+    }
+    if status.state != PlaybackState::Stop {
+        let _ = writeln!(response, "time: {}:{}", status.elapsed.round() as i64, status.duration.round() as i64);
+        let _ = writeln!(response, "elapsed: {:.3}", status.elapsed);
+        let _ = writeln!(response, "duration: {:.3}", status.duration);
+    }
+}
+
+fn arg_count(args: &[String], min: usize, max: usize) -> Result<(), MpdError> {
+    match (min..=max).contains(&(args.len() - 1)) {
+        true => Ok(()),
+        false => Err(MpdError::arg(format!("Wrong number of arguments for \"{}\"", args[0]))),
+    }
+}
+
+/// Run a single command (already split into arguments), and return its response, without the final `OK`
+pub fn execute<B: Backend + ?Sized>(backend: &mut B, args: &[String]) -> Result<String, MpdError> {
+    let mut response = String::new();
+    let command = match args.first() {
+        None => return Err(MpdError::new(AckError::Unknown, "No command given")),
+        Some(command) => command.as_str(),
+    };
+
+    match command {
+        "ping" => arg_count(args, 0, 0)?,
+        "status" => {
+            arg_count(args, 0, 0)?;
+            write_status(&mut response, &backend.status()?);
+        },
+        "currentsong" => {
+            arg_count(args, 0, 0)?;
+            if let Some(song) = backend.current_song()? {
+                write_song(&mut response, &song.track, song.position);
+            }
+        },
+        "play" => {
+            arg_count(args, 0, 1)?;
+            match args.get(1) {
+                None => backend.play()?,
+                Some(position) => backend.play_at(position.parse().map_err(|_| MpdError::arg("Bad song index"))?)?,
+            }
+        },
+        "pause" => {
+            arg_count(args, 0, 1)?;
+            match args.get(1).map(String::as_str) {
+                None => backend.play_pause()?,
+                Some("1") => backend.pause()?,
+                Some("0") => backend.play()?,
+                Some(_) => return Err(MpdError::arg("Boolean (0/1) expected")),
+            }
+        },
+        "stop" => {
+            arg_count(args, 0, 0)?;
+            backend.stop()?;
+        },
+        "next" => {
+            arg_count(args, 0, 0)?;
+            backend.next_track()?;
+        },
+        "previous" => {
+            arg_count(args, 0, 0)?;
+            backend.previous_track()?;
+        },
+        "setvol" => {
+            arg_count(args, 1, 1)?;
+            let volume: LONG = args[1].parse().ok()
+                .filter(|volume| (0..=100).contains(volume))
+                .ok_or_else(|| MpdError::arg("Invalid volume value"))?;
+            backend.set_volume(volume)?;
+        },
+        "seekcur" => {
+            arg_count(args, 1, 1)?;
+            let text = args[1].as_str();
+            let offset: f64 = text.parse().ok()
+                .filter(|offset: &f64| offset.is_finite())
+                .ok_or_else(|| MpdError::arg("Float expected"))?;
+            let target = match text.starts_with('+') || text.starts_with('-') {
+                true => backend.status()?.elapsed + offset,
+                false => offset,
+            };
+            backend.seek(target.max(0.0))?;
+        },
+        "listplaylists" => {
+            arg_count(args, 0, 0)?;
+            for name in backend.playlists()? {
+                response.push_str(&format!("playlist: {}\nLast-Modified: 1970-01-01T00:00:00Z\n", name));
+            }
+        },
+        "listplaylistinfo" => {
+            arg_count(args, 1, 1)?;
+            for track in backend.playlist_tracks(&args[1])? {
+                write_song(&mut response, &track, None);
+            }
+        },
+        "find" | "search" => {
+            let query = parse_filter(&args[1..], command == "find")?;
+            for track in backend.search(&query)? {
+                write_song(&mut response, &track, None);
+            }
+        },
+        _ => return Err(MpdError::new(AckError::Unknown, format!("unknown command \"{}\"", command))),
+    }
+    Ok(response)
+}
+
+/// Run a command, or a command list, and return the whole response (ending with `OK`, or with an `ACK` line at the first error)
+///
+/// With `list_ok`, every successful command of the list is followed by `list_OK`.
+pub fn handle_commands<B: Backend + ?Sized>(backend: &mut B, lines: &[String], list_ok: bool) -> String {
+    let mut response = String::new();
+    for (index, line) in lines.iter().enumerate() {
+        let args = tokenize(line);
+        let command = args.as_ref().ok().and_then(|args| args.first().cloned()).unwrap_or_default();
+        match args.and_then(|args| execute(backend, &args)) {
+            Ok(output) => {
+                response.push_str(&output);
+                if list_ok {
+                    response.push_str("list_OK\n");
+                }
+            },
+            Err(err) => {
+                response.push_str(&format!("ACK [{}@{}] {{{}}} {}\n", err.code as u32, index, command, err.message));
+                return response;
+            },
+        }
+    }
+    response.push_str("OK\n");
+    response
+}
+
+/// What connection threads send to the thread that runs [`Server::run`]
+enum ServerMessage {
+    Commands { connection: u64, lines: Vec<String>, list_ok: bool, response: Sender<String> },
+    Idle { connection: u64, subsystems: Vec<Subsystem>, events: Sender<ConnectionEvent> },
+    NoIdle { connection: u64 },
+    Closed { connection: u64 },
+}
+
+/// What a connection thread waits for
+enum ConnectionEvent {
+    Line(String),
+    /// The end of an `idle` command, with the subsystems that have changed (none after `noidle`)
+    Changed(Vec<Subsystem>),
+    Closed,
+}
+
+struct IdleWaiter {
+    connection: u64,
+    subsystems: Vec<Subsystem>,
+    events: Sender<ConnectionEvent>,
+}
+
+/// An MPD server.
+///
+/// Connections are handled by background threads, that hand every command over to the thread that calls [`Server::run`].
+pub struct Server {
+    sender: Sender<ServerMessage>,
+    receiver: Receiver<ServerMessage>,
+    acceptor: Acceptor,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Server {
+    pub fn new() -> Self {
+        let (sender, receiver) = channel();
+        Self { sender, receiver, acceptor: Acceptor::new() }
+    }
+
+    /// Accept connections on an address (MPD usually listens on port 6600), until this server is dropped.
+    ///
+    /// This returns the actual address, which is useful when binding port 0.
+    pub fn listen<A: ToSocketAddrs>(&self, address: A) -> std::io::Result<SocketAddr> {
+        let sender = self.sender.clone();
+        self.acceptor.listen(address, move |stream, connection| {
+            let result = serve_connection(stream, connection, &sender);
+            let _ = sender.send(ServerMessage::Closed { connection });
+            result
+        })
+    }
+
+    /// Serve commands with `backend` on the current thread, until `keep_running` returns `false`.
+    ///
+    /// `keep_running` is called after every command, and at least every `poll_interval`. Clients that are idle are notified of changes
+    /// that happened since the end of their previous command, with a delay of at most `poll_interval`.
+    pub fn run<B, F>(&self, backend: &mut B, poll_interval: Duration, mut keep_running: F)
+    where B: Backend + ?Sized, F: FnMut() -> bool
+    {
+        let mut baselines: HashMap<u64, IdleState> = HashMap::new();
+        let mut waiters: Vec<IdleWaiter> = Vec::new();
+
+        while keep_running() {
+            match self.receiver.recv_timeout(poll_interval) {
+                Ok(ServerMessage::Commands { connection, lines, list_ok, response }) => {
+                    let _ = response.send(handle_commands(backend, &lines, list_ok));
+                    // The changes made by this command are reported to other clients only
+                    if let Ok(state) = IdleState::from_backend(backend) {
+                        baselines.insert(connection, state);
+                    }
+                },
+                Ok(ServerMessage::Idle { connection, subsystems, events }) => {
+                    if let Entry::Vacant(entry) = baselines.entry(connection) {
+                        if let Ok(state) = IdleState::from_backend(backend) {
+                            entry.insert(state);
+                        }
+                    }
+                    waiters.push(IdleWaiter { connection, subsystems, events });
+                },
+                Ok(ServerMessage::NoIdle { connection }) => {
+                    for waiter in waiters.iter().filter(|w| w.connection == connection) {
+                        let _ = waiter.events.send(ConnectionEvent::Changed(Vec::new()));
+                    }
+                    waiters.retain(|w| w.connection != connection);
+                },
+                Ok(ServerMessage::Closed { connection }) => {
+                    waiters.retain(|w| w.connection != connection);
+                    baselines.remove(&connection);
+                },
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+
+            if waiters.is_empty() {
+                continue;
+            }
+            let state = match IdleState::from_backend(backend) {
+                Ok(state) => state,
+                Err(_) => continue,
+            };
+            waiters.retain(|waiter| {
+                let changes: Vec<Subsystem> = match baselines.get(&waiter.connection) {
+                    None => Vec::new(),
+                    Some(baseline) => state.changes_since(baseline).into_iter().filter(|s| waiter.subsystems.contains(s)).collect(),
+                };
+                if changes.is_empty() {
+                    return true;
+                }
+                let _ = waiter.events.send(ConnectionEvent::Changed(changes));
+                baselines.insert(waiter.connection, state.clone());
+                false
+            });
+        }
+    }
+}
+
+fn serve_connection(stream: TcpStream, connection: u64, sender: &Sender<ServerMessage>) -> std::io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    writer.write_all(GREETING.as_bytes())?;
+
+    // Lines are read by another thread, so that `noidle` can be received while waiting for changes
+    let (events_sender, events) = channel();
+    let lines_sender = events_sender.clone();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stream).lines() {
+            match line {
+                Ok(line) => if lines_sender.send(ConnectionEvent::Line(line)).is_err() { return },
+                Err(_) => break,
+            }
+        }
+        let _ = lines_sender.send(ConnectionEvent::Closed);
+    });
+
+    let mut command_list: Option<(bool, Vec<String>)> = None;
+    let mut idling = false;
+    let run_commands = |lines: Vec<String>, list_ok: bool| -> std::io::Result<String> {
+        let (response_sender, response) = channel();
+        sender.send(ServerMessage::Commands { connection, lines, list_ok, response: response_sender }).map_err(|_| server_stopped())?;
+        response.recv().map_err(|_| server_stopped())
+    };
+
+    for event in events {
+        let line = match event {
+            ConnectionEvent::Closed => break,
+            ConnectionEvent::Changed(subsystems) => {
+                if idling {
+                    for subsystem in subsystems {
+                        writeln!(writer, "changed: {}", subsystem.name())?;
+                    }
+                    writer.write_all(b"OK\n")?;
+                    idling = false;
+                }
+                continue;
+            },
+            ConnectionEvent::Line(line) => line,
+        };
+        let trimmed = line.trim();
+
+        if idling {
+            // Any other command than `noidle` is a protocol error, which closes the connection
+            match trimmed {
+                "noidle" => sender.send(ServerMessage::NoIdle { connection }).map_err(|_| server_stopped())?,
+                _ => break,
+            }
+            continue;
+        }
+
+        match (&mut command_list, trimmed) {
+            (None, "command_list_begin") => command_list = Some((false, Vec::new())),
+            (None, "command_list_ok_begin") => command_list = Some((true, Vec::new())),
+            (Some(_), "command_list_end") => {
+                if let Some((list_ok, lines)) = command_list.take() {
+                    writer.write_all(run_commands(lines, list_ok)?.as_bytes())?;
+                }
+            },
+            (Some((_, lines)), _) => lines.push(line),
+            (None, "close") => break,
+            (None, "noidle") => (),
+            (None, _) => {
+                let args = tokenize(trimmed).unwrap_or_default();
+                if args.first().map(String::as_str) == Some("idle") {
+                    match parse_subsystems(&args[1..]) {
+                        Ok(subsystems) => {
+                            sender.send(ServerMessage::Idle { connection, subsystems, events: events_sender.clone() }).map_err(|_| server_stopped())?;
+                            idling = true;
+                        },
+                        Err(err) => writeln!(writer, "ACK [{}@0] {{idle}} {}", err.code as u32, err.message)?,
+                    }
+                } else {
+                    writer.write_all(run_commands(vec![line], false)?.as_bytes())?;
+                }
+            },
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,99 @@
+//! Finder for media files that are not in the library
+//!
+//! This is the reverse of [`find_dead_tracks`](super::relocate::find_dead_tracks): [`find_orphans`] lists the files of a media folder that no track points to,
+//! and [`OrphanReport::import`] adds them to the library.
+//!
+//! Paths are compared with [`windows_path_key`], so that differences in case, separators or Unicode normalization do not matter.
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use super::iTunes;
+use super::{OperationStatus, UserPlaylist};
+use super::files::{MediaFile, walk_media_files, windows_path_key};
+use super::library::TrackSnapshot;
+use super::types::StringArray;
+
+/// Media files that are not in the library, grouped by folder
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct OrphanReport {
+    pub folders: BTreeMap<PathBuf, Vec<MediaFile>>,
+}
+
+/// List the `files` that no track points to
+pub fn find_orphans<'a, I>(files: &[MediaFile], tracks: I) -> OrphanReport
+where I: IntoIterator<Item = &'a TrackSnapshot>
+{
+    let known: HashSet<String> = tracks.into_iter()
+        .filter_map(|t| t.location.as_deref())
+        .filter(|l| !l.is_empty())
+        .map(windows_path_key)
+        .collect();
+
+    let mut report = OrphanReport::default();
+    for file in files {
+        if known.contains(&windows_path_key(&file.path.to_string_lossy())) {
+            continue;
+        }
+        let folder = file.path.parent().map(Path::to_path_buf).unwrap_or_default();
+        report.folders.entry(folder).or_default().push(file.clone());
+    }
+    report
+}
+
+/// Walk a media folder (see [`walk_media_files`]), and list the files that no track points to
+pub fn scan_orphans<'a, P, I>(root: P, extensions: &[&str], tracks: I) -> std::io::Result<OrphanReport>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = &'a TrackSnapshot>,
+{
+    let files = walk_media_files(root, extensions)?;
+    Ok(find_orphans(&files, tracks))
+}
+
+impl OrphanReport {
+    pub fn is_empty(&self) -> bool {
+        self.folders.is_empty()
+    }
+
+    pub fn file_count(&self) -> usize {
+        self.folders.values().map(Vec::len).sum()
+    }
+
+    /// Every orphan file, sorted by folder
+    pub fn paths(&self) -> Vec<&Path> {
+        self.folders.values().flatten().map(|f| f.path.as_path()).collect()
+    }
+
+    /// Add every orphan file to the library, and to `playlist` if one is given.
+    ///
+    /// iTunes imports the files asynchronously: the returned status tells when the import is over, and which tracks have been created.
+    /// `None` is returned when there is nothing to import.
+    pub fn import(&self, iTunes: &iTunes, playlist: Option<&UserPlaylist>) -> windows::core::Result<Option<OperationStatus>> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+
+        let paths: Vec<String> = self.paths().iter().map(|p| p.to_string_lossy().into_owned()).collect();
+        let array = StringArray::new(&paths)?;
+        let status = match playlist {
+            Some(playlist) => playlist.AddFiles(&array.as_variant())?,
+            None => iTunes.LibraryPlaylist()?.AddFiles(&array.as_variant())?,
+        };
+        Ok(Some(status))
+    }
+}
+
+impl std::fmt::Display for OrphanReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} file(s) not in the library, in {} folder(s)", self.file_count(), self.folders.len())?;
+        for (folder, files) in &self.folders {
+            writeln!(f, "  {} ({})", folder.display(), files.len())?;
+            for file in files {
+                let name = file.path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+                writeln!(f, "      {}", name)?;
+            }
+        }
+        Ok(())
+    }
+}
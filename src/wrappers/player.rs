@@ -0,0 +1,112 @@
+//! Sampling of the player state
+//!
+//! iTunes notifies COM clients about play/stop/track-changed events through connection points, that this crate does not subscribe to (yet?).<br/>
+//! Instead, this module takes periodic [`PlayerSample`]s, and infers the same events by comparing two consecutive samples (see [`PlayerSample::events_since`]).
+//!
+//! Samples are plain data, so that anything built on top of them can be tested with synthetic timelines.
+
+use std::time::SystemTime;
+
+use crate::sys::ITPlayerState;
+use super::{iTunes, IITObjectWrapper, IITTrackWrapper};
+use super::types::PersistentId;
+use super::LONG;
+
+/// The track loaded in the player when a [`PlayerSample`] was taken
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PlayingTrack {
+    pub persistent_id: PersistentId,
+    pub name: String,
+    pub artist: String,
+    pub album: String,
+    /// The index of the track on the source album (0 if unknown)
+    pub track_number: LONG,
+    /// The length of the track (in seconds)
+    pub duration: LONG,
+}
+
+impl PlayingTrack {
+    /// Read the info of a live track
+    pub fn from_track<T: IITTrackWrapper + IITObjectWrapper>(track: &T) -> windows::core::Result<Self> {
+        Ok(Self {
+            persistent_id: track.persistent_id()?,
+            name: track.Name()?,
+            artist: track.Artist()?,
+            album: track.Album()?,
+            track_number: track.TrackNumber()?,
+            duration: track.Duration()?,
+        })
+    }
+}
+
+/// The state of the player at a given time
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PlayerSample {
+    /// When this sample has been taken
+    pub time: SystemTime,
+    /// Whether the player is playing (fast forwarding and rewinding count as playing).<br/>
+    /// iTunes reports paused tracks as stopped.
+    pub playing: bool,
+    /// The currently targeted track, if any
+    pub track: Option<PlayingTrack>,
+    /// The player's position within the current track (in seconds)
+    pub position: LONG,
+}
+
+/// An event inferred from two consecutive [`PlayerSample`]s
+///
+/// These mimic the matching [`ITEvent`](crate::sys::ITEvent)s
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PlayerEvent {
+    /// A track has started playing
+    Play(PlayingTrack),
+    /// The player has stopped (or has been paused)
+    Stop,
+    /// The currently targeted track has changed
+    PlayingTrackChanged(Option<PlayingTrack>),
+}
+
+impl PlayerSample {
+    /// Sample the current state of the player
+    pub fn from_iTunes(iTunes: &iTunes) -> windows::core::Result<Self> {
+        let time = SystemTime::now();
+        let playing = iTunes.PlayerState()? != ITPlayerState::ITPlayerStateStopped;
+
+        // There is no current track when iTunes has nothing to play
+        let track = match iTunes.CurrentTrack() {
+            Ok(track) => Some(PlayingTrack::from_track(&track)?),
+            Err(_) => None,
+        };
+        let position = match track {
+            Some(_) => iTunes.PlayerPosition()?,
+            None => 0,
+        };
+
+        Ok(Self { time, playing, track, position })
+    }
+
+    /// The persistent ID of the current track, if any
+    pub fn persistent_id(&self) -> Option<PersistentId> {
+        self.track.as_ref().map(|t| t.persistent_id)
+    }
+
+    /// List the events that happened between `previous` and this sample
+    pub fn events_since(&self, previous: &PlayerSample) -> Vec<PlayerEvent> {
+        let mut events = Vec::new();
+        let track_changed = self.persistent_id() != previous.persistent_id();
+
+        if previous.playing && (!self.playing || track_changed) {
+            events.push(PlayerEvent::Stop);
+        }
+        if track_changed {
+            events.push(PlayerEvent::PlayingTrackChanged(self.track.clone()));
+        }
+        if self.playing && (!previous.playing || track_changed) {
+            if let Some(track) = &self.track {
+                events.push(PlayerEvent::Play(track.clone()));
+            }
+        }
+
+        events
+    }
+}
@@ -0,0 +1,124 @@
+//! Set operations over playlists
+//!
+//! Playlists are compared by the persistent IDs of their tracks. Results are lists of persistent IDs, that can be ordered (see [`SetOrder`])
+//! and saved as a static playlist (see [`save_combination`]).
+//!
+//! Operations work the same on snapshots (e.g. from a [`LibrarySnapshot`]) and on live playlists.
+
+use std::collections::{HashMap, HashSet};
+
+use super::{iTunes, UserPlaylist, IITPlaylistWrapper};
+use super::library::{LibrarySnapshot, PlaylistSnapshot, TrackSnapshot};
+use super::materialize::{MaterializeReport, materialize};
+use super::query::{Query, SortKey};
+use super::types::PersistentId;
+
+/// A set operation
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SetOperation {
+    /// Tracks that are in any operand
+    Union,
+    /// Tracks that are in every operand
+    Intersection,
+    /// Tracks of the first operand that are in none of the other ones
+    Difference,
+    /// Tracks that are in an odd number of operands (i.e. in exactly one of two operands)
+    SymmetricDifference,
+}
+
+/// How to order the result of a set operation
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SetOrder {
+    /// The order in which tracks first appear in the operands (the first operand first)
+    FirstAppearance,
+    /// By increasing persistent ID
+    PersistentId,
+    /// By track fields. Ties keep the order of first appearance
+    Sorted(Vec<SortKey>),
+}
+
+/// Apply a set operation on track lists. The result is in the order of first appearance, without duplicates
+pub fn combine(operation: SetOperation, operands: &[&[PersistentId]]) -> Vec<PersistentId> {
+    let mut counts: HashMap<PersistentId, usize> = HashMap::new();
+    let mut appearance = Vec::new();
+    for operand in operands {
+        let unique: HashSet<PersistentId> = operand.iter().copied().collect();
+        for id in operand.iter() {
+            if !counts.contains_key(id) {
+                appearance.push(*id);
+            }
+            counts.entry(*id).or_default();
+        }
+        for id in unique {
+            *counts.entry(id).or_default() += 1;
+        }
+    }
+
+    let first: HashSet<PersistentId> = operands.first().map(|o| o.iter().copied().collect()).unwrap_or_default();
+    appearance.into_iter()
+        .filter(|id| match operation {
+            SetOperation::Union => true,
+            SetOperation::Intersection => counts[id] == operands.len(),
+            SetOperation::Difference => first.contains(id) && counts[id] == 1,
+            SetOperation::SymmetricDifference => counts[id] % 2 == 1,
+        })
+        .collect()
+}
+
+/// Order a track list. `lookup` gives the info of tracks, which is only needed for [`SetOrder::Sorted`] (tracks it does not know about are put last)
+pub fn order_tracks<'a, F>(tracks: Vec<PersistentId>, order: &SetOrder, lookup: F) -> Vec<PersistentId>
+where F: Fn(PersistentId) -> Option<&'a TrackSnapshot>
+{
+    match order {
+        SetOrder::FirstAppearance => tracks,
+        SetOrder::PersistentId => {
+            let mut tracks = tracks;
+            tracks.sort_unstable();
+            tracks
+        },
+        SetOrder::Sorted(keys) => {
+            let query = Query { sort: keys.clone(), ..Query::default() };
+            let mut known: Vec<&TrackSnapshot> = Vec::new();
+            let mut unknown = Vec::new();
+            for id in tracks {
+                match lookup(id) {
+                    Some(track) => known.push(track),
+                    None => unknown.push(id),
+                }
+            }
+            known.sort_by(|a, b| query.compare(a, b));
+            known.into_iter().map(|t| t.persistent_id).chain(unknown).collect()
+        },
+    }
+}
+
+/// Apply a set operation on playlist snapshots
+pub fn combine_snapshots(operation: SetOperation, operands: &[&PlaylistSnapshot], order: &SetOrder, library: &LibrarySnapshot) -> Vec<PersistentId> {
+    let lists: Vec<&[PersistentId]> = operands.iter().map(|p| p.tracks.as_slice()).collect();
+    order_tracks(combine(operation, &lists), order, |id| library.track(id))
+}
+
+/// Apply a set operation on live playlists.
+///
+/// With [`SetOrder::Sorted`], only the tracks of the result are read from iTunes.
+pub fn combine_playlists<P: IITPlaylistWrapper>(iTunes: &iTunes, operation: SetOperation, operands: &[&P], order: &SetOrder) -> windows::core::Result<Vec<PersistentId>> {
+    let lists = operands.iter().map(|p| p.track_ids()).collect::<windows::core::Result<Vec<_>>>()?;
+    let slices: Vec<&[PersistentId]> = lists.iter().map(Vec::as_slice).collect();
+    let result = combine(operation, &slices);
+
+    let mut snapshots = HashMap::new();
+    if let SetOrder::Sorted(_) = order {
+        let library_tracks = iTunes.LibraryPlaylist()?.Tracks()?;
+        for id in &result {
+            let track = library_tracks.ItemByPersistentID(*id)?;
+            snapshots.insert(*id, TrackSnapshot::from_track(&track)?);
+        }
+    }
+    Ok(order_tracks(result, order, |id| snapshots.get(&id)))
+}
+
+/// Apply a set operation on live playlists, and save the result as the static playlist `name` (see [`materialize`])
+pub fn save_combination<P: IITPlaylistWrapper>(iTunes: &iTunes, operation: SetOperation, operands: &[&P], order: &SetOrder, name: &str, folder: Option<&UserPlaylist>) -> windows::core::Result<MaterializeReport> {
+    let tracks = combine_playlists(iTunes, operation, operands, order)?;
+    materialize(iTunes, name, folder, &tracks)
+}
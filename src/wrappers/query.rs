@@ -0,0 +1,308 @@
+//! Typed queries over tracks
+//!
+//! [`IITPlaylistWrapper::Search`] only searches a text in one kind of field. A [`Query`] combines conditions on any [`TrackField`], sorts on several keys, and limits the result:
+//! ```no_run
+//! # use itunes_com::wrappers::library::TrackField::*;
+//! # use itunes_com::wrappers::query::{Query, field};
+//! let query = Query::new()
+//!     .filter(field(Artist).contains("beatles").and(field(Year).between(1965, 1969)).and(field(Rating).gte(80)))
+//!     .sort_by_desc(PlayedCount)
+//!     .sort_by(Name)
+//!     .limit(50);
+//! ```
+//!
+//! Queries are evaluated on [`TrackSnapshot`]s. When a query runs on a live playlist (see [`Query::run_on_playlist`]), a text condition is passed to `Search`
+//! first, so that only the matching tracks have to be read from iTunes.
+
+use std::cmp::Ordering;
+
+use crate::sys::ITPlaylistSearchField;
+use super::{TrackCollection, IITPlaylistWrapper};
+use super::library::{TrackSnapshot, TrackField, FieldValue, snapshot_tracks, normalize_text};
+
+/// How a field is compared with a value
+#[derive(Clone, Debug, PartialEq)]
+pub enum Comparison {
+    /// The text contains this text (case-insensitively)
+    Contains(String),
+    StartsWith(String),
+    EndsWith(String),
+    /// Texts are compared case-insensitively
+    Equals(FieldValue),
+    LessThan(FieldValue),
+    LessOrEqual(FieldValue),
+    GreaterThan(FieldValue),
+    GreaterOrEqual(FieldValue),
+    /// Both bounds are included
+    Between(FieldValue, FieldValue),
+}
+
+/// A condition on tracks
+#[derive(Clone, Debug, PartialEq)]
+pub enum Filter {
+    /// Matches every track
+    All,
+    Field(TrackField, Comparison),
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+}
+
+/// Start a condition on a field, e.g. `field(TrackField::Artist).contains("beatles")`
+pub fn field(field: TrackField) -> FieldFilter {
+    FieldFilter { field }
+}
+
+/// A field, waiting for a comparison to become a [`Filter`]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FieldFilter {
+    field: TrackField,
+}
+
+impl FieldFilter {
+    pub fn contains(self, text: &str) -> Filter {
+        Filter::Field(self.field, Comparison::Contains(text.to_string()))
+    }
+
+    pub fn starts_with(self, text: &str) -> Filter {
+        Filter::Field(self.field, Comparison::StartsWith(text.to_string()))
+    }
+
+    pub fn ends_with(self, text: &str) -> Filter {
+        Filter::Field(self.field, Comparison::EndsWith(text.to_string()))
+    }
+
+    pub fn eq<V: Into<FieldValue>>(self, value: V) -> Filter {
+        Filter::Field(self.field, Comparison::Equals(value.into()))
+    }
+
+    pub fn lt<V: Into<FieldValue>>(self, value: V) -> Filter {
+        Filter::Field(self.field, Comparison::LessThan(value.into()))
+    }
+
+    pub fn lte<V: Into<FieldValue>>(self, value: V) -> Filter {
+        Filter::Field(self.field, Comparison::LessOrEqual(value.into()))
+    }
+
+    pub fn gt<V: Into<FieldValue>>(self, value: V) -> Filter {
+        Filter::Field(self.field, Comparison::GreaterThan(value.into()))
+    }
+
+    pub fn gte<V: Into<FieldValue>>(self, value: V) -> Filter {
+        Filter::Field(self.field, Comparison::GreaterOrEqual(value.into()))
+    }
+
+    pub fn between<V: Into<FieldValue>>(self, min: V, max: V) -> Filter {
+        Filter::Field(self.field, Comparison::Between(min.into(), max.into()))
+    }
+}
+
+/// Compare two values of the same type. Texts are compared case-insensitively
+fn compare_values(a: &FieldValue, b: &FieldValue) -> Option<Ordering> {
+    match (a, b) {
+        (FieldValue::Text(a), FieldValue::Text(b)) => Some(normalize_text(a).cmp(&normalize_text(b))),
+        (FieldValue::Integer(a), FieldValue::Integer(b)) => Some(a.cmp(b)),
+        (FieldValue::Bool(a), FieldValue::Bool(b)) => Some(a.cmp(b)),
+        (FieldValue::Date(a), FieldValue::Date(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+impl Comparison {
+    /// Whether a value satisfies this comparison. Values of a different type never do
+    pub fn matches(&self, value: &FieldValue) -> bool {
+        let text = || value.as_text().map(normalize_text);
+        match self {
+            Comparison::Contains(t) => text().is_some_and(|v| v.contains(&normalize_text(t))),
+            Comparison::StartsWith(t) => text().is_some_and(|v| v.starts_with(&normalize_text(t))),
+            Comparison::EndsWith(t) => text().is_some_and(|v| v.ends_with(&normalize_text(t))),
+            Comparison::Equals(other) => compare_values(value, other) == Some(Ordering::Equal),
+            Comparison::LessThan(other) => compare_values(value, other) == Some(Ordering::Less),
+            Comparison::LessOrEqual(other) => matches!(compare_values(value, other), Some(Ordering::Less | Ordering::Equal)),
+            Comparison::GreaterThan(other) => compare_values(value, other) == Some(Ordering::Greater),
+            Comparison::GreaterOrEqual(other) => matches!(compare_values(value, other), Some(Ordering::Greater | Ordering::Equal)),
+            Comparison::Between(min, max) => {
+                matches!(compare_values(value, min), Some(Ordering::Greater | Ordering::Equal))
+                    && matches!(compare_values(value, max), Some(Ordering::Less | Ordering::Equal))
+            },
+        }
+    }
+}
+
+impl Filter {
+    /// Both conditions must match
+    pub fn and(self, other: Filter) -> Filter {
+        match self {
+            Filter::And(mut filters) => {
+                filters.push(other);
+                Filter::And(filters)
+            },
+            Filter::All => other,
+            filter => Filter::And(vec![filter, other]),
+        }
+    }
+
+    /// Any of the conditions must match
+    pub fn or(self, other: Filter) -> Filter {
+        match self {
+            Filter::Or(mut filters) => {
+                filters.push(other);
+                Filter::Or(filters)
+            },
+            filter => Filter::Or(vec![filter, other]),
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(self) -> Filter {
+        Filter::Not(Box::new(self))
+    }
+
+    pub fn matches(&self, track: &TrackSnapshot) -> bool {
+        match self {
+            Filter::All => true,
+            Filter::Field(field, comparison) => comparison.matches(&track.get(*field)),
+            Filter::And(filters) => filters.iter().all(|f| f.matches(track)),
+            Filter::Or(filters) => filters.iter().any(|f| f.matches(track)),
+            Filter::Not(filter) => !filter.matches(track),
+        }
+    }
+
+    /// A text that every matching track must contain in a field `Search` knows about, if any.
+    ///
+    /// The longest such text is returned, as it is likely to be the most selective.
+    fn search_hint(&self) -> Option<(&str, TrackField)> {
+        match self {
+            Filter::Field(field, comparison) if search_field(*field).is_some() => match comparison {
+                Comparison::Contains(t) | Comparison::StartsWith(t) | Comparison::EndsWith(t) => Some((t.as_str(), *field)),
+                Comparison::Equals(FieldValue::Text(t)) => Some((t.as_str(), *field)),
+                _ => None,
+            },
+            Filter::And(filters) => filters.iter().filter_map(Filter::search_hint).max_by_key(|(t, _)| t.len()),
+            _ => None,
+        }
+        .filter(|(t, _)| !t.trim().is_empty())
+    }
+}
+
+/// The `Search` kind that matches a field
+fn search_field(field: TrackField) -> Option<ITPlaylistSearchField> {
+    match field {
+        TrackField::Artist => Some(ITPlaylistSearchField::ITPlaylistSearchFieldArtists),
+        TrackField::Album => Some(ITPlaylistSearchField::ITPlaylistSearchFieldAlbums),
+        TrackField::Composer => Some(ITPlaylistSearchField::ITPlaylistSearchFieldComposers),
+        TrackField::Name => Some(ITPlaylistSearchField::ITPlaylistSearchFieldSongNames),
+        _ => None,
+    }
+}
+
+/// A sort criterion
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SortKey {
+    pub field: TrackField,
+    pub descending: bool,
+}
+
+/// A filter, a sort order and a limit
+#[derive(Clone, Debug, PartialEq)]
+pub struct Query {
+    pub filter: Filter,
+    /// Tracks are sorted by the first key, then by the second one in case of equality, etc.
+    pub sort: Vec<SortKey>,
+    pub limit: Option<usize>,
+}
+
+impl Default for Query {
+    fn default() -> Self {
+        Self { filter: Filter::All, sort: Vec::new(), limit: None }
+    }
+}
+
+impl Query {
+    /// A query that returns every track
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a condition (in addition to the existing ones)
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filter = std::mem::replace(&mut self.filter, Filter::All).and(filter);
+        self
+    }
+
+    /// Add an ascending sort key (with a lower priority than the existing ones)
+    pub fn sort_by(mut self, field: TrackField) -> Self {
+        self.sort.push(SortKey { field, descending: false });
+        self
+    }
+
+    /// Add a descending sort key (with a lower priority than the existing ones)
+    pub fn sort_by_desc(mut self, field: TrackField) -> Self {
+        self.sort.push(SortKey { field, descending: true });
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Compare two tracks according to the sort keys of this query
+    pub fn compare(&self, a: &TrackSnapshot, b: &TrackSnapshot) -> Ordering {
+        for key in &self.sort {
+            let ordering = compare_values(&a.get(key.field), &b.get(key.field)).unwrap_or(Ordering::Equal);
+            let ordering = if key.descending { ordering.reverse() } else { ordering };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    }
+
+    /// Run this query on snapshots. Tracks that compare equal keep their original order
+    pub fn run<'a, I>(&self, tracks: I) -> Vec<&'a TrackSnapshot>
+    where I: IntoIterator<Item = &'a TrackSnapshot>
+    {
+        let mut result: Vec<&TrackSnapshot> = tracks.into_iter().filter(|t| self.filter.matches(t)).collect();
+        result.sort_by(|a, b| self.compare(a, b));
+        if let Some(limit) = self.limit {
+            result.truncate(limit);
+        }
+        result
+    }
+
+    /// Run this query on owned snapshots
+    pub fn run_owned(&self, tracks: Vec<TrackSnapshot>) -> Vec<TrackSnapshot> {
+        let mut result: Vec<TrackSnapshot> = tracks.into_iter().filter(|t| self.filter.matches(t)).collect();
+        result.sort_by(|a, b| self.compare(a, b));
+        if let Some(limit) = self.limit {
+            result.truncate(limit);
+        }
+        result
+    }
+
+    /// Run this query on a live collection (every track of the collection is read)
+    pub fn run_on_collection(&self, collection: &TrackCollection) -> windows::core::Result<Vec<TrackSnapshot>> {
+        Ok(self.run_owned(snapshot_tracks(collection)?))
+    }
+
+    /// Run this query on the tracks of a live playlist (e.g. the library playlist).
+    ///
+    /// In case the query requires some text in an artist, album, composer or name, `Search` is used to only read the tracks that may match.
+    pub fn run_on_playlist<P: IITPlaylistWrapper>(&self, playlist: &P) -> windows::core::Result<Vec<TrackSnapshot>> {
+        let collection = match self.filter.search_hint() {
+            None => playlist.Tracks()?,
+            Some((text, field)) => {
+                // `search_hint` only returns searchable fields
+                let search_field = search_field(field).unwrap();
+                match playlist.Search(text.to_string(), search_field) {
+                    Ok(collection) => collection,
+                    // iTunes returns no collection at all when nothing matches
+                    Err(err) if err.code() == windows::Win32::Media::Multimedia::NS_E_PROPERTY_NOT_FOUND => return Ok(Vec::new()),
+                    Err(err) => return Err(err),
+                }
+            },
+        };
+        self.run_on_collection(&collection)
+    }
+}
@@ -0,0 +1,481 @@
+//! A textual language for [`Query`]s
+//!
+//! This makes it possible to accept queries from users (e.g. in command-line tools or chat bots):
+//! ```text
+//! artist:"Beatles" year:1965..1969 rating>=4 -genre:podcast sort:-playcount,name limit:50
+//! ```
+//! A query is a whitespace-separated list of terms, that must all match:
+//! * `field:value` matches texts that contain `value` (case-insensitively), and other values that are equal to `value`.
+//!   Numbers and dates also accept ranges (`1965..1969`, `2000..` or `..1999`)
+//! * `field=value`, `field!=value`, `field<value`, `field<=value`, `field>value` and `field>=value` compare values
+//! * a term without a field (e.g. `yesterday` or `"let it be"`) matches the name, artist or album
+//! * a leading `-` negates a term
+//! * `sort:` lists fields to sort on (descending with a leading `-`), `limit:` limits the count of tracks
+//!
+//! Field names are the COM property names (case-insensitive, e.g. `playedcount`), or a few aliases (e.g. `plays` or `title`).<br/>
+//! Ratings are expressed in stars (0 to 5), durations in seconds or as `m:ss`, dates as `YYYY`, `YYYY-MM` or `YYYY-MM-DD` (UTC), that stand for the whole period.
+//!
+//! [`parse`] produces a typed [`QueryAst`], that keeps the position of every term so that errors can be reported precisely (see [`QueryError::render`]).
+
+use std::ops::Range;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::library::{TrackSnapshot, TrackField, FieldValue};
+use super::query::{Query, Filter, Comparison, SortKey, field};
+
+/// A node of the AST, and its position in the query (in bytes)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Range<usize>,
+}
+
+/// How a field is compared with a value
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Operator {
+    /// `:` (contains for texts, equals for other values)
+    Matches,
+    /// `=`
+    Equals,
+    /// `!=`
+    NotEquals,
+    /// `<`
+    Less,
+    /// `<=`
+    LessOrEqual,
+    /// `>`
+    Greater,
+    /// `>=`
+    GreaterOrEqual,
+}
+
+/// A typed value.
+///
+/// Dates are periods (e.g. `2021` is the whole year), which are stored as their first and last instants
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Single(FieldValue),
+    Period { first: SystemTime, last: SystemTime },
+    /// Bounds are included. At least one bound is set
+    Range { min: Option<Box<Value>>, max: Option<Box<Value>> },
+}
+
+/// A term of a query
+#[derive(Clone, Debug, PartialEq)]
+pub enum Term {
+    /// A text to look for in the name, artist or album
+    Text(String),
+    Field { field: TrackField, operator: Operator, value: Value },
+    Sort(Vec<SortKey>),
+    Limit(usize),
+}
+
+/// A term, possibly negated
+#[derive(Clone, Debug, PartialEq)]
+pub struct TermNode {
+    pub negated: bool,
+    pub term: Term,
+}
+
+/// A parsed query
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct QueryAst {
+    pub terms: Vec<Spanned<TermNode>>,
+}
+
+/// An error in a query, and where it is (in bytes)
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct QueryError {
+    pub message: String,
+    pub span: Range<usize>,
+}
+
+impl QueryError {
+    fn new<S: Into<String>>(message: S, span: Range<usize>) -> Self {
+        Self { message: message.into(), span }
+    }
+
+    /// Show the error under the query, e.g.
+    /// ```text
+    /// rating>=x
+    ///         ^ expected a number of stars (0 to 5)
+    /// ```
+    pub fn render(&self, query: &str) -> String {
+        let start = query.get(..self.span.start).map_or(0, |s| s.chars().count());
+        let width = query.get(self.span.clone()).map_or(1, |s| s.chars().count().max(1));
+        format!("{}\n{}{} {}", query, " ".repeat(start), "^".repeat(width), self.message)
+    }
+}
+
+impl std::fmt::Display for QueryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (at {}..{})", self.message, self.span.start, self.span.end)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+/// Retrieve a field from its name in queries
+pub fn field_from_query_name(name: &str) -> Option<TrackField> {
+    let alias = match name.to_ascii_lowercase().as_str() {
+        "title" | "song" => Some(TrackField::Name),
+        "comments" => Some(TrackField::Comment),
+        "track" => Some(TrackField::TrackNumber),
+        "disc" => Some(TrackField::DiscNumber),
+        "time" | "length" => Some(TrackField::Duration),
+        "plays" | "playcount" => Some(TrackField::PlayedCount),
+        "played" | "lastplayed" => Some(TrackField::PlayedDate),
+        "skips" | "skipcount" => Some(TrackField::SkippedCount),
+        "skipped" | "lastskipped" => Some(TrackField::SkippedDate),
+        "added" => Some(TrackField::DateAdded),
+        _ => None,
+    };
+    alias.or_else(|| TrackField::from_name(name))
+}
+
+/// Parse a query
+pub fn parse(query: &str) -> Result<QueryAst, QueryError> {
+    Parser { query, position: 0 }.parse()
+}
+
+impl std::str::FromStr for Query {
+    type Err = QueryError;
+
+    fn from_str(query: &str) -> Result<Self, Self::Err> {
+        Ok(parse(query)?.to_query())
+    }
+}
+
+struct Parser<'q> {
+    query: &'q str,
+    position: usize,
+}
+
+impl<'q> Parser<'q> {
+    fn rest(&self) -> &'q str {
+        &self.query[self.position..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn parse(mut self) -> Result<QueryAst, QueryError> {
+        let mut ast = QueryAst::default();
+        loop {
+            self.skip_whitespace();
+            if self.rest().is_empty() {
+                return Ok(ast);
+            }
+            ast.terms.push(self.parse_term()?);
+        }
+    }
+
+    fn parse_term(&mut self) -> Result<Spanned<TermNode>, QueryError> {
+        let start = self.position;
+        let negated = self.peek() == Some('-');
+        if negated {
+            self.position += 1;
+        }
+
+        let key_len = self.rest().find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(self.rest().len());
+        let after_key = &self.rest()[key_len..];
+        let operator = [("!=", Operator::NotEquals), ("<=", Operator::LessOrEqual), (">=", Operator::GreaterOrEqual),
+            (":", Operator::Matches), ("=", Operator::Equals), ("<", Operator::Less), (">", Operator::Greater)]
+            .into_iter()
+            .find(|(symbol, _)| after_key.starts_with(symbol));
+
+        let term = match operator {
+            Some((symbol, operator)) if key_len > 0 => {
+                let key_span = self.position..self.position + key_len;
+                let key = &self.query[key_span.clone()];
+                self.position += key_len + symbol.len();
+                let value = self.read_value()?;
+                self.parse_field_term(key, key_span, operator, value)?
+            },
+            _ => {
+                let text = self.read_value()?;
+                if text.node.is_empty() {
+                    return Err(QueryError::new("expected a term", start..self.position.max(start + 1)));
+                }
+                Term::Text(text.node)
+            },
+        };
+
+        if negated && matches!(term, Term::Sort(_) | Term::Limit(_)) {
+            return Err(QueryError::new("this term cannot be negated", start..start + 1));
+        }
+        Ok(Spanned { node: TermNode { negated, term }, span: start..self.position })
+    }
+
+    /// Read a quoted string, or a word up to the next whitespace
+    fn read_value(&mut self) -> Result<Spanned<String>, QueryError> {
+        let start = self.position;
+        if self.peek() != Some('"') {
+            let len = self.rest().find(char::is_whitespace).unwrap_or(self.rest().len());
+            self.position += len;
+            return Ok(Spanned { node: self.query[start..self.position].to_string(), span: start..self.position });
+        }
+
+        self.position += 1;
+        let mut value = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.position += i + 1;
+                    return Ok(Spanned { node: value, span: start..self.position });
+                },
+                '\\' => match chars.next() {
+                    Some((_, escaped)) => value.push(escaped),
+                    None => break,
+                },
+                c => value.push(c),
+            }
+        }
+        Err(QueryError::new("unterminated quote", start..self.query.len()))
+    }
+
+    fn parse_field_term(&self, key: &str, key_span: Range<usize>, operator: Operator, value: Spanned<String>) -> Result<Term, QueryError> {
+        match key.to_ascii_lowercase().as_str() {
+            "sort" if operator == Operator::Matches => return parse_sort_keys(&value),
+            "limit" if operator == Operator::Matches => {
+                return value.node.parse()
+                    .map(Term::Limit)
+                    .map_err(|_| QueryError::new("expected a count of tracks", value.span));
+            },
+            _ => {},
+        }
+
+        let field = field_from_query_name(key).ok_or_else(|| QueryError::new(format!("unknown field `{}`", key), key_span))?;
+        let value = parse_typed_value(field, operator, &value)?;
+        Ok(Term::Field { field, operator, value })
+    }
+}
+
+fn parse_sort_keys(value: &Spanned<String>) -> Result<Term, QueryError> {
+    let mut keys = Vec::new();
+    let mut offset = value.span.start;
+    for item in value.node.split(',') {
+        let span = offset..offset + item.len();
+        offset += item.len() + 1;
+        let (descending, name) = match item.strip_prefix('-') {
+            Some(name) => (true, name),
+            None => (false, item),
+        };
+        let field = field_from_query_name(name).ok_or_else(|| QueryError::new(format!("unknown field `{}`", name), span))?;
+        keys.push(SortKey { field, descending });
+    }
+    Ok(Term::Sort(keys))
+}
+
+fn parse_typed_value(field: TrackField, operator: Operator, value: &Spanned<String>) -> Result<Value, QueryError> {
+    let is_text = matches!(TrackSnapshot::default().get(field), FieldValue::Text(_));
+    if is_text {
+        return Ok(Value::Single(FieldValue::Text(value.node.clone())));
+    }
+
+    if let Some(separator) = value.node.find("..") {
+        if operator != Operator::Matches {
+            return Err(QueryError::new("ranges can only be used with `:`", value.span.clone()));
+        }
+        let min_text = &value.node[..separator];
+        let max_text = &value.node[separator + 2..];
+        let min_span = value.span.start..value.span.start + separator;
+        let max_span = min_span.end + 2..value.span.end;
+        if min_text.is_empty() && max_text.is_empty() {
+            return Err(QueryError::new("a range needs at least one bound", value.span.clone()));
+        }
+        let bound = |text: &str, span: Range<usize>| -> Result<Option<Box<Value>>, QueryError> {
+            match text.is_empty() {
+                true => Ok(None),
+                false => parse_single_value(field, text, span).map(|v| Some(Box::new(v))),
+            }
+        };
+        let min = bound(min_text, min_span)?;
+        let max = bound(max_text, max_span)?;
+        if let (Some(Value::Single(FieldValue::Bool(_))), _) | (_, Some(Value::Single(FieldValue::Bool(_)))) = (min.as_deref(), max.as_deref()) {
+            return Err(QueryError::new("ranges can only be used on numbers and dates", value.span.clone()));
+        }
+        return Ok(Value::Range { min, max });
+    }
+
+    let parsed = parse_single_value(field, &value.node, value.span.clone())?;
+    let is_ordering = !matches!(operator, Operator::Matches | Operator::Equals | Operator::NotEquals);
+    if is_ordering && matches!(parsed, Value::Single(FieldValue::Bool(_))) {
+        return Err(QueryError::new("booleans can only be compared with `:`, `=` or `!=`", value.span.clone()));
+    }
+    Ok(parsed)
+}
+
+fn parse_single_value(field: TrackField, text: &str, span: Range<usize>) -> Result<Value, QueryError> {
+    match TrackSnapshot::default().get(field) {
+        FieldValue::Text(_) => Ok(Value::Single(FieldValue::Text(text.to_string()))),
+        FieldValue::Bool(_) => match text.to_ascii_lowercase().as_str() {
+            "true" | "yes" | "1" => Ok(Value::Single(FieldValue::Bool(true))),
+            "false" | "no" | "0" => Ok(Value::Single(FieldValue::Bool(false))),
+            _ => Err(QueryError::new("expected `true` or `false`", span)),
+        },
+        FieldValue::Date(_) => {
+            let (first, last) = parse_period(text).ok_or_else(|| QueryError::new("expected a date (YYYY, YYYY-MM or YYYY-MM-DD)", span))?;
+            Ok(Value::Period { first, last })
+        },
+        FieldValue::Integer(_) => match field {
+            TrackField::Rating | TrackField::AlbumRating => match text.parse::<i64>() {
+                Ok(stars) if (0..=5).contains(&stars) => Ok(Value::Single(FieldValue::Integer(stars * 20))),
+                _ => Err(QueryError::new("expected a number of stars (0 to 5)", span)),
+            },
+            TrackField::Duration => parse_duration(text)
+                .map(|seconds| Value::Single(FieldValue::Integer(seconds)))
+                .ok_or_else(|| QueryError::new("expected a duration (seconds, or m:ss)", span)),
+            _ => text.parse::<i64>()
+                .map(|i| Value::Single(FieldValue::Integer(i)))
+                .map_err(|_| QueryError::new("expected a number", span)),
+        },
+    }
+}
+
+/// Parse `ss`, `m:ss` or `h:mm:ss` into seconds
+pub fn parse_duration(text: &str) -> Option<i64> {
+    text.split(':').try_fold(0i64, |total, part| {
+        let part: i64 = part.parse().ok()?;
+        Some(total * 60 + part)
+    })
+}
+
+/// The number of days from 1970-01-01 to a date, see http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn days_to_system_time(days: i64) -> Option<SystemTime> {
+    match days >= 0 {
+        true => UNIX_EPOCH.checked_add(Duration::from_secs(days as u64 * 86400)),
+        false => UNIX_EPOCH.checked_sub(Duration::from_secs(days.unsigned_abs() * 86400)),
+    }
+}
+
+/// The number of days from 1970-01-01 to the first day of a month, and to the first day of the next month
+fn month_days(year: i64, month: i64) -> (i64, i64) {
+    let next = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+    (days_from_civil(year, month, 1), days_from_civil(next.0, next.1, 1))
+}
+
+/// Parse `YYYY`, `YYYY-MM` or `YYYY-MM-DD` into the first and last instants of this period (UTC)
+fn parse_period(text: &str) -> Option<(SystemTime, SystemTime)> {
+    let parts: Vec<i64> = text.split('-').map(|p| p.parse().ok()).collect::<Option<_>>()?;
+    let (first_day, next_first_day) = match parts.as_slice() {
+        [year] => (days_from_civil(*year, 1, 1), days_from_civil(year + 1, 1, 1)),
+        [year, month] if (1..=12).contains(month) => month_days(*year, *month),
+        [year, month, day] if (1..=12).contains(month) && (1..=31).contains(day) => {
+            // e.g. `2021-02-31`
+            let (month_first, next_month_first) = month_days(*year, *month);
+            if *day > next_month_first - month_first {
+                return None;
+            }
+            let first = month_first + day - 1;
+            (first, first + 1)
+        },
+        _ => return None,
+    };
+    let first = days_to_system_time(first_day)?;
+    let last = days_to_system_time(next_first_day)?.checked_sub(Duration::from_secs(1))?;
+    Some((first, last))
+}
+
+impl Value {
+    /// The first and last values this value stands for
+    fn bounds(&self) -> (Option<FieldValue>, Option<FieldValue>) {
+        match self {
+            Value::Single(value) => (Some(value.clone()), Some(value.clone())),
+            Value::Period { first, last } => (Some(FieldValue::Date(Some(*first))), Some(FieldValue::Date(Some(*last)))),
+            Value::Range { min, max } => (
+                min.as_ref().and_then(|v| v.bounds().0),
+                max.as_ref().and_then(|v| v.bounds().1),
+            ),
+        }
+    }
+}
+
+impl Term {
+    /// The filter that matches this term (`None` for sort and limit terms)
+    pub fn to_filter(&self) -> Option<Filter> {
+        match self {
+            Term::Sort(_) | Term::Limit(_) => None,
+            Term::Text(text) => Some(field(TrackField::Name).contains(text)
+                .or(field(TrackField::Artist).contains(text))
+                .or(field(TrackField::Album).contains(text))),
+            Term::Field { field: f, operator, value } => {
+                if let (Value::Single(FieldValue::Text(text)), Operator::Matches) = (value, operator) {
+                    return Some(field(*f).contains(text));
+                }
+
+                let (first, last) = value.bounds();
+                let filter = |comparison: Comparison| Filter::Field(*f, comparison);
+                let filter = match (operator, first, last) {
+                    (Operator::Matches | Operator::Equals, Some(first), Some(last)) if first == last => filter(Comparison::Equals(first)),
+                    (Operator::Matches | Operator::Equals, Some(first), Some(last)) => filter(Comparison::Between(first, last)),
+                    (Operator::Matches | Operator::Equals, Some(first), None) => filter(Comparison::GreaterOrEqual(first)),
+                    (Operator::Matches | Operator::Equals, None, Some(last)) => filter(Comparison::LessOrEqual(last)),
+                    (Operator::NotEquals, Some(first), Some(last)) if first == last => filter(Comparison::Equals(first)).not(),
+                    (Operator::NotEquals, Some(first), Some(last)) => filter(Comparison::Between(first, last)).not(),
+                    (Operator::Less, Some(first), _) => filter(Comparison::LessThan(first)),
+                    (Operator::LessOrEqual, _, Some(last)) => filter(Comparison::LessOrEqual(last)),
+                    (Operator::Greater, _, Some(last)) => filter(Comparison::GreaterThan(last)),
+                    (Operator::GreaterOrEqual, Some(first), _) => filter(Comparison::GreaterOrEqual(first)),
+                    // Ranges are only accepted with `:`, and always have a bound
+                    _ => Filter::All,
+                };
+                Some(filter)
+            },
+        }
+    }
+}
+
+impl QueryAst {
+    /// Turn this AST into a query that can be run
+    pub fn to_query(&self) -> Query {
+        let mut query = Query::new();
+        for term in &self.terms {
+            match &term.node.term {
+                Term::Sort(keys) => query.sort.extend(keys.iter().copied()),
+                Term::Limit(limit) => query.limit = Some(*limit),
+                other => if let Some(filter) = other.to_filter() {
+                    query = query.filter(match term.node.negated {
+                        true => filter.not(),
+                        false => filter,
+                    });
+                },
+            }
+        }
+        query
+    }
+}
+
+/// Parse a single value of a field, with the syntax of queries (e.g. stars for ratings, or `m:ss` for durations).
+///
+/// This is meant for values to write, so a date stands for the first instant of its period.
+pub fn parse_field_value(field: TrackField, text: &str) -> Result<FieldValue, QueryError> {
+    match parse_single_value(field, text, 0..text.len())? {
+        Value::Single(value) => Ok(value),
+        Value::Period { first, .. } => Ok(FieldValue::Date(Some(first))),
+        Value::Range { .. } => Err(QueryError::new("expected a single value", 0..text.len())),
+    }
+}
+
+/// Parse a query, and run it on snapshots
+pub fn run<'a, I>(query: &str, tracks: I) -> Result<Vec<&'a TrackSnapshot>, QueryError>
+where I: IntoIterator<Item = &'a TrackSnapshot>
+{
+    Ok(parse(query)?.to_query().run(tracks))
+}
@@ -0,0 +1,249 @@
+//! Scanner for tracks whose file is missing, and relocator
+//!
+//! iTunes shows a "!" next to tracks whose file has been moved or deleted, and [`FileOrCDTrack::Location`](super::FileOrCDTrack::Location) returns an empty string for them.
+//! [`find_dead_tracks`] lists such tracks, [`plan_relink`] looks for their files in a [`FileIndex`] built from some folders,
+//! and [`RelinkPlan::apply`] updates their locations. Not applying the plan is a dry run.
+//!
+//! Apart from the final step, this only relies on snapshots and on the file system, so that the matching logic can be tested on any folder.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use super::iTunes;
+use super::IITPlaylistWrapper;
+use super::files::{MediaFile, walk_media_files};
+use super::library::{TrackSnapshot, TrackField, FieldValue, set_track_field, normalize_text, format_persistent_id};
+use super::types::PersistentId;
+use super::LONG;
+
+/// Whether the file of this track is missing
+///
+/// Tracks that are not files (e.g. streams) are never dead. Tracks with a known location (e.g. parsed from an XML file) are checked on disk.
+pub fn is_dead(track: &TrackSnapshot) -> bool {
+    match &track.location {
+        None => false,
+        Some(location) => location.is_empty() || !Path::new(location).is_file(),
+    }
+}
+
+/// List the tracks whose file is missing
+pub fn find_dead_tracks<'a, I>(tracks: I) -> Vec<&'a TrackSnapshot>
+where I: IntoIterator<Item = &'a TrackSnapshot>
+{
+    tracks.into_iter().filter(|t| is_dead(t)).collect()
+}
+
+/// The key used to match file names with track names: the normalized file stem, without any leading track number (e.g. `01 ` or `1-01 `)
+fn title_key(stem: &str) -> String {
+    let normalized = normalize_text(stem);
+    let mut words = normalized.splitn(2, ' ');
+    match (words.next(), words.next()) {
+        (Some(first), Some(rest)) if first.trim_end_matches('.').split('-').all(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit())) => rest.to_string(),
+        _ => normalized,
+    }
+}
+
+fn file_name_key(path: &Path) -> Option<String> {
+    path.file_name().and_then(|n| n.to_str()).map(|n| n.to_lowercase())
+}
+
+/// Files found on disk, indexed for fast lookups
+#[derive(Debug, Default)]
+pub struct FileIndex {
+    files: Vec<MediaFile>,
+    by_size: HashMap<u64, Vec<usize>>,
+    by_file_name: HashMap<String, Vec<usize>>,
+    by_title: HashMap<String, Vec<usize>>,
+}
+
+impl FileIndex {
+    pub fn new(files: Vec<MediaFile>) -> Self {
+        let mut index = Self::default();
+        for (i, file) in files.iter().enumerate() {
+            index.by_size.entry(file.size).or_default().push(i);
+            if let Some(name) = file_name_key(&file.path) {
+                index.by_file_name.entry(name).or_default().push(i);
+            }
+            if let Some(stem) = file.path.file_stem().and_then(|s| s.to_str()) {
+                index.by_title.entry(title_key(stem)).or_default().push(i);
+            }
+        }
+        index.files = files;
+        index
+    }
+
+    /// Index every media file under these folders (see [`walk_media_files`])
+    pub fn scan<P: AsRef<Path>>(roots: &[P], extensions: &[&str]) -> std::io::Result<Self> {
+        let mut files = Vec::new();
+        for root in roots {
+            files.extend(walk_media_files(root, extensions)?);
+        }
+        Ok(Self::new(files))
+    }
+
+    pub fn files(&self) -> &[MediaFile] {
+        &self.files
+    }
+}
+
+/// A function that reads the duration of a media file (in seconds)
+pub type DurationProbe = dyn Fn(&Path) -> Option<LONG>;
+
+/// How to match missing tracks with files
+pub struct MatchOptions<'p> {
+    /// Maximum difference between durations (in seconds), when they are known
+    pub duration_tolerance: LONG,
+    /// Reads the duration of a file (in seconds).<br/>
+    /// This crate does not decode media files, so durations are only compared if such a probe is provided (e.g. using a tag-reading crate).
+    pub duration_probe: Option<&'p DurationProbe>,
+}
+
+impl<'p> Default for MatchOptions<'p> {
+    fn default() -> Self {
+        Self { duration_tolerance: 2, duration_probe: None }
+    }
+}
+
+/// A file that may be the missing file of a track
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Candidate {
+    pub path: PathBuf,
+    pub same_size: bool,
+    /// Whether the file name matches the previous file name of the track (when known), or its name
+    pub same_name: bool,
+    /// `None` when the duration of the file is unknown
+    pub same_duration: Option<bool>,
+}
+
+impl Candidate {
+    /// The higher, the more likely
+    pub fn score(&self) -> u32 {
+        3 * u32::from(self.same_size) + 2 * u32::from(self.same_name) + u32::from(self.same_duration == Some(true))
+    }
+}
+
+/// List the files that may be the missing file of this track, best candidates first.
+///
+/// Candidates must have the same size or a matching name, and must not have a different duration.
+pub fn find_candidates(track: &TrackSnapshot, index: &FileIndex, options: &MatchOptions) -> Vec<Candidate> {
+    let old_file_name = track.location.as_deref()
+        .filter(|l| !l.is_empty())
+        .and_then(|l| file_name_key(Path::new(&l.replace('\\', "/"))));
+
+    let mut indices: Vec<usize> = Vec::new();
+    if track.size > 0 {
+        indices.extend(index.by_size.get(&(track.size as u64)).into_iter().flatten());
+    }
+    if let Some(name) = &old_file_name {
+        indices.extend(index.by_file_name.get(name).into_iter().flatten());
+    }
+    indices.extend(index.by_title.get(&title_key(&track.name)).into_iter().flatten());
+    indices.sort_unstable();
+    indices.dedup();
+
+    let mut candidates: Vec<Candidate> = indices.into_iter()
+        .map(|i| &index.files[i])
+        .map(|file| {
+            let same_name = match &old_file_name {
+                Some(name) => file_name_key(&file.path).as_ref() == Some(name),
+                None => file.path.file_stem().and_then(|s| s.to_str()).map(title_key) == Some(title_key(&track.name)),
+            };
+            let same_duration = options.duration_probe
+                .and_then(|probe| probe(&file.path))
+                .map(|d| (d - track.duration).abs() <= options.duration_tolerance);
+            Candidate {
+                path: file.path.clone(),
+                same_size: track.size > 0 && file.size == track.size as u64,
+                same_name,
+                same_duration,
+            }
+        })
+        .filter(|c| (c.same_size || c.same_name) && c.same_duration != Some(false))
+        .collect();
+
+    candidates.sort_by(|a, b| b.score().cmp(&a.score()).then_with(|| a.path.cmp(&b.path)));
+    candidates
+}
+
+/// A track that would be relinked to a file
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Relink {
+    pub track: PersistentId,
+    pub name: String,
+    pub new_location: PathBuf,
+}
+
+/// What would be done for every missing track
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RelinkPlan {
+    pub relinks: Vec<Relink>,
+    /// Tracks for which no file has been found
+    pub not_found: Vec<PersistentId>,
+    /// Tracks for which several files are equally likely. These are not relinked
+    pub ambiguous: Vec<(PersistentId, Vec<PathBuf>)>,
+}
+
+/// Look for the missing file of every track
+///
+/// A track is only relinked when its best candidate is better than the other ones.
+pub fn plan_relink(dead_tracks: &[&TrackSnapshot], index: &FileIndex, options: &MatchOptions) -> RelinkPlan {
+    let mut plan = RelinkPlan::default();
+
+    for track in dead_tracks {
+        let candidates = find_candidates(track, index, options);
+        match candidates.as_slice() {
+            [] => plan.not_found.push(track.persistent_id),
+            [best, second, ..] if best.score() == second.score() => {
+                let paths = candidates.iter().take_while(|c| c.score() == best.score()).map(|c| c.path.clone()).collect();
+                plan.ambiguous.push((track.persistent_id, paths));
+            },
+            [best, ..] => plan.relinks.push(Relink {
+                track: track.persistent_id,
+                name: track.name.clone(),
+                new_location: best.path.clone(),
+            }),
+        }
+    }
+
+    plan
+}
+
+impl RelinkPlan {
+    /// Set the location of every track that has a match.
+    ///
+    /// This returns the tracks that could not be relinked.
+    pub fn apply(&self, iTunes: &iTunes) -> windows::core::Result<Vec<(PersistentId, windows::core::Error)>> {
+        let library_tracks = iTunes.LibraryPlaylist()?.Tracks()?;
+        let mut failures = Vec::new();
+
+        for relink in &self.relinks {
+            let location = FieldValue::Text(relink.new_location.to_string_lossy().into_owned());
+            let result = library_tracks.ItemByPersistentID(relink.track)
+                .and_then(|track| set_track_field(&track, TrackField::Location, &location));
+            if let Err(err) = result {
+                failures.push((relink.track, err));
+            }
+        }
+
+        Ok(failures)
+    }
+}
+
+impl std::fmt::Display for RelinkPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} track(s) to relink, {} ambiguous, {} not found", self.relinks.len(), self.ambiguous.len(), self.not_found.len())?;
+        for relink in &self.relinks {
+            writeln!(f, "  [{}] {} -> {}", format_persistent_id(relink.track), relink.name, relink.new_location.display())?;
+        }
+        for (track, paths) in &self.ambiguous {
+            writeln!(f, "  [{}] ambiguous:", format_persistent_id(*track))?;
+            for path in paths {
+                writeln!(f, "      {}", path.display())?;
+            }
+        }
+        for track in &self.not_found {
+            writeln!(f, "  [{}] not found", format_persistent_id(*track))?;
+        }
+        Ok(())
+    }
+}
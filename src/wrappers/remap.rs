@@ -0,0 +1,271 @@
+//! Bulk rewriting of track locations
+//!
+//! This is useful when music files have been moved to another folder or another drive (e.g. from `\\nas\music` to `M:\Music`).
+//! A [`PathRemapper`] holds an ordered list of [`RemapRule`]s. [`plan_remap`] previews every location that would change,
+//! and [`RemapPlan::apply`] writes them to iTunes, while recording an [`UndoLog`] that can be replayed to revert the change.
+//!
+//! Paths are compared the way Windows does: case-insensitively, regardless of `/` or `\` separators and of `\\?\` prefixes (see [`canonical_windows_path`]).
+//!
+//! Note that iTunes reports an empty location for tracks whose file is already missing. In case the files have already been moved,
+//! the previous locations can be read from the `iTunes Library.xml` file (see `LibrarySnapshot::from_xml_file`).
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use super::iTunes;
+use super::IITPlaylistWrapper;
+use super::files::{canonical_windows_path, windows_path_key};
+use super::library::{TrackSnapshot, TrackField, FieldValue, set_track_field, format_persistent_id, parse_persistent_id};
+use super::types::PersistentId;
+
+/// How many locations are written between two calls of the progress callback of [`RemapPlan::apply`], by default
+pub const DEFAULT_PROGRESS_INTERVAL: usize = 500;
+
+/// A rule that rewrites locations
+#[derive(Clone, Debug)]
+pub enum RemapRule {
+    /// Replace a leading folder (e.g. `\\old\music` by `M:\Music`).<br/>
+    /// The prefix is matched case-insensitively, and only on whole path components (`C:\Music` does not match `C:\Musicals\...`)
+    Prefix { from: String, to: String },
+    /// Replace the matches of a regular expression. The pattern is matched against the canonical form of the location
+    #[cfg(feature = "remap_regex")]
+    Regex { pattern: regex::Regex, replacement: String },
+}
+
+impl RemapRule {
+    pub fn prefix(from: &str, to: &str) -> Self {
+        Self::Prefix {
+            from: canonical_windows_path(from),
+            to: canonical_windows_path(to),
+        }
+    }
+
+    /// A case-insensitive regular expression. `replacement` may refer to capture groups (e.g. `$1` or `${name}`)
+    #[cfg(feature = "remap_regex")]
+    pub fn regex(pattern: &str, replacement: &str) -> Result<Self, regex::Error> {
+        let pattern = regex::RegexBuilder::new(pattern).case_insensitive(true).build()?;
+        Ok(Self::Regex { pattern, replacement: replacement.to_string() })
+    }
+
+    /// Rewrite a location, or return `None` if this rule does not match it
+    pub fn apply(&self, location: &str) -> Option<String> {
+        let location = canonical_windows_path(location);
+        match self {
+            RemapRule::Prefix { from, to } => {
+                let rest = strip_path_prefix(&location, from)?;
+                Some(join_path(to, rest))
+            },
+            #[cfg(feature = "remap_regex")]
+            RemapRule::Regex { pattern, replacement } => {
+                match pattern.is_match(&location) {
+                    true => Some(canonical_windows_path(&pattern.replace_all(&location, replacement.as_str()))),
+                    false => None,
+                }
+            },
+        }
+    }
+}
+
+/// Strip a canonical prefix from a canonical path, case-insensitively and on component boundaries
+fn strip_path_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    if prefix.is_empty() {
+        return None;
+    }
+
+    let mut path_chars = path.char_indices();
+    for p in prefix.chars() {
+        let (_, c) = path_chars.next()?;
+        if !c.to_lowercase().eq(p.to_lowercase()) {
+            return None;
+        }
+    }
+    let rest = match path_chars.next() {
+        None => "",
+        Some((i, _)) => &path[i..],
+    };
+
+    match rest.is_empty() || rest.starts_with('\\') || prefix.ends_with('\\') {
+        true => Some(rest),
+        false => None,
+    }
+}
+
+fn join_path(folder: &str, rest: &str) -> String {
+    let rest = rest.trim_start_matches('\\');
+    match (rest.is_empty(), folder.ends_with('\\')) {
+        (true, _) => folder.to_string(),
+        (false, true) => format!("{}{}", folder, rest),
+        (false, false) => format!("{}\\{}", folder, rest),
+    }
+}
+
+/// An ordered list of rules. The first rule that matches a location is used
+#[derive(Clone, Debug, Default)]
+pub struct PathRemapper {
+    rules: Vec<RemapRule>,
+}
+
+impl PathRemapper {
+    pub fn new(rules: Vec<RemapRule>) -> Self {
+        Self { rules }
+    }
+
+    /// Add a rule, that has a lower priority than the existing ones
+    pub fn push(&mut self, rule: RemapRule) {
+        self.rules.push(rule);
+    }
+
+    pub fn rules(&self) -> &[RemapRule] {
+        &self.rules
+    }
+
+    /// The new location of a file, or `None` if no rule matches, or if the location would not change.
+    ///
+    /// Locations that only differ in case, separators or Unicode normalization are the same file (see [`windows_path_key`]), so they are not changed.
+    pub fn remap(&self, location: &str) -> Option<String> {
+        let new = self.rules.iter().find_map(|rule| rule.apply(location))?;
+        match windows_path_key(&new) == windows_path_key(location) {
+            true => None,
+            false => Some(new),
+        }
+    }
+}
+
+/// The location of a track, before and after remapping
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LocationChange {
+    pub track: PersistentId,
+    pub old: String,
+    pub new: String,
+}
+
+/// Every location that would change
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RemapPlan {
+    pub changes: Vec<LocationChange>,
+}
+
+/// Compute the new location of every track. Tracks that are not files, or whose location is unknown, are ignored.
+pub fn plan_remap<'a, I>(tracks: I, remapper: &PathRemapper) -> RemapPlan
+where I: IntoIterator<Item = &'a TrackSnapshot>
+{
+    let changes = tracks.into_iter()
+        .filter_map(|track| {
+            let old = track.location.as_deref().filter(|l| !l.is_empty())?;
+            let new = remapper.remap(old)?;
+            Some(LocationChange { track: track.persistent_id, old: old.to_string(), new })
+        })
+        .collect();
+
+    RemapPlan { changes }
+}
+
+impl RemapPlan {
+    /// Read an undo log, and return the plan that reverts the changes it records (latest change first)
+    pub fn from_undo_log<R: BufRead>(reader: R) -> std::io::Result<Self> {
+        let mut changes = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Invalid undo log line: {}", line));
+            let mut fields = line.split('\t');
+            let (id, old, new) = match (fields.next(), fields.next(), fields.next(), fields.next()) {
+                (Some(id), Some(old), Some(new), None) => (id, old, new),
+                _ => return Err(invalid()),
+            };
+            let track = parse_persistent_id(id).ok_or_else(invalid)?;
+            changes.push(LocationChange { track, old: new.to_string(), new: old.to_string() });
+        }
+        changes.reverse();
+        Ok(Self { changes })
+    }
+
+    /// Read an undo log file, see [`Self::from_undo_log`]
+    pub fn load_undo_file<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        Self::from_undo_log(BufReader::new(File::open(path)?))
+    }
+
+    /// Apply this plan to the main library of a live iTunes instance.
+    ///
+    /// Every location that has been written is recorded and flushed to `undo` right away, so that the log is complete even if the process is killed midway.
+    /// Every `progress_interval` changes (and after the last one), `progress` is called with the number of processed changes.
+    /// This does not stop at the first failure (e.g. a locked track), but returns every location that could not be written.
+    pub fn apply<W, F>(&self, iTunes: &iTunes, undo: &mut UndoLog<W>, progress_interval: usize, mut progress: F) -> std::io::Result<Vec<(PersistentId, windows::core::Error)>>
+    where
+        W: Write,
+        F: FnMut(usize),
+    {
+        let library_tracks = iTunes.LibraryPlaylist()?.Tracks()?;
+        let mut failures = Vec::new();
+        let mut done = 0;
+
+        for chunk in self.changes.chunks(progress_interval.max(1)) {
+            for change in chunk {
+                let location = FieldValue::Text(change.new.clone());
+                let result = library_tracks.ItemByPersistentID(change.track)
+                    .and_then(|track| set_track_field(&track, TrackField::Location, &location));
+                match result {
+                    Ok(()) => {
+                        undo.record(change)?;
+                        undo.flush()?;
+                    },
+                    Err(err) => failures.push((change.track, err)),
+                }
+            }
+            done += chunk.len();
+            progress(done);
+        }
+
+        Ok(failures)
+    }
+}
+
+impl std::fmt::Display for RemapPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} location(s) to change", self.changes.len())?;
+        for change in &self.changes {
+            writeln!(f, "  [{}] {}", format_persistent_id(change.track), change.old)?;
+            writeln!(f, "      -> {}", change.new)?;
+        }
+        Ok(())
+    }
+}
+
+/// A record of the locations that have been changed, so that they can be restored (see [`RemapPlan::from_undo_log`]).
+///
+/// This is a tab-separated text file, with one `persistent ID`, `old location`, `new location` line per change.
+/// (Windows paths cannot contain tabs or newlines.)
+pub struct UndoLog<W: Write> {
+    writer: W,
+}
+
+impl UndoLog<BufWriter<File>> {
+    /// Create an undo log file. This fails if the file already exists, so that a previous log is never overwritten
+    pub fn create<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let file = OpenOptions::new().write(true).create_new(true).open(path)?;
+        let mut log = Self::new(BufWriter::new(file));
+        writeln!(log.writer, "# itunes-com location undo log: persistent ID, old location, new location")?;
+        Ok(log)
+    }
+}
+
+impl<W: Write> UndoLog<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn record(&mut self, change: &LocationChange) -> std::io::Result<()> {
+        writeln!(self.writer, "{}\t{}\t{}", format_persistent_id(change.track), change.old, change.new)
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
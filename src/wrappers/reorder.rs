@@ -0,0 +1,100 @@
+//! Sorting and reordering of user playlists
+//!
+//! The COM API cannot move a track within a playlist. These methods compute the target order, then remove and append tracks
+//! (see [`plan_playlist_update`]). The playlist itself is updated in place, so that it keeps its persistent ID and its parent folder.
+//! Its `Shuffle` and `SongRepeat` settings are restored, in case iTunes changed them.
+//!
+//! The order computations are available as plain functions, so that they can be used on snapshots as well.
+
+use super::{UserPlaylist, IITPlaylistWrapper};
+use super::library::{TrackSnapshot, snapshot_tracks};
+use super::materialize::{UpdateReport, plan_playlist_update, apply_playlist_update};
+use super::query::{Query, SortKey};
+use super::types::PersistentId;
+
+/// The order of `tracks` once sorted by `keys`. Tracks that compare equal keep their relative order
+pub fn sorted_order(tracks: &[TrackSnapshot], keys: &[SortKey]) -> Vec<PersistentId> {
+    let query = Query { sort: keys.to_vec(), ..Query::default() };
+    query.run(tracks).iter().map(|t| t.persistent_id).collect()
+}
+
+/// A small, seedable pseudo-random generator (SplitMix64), so that shuffles can be reproduced
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+/// Shuffle `tracks`. The same seed always gives the same order
+pub fn shuffled_order(tracks: &[PersistentId], seed: u64) -> Vec<PersistentId> {
+    let mut order = tracks.to_vec();
+    let mut rng = SplitMix64(seed);
+    // Fisher-Yates
+    for i in (1..order.len()).rev() {
+        let j = (rng.next() % (i as u64 + 1)) as usize;
+        order.swap(i, j);
+    }
+    order
+}
+
+/// Move the track at index `from` to index `to`. Returns `None` if an index is out of bounds
+pub fn moved_order(tracks: &[PersistentId], from: usize, to: usize) -> Option<Vec<PersistentId>> {
+    if from >= tracks.len() || to >= tracks.len() {
+        return None;
+    }
+    let mut order = tracks.to_vec();
+    let track = order.remove(from);
+    order.insert(to, track);
+    Some(order)
+}
+
+impl UserPlaylist {
+    /// Make this playlist contain `order`, keeping as many tracks in place as possible
+    pub fn reorder(&self, order: &[PersistentId]) -> windows::core::Result<UpdateReport> {
+        let current = self.track_ids()?;
+        let shuffle = self.is_Shuffle()?;
+        let song_repeat = self.SongRepeat()?;
+
+        let report = apply_playlist_update(self, &current, &plan_playlist_update(&current, order))?;
+
+        if self.is_Shuffle()? != shuffle {
+            self.set_Shuffle(shuffle)?;
+        }
+        if self.SongRepeat()? != song_repeat {
+            self.set_SongRepeat(song_repeat)?;
+        }
+        Ok(report)
+    }
+
+    /// Sort this playlist on several keys (see [`sorted_order`])
+    pub fn sort_by(&self, keys: &[SortKey]) -> windows::core::Result<UpdateReport> {
+        let tracks = snapshot_tracks(&self.Tracks()?)?;
+        self.reorder(&sorted_order(&tracks, keys))
+    }
+
+    /// Shuffle this playlist (see [`shuffled_order`]).
+    ///
+    /// Unlike the `Shuffle` setting, this changes the actual order of the tracks.
+    pub fn shuffle(&self, seed: u64) -> windows::core::Result<UpdateReport> {
+        self.reorder(&shuffled_order(&self.track_ids()?, seed))
+    }
+
+    pub fn reverse(&self) -> windows::core::Result<UpdateReport> {
+        let mut order = self.track_ids()?;
+        order.reverse();
+        self.reorder(&order)
+    }
+
+    /// Move the track at index `from` (zero-based) to index `to`
+    pub fn move_track(&self, from: usize, to: usize) -> windows::core::Result<UpdateReport> {
+        let order = moved_order(&self.track_ids()?, from, to)
+            .ok_or_else(|| windows::core::Error::from(windows::Win32::Foundation::E_BOUNDS))?;
+        self.reorder(&order)
+    }
+}
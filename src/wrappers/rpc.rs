@@ -0,0 +1,628 @@
+//! JSON-RPC control server
+//!
+//! A [`Server`] makes it possible for other machines to query and control iTunes with [JSON-RPC 2.0](https://www.jsonrpc.org/specification) requests,
+//! either over plain TCP (one JSON message per line) or over WebSocket (one JSON message per text frame). [`Client`] is the matching client.
+//!
+//! Requests are served by a [`Backend`]. It is implemented for [`iTunes`], and by [`SnapshotBackend`], an in-memory stand-in
+//! that makes it possible to run a server without iTunes (e.g. in tests).
+//!
+//! | Method              | Parameters                   | Result                        |
+//! |---------------------|------------------------------|-------------------------------|
+//! | `player.play`, `player.pause`, `player.playPause`, `player.next`, `player.previous` | | `null` |
+//! | `player.setVolume`  | `volume` (0 to 100)          | `null`                        |
+//! | `player.getState`   |                              | a [`PlayerInfo`]              |
+//! | `library.search`    | `query` (see [`query_language`](super::query_language)) | an array of [`TrackInfo`] |
+//! | `library.playlists` |                              | an array of [`PlaylistInfo`]  |
+//! | `playlist.tracks`   | `playlist`                   | an array of [`TrackInfo`]     |
+//! | `track.setRating`   | `track`, `rating` (0 to 100) | `null`                        |
+//!
+//! Parameters are given by name (in an object) or by position (in an array, in the order of this table).<br/>
+//! Tracks and playlists are designated by their persistent IDs, formatted as 16 hexadecimal digits (see [`format_persistent_id`]),
+//! since JSON numbers cannot hold every 64-bit integer.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+use super::{iTunes, IITPlaylistWrapper};
+use super::library::{LibrarySnapshot, TrackSnapshot, TrackField, FieldValue, snapshot_tracks, set_track_field, format_persistent_id, parse_persistent_id};
+use super::player::PlayerSample;
+use super::query::Query;
+use super::server::{Acceptor, library_playlists, server_stopped, skip};
+pub use super::server::{PlaylistInfo, TrackInfo};
+use super::types::PersistentId;
+use super::LONG;
+
+/// The state of the player, as returned by the server
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerInfo {
+    pub playing: bool,
+    /// The position within the current track (in seconds)
+    pub position: LONG,
+    /// From 0 to 100
+    pub volume: LONG,
+    pub track: Option<TrackInfo>,
+}
+
+/// The request is not valid JSON
+pub const PARSE_ERROR: i64 = -32700;
+/// The request is not a valid JSON-RPC request
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+/// The backend has failed (e.g. a COM error). Its `data` is the `HRESULT`, if any
+pub const BACKEND_ERROR: i64 = -32000;
+/// There is no track or playlist with the requested persistent ID
+pub const NOT_FOUND: i64 = -32001;
+
+/// A JSON-RPC error object
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl RpcError {
+    pub fn new<S: Into<String>>(code: i64, message: S) -> Self {
+        Self { code, message: message.into(), data: None }
+    }
+
+    pub fn invalid_params<S: Into<String>>(message: S) -> Self {
+        Self::new(INVALID_PARAMS, message)
+    }
+
+    pub fn not_found(id: PersistentId) -> Self {
+        Self::new(NOT_FOUND, format!("No object with persistent ID {}", format_persistent_id(id)))
+    }
+}
+
+impl From<windows::core::Error> for RpcError {
+    fn from(err: windows::core::Error) -> Self {
+        Self { code: BACKEND_ERROR, message: err.message().to_string(), data: Some(json!({ "hresult": err.code().0 })) }
+    }
+}
+
+impl std::fmt::Display for RpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (code {})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+/// What serves the requests of a [`Server`]
+pub trait Backend {
+    fn play(&mut self) -> Result<(), RpcError>;
+    fn pause(&mut self) -> Result<(), RpcError>;
+    fn play_pause(&mut self) -> Result<(), RpcError>;
+    fn next_track(&mut self) -> Result<(), RpcError>;
+    fn previous_track(&mut self) -> Result<(), RpcError>;
+    /// Set the volume (already checked to be between 0 and 100)
+    fn set_volume(&mut self, volume: LONG) -> Result<(), RpcError>;
+    fn state(&mut self) -> Result<PlayerInfo, RpcError>;
+    /// Run a query on the library
+    fn search(&mut self, query: &Query) -> Result<Vec<TrackInfo>, RpcError>;
+    /// The playlists of the library (apart from the main library playlist)
+    fn playlists(&mut self) -> Result<Vec<PlaylistInfo>, RpcError>;
+    fn playlist_tracks(&mut self, playlist: PersistentId) -> Result<Vec<TrackInfo>, RpcError>;
+    /// Set the rating of a track (already checked to be between 0 and 100)
+    fn set_rating(&mut self, track: PersistentId, rating: LONG) -> Result<(), RpcError>;
+}
+
+impl Backend for iTunes {
+    fn play(&mut self) -> Result<(), RpcError> {
+        Ok(self.Play()?)
+    }
+
+    fn pause(&mut self) -> Result<(), RpcError> {
+        Ok(self.Pause()?)
+    }
+
+    fn play_pause(&mut self) -> Result<(), RpcError> {
+        Ok(self.PlayPause()?)
+    }
+
+    fn next_track(&mut self) -> Result<(), RpcError> {
+        Ok(self.NextTrack()?)
+    }
+
+    fn previous_track(&mut self) -> Result<(), RpcError> {
+        Ok(self.PreviousTrack()?)
+    }
+
+    fn set_volume(&mut self, volume: LONG) -> Result<(), RpcError> {
+        Ok(self.set_SoundVolume(volume)?)
+    }
+
+    fn state(&mut self) -> Result<PlayerInfo, RpcError> {
+        let sample = PlayerSample::from_iTunes(self)?;
+        let track = match sample.track {
+            Some(_) => Some(TrackInfo::from(&TrackSnapshot::from_track(&self.CurrentTrack()?)?)),
+            None => None,
+        };
+        Ok(PlayerInfo { playing: sample.playing, position: sample.position, volume: self.SoundVolume()?, track })
+    }
+
+    fn search(&mut self, query: &Query) -> Result<Vec<TrackInfo>, RpcError> {
+        let tracks = query.run_on_playlist(&self.LibraryPlaylist()?)?;
+        Ok(tracks.iter().map(TrackInfo::from).collect())
+    }
+
+    fn playlists(&mut self) -> Result<Vec<PlaylistInfo>, RpcError> {
+        Ok(library_playlists(self)?)
+    }
+
+    fn playlist_tracks(&mut self, playlist: PersistentId) -> Result<Vec<TrackInfo>, RpcError> {
+        let playlist = self.LibrarySource()?.Playlists()?.ItemByPersistentID(playlist)
+            .map_err(|_| RpcError::not_found(playlist))?;
+        let tracks = snapshot_tracks(&playlist.Tracks()?)?;
+        Ok(tracks.iter().map(TrackInfo::from).collect())
+    }
+
+    fn set_rating(&mut self, track: PersistentId, rating: LONG) -> Result<(), RpcError> {
+        let track = self.LibraryPlaylist()?.Tracks()?.ItemByPersistentID(track)
+            .map_err(|_| RpcError::not_found(track))?;
+        Ok(set_track_field(&track, TrackField::Rating, &FieldValue::Integer(rating.into()))?)
+    }
+}
+
+/// A [`Backend`] that plays a library snapshot, without actually playing anything.
+///
+/// The player goes through the tracks of the library in order of persistent IDs.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SnapshotBackend {
+    pub library: LibrarySnapshot,
+    pub playing: bool,
+    /// The current track, if any
+    pub current: Option<PersistentId>,
+    /// The position within the current track (in seconds)
+    pub position: LONG,
+    pub volume: LONG,
+}
+
+impl SnapshotBackend {
+    pub fn new(library: LibrarySnapshot) -> Self {
+        Self { library, playing: false, current: None, position: 0, volume: 100 }
+    }
+
+    /// Go to another track of the library. Going past the last track stops the player
+    fn skip(&mut self, forward: bool) {
+        self.current = skip(&self.library, self.current, forward);
+        self.position = 0;
+        if self.current.is_none() {
+            self.playing = false;
+        }
+    }
+}
+
+impl Backend for SnapshotBackend {
+    fn play(&mut self) -> Result<(), RpcError> {
+        if self.current.is_none() {
+            self.skip(true);
+        }
+        self.playing = self.current.is_some();
+        Ok(())
+    }
+
+    fn pause(&mut self) -> Result<(), RpcError> {
+        self.playing = false;
+        Ok(())
+    }
+
+    fn play_pause(&mut self) -> Result<(), RpcError> {
+        match self.playing {
+            true => self.pause(),
+            false => self.play(),
+        }
+    }
+
+    fn next_track(&mut self) -> Result<(), RpcError> {
+        self.skip(true);
+        Ok(())
+    }
+
+    fn previous_track(&mut self) -> Result<(), RpcError> {
+        self.skip(false);
+        Ok(())
+    }
+
+    fn set_volume(&mut self, volume: LONG) -> Result<(), RpcError> {
+        self.volume = volume;
+        Ok(())
+    }
+
+    fn state(&mut self) -> Result<PlayerInfo, RpcError> {
+        Ok(PlayerInfo {
+            playing: self.playing,
+            position: self.position,
+            volume: self.volume,
+            track: self.current.and_then(|id| self.library.track(id)).map(TrackInfo::from),
+        })
+    }
+
+    fn search(&mut self, query: &Query) -> Result<Vec<TrackInfo>, RpcError> {
+        Ok(query.run(self.library.tracks.values()).into_iter().map(TrackInfo::from).collect())
+    }
+
+    fn playlists(&mut self) -> Result<Vec<PlaylistInfo>, RpcError> {
+        Ok(self.library.playlists.iter().map(PlaylistInfo::from).collect())
+    }
+
+    fn playlist_tracks(&mut self, playlist: PersistentId) -> Result<Vec<TrackInfo>, RpcError> {
+        let playlist = self.library.playlist(playlist).ok_or_else(|| RpcError::not_found(playlist))?;
+        Ok(playlist.tracks.iter().filter_map(|id| self.library.track(*id)).map(TrackInfo::from).collect())
+    }
+
+    fn set_rating(&mut self, track: PersistentId, rating: LONG) -> Result<(), RpcError> {
+        let snapshot = self.library.track_mut(track).ok_or_else(|| RpcError::not_found(track))?;
+        snapshot.rating = rating;
+        Ok(())
+    }
+}
+
+/// The parameters of a request, given by name or by position
+struct Params<'a>(Option<&'a Value>);
+
+impl<'a> Params<'a> {
+    fn get<T: DeserializeOwned>(&self, position: usize, name: &str) -> Result<T, RpcError> {
+        let value = match self.0 {
+            Some(Value::Object(map)) => map.get(name),
+            Some(Value::Array(values)) => values.get(position),
+            _ => None,
+        };
+        let value = value.ok_or_else(|| RpcError::invalid_params(format!("Missing parameter `{}`", name)))?;
+        T::deserialize(value).map_err(|err| RpcError::invalid_params(format!("Invalid parameter `{}`: {}", name, err)))
+    }
+
+    fn get_id(&self, position: usize, name: &str) -> Result<PersistentId, RpcError> {
+        let text: String = self.get(position, name)?;
+        parse_persistent_id(&text).ok_or_else(|| RpcError::invalid_params(format!("Invalid persistent ID for `{}`: {:?}", name, text)))
+    }
+
+    /// An integer between 0 and 100
+    fn get_percentage(&self, position: usize, name: &str) -> Result<LONG, RpcError> {
+        let value: LONG = self.get(position, name)?;
+        match (0..=100).contains(&value) {
+            true => Ok(value),
+            false => Err(RpcError::invalid_params(format!("`{}` must be between 0 and 100", name))),
+        }
+    }
+}
+
+fn to_value<T: Serialize>(value: T) -> Result<Value, RpcError> {
+    serde_json::to_value(value).map_err(|err| RpcError::new(INTERNAL_ERROR, err.to_string()))
+}
+
+fn call<B: Backend + ?Sized>(backend: &mut B, method: &str, params: &Params) -> Result<Value, RpcError> {
+    match method {
+        "player.play" => to_value(backend.play()?),
+        "player.pause" => to_value(backend.pause()?),
+        "player.playPause" => to_value(backend.play_pause()?),
+        "player.next" => to_value(backend.next_track()?),
+        "player.previous" => to_value(backend.previous_track()?),
+        "player.setVolume" => to_value(backend.set_volume(params.get_percentage(0, "volume")?)?),
+        "player.getState" => to_value(backend.state()?),
+        "library.search" => {
+            let text: String = params.get(0, "query")?;
+            let query: Query = text.parse().map_err(|err: super::query_language::QueryError| RpcError::invalid_params(err.render(&text)))?;
+            to_value(backend.search(&query)?)
+        },
+        "library.playlists" => to_value(backend.playlists()?),
+        "playlist.tracks" => to_value(backend.playlist_tracks(params.get_id(0, "playlist")?)?),
+        "track.setRating" => to_value(backend.set_rating(params.get_id(0, "track")?, params.get_percentage(1, "rating")?)?),
+        _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("Unknown method `{}`", method))),
+    }
+}
+
+fn response(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => json!({ "jsonrpc": "2.0", "id": id, "error": error }),
+    }
+}
+
+/// Handle a single request. Returns `None` for notifications
+fn handle_request<B: Backend + ?Sized>(backend: &mut B, request: &Value) -> Option<Value> {
+    let id = request.get("id");
+    let is_valid_id = match id {
+        None => true,
+        Some(id) => id.is_string() || id.is_number() || id.is_null(),
+    };
+    let is_valid_params = match request.get("params") {
+        None => true,
+        Some(params) => params.is_object() || params.is_array(),
+    };
+    let method = request.get("method").and_then(Value::as_str);
+    let method = match method {
+        Some(method) if is_valid_id && is_valid_params && request.get("jsonrpc") == Some(&json!("2.0")) => method,
+        _ => return Some(response(id.cloned().unwrap_or(Value::Null), Err(RpcError::new(INVALID_REQUEST, "Invalid request")))),
+    };
+
+    let result = call(backend, method, &Params(request.get("params")));
+    id.map(|id| response(id.clone(), result))
+}
+
+/// Handle a JSON-RPC message (a request or a batch of requests), and return the response to send back, if any.
+///
+/// This is what a [`Server`] does with every message it receives.
+pub fn handle_message<B: Backend + ?Sized>(backend: &mut B, message: &str) -> Option<String> {
+    let response = match serde_json::from_str::<Value>(message) {
+        Err(err) => Some(response(Value::Null, Err(RpcError::new(PARSE_ERROR, err.to_string())))),
+        Ok(Value::Array(batch)) if batch.is_empty() => Some(response(Value::Null, Err(RpcError::new(INVALID_REQUEST, "Empty batch")))),
+        Ok(Value::Array(batch)) => {
+            let responses: Vec<Value> = batch.iter().filter_map(|request| handle_request(backend, request)).collect();
+            match responses.is_empty() {
+                true => None,
+                false => Some(Value::Array(responses)),
+            }
+        },
+        Ok(request) => handle_request(backend, &request),
+    };
+    response.map(|response| response.to_string())
+}
+
+/// How a [`Server`] talks to its clients
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Transport {
+    /// One JSON message per line
+    Tcp,
+    /// One JSON message per text frame
+    WebSocket,
+}
+
+/// A message received by a connection thread, and where to send its response
+struct PendingMessage {
+    text: String,
+    response: Sender<Option<String>>,
+}
+
+/// A JSON-RPC server.
+///
+/// Connections are handled by background threads, that hand every message over to the thread that calls [`Server::run`].
+pub struct Server {
+    sender: Sender<PendingMessage>,
+    receiver: Receiver<PendingMessage>,
+    acceptor: Acceptor,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Server {
+    pub fn new() -> Self {
+        let (sender, receiver) = channel();
+        Self { sender, receiver, acceptor: Acceptor::new() }
+    }
+
+    /// Accept connections on an address, until this server is dropped.
+    ///
+    /// This returns the actual address, which is useful when binding port 0.
+    pub fn listen<A: ToSocketAddrs>(&self, address: A, transport: Transport) -> std::io::Result<SocketAddr> {
+        let sender = self.sender.clone();
+        self.acceptor.listen(address, move |stream, _| match transport {
+            Transport::Tcp => serve_tcp(stream, &sender),
+            Transport::WebSocket => serve_websocket(stream, &sender),
+        })
+    }
+
+    /// Serve requests with `backend` on the current thread, until `keep_running` returns `false`.
+    ///
+    /// `keep_running` is called after every message, and at least every `poll_interval`.
+    pub fn run<B, F>(&self, backend: &mut B, poll_interval: Duration, mut keep_running: F)
+    where B: Backend + ?Sized, F: FnMut() -> bool
+    {
+        while keep_running() {
+            match self.receiver.recv_timeout(poll_interval) {
+                Ok(message) => {
+                    // The connection may have been closed in the meantime
+                    let _ = message.response.send(handle_message(backend, &message.text));
+                },
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    }
+}
+
+/// Hand a message over to the server thread, and wait for its response
+fn forward(sender: &Sender<PendingMessage>, text: String) -> std::io::Result<Option<String>> {
+    let (response_sender, response_receiver) = channel();
+    sender.send(PendingMessage { text, response: response_sender }).map_err(|_| server_stopped())?;
+    response_receiver.recv().map_err(|_| server_stopped())
+}
+
+fn serve_tcp(stream: TcpStream, sender: &Sender<PendingMessage>) -> std::io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = forward(sender, line)? {
+            writeln!(writer, "{}", response)?;
+        }
+    }
+    Ok(())
+}
+
+fn websocket_error(err: tungstenite::Error) -> std::io::Error {
+    match err {
+        tungstenite::Error::Io(err) => err,
+        err => std::io::Error::other(err),
+    }
+}
+
+fn serve_websocket(stream: TcpStream, sender: &Sender<PendingMessage>) -> std::io::Result<()> {
+    let mut socket = tungstenite::accept(stream)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::ConnectionRefused, err.to_string()))?;
+    loop {
+        match socket.read() {
+            Ok(tungstenite::Message::Text(text)) => {
+                if let Some(response) = forward(sender, text)? {
+                    socket.send(tungstenite::Message::Text(response)).map_err(websocket_error)?;
+                }
+            },
+            Ok(tungstenite::Message::Close(_)) | Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+            // Pings are answered by tungstenite itself
+            Ok(_) => (),
+            Err(err) => return Err(websocket_error(err)),
+        }
+    }
+}
+
+/// Why a [`Client`] call has failed
+#[derive(Debug)]
+pub enum CallError {
+    /// The server could not be reached, or its response is invalid
+    Io(std::io::Error),
+    /// The server has returned an error
+    Rpc(RpcError),
+}
+
+impl From<std::io::Error> for CallError {
+    fn from(err: std::io::Error) -> Self {
+        CallError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for CallError {
+    fn from(err: serde_json::Error) -> Self {
+        CallError::Io(err.into())
+    }
+}
+
+impl std::fmt::Display for CallError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CallError::Io(err) => write!(f, "{}", err),
+            CallError::Rpc(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for CallError {}
+
+enum Connection {
+    Tcp { reader: BufReader<TcpStream>, writer: TcpStream },
+    WebSocket(Box<tungstenite::WebSocket<tungstenite::stream::MaybeTlsStream<TcpStream>>>),
+}
+
+/// A client of a [`Server`]
+pub struct Client {
+    connection: Connection,
+    next_id: u64,
+}
+
+impl Client {
+    /// Connect to a server that uses [`Transport::Tcp`]
+    pub fn connect_tcp<A: ToSocketAddrs>(address: A) -> std::io::Result<Self> {
+        let writer = TcpStream::connect(address)?;
+        let reader = BufReader::new(writer.try_clone()?);
+        Ok(Self { connection: Connection::Tcp { reader, writer }, next_id: 1 })
+    }
+
+    /// Connect to a server that uses [`Transport::WebSocket`], e.g. at `ws://192.168.1.10:9000`
+    pub fn connect_websocket(url: &str) -> std::io::Result<Self> {
+        let (socket, _) = tungstenite::connect(url).map_err(websocket_error)?;
+        Ok(Self { connection: Connection::WebSocket(Box::new(socket)), next_id: 1 })
+    }
+
+    /// Send a message, and wait for the response
+    fn exchange(&mut self, message: String) -> std::io::Result<String> {
+        match &mut self.connection {
+            Connection::Tcp { reader, writer } => {
+                writeln!(writer, "{}", message)?;
+                let mut line = String::new();
+                if reader.read_line(&mut line)? == 0 {
+                    return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "The server has closed the connection"));
+                }
+                Ok(line)
+            },
+            Connection::WebSocket(socket) => {
+                socket.send(tungstenite::Message::Text(message)).map_err(websocket_error)?;
+                loop {
+                    match socket.read().map_err(websocket_error)? {
+                        tungstenite::Message::Text(text) => return Ok(text),
+                        tungstenite::Message::Close(_) => return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "The server has closed the connection")),
+                        _ => (),
+                    }
+                }
+            },
+        }
+    }
+
+    /// Call any method. `params` may be `null`, an array or an object
+    pub fn call<T: DeserializeOwned>(&mut self, method: &str, params: Value) -> Result<T, CallError> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut request = json!({ "jsonrpc": "2.0", "id": id, "method": method });
+        if !params.is_null() {
+            request["params"] = params;
+        }
+
+        let response: Value = serde_json::from_str(&self.exchange(request.to_string())?)?;
+        if response.get("id") != Some(&json!(id)) {
+            return Err(CallError::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, "Unexpected response ID")));
+        }
+        if let Some(error) = response.get("error") {
+            return Err(CallError::Rpc(RpcError::deserialize(error)?));
+        }
+        Ok(T::deserialize(response.get("result").unwrap_or(&Value::Null))?)
+    }
+
+    pub fn play(&mut self) -> Result<(), CallError> {
+        self.call("player.play", Value::Null)
+    }
+
+    pub fn pause(&mut self) -> Result<(), CallError> {
+        self.call("player.pause", Value::Null)
+    }
+
+    pub fn play_pause(&mut self) -> Result<(), CallError> {
+        self.call("player.playPause", Value::Null)
+    }
+
+    pub fn next_track(&mut self) -> Result<(), CallError> {
+        self.call("player.next", Value::Null)
+    }
+
+    pub fn previous_track(&mut self) -> Result<(), CallError> {
+        self.call("player.previous", Value::Null)
+    }
+
+    pub fn set_volume(&mut self, volume: LONG) -> Result<(), CallError> {
+        self.call("player.setVolume", json!({ "volume": volume }))
+    }
+
+    pub fn state(&mut self) -> Result<PlayerInfo, CallError> {
+        self.call("player.getState", Value::Null)
+    }
+
+    pub fn search(&mut self, query: &str) -> Result<Vec<TrackInfo>, CallError> {
+        self.call("library.search", json!({ "query": query }))
+    }
+
+    pub fn playlists(&mut self) -> Result<Vec<PlaylistInfo>, CallError> {
+        self.call("library.playlists", Value::Null)
+    }
+
+    pub fn playlist_tracks(&mut self, playlist: PersistentId) -> Result<Vec<TrackInfo>, CallError> {
+        self.call("playlist.tracks", json!({ "playlist": format_persistent_id(playlist) }))
+    }
+
+    pub fn set_rating(&mut self, track: PersistentId, rating: LONG) -> Result<(), CallError> {
+        self.call("track.setRating", json!({ "track": format_persistent_id(track), "rating": rating }))
+    }
+}
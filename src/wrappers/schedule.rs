@@ -0,0 +1,491 @@
+//! Sleep timers, alarms and volume fades
+//!
+//! A [`Scheduler`] runs [`Task`]s on top of the player: volume [`Task::Fade`]s, [`SleepTimer`]s that stop playback after some time
+//! (or at the end of the current track), and [`Alarm`]s that start a playlist with a rising volume.
+//!
+//! Nothing happens in the background: the scheduler acts when [`Scheduler::tick`] is called, according to the time of its [`Clock`].
+//! [`Scheduler::run`] calls it periodically, on the calling thread. The player is reached through the [`PlayerControl`] trait,
+//! which is implemented for [`iTunes`]. With a [`ManualClock`] and another implementation of [`PlayerControl`], tasks can be
+//! tested against synthetic timelines.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use itunes_com::wrappers::iTunes;
+//! use itunes_com::wrappers::schedule::{FadeCurve, Scheduler, SleepTimer, SystemClock, Task};
+//!
+//! let mut iTunes = iTunes::new().unwrap();
+//! let mut scheduler = Scheduler::new(SystemClock);
+//! // Stop after 30 minutes, once the track playing at this time has ended, with a 20 second fade out
+//! let timer = SleepTimer::after(Duration::from_secs(30 * 60)).finish_track().fade_out(Duration::from_secs(20), FadeCurve::Logarithmic);
+//! scheduler.add(Task::SleepTimer(timer));
+//! scheduler.run(&mut iTunes, Duration::from_millis(250), |event| println!("{:?}", event), || true).unwrap();
+//! ```
+
+use std::cell::Cell;
+use std::time::{Duration, SystemTime};
+
+use super::{iTunes, IITPlaylistWrapper};
+use super::player::PlayerSample;
+use super::types::PersistentId;
+use super::LONG;
+
+/// The lowest level of logarithmic fades (in dB). Quieter volumes are silent
+pub const LOGARITHMIC_FADE_FLOOR_DB: f64 = -40.0;
+
+/// The highest sound volume of iTunes
+pub const MAX_VOLUME: LONG = 100;
+
+/// A source of time
+pub trait Clock {
+    fn now(&self) -> SystemTime;
+    fn sleep(&self, duration: Duration);
+}
+
+/// The system clock
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration)
+    }
+}
+
+/// A clock that only moves when told to. Sleeping advances it immediately
+#[derive(Debug)]
+pub struct ManualClock {
+    now: Cell<SystemTime>,
+}
+
+impl ManualClock {
+    pub fn new(now: SystemTime) -> Self {
+        Self { now: Cell::new(now) }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration);
+    }
+
+    pub fn set(&self, now: SystemTime) {
+        self.now.set(now);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        self.now.get()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for &C {
+    fn now(&self) -> SystemTime {
+        (**self).now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        (**self).sleep(duration)
+    }
+}
+
+/// What the scheduler needs from a player
+pub trait PlayerControl {
+    fn sample(&mut self) -> windows::core::Result<PlayerSample>;
+    /// The sound volume, from 0 to [`MAX_VOLUME`]
+    fn volume(&mut self) -> windows::core::Result<LONG>;
+    fn set_volume(&mut self, volume: LONG) -> windows::core::Result<()>;
+    /// Start playing the first track of a playlist
+    fn play_playlist(&mut self, playlist: PersistentId) -> windows::core::Result<()>;
+    fn pause(&mut self) -> windows::core::Result<()>;
+    fn stop(&mut self) -> windows::core::Result<()>;
+}
+
+impl PlayerControl for iTunes {
+    fn sample(&mut self) -> windows::core::Result<PlayerSample> {
+        PlayerSample::from_iTunes(self)
+    }
+
+    fn volume(&mut self) -> windows::core::Result<LONG> {
+        self.SoundVolume()
+    }
+
+    fn set_volume(&mut self, volume: LONG) -> windows::core::Result<()> {
+        self.set_SoundVolume(volume)
+    }
+
+    fn play_playlist(&mut self, playlist: PersistentId) -> windows::core::Result<()> {
+        self.LibrarySource()?.Playlists()?.ItemByPersistentID(playlist)?.PlayFirstTrack()
+    }
+
+    fn pause(&mut self) -> windows::core::Result<()> {
+        self.Pause()
+    }
+
+    fn stop(&mut self) -> windows::core::Result<()> {
+        self.Stop()
+    }
+}
+
+/// How the volume evolves during a fade
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum FadeCurve {
+    /// The volume changes by the same amount at every step
+    Linear,
+    /// The volume changes by the same number of decibels at every step (down to [`LOGARITHMIC_FADE_FLOOR_DB`]), which sounds more even
+    #[default]
+    Logarithmic,
+}
+
+impl FadeCurve {
+    /// The volume when a fade from `from` to `to` is at `progress` (from 0.0 to 1.0)
+    pub fn volume(&self, from: LONG, to: LONG, progress: f64) -> LONG {
+        let progress = progress.clamp(0.0, 1.0);
+        if progress >= 1.0 {
+            return to;
+        }
+        let volume = match self {
+            FadeCurve::Linear => f64::from(from) + f64::from(to - from) * progress,
+            FadeCurve::Logarithmic => {
+                let (from, to) = (volume_to_db(from), volume_to_db(to));
+                db_to_volume(from + (to - from) * progress)
+            },
+        };
+        (volume.round() as LONG).clamp(0, MAX_VOLUME)
+    }
+}
+
+fn volume_to_db(volume: LONG) -> f64 {
+    match volume {
+        v if v <= 0 => LOGARITHMIC_FADE_FLOOR_DB,
+        v => (20.0 * (f64::from(v) / f64::from(MAX_VOLUME)).log10()).max(LOGARITHMIC_FADE_FLOOR_DB),
+    }
+}
+
+fn db_to_volume(db: f64) -> f64 {
+    match db <= LOGARITHMIC_FADE_FLOOR_DB {
+        true => 0.0,
+        false => f64::from(MAX_VOLUME) * 10f64.powf(db / 20.0),
+    }
+}
+
+/// How far `elapsed` is into `duration` (from 0.0 to 1.0)
+fn progress(elapsed: Duration, duration: Duration) -> f64 {
+    match duration.is_zero() {
+        true => 1.0,
+        false => (elapsed.as_secs_f64() / duration.as_secs_f64()).min(1.0),
+    }
+}
+
+/// What to do once a fade has ended
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum AfterFade {
+    /// Leave the player as is
+    #[default]
+    Nothing,
+    /// Pause, then restore the volume from before the fade
+    Pause,
+    /// Stop, then restore the volume from before the fade
+    Stop,
+}
+
+/// Stop playback after some time, and/or at the end of a track
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SleepTimer {
+    /// How long to wait, from the time the timer is added to the scheduler
+    pub after: Option<Duration>,
+    /// Then, let the current track finish
+    pub finish_track: bool,
+    /// Fade out before stopping. The volume is restored once stopped
+    pub fade_out: Option<(Duration, FadeCurve)>,
+}
+
+impl SleepTimer {
+    /// Stop after some time
+    pub fn after(duration: Duration) -> Self {
+        Self { after: Some(duration), ..Self::default() }
+    }
+
+    /// Stop at the end of the current track
+    pub fn end_of_track() -> Self {
+        Self { finish_track: true, ..Self::default() }
+    }
+
+    /// Also let the track that plays when time is up finish
+    pub fn finish_track(mut self) -> Self {
+        self.finish_track = true;
+        self
+    }
+
+    pub fn fade_out(mut self, duration: Duration, curve: FadeCurve) -> Self {
+        self.fade_out = Some((duration, curve));
+        self
+    }
+}
+
+/// Start a playlist at a given time, with a rising volume
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Alarm {
+    pub at: SystemTime,
+    pub playlist: PersistentId,
+    /// The volume to reach, from silence
+    pub volume: LONG,
+    /// How long the volume takes to rise
+    pub ramp: Duration,
+    pub curve: FadeCurve,
+}
+
+/// Something the scheduler does
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Task {
+    /// Bring the volume from its current level to `to`
+    Fade { to: LONG, duration: Duration, curve: FadeCurve, then: AfterFade },
+    SleepTimer(SleepTimer),
+    Alarm(Alarm),
+}
+
+/// Identifies the tasks of a [`Scheduler`]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct TaskId(u64);
+
+/// What happened during a [`Scheduler::tick`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ScheduleEvent {
+    FadeFinished(TaskId),
+    /// A sleep timer has started to fade out
+    SleepFadeStarted(TaskId),
+    /// A sleep timer has stopped playback
+    SleepTimerExpired(TaskId),
+    /// An alarm has started its playlist
+    AlarmStarted(TaskId),
+    /// The volume of an alarm has reached its level
+    AlarmRampFinished(TaskId),
+}
+
+/// The progress of a task
+#[derive(Clone, Copy, Debug)]
+enum TaskState {
+    Pending,
+    /// A fade or an alarm ramp has started at this time, from this volume
+    Ramping { started: SystemTime, from: LONG },
+    /// A sleep timer waits for the end of this track
+    WaitingForTrackEnd { track: Option<PersistentId> },
+}
+
+#[derive(Debug)]
+struct ScheduledTask {
+    id: TaskId,
+    added: SystemTime,
+    task: Task,
+    state: TaskState,
+    /// The volume to restore once a sleep timer or a fade has stopped playback
+    restore_volume: Option<LONG>,
+}
+
+/// Runs [`Task`]s according to a [`Clock`]
+///
+/// Tasks are independent. Tasks that change the volume at the same time override each other, in the order they have been added.
+#[derive(Debug)]
+pub struct Scheduler<C: Clock> {
+    clock: C,
+    tasks: Vec<ScheduledTask>,
+    next_id: u64,
+}
+
+impl<C: Clock> Scheduler<C> {
+    pub fn new(clock: C) -> Self {
+        Self { clock, tasks: Vec::new(), next_id: 0 }
+    }
+
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    /// Schedule a task. Durations of sleep timers count from now
+    pub fn add(&mut self, task: Task) -> TaskId {
+        let id = TaskId(self.next_id);
+        self.next_id += 1;
+        self.tasks.push(ScheduledTask { id, added: self.clock.now(), task, state: TaskState::Pending, restore_volume: None });
+        id
+    }
+
+    /// Remove a task, leaving the player as is. Returns `false` if the task has already ended
+    pub fn cancel(&mut self, id: TaskId) -> bool {
+        let count = self.tasks.len();
+        self.tasks.retain(|t| t.id != id);
+        self.tasks.len() != count
+    }
+
+    /// The tasks that have not ended yet
+    pub fn tasks(&self) -> impl Iterator<Item = (TaskId, &Task)> {
+        self.tasks.iter().map(|t| (t.id, &t.task))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// Make every task progress, according to the current time of the clock.
+    ///
+    /// Tasks that have ended are removed. If the player fails, the remaining tasks are left for the next tick.
+    pub fn tick<P: PlayerControl + ?Sized>(&mut self, player: &mut P) -> windows::core::Result<Vec<ScheduleEvent>> {
+        let now = self.clock.now();
+        let mut events = Vec::new();
+        let mut index = 0;
+        while index < self.tasks.len() {
+            if self.tasks[index].step(now, player, &mut events)? {
+                self.tasks.remove(index);
+            } else {
+                index += 1;
+            }
+        }
+        Ok(events)
+    }
+
+    /// Call [`tick`](Scheduler::tick) every `interval`, until every task has ended or `keep_going` returns `false`.
+    ///
+    /// `on_event` is called with every event. This runs on the calling thread (COM objects should not be shared between threads).
+    pub fn run<P, E, F>(&mut self, player: &mut P, interval: Duration, mut on_event: E, mut keep_going: F) -> windows::core::Result<()>
+    where
+        P: PlayerControl + ?Sized,
+        E: FnMut(&ScheduleEvent),
+        F: FnMut() -> bool,
+    {
+        while !self.is_empty() && keep_going() {
+            for event in self.tick(player)? {
+                on_event(&event);
+            }
+            if !self.is_empty() {
+                self.clock.sleep(interval);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ScheduledTask {
+    /// Make this task progress. Returns whether it has ended
+    fn step<P: PlayerControl + ?Sized>(&mut self, now: SystemTime, player: &mut P, events: &mut Vec<ScheduleEvent>) -> windows::core::Result<bool> {
+        match self.task {
+            Task::Fade { to, duration, curve, then } => {
+                let (started, from) = match self.state {
+                    TaskState::Ramping { started, from } => (started, from),
+                    _ => {
+                        let from = player.volume()?;
+                        self.state = TaskState::Ramping { started: now, from };
+                        (now, from)
+                    },
+                };
+                let progress = progress(now.duration_since(started).unwrap_or_default(), duration);
+                player.set_volume(curve.volume(from, to, progress))?;
+                if progress < 1.0 {
+                    return Ok(false);
+                }
+                match then {
+                    AfterFade::Nothing => (),
+                    AfterFade::Pause => {
+                        player.pause()?;
+                        player.set_volume(from)?;
+                    },
+                    AfterFade::Stop => {
+                        player.stop()?;
+                        player.set_volume(from)?;
+                    },
+                }
+                events.push(ScheduleEvent::FadeFinished(self.id));
+                Ok(true)
+            },
+
+            Task::SleepTimer(timer) => {
+                let left = match self.sleep_time_left(&timer, now, player)? {
+                    Some(left) => left,
+                    None => return Ok(false),
+                };
+                if left.is_zero() {
+                    player.stop()?;
+                    if let Some(volume) = self.restore_volume {
+                        player.set_volume(volume)?;
+                    }
+                    events.push(ScheduleEvent::SleepTimerExpired(self.id));
+                    return Ok(true);
+                }
+                if let Some((duration, curve)) = timer.fade_out {
+                    if left <= duration {
+                        let from = match self.restore_volume {
+                            Some(volume) => volume,
+                            None => {
+                                events.push(ScheduleEvent::SleepFadeStarted(self.id));
+                                *self.restore_volume.insert(player.volume()?)
+                            },
+                        };
+                        // Based on the time left rather than the time elapsed, so that seeking within the track is followed
+                        player.set_volume(curve.volume(from, 0, 1.0 - progress(left, duration)))?;
+                    }
+                }
+                Ok(false)
+            },
+
+            Task::Alarm(alarm) => {
+                let started = match self.state {
+                    TaskState::Ramping { started, .. } => started,
+                    _ if now < alarm.at => return Ok(false),
+                    _ => {
+                        // Start from silence, so that the playlist does not start at the last volume.
+                        // The volume is put back if the playlist cannot be played
+                        let previous = player.volume()?;
+                        player.set_volume(0)?;
+                        if let Err(err) = player.play_playlist(alarm.playlist) {
+                            player.set_volume(previous)?;
+                            return Err(err);
+                        }
+                        self.state = TaskState::Ramping { started: now, from: 0 };
+                        events.push(ScheduleEvent::AlarmStarted(self.id));
+                        now
+                    },
+                };
+                let progress = progress(now.duration_since(started).unwrap_or_default(), alarm.ramp);
+                player.set_volume(alarm.curve.volume(0, alarm.volume, progress))?;
+                if progress < 1.0 {
+                    return Ok(false);
+                }
+                events.push(ScheduleEvent::AlarmRampFinished(self.id));
+                Ok(true)
+            },
+        }
+    }
+
+    /// How long until a sleep timer stops playback, or `None` if this is not known yet
+    fn sleep_time_left<P: PlayerControl + ?Sized>(&mut self, timer: &SleepTimer, now: SystemTime, player: &mut P) -> windows::core::Result<Option<Duration>> {
+        if let Some(after) = timer.after {
+            let left = (self.added + after).duration_since(now).unwrap_or_default();
+            if !left.is_zero() {
+                // The track to finish is the one playing when time is up
+                return Ok(if timer.finish_track { None } else { Some(left) });
+            }
+        }
+        if !timer.finish_track {
+            return Ok(Some(Duration::ZERO));
+        }
+
+        let sample = player.sample()?;
+        let track = match self.state {
+            TaskState::WaitingForTrackEnd { track } => track,
+            _ => {
+                self.state = TaskState::WaitingForTrackEnd { track: sample.persistent_id() };
+                sample.persistent_id()
+            },
+        };
+        // Once the track has ended, iTunes goes on with the next one
+        if !sample.playing || track.is_none() || sample.persistent_id() != track {
+            return Ok(Some(Duration::ZERO));
+        }
+        let duration = sample.track.as_ref().map_or(0, |t| t.duration);
+        Ok(Some(Duration::from_secs((duration - sample.position).max(0) as u64)))
+    }
+}
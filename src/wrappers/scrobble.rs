@@ -0,0 +1,389 @@
+//! Local scrobble journal
+//!
+//! A [`Scrobbler`] is fed with consecutive [`PlayerSample`]s, follows the [`PlayerEvent`]s between them, and tells when a track has been listened to (or skipped).<br/>
+//! It does not talk to iTunes by itself, so that it can be fed with a synthetic timeline as well. See [`watch`] to feed it from a live iTunes instance.
+//!
+//! The resulting [`Scrobble`]s can be appended to a [`ScrobbleJournal`], so that other tools can submit them later:
+//! * [`ScrobblerLog`] writes the `.scrobbler.log` format (as defined by the Audioscrobbler "portable player logging" specification, version 1.1)
+//! * [`JsonLinesJournal`] writes one JSON object per line (requires the `json` Cargo feature)
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::iTunes;
+use super::player::{PlayerEvent, PlayerSample, PlayingTrack};
+use super::LONG;
+
+/// How much the player position may drift from the wall clock before we consider the user has seeked within the track
+const SEEK_TOLERANCE: Duration = Duration::from_secs(2);
+
+/// The rules that tell whether a track has been listened to
+///
+/// The default values are the usual ones: a track is scrobbled once it has been played for half its duration, or for four minutes, whichever comes first.
+/// Tracks shorter than 30 seconds are never scrobbled.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScrobbleRules {
+    /// Tracks shorter than this are ignored altogether (in seconds)
+    pub min_track_duration: LONG,
+    /// The fraction of the track duration that must have been played
+    pub min_played_fraction: f64,
+    /// Playing a track for this long is always enough, regardless of its duration (in seconds)
+    pub max_required_playtime: LONG,
+}
+
+impl Default for ScrobbleRules {
+    fn default() -> Self {
+        Self {
+            min_track_duration: 30,
+            min_played_fraction: 0.5,
+            max_required_playtime: 240,
+        }
+    }
+}
+
+impl ScrobbleRules {
+    /// Whether a track of this duration should be logged at all
+    pub fn is_eligible(&self, duration: LONG) -> bool {
+        duration >= self.min_track_duration
+    }
+
+    /// How long a track of this duration must be played before it is scrobbled (in seconds)
+    pub fn required_playtime(&self, duration: LONG) -> f64 {
+        let fraction = f64::from(duration) * self.min_played_fraction;
+        fraction.min(f64::from(self.max_required_playtime))
+    }
+
+    /// Whether a track of this duration, that has been played for `played`, counts as listened
+    pub fn is_listened(&self, duration: LONG, played: Duration) -> bool {
+        self.is_eligible(duration) && played.as_secs_f64() >= self.required_playtime(duration)
+    }
+}
+
+/// Whether a track has been listened to, or skipped
+///
+/// These are the `L` and `S` ratings of the `.scrobbler.log` format
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ScrobbleStatus {
+    Listened,
+    Skipped,
+}
+
+impl ScrobbleStatus {
+    /// The code used in `.scrobbler.log` files
+    pub fn code(&self) -> &'static str {
+        match self {
+            ScrobbleStatus::Listened => "L",
+            ScrobbleStatus::Skipped => "S",
+        }
+    }
+}
+
+/// A finished play of a track
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Scrobble {
+    pub track: PlayingTrack,
+    /// When the track started playing
+    pub started: SystemTime,
+    /// How long the track has actually been played (seeking does not count)
+    pub played: Duration,
+    pub status: ScrobbleStatus,
+}
+
+impl Scrobble {
+    /// The start time, as a UNIX timestamp
+    pub fn timestamp(&self) -> u64 {
+        self.started.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+    }
+}
+
+/// The track that is currently being listened to
+#[derive(Debug)]
+struct Listening {
+    track: PlayingTrack,
+    started: SystemTime,
+    played: Duration,
+}
+
+/// Turns a timeline of [`PlayerSample`]s into [`Scrobble`]s
+#[derive(Debug, Default)]
+pub struct Scrobbler {
+    rules: ScrobbleRules,
+    previous: Option<PlayerSample>,
+    current: Option<Listening>,
+}
+
+impl Scrobbler {
+    pub fn new(rules: ScrobbleRules) -> Self {
+        Self { rules, previous: None, current: None }
+    }
+
+    pub fn rules(&self) -> &ScrobbleRules {
+        &self.rules
+    }
+
+    /// Feed the next sample of the timeline.
+    ///
+    /// Samples must be fed in chronological order.
+    /// This returns a scrobble when a play has just ended (because the track has changed, or has been restarted)
+    pub fn feed(&mut self, sample: PlayerSample) -> Option<Scrobble> {
+        let mut finished = None;
+        let mut started = None;
+
+        // The first sample is compared with a stopped player, so that a track that is already playing starts a play
+        let stopped = PlayerSample { time: sample.time, playing: false, track: None, position: 0 };
+        for event in sample.events_since(self.previous.as_ref().unwrap_or(&stopped)) {
+            match event {
+                PlayerEvent::PlayingTrackChanged(_) => finished = self.end_play(),
+                PlayerEvent::Play(track) => started = Some(track),
+                // Paused time is just not counted
+                PlayerEvent::Stop => (),
+            }
+        }
+
+        // Unless the track has changed, the current play goes on
+        if let (Some(previous), true) = (&self.previous, self.current.is_some()) {
+            let elapsed = sample.time.duration_since(previous.time).unwrap_or_default();
+
+            if Self::has_restarted(previous, &sample, elapsed) {
+                // e.g. "repeat one": this is a new play of the same track, although the player reports no event
+                finished = self.end_play();
+                started = sample.track.clone().filter(|_| sample.playing);
+            } else if previous.playing && sample.playing {
+                let listened = Self::listened_between(previous, &sample, elapsed);
+                if let Some(listening) = &mut self.current {
+                    listening.played += listened;
+                }
+            }
+        }
+
+        // Resuming a paused track is not a new play
+        if let (Some(track), None) = (started, &self.current) {
+            self.current = Some(Listening { track, started: sample.time, played: Duration::ZERO });
+        }
+
+        self.previous = Some(sample);
+        finished
+    }
+
+    /// End the current play (if any), e.g. because we are going to stop watching the player.
+    ///
+    /// The next sample starts a new timeline: a track that is playing then starts a new play.
+    pub fn finish(&mut self) -> Option<Scrobble> {
+        self.previous = None;
+        self.end_play()
+    }
+
+    /// End the current play, and scrobble it if it is eligible
+    fn end_play(&mut self) -> Option<Scrobble> {
+        let listening = self.current.take()?;
+        if !self.rules.is_eligible(listening.track.duration) {
+            return None;
+        }
+
+        let status = match self.rules.is_listened(listening.track.duration, listening.played) {
+            true => ScrobbleStatus::Listened,
+            false => ScrobbleStatus::Skipped,
+        };
+
+        Some(Scrobble {
+            track: listening.track,
+            started: listening.started,
+            played: listening.played,
+            status,
+        })
+    }
+
+    /// Whether the track would have reached its end between the two samples, and started over from the beginning
+    fn has_restarted(previous: &PlayerSample, sample: &PlayerSample, elapsed: Duration) -> bool {
+        let duration = match &sample.track {
+            Some(track) => track.duration,
+            None => return false,
+        };
+        let elapsed = elapsed + SEEK_TOLERANCE;
+
+        sample.position < previous.position
+            && Duration::from_secs(sample.position.max(0) as u64) <= elapsed
+            && Duration::from_secs((duration - previous.position).max(0) as u64) <= elapsed
+    }
+
+    /// How much of the track has been listened to between two samples taken while playing
+    ///
+    /// Intervals that contain a seek do not count at all
+    fn listened_between(previous: &PlayerSample, sample: &PlayerSample, elapsed: Duration) -> Duration {
+        let progress = sample.position - previous.position;
+        if progress < 0 {
+            return Duration::ZERO;
+        }
+
+        let progress = Duration::from_secs(progress as u64);
+        if progress > elapsed + SEEK_TOLERANCE {
+            return Duration::ZERO;
+        }
+        elapsed
+    }
+}
+
+/// Something scrobbles can be appended to
+pub trait ScrobbleJournal {
+    fn append(&mut self, scrobble: &Scrobble) -> std::io::Result<()>;
+}
+
+/// Open a file in append mode, and tell whether it was empty
+fn open_for_append(path: &Path) -> std::io::Result<(File, bool)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let is_empty = file.metadata()?.len() == 0;
+    Ok((file, is_empty))
+}
+
+/// A journal in the `.scrobbler.log` format
+pub struct ScrobblerLog<W: Write> {
+    writer: W,
+}
+
+impl ScrobblerLog<File> {
+    /// Open (or create) a `.scrobbler.log` file.
+    ///
+    /// The header is written in case the file is empty. `client` identifies the software that writes this log (e.g. `"itunes-com 0.2.0"`).
+    pub fn open<P: AsRef<Path>>(path: P, client: &str) -> std::io::Result<Self> {
+        let (file, is_empty) = open_for_append(path.as_ref())?;
+        let mut log = Self::new(file);
+        if is_empty {
+            log.write_header(client)?;
+        }
+        Ok(log)
+    }
+}
+
+impl<W: Write> ScrobblerLog<W> {
+    /// Wrap a writer. No header is written, see [`Self::write_header`]
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Write the header that must start every `.scrobbler.log` file
+    pub fn write_header(&mut self, client: &str) -> std::io::Result<()> {
+        write!(self.writer, "#AUDIOSCROBBLER/1.1\n#TZ/UTC\n#CLIENT/{}\n", Self::sanitize(client))?;
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Fields are tab-separated, and lines are newline-separated
+    fn sanitize(field: &str) -> String {
+        field.replace(['\t', '\r', '\n'], " ")
+    }
+}
+
+impl<W: Write> ScrobbleJournal for ScrobblerLog<W> {
+    fn append(&mut self, scrobble: &Scrobble) -> std::io::Result<()> {
+        let track = &scrobble.track;
+        let track_number = match track.track_number {
+            n if n > 0 => n.to_string(),
+            _ => String::new(),
+        };
+
+        // artist, album, title, track number, duration, rating, timestamp, MusicBrainz track ID
+        writeln!(self.writer, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t",
+            Self::sanitize(&track.artist),
+            Self::sanitize(&track.album),
+            Self::sanitize(&track.name),
+            track_number,
+            track.duration,
+            scrobble.status.code(),
+            scrobble.timestamp(),
+        )?;
+        self.writer.flush()
+    }
+}
+
+/// A journal that writes one JSON object per line
+#[cfg(feature = "json")]
+pub struct JsonLinesJournal<W: Write> {
+    writer: W,
+}
+
+#[cfg(feature = "json")]
+impl JsonLinesJournal<File> {
+    /// Open (or create) a JSON lines file
+    pub fn open<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let (file, _) = open_for_append(path.as_ref())?;
+        Ok(Self::new(file))
+    }
+}
+
+#[cfg(feature = "json")]
+impl<W: Write> JsonLinesJournal<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(feature = "json")]
+impl<W: Write> ScrobbleJournal for JsonLinesJournal<W> {
+    fn append(&mut self, scrobble: &Scrobble) -> std::io::Result<()> {
+        let track = &scrobble.track;
+        let value = serde_json::json!({
+            "persistent_id": format!("{:016X}", track.persistent_id),
+            "artist": track.artist,
+            "album": track.album,
+            "title": track.name,
+            "track_number": track.track_number,
+            "duration": track.duration,
+            "played": scrobble.played.as_secs(),
+            "status": scrobble.status.code(),
+            "timestamp": scrobble.timestamp(),
+        });
+        serde_json::to_writer(&mut self.writer, &value)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()
+    }
+}
+
+impl<J: ScrobbleJournal + ?Sized> ScrobbleJournal for Box<J> {
+    fn append(&mut self, scrobble: &Scrobble) -> std::io::Result<()> {
+        (**self).append(scrobble)
+    }
+}
+
+impl<J: ScrobbleJournal> ScrobbleJournal for Vec<J> {
+    fn append(&mut self, scrobble: &Scrobble) -> std::io::Result<()> {
+        for journal in self.iter_mut() {
+            journal.append(scrobble)?;
+        }
+        Ok(())
+    }
+}
+
+/// Poll a live iTunes instance every `interval`, and append scrobbles to `journal`.
+///
+/// This runs until `keep_going` returns `false`. The play that is in progress at this time is then finished and journaled as well.
+///
+/// By default, skipped tracks are journaled as well. Set `listened_only` to only journal tracks that have been listened to.
+pub fn watch<J, F>(iTunes: &iTunes, scrobbler: &mut Scrobbler, journal: &mut J, interval: Duration, listened_only: bool, mut keep_going: F) -> std::io::Result<()>
+where
+    J: ScrobbleJournal + ?Sized,
+    F: FnMut() -> bool,
+{
+    let mut journal_scrobble = |scrobble: Option<Scrobble>| -> std::io::Result<()> {
+        match scrobble {
+            Some(s) if !listened_only || s.status == ScrobbleStatus::Listened => journal.append(&s),
+            _ => Ok(()),
+        }
+    };
+
+    while keep_going() {
+        let sample = PlayerSample::from_iTunes(iTunes)?;
+        journal_scrobble(scrobbler.feed(sample))?;
+        std::thread::sleep(interval);
+    }
+
+    journal_scrobble(scrobbler.finish())
+}
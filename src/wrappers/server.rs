@@ -0,0 +1,224 @@
+//! What the [`rpc`](super::rpc), [`mpd`](super::mpd) and [`http`](super::http) servers have in common
+//!
+//! * [`TrackInfo`] and [`PlaylistInfo`], the info of tracks and playlists the JSON APIs return
+//! * the background threads that accept connections
+//! * how the snapshot backends go from track to track
+
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+#[cfg(feature = "json")]
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "json")]
+use crate::sys::ITPlaylistKind;
+#[cfg(feature = "json")]
+use super::{iTunes, Playlist, Iterable, IITObjectWrapper, IITPlaylistWrapper};
+#[cfg(feature = "json")]
+use super::library::{PlaylistSnapshot, TrackSnapshot, playlist_kind, format_persistent_id, parse_persistent_id};
+use super::library::LibrarySnapshot;
+use super::types::PersistentId;
+#[cfg(feature = "json")]
+use super::LONG;
+
+/// How often the accepting threads check whether their server has been dropped
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Persistent IDs are serialized as [`format_persistent_id`] does
+#[cfg(feature = "json")]
+pub(crate) mod hex_id {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use super::{PersistentId, format_persistent_id, parse_persistent_id};
+
+    pub fn serialize<S: Serializer>(id: &PersistentId, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format_persistent_id(*id))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PersistentId, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        parse_persistent_id(&text).ok_or_else(|| serde::de::Error::custom(format!("invalid persistent ID {:?}", text)))
+    }
+
+    pub mod option {
+        use serde::{Deserialize, Deserializer, Serializer};
+        use super::super::PersistentId;
+
+        pub fn serialize<S: Serializer>(id: &Option<PersistentId>, serializer: S) -> Result<S::Ok, S::Error> {
+            match id {
+                Some(id) => super::serialize(id, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<PersistentId>, D::Error> {
+            #[derive(Deserialize)]
+            struct Wrapper(#[serde(with = "super")] PersistentId);
+            Ok(Option::<Wrapper>::deserialize(deserializer)?.map(|Wrapper(id)| id))
+        }
+    }
+}
+
+/// The info of a track, as returned by the servers
+#[cfg(feature = "json")]
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrackInfo {
+    #[serde(with = "hex_id")]
+    pub persistent_id: PersistentId,
+    pub name: String,
+    pub artist: String,
+    pub album_artist: String,
+    pub album: String,
+    pub genre: String,
+    pub year: LONG,
+    pub track_number: LONG,
+    /// In seconds
+    pub duration: LONG,
+    /// From 0 to 100 (20 per star)
+    pub rating: LONG,
+    pub played_count: LONG,
+}
+
+#[cfg(feature = "json")]
+impl From<&TrackSnapshot> for TrackInfo {
+    fn from(track: &TrackSnapshot) -> Self {
+        Self {
+            persistent_id: track.persistent_id,
+            name: track.name.clone(),
+            artist: track.artist.clone(),
+            album_artist: track.album_artist.clone(),
+            album: track.album.clone(),
+            genre: track.genre.clone(),
+            year: track.year,
+            track_number: track.track_number,
+            duration: track.duration,
+            rating: track.rating,
+            played_count: track.played_count,
+        }
+    }
+}
+
+/// The info of a playlist, as returned by the servers
+#[cfg(feature = "json")]
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlaylistInfo {
+    #[serde(with = "hex_id")]
+    pub persistent_id: PersistentId,
+    pub name: String,
+    /// The folder that contains this playlist, if any
+    #[serde(with = "hex_id::option")]
+    pub parent: Option<PersistentId>,
+    pub is_folder: bool,
+    pub is_smart: bool,
+    pub track_count: usize,
+}
+
+#[cfg(feature = "json")]
+impl PlaylistInfo {
+    /// Read the info of a live playlist. Only the number of its tracks is read, rather than the tracks one by one
+    pub fn from_playlist(playlist: &Playlist) -> windows::core::Result<Self> {
+        let (parent, is_folder, is_smart) = playlist_kind(playlist)?;
+        let track_count = match is_folder {
+            true => 0,
+            false => playlist.Tracks()?.Count()? as usize,
+        };
+        Ok(Self { persistent_id: playlist.persistent_id()?, name: playlist.Name()?, parent, is_folder, is_smart, track_count })
+    }
+}
+
+#[cfg(feature = "json")]
+impl From<&PlaylistSnapshot> for PlaylistInfo {
+    fn from(playlist: &PlaylistSnapshot) -> Self {
+        Self {
+            persistent_id: playlist.persistent_id,
+            name: playlist.name.clone(),
+            parent: playlist.parent,
+            is_folder: playlist.is_folder,
+            is_smart: playlist.is_smart,
+            track_count: playlist.tracks.len(),
+        }
+    }
+}
+
+/// The info of the playlists of a live library (apart from the main library playlist)
+#[cfg(feature = "json")]
+pub fn library_playlists(iTunes: &iTunes) -> windows::core::Result<Vec<PlaylistInfo>> {
+    let mut playlists = Vec::new();
+    for playlist in iTunes.LibrarySource()?.Playlists()?.iter()? {
+        if playlist.Kind()? != ITPlaylistKind::ITPlaylistKindLibrary {
+            playlists.push(PlaylistInfo::from_playlist(&playlist)?);
+        }
+    }
+    Ok(playlists)
+}
+
+/// Accepts connections on background threads, until it is dropped
+pub(crate) struct Acceptor {
+    stopped: Arc<AtomicBool>,
+}
+
+impl Acceptor {
+    pub fn new() -> Self {
+        Self { stopped: Arc::new(AtomicBool::new(false)) }
+    }
+
+    /// Accept connections on an address, and serve each of them with `serve`, on a thread of its own.
+    /// `serve` is given the connection, and its number (counted from 0 for every address).
+    ///
+    /// This returns the actual address, which is useful when binding port 0.
+    pub fn listen<A, F>(&self, address: A, serve: F) -> std::io::Result<SocketAddr>
+    where
+        A: ToSocketAddrs,
+        F: Fn(TcpStream, u64) -> std::io::Result<()> + Clone + Send + 'static,
+    {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        let local_address = listener.local_addr()?;
+
+        let stopped = Arc::clone(&self.stopped);
+        std::thread::spawn(move || {
+            let mut next_connection = 0;
+            while !stopped.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let serve = serve.clone();
+                        let connection = next_connection;
+                        next_connection += 1;
+                        std::thread::spawn(move || {
+                            // Errors only affect this connection
+                            let _ = stream.set_nonblocking(false).and_then(|_| serve(stream, connection));
+                        });
+                    },
+                    Err(_) => std::thread::sleep(ACCEPT_POLL_INTERVAL),
+                }
+            }
+        });
+
+        Ok(local_address)
+    }
+}
+
+impl Drop for Acceptor {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+/// The error of connection threads whose server has stopped
+pub(crate) fn server_stopped() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::BrokenPipe, "The server has stopped")
+}
+
+/// The track the snapshot backends go to, as they play the tracks of a library in order of persistent IDs.
+///
+/// Without a current track, this is the first track. Going back from the first track restarts it, and going past the last track gives `None`.
+pub(crate) fn skip(library: &LibrarySnapshot, current: Option<PersistentId>, forward: bool) -> Option<PersistentId> {
+    match (current, forward) {
+        (None, _) => library.tracks.keys().next().copied(),
+        (Some(current), true) => library.tracks.range(current + 1..).next().map(|(id, _)| *id),
+        (Some(current), false) => library.tracks.range(..current).next_back().map(|(id, _)| *id).or(Some(current)),
+    }
+}
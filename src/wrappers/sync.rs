@@ -0,0 +1,335 @@
+//! Synchronization of play counts, ratings, etc. between two libraries
+//!
+//! This is typically useful when migrating to another machine: the usage statistics of a `source` library (e.g. parsed from the XML file of the old machine)
+//! are merged into a `target` library.
+//!
+//! Tracks are matched by persistent ID, or by a [`Fingerprint`] of their metadata in case persistent IDs differ (e.g. when the files have been imported again).<br/>
+//! [`plan`] computes every change before anything is written, so that it can be reviewed, then applied to a live iTunes instance (or to a snapshot).
+
+use std::collections::{HashMap, HashSet};
+use std::time::SystemTime;
+
+use super::{iTunes, IITPlaylistWrapper};
+use super::library::{LibrarySnapshot, TrackSnapshot, TrackField, FieldValue, FieldChange, set_track_field, normalize_text};
+use super::types::PersistentId;
+use super::LONG;
+
+/// The fields that are synchronized
+pub const SYNCED_FIELDS: [TrackField; 6] = [
+    TrackField::PlayedCount,
+    TrackField::SkippedCount,
+    TrackField::Rating,
+    TrackField::AlbumRating,
+    TrackField::PlayedDate,
+    TrackField::Enabled,
+];
+
+/// How to merge the source and target values of a field
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MergePolicy {
+    /// Do not change the target value
+    KeepTarget,
+    /// Overwrite the target value with the source value
+    UseSource,
+    /// Use the greatest value (the latest one for dates, `true` for booleans)
+    Max,
+    /// Add both values.<br/>
+    /// This only makes sense for counts, and behaves as `Max` for other fields. Beware that applying a plan twice would count twice.
+    Sum,
+    /// Use the value of the copy that has been played the most recently (or skipped the most recently, for `SkippedCount`).<br/>
+    /// The target value is kept when this cannot be decided.
+    NewestWins,
+}
+
+/// The policies to use for each synchronized field
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SyncPolicies {
+    pub played_count: MergePolicy,
+    pub skipped_count: MergePolicy,
+    pub rating: MergePolicy,
+    pub album_rating: MergePolicy,
+    pub played_date: MergePolicy,
+    pub enabled: MergePolicy,
+}
+
+impl Default for SyncPolicies {
+    fn default() -> Self {
+        Self {
+            played_count: MergePolicy::Max,
+            skipped_count: MergePolicy::Max,
+            rating: MergePolicy::NewestWins,
+            album_rating: MergePolicy::NewestWins,
+            played_date: MergePolicy::Max,
+            enabled: MergePolicy::NewestWins,
+        }
+    }
+}
+
+impl SyncPolicies {
+    /// Use the same policy for every field
+    pub fn all(policy: MergePolicy) -> Self {
+        Self {
+            played_count: policy,
+            skipped_count: policy,
+            rating: policy,
+            album_rating: policy,
+            played_date: policy,
+            enabled: policy,
+        }
+    }
+
+    /// The policy of a field. Fields that are not synchronized are never changed
+    pub fn policy(&self, field: TrackField) -> MergePolicy {
+        match field {
+            TrackField::PlayedCount => self.played_count,
+            TrackField::SkippedCount => self.skipped_count,
+            TrackField::Rating => self.rating,
+            TrackField::AlbumRating => self.album_rating,
+            TrackField::PlayedDate => self.played_date,
+            TrackField::Enabled => self.enabled,
+            _ => MergePolicy::KeepTarget,
+        }
+    }
+}
+
+/// Metadata that identify a track, regardless of its persistent ID
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Fingerprint {
+    pub artist: String,
+    pub album: String,
+    pub name: String,
+    pub track_number: LONG,
+}
+
+impl Fingerprint {
+    pub fn of(track: &TrackSnapshot) -> Self {
+        Self {
+            artist: normalize_text(&track.artist),
+            album: normalize_text(&track.album),
+            name: normalize_text(&track.name),
+            track_number: track.track_number,
+        }
+    }
+}
+
+/// How two tracks have been matched
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MatchKind {
+    PersistentId,
+    Fingerprint,
+}
+
+/// A source track, and the target track it matches
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TrackMatch {
+    pub source: PersistentId,
+    pub target: PersistentId,
+    pub kind: MatchKind,
+}
+
+/// The way tracks of two libraries match each other
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Matching {
+    pub matches: Vec<TrackMatch>,
+    /// Source tracks that have no match in the target library
+    pub unmatched: Vec<PersistentId>,
+    /// Source tracks whose fingerprint matches several target tracks (or is shared with other source tracks)
+    pub ambiguous: Vec<PersistentId>,
+}
+
+/// Match the tracks of two libraries.
+///
+/// Tracks are matched by persistent ID first. Remaining tracks are matched by [`Fingerprint`], provided their durations do not differ by more than `duration_tolerance` seconds.
+/// Every target track is matched at most once.
+pub fn match_tracks(source: &LibrarySnapshot, target: &LibrarySnapshot, duration_tolerance: LONG) -> Matching {
+    let mut matching = Matching::default();
+    let mut used_targets = HashSet::new();
+    let mut remaining = Vec::new();
+
+    for id in source.tracks.keys() {
+        if target.tracks.contains_key(id) {
+            matching.matches.push(TrackMatch { source: *id, target: *id, kind: MatchKind::PersistentId });
+            used_targets.insert(*id);
+        } else {
+            remaining.push(*id);
+        }
+    }
+
+    let mut targets_by_fingerprint: HashMap<Fingerprint, Vec<&TrackSnapshot>> = HashMap::new();
+    for track in target.tracks.values().filter(|t| !used_targets.contains(&t.persistent_id)) {
+        targets_by_fingerprint.entry(Fingerprint::of(track)).or_default().push(track);
+    }
+    let mut source_fingerprint_count: HashMap<Fingerprint, usize> = HashMap::new();
+    for id in &remaining {
+        *source_fingerprint_count.entry(Fingerprint::of(&source.tracks[id])).or_default() += 1;
+    }
+
+    for id in remaining {
+        let track = &source.tracks[&id];
+        let fingerprint = Fingerprint::of(track);
+        let candidates: Vec<&TrackSnapshot> = targets_by_fingerprint.get(&fingerprint)
+            .map(|c| c.iter().copied().filter(|t| (t.duration - track.duration).abs() <= duration_tolerance).collect())
+            .unwrap_or_default();
+
+        match candidates.as_slice() {
+            [] => matching.unmatched.push(id),
+            [candidate] if source_fingerprint_count[&fingerprint] == 1 => {
+                matching.matches.push(TrackMatch { source: id, target: candidate.persistent_id, kind: MatchKind::Fingerprint });
+            },
+            _ => matching.ambiguous.push(id),
+        }
+    }
+
+    matching
+}
+
+/// The changes to apply to a target track
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrackSyncChange {
+    pub source: PersistentId,
+    pub target: PersistentId,
+    pub matched_by: MatchKind,
+    pub changes: Vec<FieldChange>,
+}
+
+/// Every change a synchronization would make
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SyncPlan {
+    /// Target tracks that would change
+    pub tracks: Vec<TrackSyncChange>,
+    /// Source tracks that have no match in the target library
+    pub unmatched: Vec<PersistentId>,
+    /// Source tracks that could not be matched unambiguously
+    pub ambiguous: Vec<PersistentId>,
+}
+
+/// Compute the changes that would merge the fields of `source` tracks into `target` tracks
+pub fn plan(source: &LibrarySnapshot, target: &LibrarySnapshot, policies: &SyncPolicies, duration_tolerance: LONG) -> SyncPlan {
+    let matching = match_tracks(source, target, duration_tolerance);
+
+    let tracks = matching.matches.iter()
+        .filter_map(|m| {
+            let source_track = &source.tracks[&m.source];
+            let target_track = &target.tracks[&m.target];
+            let changes = merge_tracks(source_track, target_track, policies);
+            if changes.is_empty() {
+                return None;
+            }
+            Some(TrackSyncChange { source: m.source, target: m.target, matched_by: m.kind, changes })
+        })
+        .collect();
+
+    SyncPlan {
+        tracks,
+        unmatched: matching.unmatched,
+        ambiguous: matching.ambiguous,
+    }
+}
+
+/// The changes that would merge the fields of `source` into `target`
+pub fn merge_tracks(source: &TrackSnapshot, target: &TrackSnapshot, policies: &SyncPolicies) -> Vec<FieldChange> {
+    SYNCED_FIELDS.iter()
+        .filter_map(|field| {
+            let old = target.get(*field);
+            let new = merge_field(*field, source, target, policies.policy(*field));
+            match new == old {
+                true => None,
+                false => Some(FieldChange { field: *field, old, new }),
+            }
+        })
+        .collect()
+}
+
+/// Tells whether the source copy is more recent than the target copy, as far as `field` is concerned
+fn source_is_newest(field: TrackField, source: &TrackSnapshot, target: &TrackSnapshot) -> bool {
+    let (source_date, target_date) = match field {
+        TrackField::SkippedCount => (source.skipped_date, target.skipped_date),
+        _ => (source.played_date, target.played_date),
+    };
+    match (source_date, target_date) {
+        (Some(s), Some(t)) => s > t,
+        (Some(_), None) => true,
+        _ => false,
+    }
+}
+
+fn merge_field(field: TrackField, source: &TrackSnapshot, target: &TrackSnapshot, policy: MergePolicy) -> FieldValue {
+    let source_value = source.get(field);
+    let target_value = target.get(field);
+
+    match policy {
+        MergePolicy::KeepTarget => target_value,
+        MergePolicy::UseSource => source_value,
+        MergePolicy::NewestWins => match source_is_newest(field, source, target) {
+            true => source_value,
+            false => target_value,
+        },
+        MergePolicy::Max | MergePolicy::Sum => match (source_value, target_value) {
+            (FieldValue::Integer(s), FieldValue::Integer(t)) => {
+                let is_count = matches!(field, TrackField::PlayedCount | TrackField::SkippedCount);
+                match policy == MergePolicy::Sum && is_count {
+                    true => FieldValue::Integer(s + t),
+                    false => FieldValue::Integer(s.max(t)),
+                }
+            },
+            (FieldValue::Bool(s), FieldValue::Bool(t)) => FieldValue::Bool(s || t),
+            (FieldValue::Date(s), FieldValue::Date(t)) => FieldValue::Date(latest(s, t)),
+            (_, t) => t,
+        },
+    }
+}
+
+fn latest(a: Option<SystemTime>, b: Option<SystemTime>) -> Option<SystemTime> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.max(b)),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+/// A change that could not be written to iTunes
+#[derive(Debug)]
+pub struct SyncFailure {
+    pub target: PersistentId,
+    pub field: Option<TrackField>,
+    pub error: windows::core::Error,
+}
+
+impl SyncPlan {
+    /// Apply this plan to a snapshot
+    pub fn apply_to_snapshot(&self, target: &mut LibrarySnapshot) {
+        for track_change in &self.tracks {
+            if let Some(track) = target.track_mut(track_change.target) {
+                for change in &track_change.changes {
+                    track.set(change.field, &change.new);
+                }
+            }
+        }
+    }
+
+    /// Apply this plan to the main library of a live iTunes instance.
+    ///
+    /// This does not stop at the first failure (e.g. a locked track), but returns every change that could not be written.
+    pub fn apply(&self, iTunes: &iTunes) -> windows::core::Result<Vec<SyncFailure>> {
+        let library_tracks = iTunes.LibraryPlaylist()?.Tracks()?;
+        let mut failures = Vec::new();
+
+        for track_change in &self.tracks {
+            let track = match library_tracks.ItemByPersistentID(track_change.target) {
+                Ok(track) => track,
+                Err(error) => {
+                    failures.push(SyncFailure { target: track_change.target, field: None, error });
+                    continue;
+                },
+            };
+
+            for change in &track_change.changes {
+                if let Err(error) = set_track_field(&track, change.field, &change.new) {
+                    failures.push(SyncFailure { target: track_change.target, field: Some(change.field), error });
+                }
+            }
+        }
+
+        Ok(failures)
+    }
+}
@@ -1,10 +1,78 @@
 use std::marker::PhantomData;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use windows::Win32::System::Com::VARIANT;
+use windows::core::BSTR;
+use windows::Win32::Foundation::E_OUTOFMEMORY;
+use windows::Win32::System::Com::{VARIANT, VARIANT_0, VARIANT_0_0, VARIANT_0_0_0, VARENUM, VT_ARRAY, VT_BSTR, SAFEARRAY};
+use windows::Win32::System::Ole::{SafeArrayCreateVector, SafeArrayPutElement, SafeArrayDestroy};
 
 
 pub type PersistentId = u64;
 
+/// The number of days between the OLE automation epoch (1899-12-30) and the UNIX epoch
+const OLE_TO_UNIX_EPOCH_DAYS: f64 = 25569.0;
+const SECONDS_PER_DAY: f64 = 86400.0;
+
+/// Convert a COM `DATE` (as used by iTunes) to a `SystemTime`.
+///
+/// iTunes uses zero to tell there is no date, which is mapped to `None`.
+///
+/// Note: iTunes returns dates in local time, but `DATE`s do not carry any time zone info.
+/// This function considers they are UTC.
+pub fn date_to_system_time(date: f64) -> Option<SystemTime> {
+    if date == 0.0 {
+        return None;
+    }
+
+    let unix_seconds = (date - OLE_TO_UNIX_EPOCH_DAYS) * SECONDS_PER_DAY;
+    if unix_seconds >= 0.0 {
+        UNIX_EPOCH.checked_add(Duration::from_secs_f64(unix_seconds))
+    } else {
+        UNIX_EPOCH.checked_sub(Duration::from_secs_f64(-unix_seconds))
+    }
+}
+
+/// Convert a `SystemTime` into a COM `DATE` (as used by iTunes).
+///
+/// `None` is mapped to zero. See [`date_to_system_time`] about time zones.
+pub fn system_time_to_date(time: Option<SystemTime>) -> f64 {
+    let unix_seconds = match time {
+        None => return 0.0,
+        Some(t) => match t.duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_secs_f64(),
+            Err(e) => -e.duration().as_secs_f64(),
+        },
+    };
+
+    unix_seconds / SECONDS_PER_DAY + OLE_TO_UNIX_EPOCH_DAYS
+}
+
+/// Format a `SystemTime` as an RFC 3339 UTC date (e.g. `2023-01-31T18:42:00Z`)
+pub fn format_system_time(time: SystemTime) -> String {
+    let unix_seconds = match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    };
+    let days = unix_seconds.div_euclid(86400);
+    let seconds_of_day = unix_seconds.rem_euclid(86400);
+
+    // Civil date from a count of days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day,
+        seconds_of_day / 3600, (seconds_of_day % 3600) / 60, seconds_of_day % 60,
+    )
+}
+
 
 /// A wrapper around a COM VARIANT type
 pub struct Variant<'a, T: 'a> {
@@ -23,6 +91,53 @@ impl<'a, T> Variant<'a, T> {
     }
 }
 
+/// An array of strings, that can be passed as a `VT_ARRAY | VT_BSTR` variant (e.g. to `AddFiles`)
+pub struct StringArray {
+    array: *mut SAFEARRAY,
+}
+
+impl StringArray {
+    pub fn new<S: AsRef<str>>(strings: &[S]) -> windows::core::Result<Self> {
+        let array = unsafe { SafeArrayCreateVector(VT_BSTR, 0, strings.len() as u32) };
+        if array.is_null() {
+            return Err(E_OUTOFMEMORY.into());
+        }
+        // Constructed right now, so that the array is destroyed in case of error
+        let string_array = Self { array };
+
+        for (index, string) in strings.iter().enumerate() {
+            let raw = BSTR::from(string.as_ref()).into_raw();
+            // SafeArrayPutElement makes its own copy of the BSTR
+            let result = unsafe { SafeArrayPutElement(array, &(index as i32), raw as *const std::ffi::c_void) };
+            drop(unsafe { BSTR::from_raw(raw) });
+            result?;
+        }
+
+        Ok(string_array)
+    }
+
+    /// Get a variant that borrows this array
+    pub fn as_variant(&self) -> Variant<'_, StringArray> {
+        Variant::new(VARIANT{
+            Anonymous: VARIANT_0 {
+                Anonymous: std::mem::ManuallyDrop::new(VARIANT_0_0 {
+                    vt: VARENUM(VT_ARRAY.0 | VT_BSTR.0),
+                    Anonymous: VARIANT_0_0_0 {
+                        parray: self.array,
+                    },
+                    ..Default::default()
+                })
+            }
+        })
+    }
+}
+
+impl Drop for StringArray {
+    fn drop(&mut self) {
+        let _ = unsafe { SafeArrayDestroy(self.array) };
+    }
+}
+
 /// The rating of a track (one to five stars)
 pub enum Rating {
     /// No rating
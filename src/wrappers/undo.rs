@@ -0,0 +1,209 @@
+//! Undo journal for metadata edits
+//!
+//! Setters such as `set_Artist` write to iTunes immediately, and cannot be undone.<br/>
+//! Edits made through an [`EditSession`] record the previous value of every field they change, so that the whole session
+//! (or individual edits) can be rolled back later. With the `json` Cargo feature, the journal can be persisted to disk
+//! (see [`EditSession::open`]), so that an edit script can be rolled back after it has exited.
+
+use std::time::SystemTime;
+
+#[cfg(feature = "json")]
+use std::fs::{File, OpenOptions};
+#[cfg(feature = "json")]
+use std::io::{BufRead, BufReader, Write};
+#[cfg(feature = "json")]
+use std::path::Path;
+
+use super::{iTunes, Track, TrackCollection, IITObjectWrapper, IITPlaylistWrapper};
+use super::library::{TrackField, FieldValue, get_track_field, set_track_field, format_persistent_id};
+use super::types::PersistentId;
+
+/// A field that has been changed
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EditEntry {
+    pub track: PersistentId,
+    pub field: TrackField,
+    pub old: FieldValue,
+    pub new: FieldValue,
+    pub time: SystemTime,
+    /// Whether this edit has been rolled back
+    pub rolled_back: bool,
+}
+
+/// A line of a journal file
+#[cfg(feature = "json")]
+#[derive(serde::Serialize, serde::Deserialize)]
+enum JournalRecord {
+    Edit(EditEntry),
+    /// The index of an entry that has been rolled back
+    RolledBack(usize),
+}
+
+/// Why an edit could not be rolled back
+#[derive(Debug)]
+pub enum RollbackFailure {
+    /// The field has been changed since this edit. It has been left untouched
+    Conflict { current: FieldValue },
+    /// The track could not be found, the field could not be written, or the journal could not be updated
+    Error(std::io::Error),
+}
+
+impl From<std::io::Error> for RollbackFailure {
+    fn from(err: std::io::Error) -> Self {
+        Self::Error(err)
+    }
+}
+
+impl From<windows::core::Error> for RollbackFailure {
+    fn from(err: windows::core::Error) -> Self {
+        Self::Error(err.into())
+    }
+}
+
+/// A sequence of edits that can be rolled back
+#[derive(Debug, Default)]
+pub struct EditSession {
+    entries: Vec<EditEntry>,
+    #[cfg(feature = "json")]
+    journal: Option<File>,
+}
+
+impl EditSession {
+    /// Start a session that is only kept in memory
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open (or create) a journal file, where every edit is appended as a JSON line.
+    ///
+    /// Edits that are already in the file are loaded, so that they can be rolled back.
+    #[cfg(feature = "json")]
+    pub fn open<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let journal = OpenOptions::new().create(true).read(true).append(true).open(path)?;
+
+        let mut entries: Vec<EditEntry> = Vec::new();
+        for line in BufReader::new(&journal).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line)? {
+                JournalRecord::Edit(entry) => entries.push(entry),
+                JournalRecord::RolledBack(index) => {
+                    if let Some(entry) = entries.get_mut(index) {
+                        entry.rolled_back = true;
+                    }
+                },
+            }
+        }
+
+        Ok(Self { entries, journal: Some(journal) })
+    }
+
+    /// Every edit of this session, oldest first
+    pub fn entries(&self) -> &[EditEntry] {
+        &self.entries
+    }
+
+    /// Write a field of a track, and record its previous value.
+    ///
+    /// Nothing is written (nor recorded) if the field already has this value. This returns whether the field has been changed.
+    ///
+    /// The value recorded is the one iTunes has actually stored, which may differ from `value` (e.g. ratings are rounded down
+    /// to whole stars, and dates to the second), so that rolling back is not mistaken for a conflict.
+    pub fn set(&mut self, track: &Track, field: TrackField, value: &FieldValue) -> std::io::Result<bool> {
+        let old = get_track_field(track, field)?;
+        if &old == value {
+            return Ok(false);
+        }
+
+        set_track_field(track, field, value)?;
+        let new = get_track_field(track, field)?;
+        if new == old {
+            return Ok(false);
+        }
+        let entry = EditEntry {
+            track: track.persistent_id()?,
+            field,
+            old,
+            new,
+            time: SystemTime::now(),
+            rolled_back: false,
+        };
+
+        #[cfg(feature = "json")]
+        if let Err(err) = self.write_record(&JournalRecord::Edit(entry.clone())) {
+            // An edit that is not journaled could not be rolled back
+            let _ = set_track_field(track, field, &entry.old);
+            return Err(err);
+        }
+
+        self.entries.push(entry);
+        Ok(true)
+    }
+
+    /// Roll back a single edit.
+    ///
+    /// This does nothing if this edit has already been rolled back.
+    pub fn rollback_entry(&mut self, iTunes: &iTunes, index: usize) -> Result<(), RollbackFailure> {
+        let tracks = iTunes.LibraryPlaylist()?.Tracks()?;
+        self.rollback_entry_in(&tracks, index)
+    }
+
+    /// Roll back every edit of this session, latest first.
+    ///
+    /// This does not stop at the first failure, but returns the index of every entry that could not be rolled back.
+    pub fn rollback(&mut self, iTunes: &iTunes) -> windows::core::Result<Vec<(usize, RollbackFailure)>> {
+        let tracks = iTunes.LibraryPlaylist()?.Tracks()?;
+        let mut failures = Vec::new();
+        for index in (0..self.entries.len()).rev() {
+            if let Err(failure) = self.rollback_entry_in(&tracks, index) {
+                failures.push((index, failure));
+            }
+        }
+        Ok(failures)
+    }
+
+    fn rollback_entry_in(&mut self, tracks: &TrackCollection, index: usize) -> Result<(), RollbackFailure> {
+        let entry = match self.entries.get(index) {
+            None => return Err(RollbackFailure::Error(std::io::Error::new(std::io::ErrorKind::NotFound, "No such journal entry"))),
+            Some(entry) if entry.rolled_back => return Ok(()),
+            Some(entry) => entry,
+        };
+
+        let track = tracks.ItemByPersistentID(entry.track)?;
+        let current = get_track_field(&track, entry.field)?;
+        if current != entry.new {
+            return Err(RollbackFailure::Conflict { current });
+        }
+        set_track_field(&track, entry.field, &entry.old)?;
+
+        #[cfg(feature = "json")]
+        self.write_record(&JournalRecord::RolledBack(index))?;
+
+        self.entries[index].rolled_back = true;
+        Ok(())
+    }
+
+    #[cfg(feature = "json")]
+    fn write_record(&mut self, record: &JournalRecord) -> std::io::Result<()> {
+        if let Some(journal) = &mut self.journal {
+            let mut line = serde_json::to_vec(record)?;
+            line.push(b'\n');
+            journal.write_all(&line)?;
+            journal.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for EditEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}] {}: {} -> {}", format_persistent_id(self.track), self.field, self.old, self.new)?;
+        if self.rolled_back {
+            write!(f, " (rolled back)")?;
+        }
+        Ok(())
+    }
+}
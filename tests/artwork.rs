@@ -0,0 +1,139 @@
+//! Sniffs the format and reads the dimensions of synthetic image headers
+
+use itunes_com::wrappers::artwork::{ArtworkImage, ImageFormat};
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// The signature and IHDR chunk of a PNG image
+fn png(width: u32, height: u32) -> Vec<u8> {
+    let mut bytes = PNG_SIGNATURE.to_vec();
+    bytes.extend(13u32.to_be_bytes());
+    bytes.extend(b"IHDR");
+    bytes.extend(width.to_be_bytes());
+    bytes.extend(height.to_be_bytes());
+    // Bit depth, color type, compression, filter, interlace, and CRC
+    bytes.extend([8, 6, 0, 0, 0, 0, 0, 0, 0]);
+    bytes
+}
+
+/// The file header and BITMAPINFOHEADER of a BMP image
+fn bmp(width: i32, height: i32) -> Vec<u8> {
+    let mut bytes = b"BM".to_vec();
+    bytes.extend(54u32.to_le_bytes());
+    bytes.extend([0; 4]);
+    bytes.extend(54u32.to_le_bytes());
+    bytes.extend(40u32.to_le_bytes());
+    bytes.extend(width.to_le_bytes());
+    bytes.extend(height.to_le_bytes());
+    bytes.extend(1u16.to_le_bytes());
+    bytes.extend(24u16.to_le_bytes());
+    bytes.extend([0; 24]);
+    bytes
+}
+
+/// A JPEG segment, with its length
+fn segment(marker: u8, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![0xFF, marker];
+    bytes.extend((payload.len() as u16 + 2).to_be_bytes());
+    bytes.extend(payload);
+    bytes
+}
+
+/// A baseline start of frame
+fn sof0(width: u16, height: u16) -> Vec<u8> {
+    let mut payload = vec![8];
+    payload.extend(height.to_be_bytes());
+    payload.extend(width.to_be_bytes());
+    payload.extend([1, 1, 0x11, 0]);
+    segment(0xC0, &payload)
+}
+
+/// A JPEG image made of a start of image, and some segments
+fn jpeg(segments: &[Vec<u8>]) -> Vec<u8> {
+    let mut bytes = vec![0xFF, 0xD8];
+    for segment in segments {
+        bytes.extend(segment);
+    }
+    bytes
+}
+
+fn app0() -> Vec<u8> {
+    segment(0xE0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0")
+}
+
+fn dimensions(bytes: Vec<u8>) -> Option<(u32, u32)> {
+    ArtworkImage::from_bytes(bytes).unwrap().dimensions()
+}
+
+#[test]
+fn sniffing() {
+    assert_eq!(ImageFormat::sniff(&jpeg(&[app0(), sof0(1, 1)])), Some(ImageFormat::Jpeg));
+    assert_eq!(ImageFormat::sniff(&png(1, 1)), Some(ImageFormat::Png));
+    assert_eq!(ImageFormat::sniff(&bmp(1, 1)), Some(ImageFormat::Bmp));
+
+    assert_eq!(ImageFormat::sniff(b""), None);
+    assert_eq!(ImageFormat::sniff(b"GIF89a"), None);
+    assert_eq!(ImageFormat::sniff(&[0xFF, 0xD8]), None);
+    assert_eq!(ImageFormat::sniff(&PNG_SIGNATURE[..7]), None);
+    // BMP file headers are 14 bytes long
+    assert_eq!(ImageFormat::sniff(&bmp(1, 1)[..13]), None);
+    assert_eq!(ImageFormat::sniff(&bmp(1, 1)[..14]), Some(ImageFormat::Bmp));
+
+    assert!(ArtworkImage::from_bytes(b"not an image".to_vec()).is_err());
+}
+
+#[test]
+fn png_dimensions() {
+    assert_eq!(dimensions(png(600, 400)), Some((600, 400)));
+    // Signature without IHDR
+    assert_eq!(dimensions(PNG_SIGNATURE.to_vec()), None);
+    assert_eq!(dimensions(png(600, 400)[..22].to_vec()), None);
+}
+
+#[test]
+fn bmp_dimensions() {
+    assert_eq!(dimensions(bmp(320, 240)), Some((320, 240)));
+    // Top-down bitmaps
+    assert_eq!(dimensions(bmp(320, -240)), Some((320, 240)));
+    assert_eq!(dimensions(bmp(320, 240)[..14].to_vec()), None);
+    assert_eq!(dimensions(bmp(320, 240)[..25].to_vec()), None);
+}
+
+#[test]
+fn jpeg_dimensions() {
+    assert_eq!(dimensions(jpeg(&[app0(), sof0(1200, 800)])), Some((1200, 800)));
+    // Progressive frames
+    let mut sof2 = sof0(640, 480);
+    sof2[1] = 0xC2;
+    assert_eq!(dimensions(jpeg(&[sof2])), Some((640, 480)));
+}
+
+#[test]
+fn jpeg_fill_bytes() {
+    let mut fill = vec![0xFF, 0xFF, 0xFF];
+    fill.extend(sof0(300, 200));
+    assert_eq!(dimensions(jpeg(&[app0(), fill])), Some((300, 200)));
+}
+
+#[test]
+fn jpeg_markers_that_are_not_frames() {
+    // DHT, JPG and DAC are in the range of SOF markers, but have other lengths and contents
+    let dht = segment(0xC4, &[0x00; 17]);
+    let jpg = segment(0xC8, &[0x12, 0x34]);
+    let dac = segment(0xCC, &[0x10, 0x01]);
+    assert_eq!(dimensions(jpeg(&[app0(), dht, jpg, dac, sof0(1024, 768)])), Some((1024, 768)));
+}
+
+#[test]
+fn truncated_jpegs() {
+    // The width ends 4 bytes before the end of the frame header
+    let complete = jpeg(&[app0(), sof0(1200, 800)]);
+    assert_eq!(dimensions(complete[..complete.len() - 4].to_vec()), Some((1200, 800)));
+    for length in 3..complete.len() - 4 {
+        assert_eq!(dimensions(complete[..length].to_vec()), None, "{} bytes", length);
+    }
+    // Segments that claim to go past the end
+    assert_eq!(dimensions(jpeg(&[vec![0xFF, 0xE1, 0xFF, 0xFF, 0x00]])), None);
+    // Bytes that are not markers
+    assert_eq!(dimensions(jpeg(&[vec![0xFF, 0xE0, 0x00, 0x02, 0x12, 0x34]])), None);
+}
@@ -0,0 +1,169 @@
+//! Audits the artwork of synthetic albums, and plans their repair
+
+mod common;
+
+use itunes_com::wrappers::artwork::ImageFormat;
+use itunes_com::wrappers::artwork_audit::{audit_tracks, AlbumAudit, AlbumIssue, AlbumKey, ArtworkSample, AuditOptions, RepairKind, RepairOptions, RepairPlan, TrackArtworks};
+use itunes_com::wrappers::artwork_cache::{CachedImage, TrackArtwork};
+use itunes_com::wrappers::types::PersistentId;
+
+use common::{library, BLUE_IN_GREEN, HELP};
+
+fn album(name: &str) -> AlbumKey {
+    AlbumKey { album_artist: "The Beatles".to_string(), album: name.to_string() }
+}
+
+fn sample(hash: &str) -> ArtworkSample {
+    ArtworkSample { hash: hash.to_string(), format: ImageFormat::Jpeg, size: 1000, dimensions: Some((600, 600)), is_downloaded: false }
+}
+
+fn downloaded(hash: &str) -> ArtworkSample {
+    ArtworkSample { is_downloaded: true, ..sample(hash) }
+}
+
+fn track(track: PersistentId, artworks: Vec<ArtworkSample>) -> TrackArtworks {
+    TrackArtworks { track, name: format!("Track {}", track), album: album("Revolver"), artworks, unreadable: Vec::new() }
+}
+
+fn unreadable(track: PersistentId, artworks: Vec<ArtworkSample>, unreadable: Vec<usize>) -> TrackArtworks {
+    TrackArtworks { unreadable, ..self::track(track, artworks) }
+}
+
+fn audit(tracks: Vec<TrackArtworks>) -> AlbumAudit {
+    AlbumAudit::new(album("Revolver"), tracks, &AuditOptions::default())
+}
+
+#[test]
+fn album_keys() {
+    let mut library = library();
+    library.tracks.get_mut(&HELP).unwrap().album_artist = String::new();
+    let help = &library.tracks[&HELP];
+    let blue_in_green = &library.tracks[&BLUE_IN_GREEN];
+
+    // Tracks without album artist go with their artist
+    assert_eq!(AlbumKey::of(help), album("Help!"));
+    assert_eq!(AlbumKey::of(blue_in_green), AlbumKey { album_artist: "Miles Davis".to_string(), album: "Kind of Blue".to_string() });
+    assert_eq!(album("Help!").to_string(), "The Beatles - Help!");
+
+    let image = CachedImage { hash: "a".to_string(), format: ImageFormat::Jpeg, file: "images/a.jpg".to_string(), size: 1000, thumbnails: Default::default() };
+    assert_eq!(TrackArtwork::new(help, image).album, AlbumKey::of(help));
+}
+
+#[test]
+fn albums_without_issues() {
+    let audit = audit(vec![track(1, vec![sample("a")]), track(2, vec![sample("a")])]);
+    assert_eq!(audit.chosen.as_deref(), Some("a"));
+    assert!(audit.issues.is_empty());
+}
+
+#[test]
+fn missing_artwork() {
+    let audit = audit(vec![track(1, vec![]), track(2, vec![])]);
+    assert_eq!(audit.chosen, None);
+    assert_eq!(audit.issues, vec![AlbumIssue::NoArtwork]);
+
+    let audit = self::audit(vec![track(1, vec![sample("a")]), track(2, vec![]), track(3, vec![])]);
+    assert_eq!(audit.chosen.as_deref(), Some("a"));
+    assert_eq!(audit.issues, vec![AlbumIssue::Missing(vec![2, 3])]);
+}
+
+#[test]
+fn differing_images() {
+    let audit = audit(vec![track(1, vec![sample("b")]), track(2, vec![sample("a")]), track(3, vec![sample("a")])]);
+    assert_eq!(audit.chosen.as_deref(), Some("a"));
+    assert_eq!(audit.issues, vec![AlbumIssue::DifferingImages(vec![("a".to_string(), vec![2, 3]), ("b".to_string(), vec![1])])]);
+
+    // Ties go to the first image found
+    let audit = self::audit(vec![track(1, vec![sample("b")]), track(2, vec![sample("a")])]);
+    assert_eq!(audit.chosen.as_deref(), Some("b"));
+    assert_eq!(audit.issues, vec![AlbumIssue::DifferingImages(vec![("b".to_string(), vec![1]), ("a".to_string(), vec![2])])]);
+}
+
+#[test]
+fn downloaded_artwork_among_embedded_artwork() {
+    let audit = audit(vec![track(1, vec![sample("a")]), track(2, vec![downloaded("a")])]);
+    assert_eq!(audit.issues, vec![AlbumIssue::MixedDownloaded(vec![2])]);
+
+    let audit = self::audit(vec![track(1, vec![downloaded("a")]), track(2, vec![downloaded("a")])]);
+    assert!(audit.issues.is_empty());
+}
+
+#[test]
+fn several_pieces() {
+    // Pieces that cannot be read count too
+    let audit = audit(vec![track(1, vec![sample("a"), sample("b")]), track(2, vec![sample("a")]), unreadable(3, vec![sample("a")], vec![2])]);
+    assert_eq!(audit.chosen.as_deref(), Some("a"));
+    assert_eq!(audit.issues, vec![AlbumIssue::SeveralPieces(vec![1, 3]), AlbumIssue::Unreadable(vec![3])]);
+}
+
+#[test]
+fn unreadable_first_pieces() {
+    // The second piece is readable, but iTunes displays the first one
+    let audit = audit(vec![track(1, vec![sample("a")]), unreadable(2, vec![sample("b")], vec![1])]);
+    assert_eq!(audit.chosen.as_deref(), Some("a"));
+    assert_eq!(audit.issues, vec![AlbumIssue::SeveralPieces(vec![2]), AlbumIssue::Unreadable(vec![2])]);
+
+    // Tracks whose only piece cannot be read are not missing artwork
+    let audit = self::audit(vec![track(1, vec![sample("a")]), unreadable(2, vec![], vec![1])]);
+    assert_eq!(audit.issues, vec![AlbumIssue::Unreadable(vec![2])]);
+}
+
+#[test]
+fn oversized_images() {
+    let large = ArtworkSample { size: 2 * 1024 * 1024, ..sample("a") };
+    let wide = ArtworkSample { dimensions: Some((3000, 1000)), ..sample("a") };
+    let unknown = ArtworkSample { dimensions: None, ..sample("a") };
+    let audit = audit(vec![track(1, vec![large]), track(2, vec![wide]), track(3, vec![unknown])]);
+    assert_eq!(audit.issues, vec![AlbumIssue::Oversized(vec![1, 2])]);
+
+    let options = AuditOptions { max_size: usize::MAX, max_dimension: 5000 };
+    let tracks = audit.tracks.clone();
+    assert!(AlbumAudit::new(album("Revolver"), tracks, &options).issues.is_empty());
+}
+
+#[test]
+fn audits_by_album() {
+    let in_album = |track: TrackArtworks, name: &str| TrackArtworks { album: album(name), ..track };
+    let tracks = vec![
+        in_album(track(1, vec![sample("a")]), "Revolver"),
+        in_album(track(2, vec![]), "Revolver"),
+        in_album(track(3, vec![sample("b")]), "Help!"),
+        in_album(track(4, vec![sample("c")]), "Help!"),
+        in_album(track(5, vec![sample("d")]), "Abbey Road"),
+        // Tracks without album are skipped
+        in_album(track(6, vec![]), ""),
+    ];
+    let audits = audit_tracks(tracks, &AuditOptions::default());
+    let albums: Vec<&str> = audits.iter().map(|a| a.album.album.as_str()).collect();
+    assert_eq!(albums, vec!["Help!", "Revolver"]);
+}
+
+#[test]
+fn repair_plans() {
+    let albums = vec![
+        audit(vec![
+            track(1, vec![sample("b")]),
+            track(2, vec![sample("a")]),
+            track(3, vec![]),
+            track(4, vec![sample("a")]),
+            // Left alone
+            unreadable(5, vec![sample("a")], vec![1]),
+        ]),
+        // Nothing to propagate
+        AlbumAudit::new(album("Help!"), vec![track(6, vec![]), unreadable(7, vec![], vec![1])], &AuditOptions::default()),
+    ];
+
+    let plan = RepairPlan::from_albums(&albums, &RepairOptions::default());
+    assert_eq!(plan.albums.len(), 1);
+    assert_eq!(plan.albums[0].album, album("Revolver"));
+    assert_eq!(plan.albums[0].image, "a");
+    assert_eq!(plan.albums[0].source, 2);
+    assert_eq!(plan.albums[0].changes, vec![(1, RepairKind::Replace), (3, RepairKind::Add)]);
+    assert_eq!(plan.len(), 2);
+
+    let add_only = RepairPlan::from_albums(&albums, &RepairOptions { add_missing: true, replace_differing: false });
+    assert_eq!(add_only.albums[0].changes, vec![(3, RepairKind::Add)]);
+    let replace_only = RepairPlan::from_albums(&albums, &RepairOptions { add_missing: false, replace_differing: true });
+    assert_eq!(replace_only.albums[0].changes, vec![(1, RepairKind::Replace)]);
+    assert!(RepairPlan::from_albums(&albums, &RepairOptions { add_missing: false, replace_differing: false }).is_empty());
+}
@@ -0,0 +1,105 @@
+//! Stores synthetic images in a cache, and indexes them
+
+mod common;
+
+use itunes_com::wrappers::artwork::{ArtworkImage, ImageFormat};
+use itunes_com::wrappers::artwork_audit::AlbumKey;
+use itunes_com::wrappers::artwork_cache::{content_hash, ArtworkCache, ArtworkIndex, CachedImage, TrackArtwork};
+use itunes_com::wrappers::types::PersistentId;
+
+use common::TempDir;
+
+/// Bytes that are sniffed as a JPEG image
+fn jpeg(content: &[u8]) -> ArtworkImage {
+    let mut bytes = vec![0xFF, 0xD8, 0xFF, 0xE0];
+    bytes.extend(content);
+    ArtworkImage::from_bytes(bytes).unwrap()
+}
+
+fn image(hash: &str) -> CachedImage {
+    CachedImage { hash: hash.to_string(), format: ImageFormat::Png, file: format!("images/{}.png", hash), size: 1000, thumbnails: Default::default() }
+}
+
+fn artwork(track: PersistentId, album: &str, hash: &str) -> TrackArtwork {
+    TrackArtwork { track, album: AlbumKey { album_artist: "The Beatles".to_string(), album: album.to_string() }, image: image(hash) }
+}
+
+#[test]
+fn content_hashes() {
+    assert_eq!(content_hash(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    assert_eq!(content_hash(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+}
+
+#[test]
+fn images_are_stored_once() {
+    let dir = TempDir::new("artwork-cache-store");
+    let cache = ArtworkCache::new(dir.path().join("cache"));
+    let revolver = jpeg(b"revolver");
+    let help = jpeg(b"help");
+
+    let (stored, failures) = cache.store(&revolver).unwrap();
+    assert!(failures.is_empty());
+    let hash = content_hash(&revolver.bytes);
+    assert_eq!(stored, CachedImage {
+        hash: hash.clone(),
+        format: ImageFormat::Jpeg,
+        file: format!("images/{}.jpg", hash),
+        size: revolver.bytes.len() as u64,
+        thumbnails: Default::default(),
+    });
+    assert_eq!(std::fs::read(cache.root().join(&stored.file)).unwrap(), revolver.bytes);
+
+    // The same image again is the same file
+    assert_eq!(cache.store(&revolver).unwrap().0, stored);
+    let (other, _) = cache.store(&help).unwrap();
+    assert_ne!(other.file, stored.file);
+
+    let mut files: Vec<String> = std::fs::read_dir(cache.root().join("images")).unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    files.sort();
+    let mut expected = vec![format!("{}.jpg", hash), format!("{}.jpg", content_hash(&help.bytes))];
+    expected.sort();
+    assert_eq!(files, expected);
+}
+
+#[test]
+fn album_images() {
+    let index = ArtworkIndex::build(&[
+        artwork(1, "Revolver", "b"),
+        artwork(2, "Revolver", "a"),
+        artwork(3, "Revolver", "a"),
+        // Ties go to the first image found
+        artwork(4, "Help!", "d"),
+        artwork(5, "Help!", "c"),
+        // Tracks without album are only indexed as tracks
+        artwork(6, "", "e"),
+    ]);
+
+    let albums: Vec<(&str, &str)> = index.albums.iter().map(|a| (a.album.as_str(), a.image.as_str())).collect();
+    assert_eq!(albums, vec![("Help!", "d"), ("Revolver", "a")]);
+    assert_eq!(index.images.len(), 5);
+    assert_eq!(index.tracks.len(), 6);
+
+    assert_eq!(index.album_image("The Beatles", "Revolver"), Some(&image("a")));
+    assert_eq!(index.album_image("The Beatles", "Abbey Road"), None);
+    assert_eq!(index.track_image(1), Some(&image("b")));
+    assert_eq!(index.track_image(6), Some(&image("e")));
+    assert_eq!(index.track_image(7), None);
+}
+
+#[test]
+fn index_files() {
+    let dir = TempDir::new("artwork-cache-index");
+    let cache = ArtworkCache::new(dir.path().join("cache"));
+    assert!(cache.load_index().is_err());
+
+    let index = ArtworkIndex::build(&[artwork(1, "Revolver", "a"), artwork(0xABCDEF0123456789, "Help!", "b")]);
+    cache.save_index(&index).unwrap();
+    assert!(cache.index_path().is_file());
+    assert_eq!(cache.load_index().unwrap(), index);
+
+    // Saving again replaces the index
+    cache.save_index(&ArtworkIndex::default()).unwrap();
+    assert_eq!(cache.load_index().unwrap(), ArtworkIndex::default());
+}
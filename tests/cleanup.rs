@@ -0,0 +1,93 @@
+//! Plans the cleanup of synthetic playlists
+
+mod common;
+
+use itunes_com::wrappers::cleanup::{plan_cleanup, CleanupOptions, CleanupPlan, KeepOccurrence, Removal, RemovalReason};
+use itunes_com::wrappers::library::{LibrarySnapshot, PlaylistSnapshot};
+use itunes_com::wrappers::types::PersistentId;
+
+use common::{library, TempDir, BLUE_IN_GREEN, FAVORITES, HELP, TAXMAN, YESTERDAY};
+
+/// A track that is not in the library
+const UNKNOWN: PersistentId = 0x50;
+
+fn playlist(tracks: &[PersistentId]) -> PlaylistSnapshot {
+    PlaylistSnapshot { persistent_id: FAVORITES, tracks: tracks.to_vec(), ..PlaylistSnapshot::default() }
+}
+
+/// The library, with every track enabled and its file in `dir`
+fn enabled_library(dir: &TempDir) -> LibrarySnapshot {
+    let mut library = library();
+    for track in library.tracks.values_mut() {
+        track.enabled = true;
+        track.location = Some(dir.file(&format!("{}.mp3", track.name), 10).to_string_lossy().into_owned());
+    }
+    library
+}
+
+fn removals(plan: &CleanupPlan) -> Vec<(usize, PersistentId, RemovalReason)> {
+    plan.removals.iter().map(|r| (r.index, r.track, r.reason)).collect()
+}
+
+#[test]
+fn duplicates() {
+    use RemovalReason::Duplicate;
+
+    let dir = TempDir::new("cleanup-duplicates");
+    let library = enabled_library(&dir);
+    let playlist = playlist(&[TAXMAN, HELP, TAXMAN, UNKNOWN, HELP, TAXMAN, UNKNOWN]);
+    let lookup = |id| library.track(id);
+
+    let plan = plan_cleanup(&playlist, lookup, &CleanupOptions::default());
+    assert_eq!(plan.playlist, FAVORITES);
+    assert_eq!(removals(&plan), vec![(2, TAXMAN, Duplicate), (4, HELP, Duplicate), (5, TAXMAN, Duplicate), (6, UNKNOWN, Duplicate)]);
+
+    let last = CleanupOptions { duplicates: Some(KeepOccurrence::Last), ..CleanupOptions::default() };
+    let plan = plan_cleanup(&playlist, lookup, &last);
+    assert_eq!(removals(&plan), vec![(0, TAXMAN, Duplicate), (1, HELP, Duplicate), (2, TAXMAN, Duplicate), (3, UNKNOWN, Duplicate)]);
+
+    let none = CleanupOptions { duplicates: None, ..CleanupOptions::default() };
+    assert!(plan_cleanup(&playlist, lookup, &none).is_empty());
+}
+
+#[test]
+fn disabled_and_dead_tracks() {
+    use RemovalReason::{Dead, Disabled, Duplicate};
+
+    let dir = TempDir::new("cleanup-disabled-and-dead");
+    let mut library = enabled_library(&dir);
+    library.tracks.get_mut(&YESTERDAY).unwrap().enabled = false;
+    std::fs::remove_file(library.tracks[&HELP].location.as_ref().unwrap()).unwrap();
+    // Tracks that are not files are never dead
+    library.tracks.get_mut(&BLUE_IN_GREEN).unwrap().location = None;
+    let playlist = playlist(&[TAXMAN, YESTERDAY, HELP, BLUE_IN_GREEN, YESTERDAY, HELP, UNKNOWN]);
+    let lookup = |id| library.track(id);
+
+    let options = CleanupOptions { duplicates: None, disabled: true, dead: true };
+    let plan = plan_cleanup(&playlist, lookup, &options);
+    assert_eq!(removals(&plan), vec![(1, YESTERDAY, Disabled), (2, HELP, Dead), (4, YESTERDAY, Disabled), (5, HELP, Dead)]);
+
+    // Tracks that are removed entirely are not duplicates as well
+    let options = CleanupOptions { duplicates: Some(KeepOccurrence::First), disabled: true, dead: false };
+    let plan = plan_cleanup(&playlist, lookup, &options);
+    assert_eq!(removals(&plan), vec![(1, YESTERDAY, Disabled), (4, YESTERDAY, Disabled), (5, HELP, Duplicate)]);
+
+    // Only the options that are set count
+    assert!(plan_cleanup(&playlist, lookup, &CleanupOptions { duplicates: None, disabled: false, dead: false }).is_empty());
+}
+
+#[test]
+fn reports() {
+    let plan = CleanupPlan {
+        playlist: FAVORITES,
+        removals: vec![
+            Removal { index: 2, track: TAXMAN, reason: RemovalReason::Duplicate },
+            Removal { index: 4, track: HELP, reason: RemovalReason::Dead },
+        ],
+    };
+    assert_eq!(plan.to_string(), "\
+2 entry(ies) to remove from playlist ABCDEF0123456789
+  #3 [0000000000000010] duplicate
+  #5 [0000000000000030] missing file
+");
+}
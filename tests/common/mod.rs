@@ -0,0 +1,82 @@
+//! Fixtures shared by the tests: a small library, and temporary folders
+
+// Every test uses a different part of it
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+
+use itunes_com::wrappers::library::{LibrarySnapshot, PlaylistSnapshot, TrackSnapshot};
+use itunes_com::wrappers::types::PersistentId;
+
+pub const TAXMAN: PersistentId = 0x10;
+pub const YESTERDAY: PersistentId = 0x20;
+pub const HELP: PersistentId = 0x30;
+pub const BLUE_IN_GREEN: PersistentId = 0x40;
+pub const FAVORITES: PersistentId = 0xABCDEF0123456789;
+
+fn track(persistent_id: PersistentId, name: &str, artist: &str, album: &str, genre: &str, duration: i32) -> TrackSnapshot {
+    TrackSnapshot {
+        persistent_id,
+        name: name.to_string(),
+        artist: artist.to_string(),
+        album_artist: artist.to_string(),
+        album: album.to_string(),
+        genre: genre.to_string(),
+        duration,
+        location: Some(format!("C:\\Music\\{}.mp3", name)),
+        ..TrackSnapshot::default()
+    }
+}
+
+/// Three Beatles tracks and a Miles Davis one, and a playlist with two of them
+pub fn library() -> LibrarySnapshot {
+    let mut library = LibrarySnapshot::default();
+    for track in [
+        track(TAXMAN, "Taxman", "The Beatles", "Revolver", "Rock", 159),
+        track(YESTERDAY, "Yesterday", "The Beatles", "Help!", "Rock", 125),
+        track(HELP, "Help!", "The Beatles", "Help!", "Rock", 138),
+        track(BLUE_IN_GREEN, "Blue in Green", "Miles Davis", "Kind of Blue", "Jazz", 337),
+    ] {
+        library.tracks.insert(track.persistent_id, track);
+    }
+    library.playlists.push(PlaylistSnapshot {
+        persistent_id: FAVORITES,
+        name: "Favorites".to_string(),
+        tracks: vec![HELP, TAXMAN],
+        ..PlaylistSnapshot::default()
+    });
+    library
+}
+
+/// A folder under the system temporary folder, removed when dropped
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// `name` must be unique among the tests of a test binary (they run in parallel)
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("itunes-com-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Create a file of `size` bytes (and its folders), and return its path
+    pub fn file(&self, relative_path: &str, size: usize) -> PathBuf {
+        let path = self.path.join(relative_path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, vec![0u8; size]).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,24 @@
+//! Plans batch edits against snapshots
+
+use itunes_com::wrappers::edit::TrackEdit;
+use itunes_com::wrappers::library::{FieldChange, FieldValue, TrackField, TrackSnapshot};
+
+fn rated(persistent_id: u64, rating: i32) -> TrackSnapshot {
+    TrackSnapshot { persistent_id, rating, ..TrackSnapshot::default() }
+}
+
+#[test]
+fn ratings_are_rounded_down_to_whole_stars() {
+    let tracks = [rated(1, 40), rated(2, 60)];
+    let plan = TrackEdit::new().rating(50).plan(&tracks);
+
+    // 50 is stored as 40 (two stars)
+    assert_eq!(plan.unchanged, vec![1]);
+    assert_eq!(plan.tracks.len(), 1);
+    assert_eq!(plan.tracks[0].track, 2);
+    assert_eq!(plan.tracks[0].changes, vec![FieldChange { field: TrackField::Rating, old: FieldValue::Integer(60), new: FieldValue::Integer(40) }]);
+    assert!(plan.invalid.is_empty());
+
+    let plan = TrackEdit::new().rating(101).plan(&tracks);
+    assert_eq!(plan.invalid.len(), 2);
+}
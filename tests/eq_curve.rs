@@ -0,0 +1,85 @@
+//! Computes equalizer curves
+
+use itunes_com::wrappers::eq_curve::{EqCurve, BAND_FREQUENCIES, MAX_GAIN};
+
+fn assert_bands(curve: &EqCurve, expected: [f64; 10]) {
+    for ((frequency, gain), expected) in curve.points().zip(expected) {
+        assert!((gain - expected).abs() < 1e-9, "{} Hz: {} instead of {}", frequency, gain, expected);
+    }
+}
+
+fn curve(preamp: f64, bands: [f64; 10]) -> EqCurve {
+    EqCurve { preamp, bands }
+}
+
+#[test]
+fn interpolation() {
+    // 250 Hz and 500 Hz are a third and two thirds of the way from 125 Hz to 1 kHz on a logarithmic scale
+    let expected = [3.0, 3.0, 3.0, 0.0, -3.0, -6.0, -6.0, -6.0, -6.0, -6.0];
+    let interpolated = EqCurve::interpolate(&[(125.0, 3.0), (1000.0, -6.0)]);
+    assert_bands(&interpolated, expected);
+    assert_eq!(interpolated.preamp, 0.0);
+
+    // The order of the points does not matter
+    assert_bands(&EqCurve::interpolate(&[(1000.0, -6.0), (125.0, 3.0)]), expected);
+
+    // Points between bands
+    assert_bands(&EqCurve::interpolate(&[(100.0, 0.0), (10000.0, 10.0)]), {
+        let mut expected = [0.0; 10];
+        for (gain, frequency) in expected.iter_mut().zip(BAND_FREQUENCIES) {
+            *gain = (5.0 * (frequency / 100.0).log10()).clamp(0.0, 10.0);
+        }
+        expected
+    });
+}
+
+#[test]
+fn points_on_the_bands_are_kept() {
+    let bands = [1.0, -2.0, 3.0, -4.0, 5.0, -6.0, 7.0, -8.0, 9.0, -10.0];
+    let points: Vec<(f64, f64)> = BAND_FREQUENCIES.iter().copied().zip(bands).rev().collect();
+    assert_eq!(EqCurve::interpolate(&points), curve(0.0, bands));
+}
+
+#[test]
+fn interpolation_without_points() {
+    assert_eq!(EqCurve::interpolate(&[]), EqCurve::FLAT);
+    assert_eq!(EqCurve::interpolate(&[(f64::NAN, 3.0), (0.0, 3.0), (-125.0, 3.0), (125.0, f64::INFINITY)]), EqCurve::FLAT);
+
+    // A single point makes the whole curve flat at its gain
+    assert_eq!(EqCurve::interpolate(&[(440.0, -2.5)]), curve(0.0, [-2.5; 10]));
+    assert_eq!(EqCurve::interpolate(&[(0.0, 3.0), (440.0, -2.5)]), curve(0.0, [-2.5; 10]));
+}
+
+#[test]
+fn average() {
+    assert_eq!(EqCurve::average(&[]), None);
+
+    let boost = curve(2.0, [6.0, 4.0, 2.0, 0.0, 0.0, 0.0, 0.0, 2.0, 4.0, 6.0]);
+    assert_eq!(EqCurve::average(&[boost]), Some(boost));
+    let average = EqCurve::average(&[boost, EqCurve::FLAT]).unwrap();
+    assert_eq!(average, curve(1.0, [3.0, 2.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 2.0, 3.0]));
+    assert_eq!(EqCurve::average(&[boost, boost.scale(-1.0)]), Some(EqCurve::FLAT));
+}
+
+#[test]
+fn scale() {
+    let boost = curve(2.0, [6.0, 4.0, 2.0, 0.0, 0.0, 0.0, 0.0, 2.0, 4.0, 6.0]);
+    assert_eq!(boost.scale(0.5), curve(1.0, [3.0, 2.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 2.0, 3.0]));
+    assert_eq!(boost.scale(-1.0), curve(-2.0, [-6.0, -4.0, -2.0, -0.0, -0.0, -0.0, -0.0, -2.0, -4.0, -6.0]));
+    assert_eq!(boost.scale(1.0), boost);
+    assert_eq!(boost.scale(0.0), EqCurve::FLAT);
+}
+
+#[test]
+fn clamp() {
+    let loud = curve(12.01, [-12.01, 12.0, -12.0, 20.0, 0.0, 0.0, 0.0, 0.0, 0.0, -100.0]);
+    assert!(!loud.is_within_range());
+    let clamped = loud.clamp();
+    assert_eq!(clamped, curve(MAX_GAIN, [-MAX_GAIN, 12.0, -12.0, MAX_GAIN, 0.0, 0.0, 0.0, 0.0, 0.0, -MAX_GAIN]));
+    assert!(clamped.is_within_range());
+
+    // The preamp is checked too
+    assert!(!curve(-12.01, [0.0; 10]).is_within_range());
+    assert!(EqCurve::FLAT.is_within_range());
+    assert_eq!(EqCurve::FLAT.clamp(), EqCurve::FLAT);
+}
@@ -0,0 +1,136 @@
+//! Writes and reads equalizer presets in every format
+
+mod common;
+
+use itunes_com::wrappers::eq_presets::{load_presets, save_presets, EqPresetData, EqPresetError, PresetFormat, BAND_FREQUENCIES};
+
+use common::TempDir;
+
+fn late_night() -> EqPresetData {
+    EqPresetData { name: "Late night".to_string(), preamp: -2.5, bands: [3.0, 2.5, 1.25, 0.0, -0.5, -0.75, 0.0, 1.0, 2.0, 12.0] }
+}
+
+fn bass_cut() -> EqPresetData {
+    EqPresetData { name: "Bass \"cut\" & more".to_string(), preamp: 0.0, bands: [-12.0, -6.0, -3.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0] }
+}
+
+fn round_trip(format: PresetFormat, presets: &[EqPresetData]) -> Vec<EqPresetData> {
+    let bytes = format.write(presets).unwrap();
+    format.read(&bytes, &presets[0].name).unwrap()
+}
+
+#[test]
+fn json_round_trip() {
+    let presets = [late_night(), bass_cut()];
+    assert_eq!(round_trip(PresetFormat::Json, &presets), presets);
+    assert!(PresetFormat::Json.read(b"{\"presets\": []}", "").unwrap().is_empty());
+
+    let text = r#"{ "presets": [ { "name": "Late night", "preamp": -2.5, "bands": [3, 2.5, 1.25, 0, -0.5, -0.75, 0, 1, 2, 12] } ] }"#;
+    assert_eq!(PresetFormat::Json.read(text.as_bytes(), "").unwrap(), [late_night()]);
+}
+
+#[test]
+fn toml_round_trip() {
+    let presets = [late_night(), bass_cut()];
+    assert_eq!(round_trip(PresetFormat::Toml, &presets), presets);
+
+    let text = "[[presets]]\nname = \"Late night\"\npreamp = -2.5\nbands = [3.0, 2.5, 1.25, 0.0, -0.5, -0.75, 0.0, 1.0, 2.0, 12.0]\n";
+    assert_eq!(PresetFormat::Toml.read(text.as_bytes(), "").unwrap(), [late_night()]);
+}
+
+#[test]
+fn apple_eq_round_trip() {
+    for preset in [[late_night()], [bass_cut()]] {
+        let bytes = PresetFormat::AppleEq.write(&preset).unwrap();
+        assert!(String::from_utf8(bytes.clone()).unwrap().contains("<key>Preamp</key>"));
+        // The name comes from the file, not from the argument
+        assert_eq!(PresetFormat::AppleEq.read(&bytes, "Another name").unwrap(), preset);
+    }
+
+    // A single preset per file
+    assert!(matches!(PresetFormat::AppleEq.write(&[late_night(), bass_cut()]), Err(EqPresetError::Parse(_))));
+    assert!(matches!(PresetFormat::AppleEq.write(&[]), Err(EqPresetError::Parse(_))));
+    assert!(matches!(PresetFormat::AppleEq.read(b"<plist>", ""), Err(EqPresetError::Parse(_))));
+}
+
+#[test]
+fn graphic_eq_round_trip() {
+    for preset in [[late_night()], [bass_cut()]] {
+        assert_eq!(round_trip(PresetFormat::GraphicEq, &preset), preset);
+    }
+    assert_eq!(
+        late_night().to_graphic_eq(),
+        "Preamp: -2.5 dB\nGraphicEQ: 32 3; 64 2.5; 125 1.25; 250 0; 500 -0.5; 1000 -0.75; 2000 0; 4000 1; 8000 2; 16000 12\n",
+    );
+    assert!(matches!(PresetFormat::GraphicEq.write(&[late_night(), bass_cut()]), Err(EqPresetError::Parse(_))));
+}
+
+#[test]
+fn graphic_eq_files_from_equalizer_apo() {
+    // Other lines are ignored, and points between the bands are interpolated
+    let text = "# Measured headphones\nFilter: ON PK Fc 100 Hz Gain -3 dB Q 1\npreamp: -6.5 db\ngraphiceq: 20 4; 125 4; 1000 -2; 20000 -2\n";
+    let preset = PresetFormat::GraphicEq.read(text.as_bytes(), "Headphones").unwrap().remove(0);
+    assert_eq!(preset.name, "Headphones");
+    assert_eq!(preset.preamp, -6.5);
+    assert_eq!(preset.bands[..3], [4.0, 4.0, 4.0]);
+    assert!((preset.bands[3] - 2.0).abs() < 1e-9);
+    assert!((preset.bands[4] - 0.0).abs() < 1e-9);
+    assert_eq!(preset.bands[5..], [-2.0; 5]);
+
+    // The preamp is optional
+    assert_eq!(EqPresetData::from_graphic_eq("Flat", "GraphicEQ: 1000 0").unwrap().preamp, 0.0);
+
+    for text in ["Preamp: -3 dB", "GraphicEQ:", "GraphicEQ: 1000", "GraphicEQ: 0 3", "GraphicEQ: 1000 loud", "Preamp: x dB\nGraphicEQ: 1000 0"] {
+        assert!(matches!(EqPresetData::from_graphic_eq("Broken", text), Err(EqPresetError::Parse(_))), "{:?}", text);
+    }
+}
+
+#[test]
+fn validation() {
+    assert!(late_night().validate().is_ok());
+    assert!(bass_cut().validate().is_ok());
+
+    for gain in [12.01, -12.01] {
+        match (EqPresetData { preamp: gain, ..late_night() }).validate() {
+            Err(EqPresetError::OutOfRange { preset, band: None, gain: found }) => assert_eq!((preset.as_str(), found), ("Late night", gain)),
+            other => panic!("{:?}", other),
+        }
+        for band in 0..BAND_FREQUENCIES.len() {
+            let mut preset = late_night();
+            preset.bands[band] = gain;
+            assert!(matches!(preset.validate(), Err(EqPresetError::OutOfRange { band: Some(b), gain: g, .. }) if b == band && g == gain));
+        }
+    }
+
+    assert!(matches!(EqPresetData { name: " ".to_string(), ..late_night() }.validate(), Err(EqPresetError::EmptyName)));
+    let error = EqPresetData { preamp: 12.01, ..late_night() }.validate().unwrap_err();
+    assert_eq!(error.to_string(), "The preamp of \"Late night\" is out of range (12.01 dB)");
+
+    // Presets are validated when they are read
+    let loud = [EqPresetData { preamp: 12.01, ..late_night() }];
+    for format in [PresetFormat::Json, PresetFormat::Toml, PresetFormat::AppleEq, PresetFormat::GraphicEq] {
+        let bytes = format.write(&loud).unwrap();
+        assert!(matches!(format.read(&bytes, "Loud"), Err(EqPresetError::OutOfRange { .. })), "{:?}", format);
+    }
+}
+
+#[test]
+fn files() {
+    let dir = TempDir::new("eq_presets");
+    let presets = [late_night(), bass_cut()];
+
+    for name in ["presets.json", "presets.TOML"] {
+        save_presets(dir.path().join(name), &presets).unwrap();
+        assert_eq!(load_presets(dir.path().join(name)).unwrap(), presets);
+    }
+    save_presets(dir.path().join("late.eq"), &[late_night()]).unwrap();
+    assert_eq!(load_presets(dir.path().join("late.eq")).unwrap(), [late_night()]);
+    // Equalizer APO presets are named after their file
+    save_presets(dir.path().join("Late night.txt"), &[late_night()]).unwrap();
+    assert_eq!(load_presets(dir.path().join("Late night.txt")).unwrap(), [late_night()]);
+
+    assert!(matches!(save_presets(dir.path().join("presets.xml"), &presets), Err(EqPresetError::Parse(_))));
+    assert!(matches!(load_presets(dir.path().join("missing.json")), Err(EqPresetError::Io(_))));
+    assert_eq!(PresetFormat::from_path("a/b.Eq"), Some(PresetFormat::AppleEq));
+    assert_eq!(PresetFormat::from_path("presets"), None);
+}
@@ -0,0 +1,212 @@
+//! Serves a library snapshot over HTTP, directly and over loopback
+
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use itunes_com::wrappers::http::{handle_request, Image, PlaybackState, Request, Response, Server, SnapshotBackend};
+
+use common::{library, HELP, TAXMAN, YESTERDAY};
+
+fn backend() -> SnapshotBackend {
+    let mut backend = SnapshotBackend::new(library());
+    backend.artwork.insert(TAXMAN, Image { content_type: "image/png", bytes: vec![0x89, b'P', b'N', b'G'] });
+    backend
+}
+
+fn request(method: &str, path: &str) -> Request {
+    Request { method: method.to_string(), path: path.to_string(), host: Some("localhost:8080".to_string()), origin: None }
+}
+
+fn json_body(response: &Response) -> Value {
+    assert_eq!(response.content_type, "application/json");
+    serde_json::from_slice(&response.body).unwrap()
+}
+
+fn error(backend: &mut SnapshotBackend, request: &Request) -> (u16, String) {
+    let response = handle_request(backend, request);
+    (response.status, json_body(&response)["error"].as_str().unwrap().to_string())
+}
+
+#[test]
+fn player() {
+    let mut backend = backend();
+
+    let page = handle_request(&mut backend, &request("GET", "/"));
+    assert_eq!((page.status, page.content_type), (200, "text/html; charset=utf-8"));
+    assert_eq!(json_body(&handle_request(&mut backend, &request("GET", "/now-playing"))), json!({
+        "state": "stopped", "positionMs": 0, "track": null, "artwork": null,
+    }));
+
+    assert_eq!(handle_request(&mut backend, &request("POST", "/player/play")), Response { status: 204, content_type: "text/plain", body: Vec::new() });
+    let now_playing = json_body(&handle_request(&mut backend, &request("GET", "/now-playing")));
+    assert_eq!(now_playing["state"], "playing");
+    assert_eq!(now_playing["track"], json!({
+        "persistentId": "0000000000000010",
+        "name": "Taxman",
+        "artist": "The Beatles",
+        "albumArtist": "The Beatles",
+        "album": "Revolver",
+        "genre": "Rock",
+        "year": 0,
+        "trackNumber": 0,
+        "duration": 159,
+        "rating": 0,
+        "playedCount": 0,
+    }));
+    assert_eq!(now_playing["artwork"], "/now-playing/artwork?track=0000000000000010");
+    let artwork = handle_request(&mut backend, &request("GET", "/now-playing/artwork"));
+    assert_eq!((artwork.status, artwork.content_type, artwork.body.as_slice()), (200, "image/png", &[0x89, b'P', b'N', b'G'][..]));
+
+    assert_eq!(handle_request(&mut backend, &request("POST", "/player/next")).status, 204);
+    assert_eq!(backend.current, Some(YESTERDAY));
+    assert_eq!(json_body(&handle_request(&mut backend, &request("GET", "/now-playing")))["artwork"], Value::Null);
+    assert_eq!(error(&mut backend, &request("GET", "/now-playing/artwork")), (404, "The current track has no artwork".to_string()));
+
+    assert_eq!(handle_request(&mut backend, &request("POST", "/player/play-pause")).status, 204);
+    assert_eq!(backend.state, PlaybackState::Stopped);
+}
+
+#[test]
+fn playlists() {
+    let mut backend = backend();
+
+    assert_eq!(json_body(&handle_request(&mut backend, &request("GET", "/playlists"))), json!([{
+        "persistentId": "ABCDEF0123456789", "name": "Favorites", "parent": null, "isFolder": false, "isSmart": false, "trackCount": 2,
+    }]));
+    let tracks = json_body(&handle_request(&mut backend, &request("GET", "/playlists/ABCDEF0123456789/tracks")));
+    let names: Vec<&str> = tracks.as_array().unwrap().iter().map(|t| t["name"].as_str().unwrap()).collect();
+    assert_eq!(names, ["Help!", "Taxman"]);
+    assert_eq!(tracks[0]["persistentId"], format!("{:016X}", HELP));
+
+    assert_eq!(error(&mut backend, &request("GET", "/playlists/0000000000001234/tracks")), (404, "No playlist with persistent ID 0000000000001234".to_string()));
+    assert_eq!(error(&mut backend, &request("GET", "/playlists/favorites/tracks")), (400, "Invalid persistent ID \"favorites\"".to_string()));
+}
+
+#[test]
+fn invalid_requests() {
+    let mut backend = backend();
+
+    assert_eq!(error(&mut backend, &request("GET", "/tracks")), (404, "No resource at /tracks".to_string()));
+    assert_eq!(error(&mut backend, &request("POST", "/player/dance")), (404, "Unknown player action `dance`".to_string()));
+    assert_eq!(error(&mut backend, &request("GET", "/player/play")), (405, "/player/play only accepts POST requests".to_string()));
+    assert_eq!(error(&mut backend, &request("POST", "/now-playing")), (405, "/now-playing only accepts GET requests".to_string()));
+    assert_eq!(handle_request(&mut backend, &request("HEAD", "/playlists")).status, 200);
+    assert_eq!(backend.state, PlaybackState::Stopped);
+}
+
+#[test]
+fn player_actions_are_refused_from_other_origins() {
+    let mut backend = backend();
+    let from = |origin: &str| Request { origin: Some(origin.to_string()), ..request("POST", "/player/play") };
+
+    assert_eq!(error(&mut backend, &from("https://example.com")), (403, "Player actions cannot be sent from other origins".to_string()));
+    assert_eq!(error(&mut backend, &from("http://localhost:9090")), (403, "Player actions cannot be sent from other origins".to_string()));
+    assert_eq!(error(&mut backend, &Request { host: None, ..from("http://localhost:8080") }).0, 403);
+    assert_eq!(backend.state, PlaybackState::Stopped);
+
+    // Other origins can still read
+    assert_eq!(handle_request(&mut backend, &Request { origin: Some("https://example.com".to_string()), ..request("GET", "/now-playing") }).status, 200);
+
+    // The now-playing page is served by the server itself
+    assert_eq!(handle_request(&mut backend, &from("http://LOCALHOST:8080")).status, 204);
+    assert_eq!(backend.state, PlaybackState::Playing);
+}
+
+/// Serve `backend` on the current thread, while `client` runs on another one. Returns what `client` returns
+fn with_server<F, T>(backend: &mut SnapshotBackend, client: F) -> T
+where
+    F: FnOnce(SocketAddr) -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let server = Server::new();
+    let address = server.listen("127.0.0.1:0").unwrap();
+    let client = std::thread::spawn(move || client(address));
+    server.run(backend, Duration::from_millis(10), || !client.is_finished());
+    client.join().unwrap()
+}
+
+/// Send a request, and return the head and the body of the response
+fn send(address: SocketAddr, method: &str, path: &str, headers: &str) -> (String, String) {
+    let mut stream = TcpStream::connect(address).unwrap();
+    write!(stream, "{} {} HTTP/1.1\r\nHost: {}\r\n{}\r\n", method, path, address, headers).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    (head.to_string(), body.to_string())
+}
+
+/// Read the next server-sent event, and return its name and its data
+fn next_event(reader: &mut BufReader<TcpStream>) -> (String, Value) {
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        assert!(reader.read_line(&mut line).unwrap() > 0, "The event stream has ended");
+        match line.trim_end() {
+            "" if !lines.is_empty() => break,
+            "" => (),
+            line => lines.push(line.to_string()),
+        }
+    }
+    let field = |name: &str| lines.iter().find_map(|line| line.strip_prefix(name)).unwrap().to_string();
+    (field("event: "), serde_json::from_str(&field("data: ")).unwrap())
+}
+
+#[test]
+fn loopback() {
+    let mut backend = backend();
+    let (get, post, refused) = with_server(&mut backend, |address| {
+        let get = send(address, "GET", "/playlists", "");
+        let post = send(address, "POST", "/player/play", &format!("Origin: http://{}\r\nContent-Length: 0\r\n", address));
+        let refused = send(address, "POST", "/player/stop", "Origin: https://example.com\r\n");
+        (get, post, refused)
+    });
+
+    assert!(get.0.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(get.0.contains("Access-Control-Allow-Origin: *"));
+    assert!(get.1.contains("\"name\":\"Favorites\""));
+    // The result of player actions cannot be read by other origins
+    assert!(post.0.starts_with("HTTP/1.1 204 No Content\r\n"));
+    assert!(!post.0.contains("Access-Control-Allow-Origin"));
+    assert!(refused.0.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+    assert_eq!(backend.state, PlaybackState::Playing);
+}
+
+#[test]
+fn event_streams() {
+    let mut backend = backend();
+    let events = with_server(&mut backend, |address| {
+        let stream = TcpStream::connect(address).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        write!(&stream, "GET /events HTTP/1.1\r\nHost: {}\r\n\r\n", address).unwrap();
+
+        let mut head = Vec::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line.trim().is_empty() {
+                break;
+            }
+            head.push(line.trim_end().to_string());
+        }
+        assert_eq!(head[0], "HTTP/1.1 200 OK");
+        assert!(head.contains(&"Content-Type: text/event-stream".to_string()));
+
+        // The stream starts with the current state, then follows the player
+        let mut events = vec![next_event(&mut reader)];
+        send(address, "POST", "/player/play", "");
+        events.push(next_event(&mut reader));
+        send(address, "POST", "/player/next", "");
+        events.push(next_event(&mut reader));
+        events
+    });
+
+    assert!(events.iter().all(|(name, _)| name == "player"));
+    let states: Vec<(&str, &Value)> = events.iter().map(|(_, data)| (data["state"].as_str().unwrap(), &data["track"]["name"])).collect();
+    assert_eq!(states, [("stopped", &Value::Null), ("playing", &json!("Taxman")), ("playing", &json!("Yesterday"))]);
+}
@@ -0,0 +1,57 @@
+//! Plans the updates of playlist track lists
+
+use itunes_com::wrappers::materialize::{plan_playlist_update, PlaylistUpdate};
+use itunes_com::wrappers::types::PersistentId;
+
+/// Plan an update, and check that it turns `current` into `desired`
+fn plan(current: &[PersistentId], desired: &[PersistentId]) -> PlaylistUpdate {
+    let update = plan_playlist_update(current, desired);
+    assert!(update.removals.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", update.removals);
+
+    let mut result: Vec<PersistentId> = current.iter().enumerate()
+        .filter(|(index, _)| !update.removals.contains(index))
+        .map(|(_, track)| *track)
+        .collect();
+    result.extend(&update.additions);
+    assert_eq!(result, desired);
+    update
+}
+
+fn update(removals: &[usize], additions: &[PersistentId]) -> PlaylistUpdate {
+    PlaylistUpdate { removals: removals.to_vec(), additions: additions.to_vec() }
+}
+
+#[test]
+fn unchanged_lists() {
+    assert!(plan(&[1, 2, 3], &[1, 2, 3]).is_empty());
+    assert!(plan(&[], &[]).is_empty());
+}
+
+#[test]
+fn appends() {
+    assert_eq!(plan(&[1, 2], &[1, 2, 3, 4]), update(&[], &[3, 4]));
+    // Empty playlists, e.g. new ones
+    assert_eq!(plan(&[], &[1, 2, 3]), update(&[], &[1, 2, 3]));
+}
+
+#[test]
+fn removals() {
+    assert_eq!(plan(&[1, 2, 3, 4], &[1, 3]), update(&[1, 3], &[]));
+    assert_eq!(plan(&[1, 2, 3], &[]), update(&[0, 1, 2], &[]));
+}
+
+#[test]
+fn reorders() {
+    // The kept tracks are the longest start of the desired list that appears in order
+    assert_eq!(plan(&[1, 2, 3, 4], &[1, 3, 2, 4]), update(&[1, 3], &[2, 4]));
+    assert_eq!(plan(&[1, 2, 3], &[3, 2, 1]), update(&[0, 1], &[2, 1]));
+    assert_eq!(plan(&[5, 1, 2], &[1, 2, 5]), update(&[0], &[5]));
+}
+
+#[test]
+fn duplicates() {
+    assert_eq!(plan(&[1, 2], &[1, 1, 2]), update(&[1], &[1, 2]));
+    assert_eq!(plan(&[1, 1, 2, 1], &[1, 2, 1]), update(&[1], &[]));
+    assert_eq!(plan(&[1, 2, 2], &[1, 2]), update(&[2], &[]));
+    assert_eq!(plan(&[], &[7, 7]), update(&[], &[7, 7]));
+}
@@ -0,0 +1,201 @@
+//! Runs an MPD server against a library snapshot, and talks to it over loopback
+
+mod common;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use itunes_com::wrappers::mpd::{PlaybackState, Server, SnapshotBackend};
+
+use common::{library, YESTERDAY};
+
+/// A client connection, that reads whole responses
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Connection {
+    /// Connect, and return the greeting
+    fn open(address: SocketAddr) -> (Self, String) {
+        let writer = TcpStream::connect(address).unwrap();
+        let mut connection = Self { reader: BufReader::new(writer.try_clone().unwrap()), writer };
+        let greeting = connection.line();
+        (connection, greeting)
+    }
+
+    fn line(&mut self) -> String {
+        let mut line = String::new();
+        self.reader.read_line(&mut line).unwrap();
+        line.trim_end_matches('\n').to_string()
+    }
+
+    fn send(&mut self, lines: &[&str]) {
+        for line in lines {
+            writeln!(self.writer, "{}", line).unwrap();
+        }
+    }
+
+    /// The lines of the next response, up to its `OK` or `ACK` line (included)
+    fn response(&mut self) -> Vec<String> {
+        let mut lines = Vec::new();
+        loop {
+            let line = self.line();
+            let is_last = line == "OK" || line.starts_with("ACK") || line.is_empty();
+            lines.push(line);
+            if is_last {
+                return lines;
+            }
+        }
+    }
+
+    fn command(&mut self, line: &str) -> Vec<String> {
+        self.send(&[line]);
+        self.response()
+    }
+
+    /// The `file:` values of the songs of a response
+    fn files(&mut self, line: &str) -> Vec<String> {
+        let response = self.command(line);
+        assert_eq!(response.last().map(String::as_str), Some("OK"), "{:?}", response);
+        response.iter().filter_map(|line| line.strip_prefix("file: ")).map(str::to_string).collect()
+    }
+}
+
+/// Serve `backend` on the current thread, while `client` runs on another one. Returns what `client` returns
+fn with_server<F, T>(backend: &mut SnapshotBackend, client: F) -> T
+where
+    F: FnOnce(SocketAddr) -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let server = Server::new();
+    let address = server.listen("127.0.0.1:0").unwrap();
+    let client = std::thread::spawn(move || client(address));
+    server.run(backend, Duration::from_millis(10), || !client.is_finished());
+    client.join().unwrap()
+}
+
+fn lines(lines: &[&str]) -> Vec<String> {
+    lines.iter().map(|line| line.to_string()).collect()
+}
+
+#[test]
+fn greeting() {
+    let mut backend = SnapshotBackend::new(library());
+    let greeting = with_server(&mut backend, |address| Connection::open(address).1);
+    assert_eq!(greeting, "OK MPD 0.21.0");
+}
+
+#[test]
+fn player() {
+    let mut backend = SnapshotBackend::new(library());
+    let (stopped, status, song, next, ack) = with_server(&mut backend, |address| {
+        let (mut connection, _) = Connection::open(address);
+        let stopped = connection.command("status");
+        assert_eq!(connection.command("play"), lines(&["OK"]));
+        let status = connection.command("status");
+        let song = connection.command("currentsong");
+        assert_eq!(connection.command("next"), lines(&["OK"]));
+        assert_eq!(connection.command("setvol 30"), lines(&["OK"]));
+        let next = connection.command("currentsong");
+        let ack = connection.command("setvol 300");
+        (stopped, status, song, next, ack)
+    });
+
+    assert_eq!(stopped, lines(&[
+        "volume: 100", "repeat: 0", "random: 0", "single: 0", "consume: 0", "playlist: 1", "playlistlength: 4", "state: stop", "OK",
+    ]));
+    assert_eq!(status, lines(&[
+        "volume: 100", "repeat: 0", "random: 0", "single: 0", "consume: 0", "playlist: 1", "playlistlength: 4", "state: play",
+        "song: 0", "songid: 0", "time: 0:159", "elapsed: 0.000", "duration: 159.000", "OK",
+    ]));
+    assert_eq!(song, lines(&[
+        "file: C:\\Music\\Taxman.mp3", "Title: Taxman", "Artist: The Beatles", "AlbumArtist: The Beatles", "Album: Revolver", "Genre: Rock",
+        "Time: 159", "duration: 159.000", "Pos: 0", "Id: 0", "OK",
+    ]));
+    assert_eq!(next[0], "file: C:\\Music\\Yesterday.mp3");
+    assert_eq!(ack, lines(&["ACK [2@0] {setvol} Invalid volume value"]));
+
+    assert_eq!(backend.state, PlaybackState::Play);
+    assert_eq!(backend.current, Some(YESTERDAY));
+    assert_eq!(backend.volume, 30);
+}
+
+#[test]
+fn command_lists() {
+    let mut backend = SnapshotBackend::new(library());
+    let (ok, failed) = with_server(&mut backend, |address| {
+        let (mut connection, _) = Connection::open(address);
+        connection.send(&["command_list_ok_begin", "play 1", "setvol 50", "currentsong", "command_list_end"]);
+        let ok = connection.response();
+        connection.send(&["command_list_begin", "setvol 60", "play nowhere", "setvol 70", "command_list_end"]);
+        let failed = connection.response();
+        (ok, failed)
+    });
+
+    assert_eq!(&ok[..2], lines(&["list_OK", "list_OK"]));
+    assert_eq!(ok[2], "file: C:\\Music\\Yesterday.mp3");
+    assert_eq!(&ok[ok.len() - 2..], lines(&["list_OK", "OK"]));
+    // The list stops at the first error, whose index is given
+    assert_eq!(failed, lines(&["ACK [2@1] {play} Bad song index"]));
+    assert_eq!(backend.volume, 60);
+}
+
+#[test]
+fn idle() {
+    let mut backend = SnapshotBackend::new(library());
+    let (changed, cancelled) = with_server(&mut backend, |address| {
+        let (mut watcher, _) = Connection::open(address);
+        let (mut other, _) = Connection::open(address);
+        // Changes are reported from the end of the previous command of the watcher
+        assert_eq!(watcher.command("ping"), lines(&["OK"]));
+        watcher.send(&["idle player mixer"]);
+        assert_eq!(other.command("setvol 10"), lines(&["OK"]));
+        let changed = watcher.response();
+
+        watcher.send(&["idle database", "noidle"]);
+        let cancelled = watcher.response();
+        (changed, cancelled)
+    });
+
+    assert_eq!(changed, lines(&["changed: mixer", "OK"]));
+    assert_eq!(cancelled, lines(&["OK"]));
+}
+
+#[test]
+fn find_and_search() {
+    let mut backend = SnapshotBackend::new(library());
+    let (results, ack) = with_server(&mut backend, |address| {
+        let (mut connection, _) = Connection::open(address);
+        let results = [
+            r#"find artist "The Beatles" album "Help!""#,
+            r#"find artist "the beatles""#,
+            r#"find artist "Beatles""#,
+            r#"search artist "beatles" title "e""#,
+            r#"search any "BLUE""#,
+            r#"find "((artist == 'The Beatles') AND (!(album == 'Revolver')))""#,
+            r#"search "(album == 'help')""#,
+            r#"search "(album contains 'help')""#,
+            r#"find "(title starts_with 'yes')""#,
+            r#"search "(genre != 'rock')""#,
+        ].map(|command| connection.files(command));
+        let ack = connection.command(r#"find "(title ~= 'yes')""#);
+        (results, ack)
+    });
+
+    let files = |names: &[&str]| -> Vec<String> { names.iter().map(|name| format!("C:\\Music\\{}.mp3", name)).collect() };
+    assert_eq!(results[0], files(&["Yesterday", "Help!"]));
+    // Whole values are compared, regardless of case
+    assert_eq!(results[1], files(&["Taxman", "Yesterday", "Help!"]));
+    assert_eq!(results[2], files(&[]));
+    assert_eq!(results[3], files(&["Yesterday", "Help!"]));
+    assert_eq!(results[4], files(&["Blue in Green"]));
+    assert_eq!(results[5], files(&["Yesterday", "Help!"]));
+    // `==` compares whole values, even in `search`
+    assert_eq!(results[6], files(&[]));
+    assert_eq!(results[7], files(&["Yesterday", "Help!"]));
+    assert_eq!(results[8], files(&["Yesterday"]));
+    assert_eq!(results[9], files(&["Blue in Green"]));
+    assert_eq!(ack, lines(&["ACK [2@0] {find} Unsupported filter operator: ~="]));
+}
@@ -0,0 +1,43 @@
+//! Looks for the files of a temporary folder that are not in a library
+
+mod common;
+
+use itunes_com::wrappers::files::MEDIA_EXTENSIONS;
+use itunes_com::wrappers::library::TrackSnapshot;
+use itunes_com::wrappers::orphans::scan_orphans;
+
+use common::TempDir;
+
+fn track(persistent_id: u64, location: &str) -> TrackSnapshot {
+    TrackSnapshot { persistent_id, location: Some(location.to_string()), ..TrackSnapshot::default() }
+}
+
+#[test]
+fn orphans() {
+    let folder = TempDir::new("orphans");
+    // Written in Unicode normalization form C
+    let cafe = folder.file("Artist/Caf\u{e9}.mp3", 10);
+    let known = folder.file("Artist/Known.m4a", 10);
+    let orphans = [folder.file("Artist/Orphan.mp3", 10), folder.file("Other Artist/Album/01 Orphan.wav", 10)];
+    folder.file("Artist/folder.jpg", 10);
+
+    let tracks = [
+        // Decomposed, as macOS writes it, in another case, and with Windows separators
+        track(1, &cafe.to_string_lossy().replace("Caf\u{e9}", "CAFE\u{301}").replace('/', "\\")),
+        track(2, &known.to_string_lossy().to_uppercase()),
+        // Dead tracks and streams point to no file
+        track(3, ""),
+        TrackSnapshot { persistent_id: 4, location: None, ..TrackSnapshot::default() },
+    ];
+
+    let report = scan_orphans(folder.path(), &MEDIA_EXTENSIONS, &tracks).unwrap();
+    assert_eq!(report.file_count(), 2);
+    assert_eq!(report.paths(), orphans.iter().map(|path| path.as_path()).collect::<Vec<_>>());
+    assert_eq!(report.folders.keys().collect::<Vec<_>>(), vec![&folder.path().join("Artist"), &folder.path().join("Other Artist").join("Album")]);
+
+    // Once every file is known, there is nothing to report
+    let everything: Vec<TrackSnapshot> = tracks.iter().cloned()
+        .chain(orphans.iter().map(|path| track(5, &path.to_string_lossy())))
+        .collect();
+    assert!(scan_orphans(folder.path(), &MEDIA_EXTENSIONS, &everything).unwrap().is_empty());
+}
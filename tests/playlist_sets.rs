@@ -0,0 +1,95 @@
+//! Combines the playlists of a small library
+
+mod common;
+
+use itunes_com::wrappers::library::{LibrarySnapshot, PlaylistSnapshot, TrackField};
+use itunes_com::wrappers::playlist_sets::{combine, combine_snapshots, SetOperation, SetOrder};
+use itunes_com::wrappers::query::SortKey;
+use itunes_com::wrappers::types::PersistentId;
+
+use common::{library, BLUE_IN_GREEN, HELP, TAXMAN, YESTERDAY};
+
+/// A track that is in a playlist, but not in the library
+const UNKNOWN: PersistentId = 0x50;
+
+fn playlist(persistent_id: PersistentId, name: &str, tracks: &[PersistentId]) -> PlaylistSnapshot {
+    PlaylistSnapshot { persistent_id, name: name.to_string(), tracks: tracks.to_vec(), ..PlaylistSnapshot::default() }
+}
+
+/// The Favorites playlist (Help!, Taxman), and two more
+fn playlists(library: &LibrarySnapshot) -> (&PlaylistSnapshot, PlaylistSnapshot, PlaylistSnapshot) {
+    let favorites = &library.playlists[0];
+    let recent = playlist(1, "Recent", &[BLUE_IN_GREEN, TAXMAN, YESTERDAY, TAXMAN]);
+    let unsorted = playlist(2, "Unsorted", &[UNKNOWN, YESTERDAY, HELP]);
+    (favorites, recent, unsorted)
+}
+
+#[test]
+fn union() {
+    let library = library();
+    let (favorites, recent, unsorted) = playlists(&library);
+    let union = |operands: &[&PlaylistSnapshot]| combine_snapshots(SetOperation::Union, operands, &SetOrder::FirstAppearance, &library);
+
+    assert_eq!(union(&[favorites, &recent]), vec![HELP, TAXMAN, BLUE_IN_GREEN, YESTERDAY]);
+    assert_eq!(union(&[&recent, favorites]), vec![BLUE_IN_GREEN, TAXMAN, YESTERDAY, HELP]);
+    assert_eq!(union(&[&recent, &unsorted, favorites]), vec![BLUE_IN_GREEN, TAXMAN, YESTERDAY, UNKNOWN, HELP]);
+    assert_eq!(union(&[&recent]), vec![BLUE_IN_GREEN, TAXMAN, YESTERDAY]);
+    assert!(union(&[]).is_empty());
+}
+
+#[test]
+fn intersection() {
+    let library = library();
+    let (favorites, recent, unsorted) = playlists(&library);
+    let intersection = |operands: &[&PlaylistSnapshot]| combine_snapshots(SetOperation::Intersection, operands, &SetOrder::FirstAppearance, &library);
+
+    assert_eq!(intersection(&[favorites, &recent]), vec![TAXMAN]);
+    assert_eq!(intersection(&[&recent, &unsorted]), vec![YESTERDAY]);
+    assert_eq!(intersection(&[&unsorted, favorites]), vec![HELP]);
+    assert!(intersection(&[favorites, &recent, &unsorted]).is_empty());
+    // A track twice in one playlist is not in two playlists
+    assert!(intersection(&[&playlist(3, "Twice", &[TAXMAN, TAXMAN]), &unsorted]).is_empty());
+}
+
+#[test]
+fn difference() {
+    let library = library();
+    let (favorites, recent, unsorted) = playlists(&library);
+    let difference = |operands: &[&PlaylistSnapshot]| combine_snapshots(SetOperation::Difference, operands, &SetOrder::FirstAppearance, &library);
+
+    assert_eq!(difference(&[&recent, favorites]), vec![BLUE_IN_GREEN, YESTERDAY]);
+    assert_eq!(difference(&[favorites, &recent]), vec![HELP]);
+    assert_eq!(difference(&[&recent, favorites, &unsorted]), vec![BLUE_IN_GREEN]);
+    assert_eq!(difference(&[&unsorted]), vec![UNKNOWN, YESTERDAY, HELP]);
+
+    let symmetric = combine_snapshots(SetOperation::SymmetricDifference, &[favorites, &recent], &SetOrder::FirstAppearance, &library);
+    assert_eq!(symmetric, vec![HELP, BLUE_IN_GREEN, YESTERDAY]);
+}
+
+#[test]
+fn results_keep_the_order_of_first_appearance() {
+    // Duplicates, within an operand or across operands, are kept where they first appear
+    let operands: [&[PersistentId]; 2] = [&[YESTERDAY, TAXMAN, YESTERDAY, HELP], &[BLUE_IN_GREEN, HELP, TAXMAN, BLUE_IN_GREEN]];
+    assert_eq!(combine(SetOperation::Union, &operands), vec![YESTERDAY, TAXMAN, HELP, BLUE_IN_GREEN]);
+    assert_eq!(combine(SetOperation::Intersection, &operands), vec![TAXMAN, HELP]);
+    assert_eq!(combine(SetOperation::Difference, &operands), vec![YESTERDAY]);
+    assert_eq!(combine(SetOperation::SymmetricDifference, &operands), vec![YESTERDAY, BLUE_IN_GREEN]);
+}
+
+#[test]
+fn orders() {
+    let library = library();
+    let (favorites, recent, unsorted) = playlists(&library);
+    let union = |order: &SetOrder| combine_snapshots(SetOperation::Union, &[&unsorted, &recent, favorites], order, &library);
+
+    assert_eq!(union(&SetOrder::FirstAppearance), vec![UNKNOWN, YESTERDAY, HELP, BLUE_IN_GREEN, TAXMAN]);
+    assert_eq!(union(&SetOrder::PersistentId), vec![TAXMAN, YESTERDAY, HELP, BLUE_IN_GREEN, UNKNOWN]);
+
+    // Tracks that are not in the library are put last
+    let by_name = SetOrder::Sorted(vec![SortKey { field: TrackField::Name, descending: false }]);
+    assert_eq!(union(&by_name), vec![BLUE_IN_GREEN, HELP, TAXMAN, YESTERDAY, UNKNOWN]);
+
+    // Ties keep the order of first appearance
+    let by_artist = SetOrder::Sorted(vec![SortKey { field: TrackField::Artist, descending: true }]);
+    assert_eq!(union(&by_artist), vec![YESTERDAY, HELP, TAXMAN, BLUE_IN_GREEN, UNKNOWN]);
+}
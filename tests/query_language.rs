@@ -0,0 +1,156 @@
+//! Parses queries, and runs them on snapshots
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use itunes_com::wrappers::library::{FieldValue, TrackField, TrackSnapshot};
+use itunes_com::wrappers::query_language::{parse, run, Operator, QueryError, Term, TermNode, Value};
+
+fn day(days_since_epoch: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(days_since_epoch * 86400)
+}
+
+fn tracks() -> Vec<TrackSnapshot> {
+    let track = |persistent_id: u64, name: &str, artist: &str, year: i32, rating: i32| TrackSnapshot {
+        persistent_id,
+        name: name.to_string(),
+        artist: artist.to_string(),
+        album: "Album".to_string(),
+        year,
+        rating,
+        ..TrackSnapshot::default()
+    };
+    vec![
+        track(1, "Yesterday", "The Beatles", 1965, 100),
+        track(2, "Let It Be", "The Beatles", 1970, 80),
+        track(3, "Say \"Hello\"", "Someone", 1966, 40),
+        TrackSnapshot { played_date: Some(day(18321)), ..track(4, "Back\\slash", "Someone Else", 1969, 0) },
+    ]
+}
+
+/// The persistent IDs of the tracks a query matches
+fn ids(query: &str) -> Vec<u64> {
+    let tracks = tracks();
+    let found = run(query, &tracks).unwrap_or_else(|err| panic!("{}", err.render(query)));
+    found.iter().map(|t| t.persistent_id).collect()
+}
+
+fn error(query: &str) -> QueryError {
+    parse(query).unwrap_err()
+}
+
+fn single_term(query: &str) -> TermNode {
+    let ast = parse(query).unwrap();
+    assert_eq!(ast.terms.len(), 1);
+    ast.terms[0].node.clone()
+}
+
+fn year_term(operator: Operator) -> TermNode {
+    TermNode { negated: false, term: Term::Field { field: TrackField::Year, operator, value: Value::Single(FieldValue::Integer(1966)) } }
+}
+
+#[test]
+fn operators_bind_greedily() {
+    // Two-character operators take precedence over their first character
+    assert_eq!(single_term("year<=1966"), year_term(Operator::LessOrEqual));
+    assert_eq!(single_term("year>=1966"), year_term(Operator::GreaterOrEqual));
+    assert_eq!(single_term("year!=1966"), year_term(Operator::NotEquals));
+    assert_eq!(single_term("year<1966"), year_term(Operator::Less));
+    assert_eq!(single_term("year=1966"), year_term(Operator::Equals));
+    assert_eq!(single_term("year:1966"), year_term(Operator::Matches));
+
+    // The first operator ends the field name, the rest belongs to the value
+    assert_eq!(single_term("title:a=b").term, Term::Field { field: TrackField::Name, operator: Operator::Matches, value: Value::Single(FieldValue::Text("a=b".to_string())) });
+    // Without a field name, this is a plain text
+    assert_eq!(single_term(":year").term, Term::Text(":year".to_string()));
+}
+
+#[test]
+fn negation_applies_to_a_single_term() {
+    let ast = parse("-artist:beatles   year>1965").unwrap();
+    assert_eq!(ast.terms.len(), 2);
+    assert!(ast.terms[0].node.negated);
+    assert_eq!(ast.terms[0].span, 0..15);
+    assert!(!ast.terms[1].node.negated);
+    assert_eq!(ast.terms[1].span, 18..27);
+
+    assert_eq!(ids("-artist:beatles year>1965"), vec![3, 4]);
+    assert_eq!(ids("-artist:beatles -year>1966"), vec![3]);
+    assert_eq!(ids("-beatles"), vec![3, 4]);
+}
+
+#[test]
+fn comparisons() {
+    assert_eq!(ids("year:1966"), vec![3]);
+    assert_eq!(ids("year=1966"), vec![3]);
+    assert_eq!(ids("year!=1966"), vec![1, 2, 4]);
+    assert_eq!(ids("year<1966"), vec![1]);
+    assert_eq!(ids("year<=1966"), vec![1, 3]);
+    assert_eq!(ids("year>1966"), vec![2, 4]);
+    assert_eq!(ids("year>=1966"), vec![2, 3, 4]);
+    assert_eq!(ids("year:1966..1969"), vec![3, 4]);
+    assert_eq!(ids("year:..1966"), vec![1, 3]);
+    assert_eq!(ids("year:1969.."), vec![2, 4]);
+
+    // Texts: `:` looks for a substring, `=` compares whole values, both regardless of case
+    assert_eq!(ids("artist:beatles"), vec![1, 2]);
+    assert!(ids("artist=beatles").is_empty());
+    assert_eq!(ids("artist=\"the beatles\""), vec![1, 2]);
+    assert_eq!(ids("artist!=someone"), vec![1, 2, 4]);
+
+    // Ratings are in stars
+    assert_eq!(ids("rating>=4"), vec![1, 2]);
+    assert_eq!(ids("rating:0"), vec![4]);
+
+    // Dates stand for whole periods
+    assert_eq!(ids("played:2020"), vec![4]);
+    assert_eq!(ids("played:2020-02"), vec![4]);
+    assert_eq!(ids("played:2020-02-29"), vec![4]);
+    assert!(ids("played:2020-02-28").is_empty());
+    assert_eq!(ids("played>2020-02-28"), vec![4]);
+    assert!(ids("played>2020-02-29").is_empty());
+}
+
+#[test]
+fn quotes_and_escapes() {
+    assert_eq!(single_term("\"let it be\"").term, Term::Text("let it be".to_string()));
+    assert_eq!(single_term(r#"title:"say \"hello\"""#).term, Term::Field {
+        field: TrackField::Name,
+        operator: Operator::Matches,
+        value: Value::Single(FieldValue::Text("say \"hello\"".to_string())),
+    });
+    assert_eq!(single_term(r#""back\\slash""#).term, Term::Text("back\\slash".to_string()));
+
+    assert_eq!(ids("\"let it\""), vec![2]);
+    assert_eq!(ids("let it"), vec![2]);
+    assert_eq!(ids(r#"title:"say \"hello\"""#), vec![3]);
+    assert_eq!(ids(r#""back\\slash""#), vec![4]);
+}
+
+#[test]
+fn errors_point_at_their_cause() {
+    let cases = [
+        ("rating>=x", "expected a number of stars (0 to 5)", 8..9),
+        ("year:1965 \"\"", "expected a term", 10..12),
+        ("colour:red", "unknown field `colour`", 0..6),
+        ("year:1965 title:\"open", "unterminated quote", 16..21),
+        ("-sort:name", "this term cannot be negated", 0..1),
+        ("year=1965..1970", "ranges can only be used with `:`", 5..15),
+        ("year:..", "a range needs at least one bound", 5..7),
+        ("year:1965..x", "expected a number", 11..12),
+        ("compilation>yes", "booleans can only be compared with `:`, `=` or `!=`", 12..15),
+        ("limit:many", "expected a count of tracks", 6..10),
+        ("sort:name,-nope", "unknown field `nope`", 10..15),
+        ("played:2021-02-29", "expected a date (YYYY, YYYY-MM or YYYY-MM-DD)", 7..17),
+        ("played:2021-02-31", "expected a date (YYYY, YYYY-MM or YYYY-MM-DD)", 7..17),
+        ("played:2021-04-31", "expected a date (YYYY, YYYY-MM or YYYY-MM-DD)", 7..17),
+        ("played:2021-13", "expected a date (YYYY, YYYY-MM or YYYY-MM-DD)", 7..14),
+        ("time:1:xx", "expected a duration (seconds, or m:ss)", 5..9),
+    ];
+    for (query, message, span) in cases {
+        assert_eq!(error(query), QueryError { message: message.to_string(), span }, "{}", query);
+    }
+
+    assert!(parse("played:2021-12-31 played:2024-02-29").is_ok());
+    assert_eq!(error("rating>=x").render("rating>=x"), "rating>=x\n        ^ expected a number of stars (0 to 5)");
+    assert_eq!(error("colour:red").render("colour:red"), "colour:red\n^^^^^^ unknown field `colour`");
+}
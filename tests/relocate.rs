@@ -0,0 +1,103 @@
+//! Looks for the missing files of tracks in a temporary folder
+
+mod common;
+
+use std::path::Path;
+
+use itunes_com::wrappers::files::MEDIA_EXTENSIONS;
+use itunes_com::wrappers::library::TrackSnapshot;
+use itunes_com::wrappers::relocate::{find_candidates, find_dead_tracks, is_dead, plan_relink, Candidate, FileIndex, MatchOptions, Relink};
+
+use common::TempDir;
+
+fn dead_track(persistent_id: u64, name: &str, location: &str, size: i64, duration: i32) -> TrackSnapshot {
+    TrackSnapshot {
+        persistent_id,
+        name: name.to_string(),
+        location: Some(location.to_string()),
+        size,
+        duration,
+        ..TrackSnapshot::default()
+    }
+}
+
+#[test]
+fn dead_tracks() {
+    let folder = TempDir::new("relocate-dead");
+    let present = folder.file("Present.mp3", 10);
+
+    let tracks = [
+        dead_track(1, "Present", &present.to_string_lossy(), 10, 0),
+        dead_track(2, "Moved", &folder.path().join("Moved.mp3").to_string_lossy(), 10, 0),
+        // iTunes reports an empty location for missing files
+        dead_track(3, "Missing", "", 10, 0),
+        // Not a file (e.g. a stream)
+        TrackSnapshot { persistent_id: 4, location: None, ..TrackSnapshot::default() },
+    ];
+    assert!(!is_dead(&tracks[0]));
+    assert_eq!(find_dead_tracks(&tracks).iter().map(|t| t.persistent_id).collect::<Vec<_>>(), vec![2, 3]);
+}
+
+#[test]
+fn candidates() {
+    let folder = TempDir::new("relocate-candidates");
+    let moved = folder.file("New Drive/The Beatles/Revolver/01 Taxman.mp3", 1000);
+    let renamed = folder.file("Unsorted/yesterday (1965).m4a", 2000);
+    let ambiguous = [folder.file("Copy 1/Help!.mp3", 3000), folder.file("Copy 2/Help!.mp3", 3000)];
+    let by_name = folder.file("Jazz/05 Blue in Green.mp3", 4321);
+    folder.file("Unsorted/cover.jpg", 1000);
+
+    let index = FileIndex::scan(&[folder.path()], &MEDIA_EXTENSIONS).unwrap();
+    assert_eq!(index.files().len(), 5);
+    let options = MatchOptions::default();
+
+    // Moved: same size and same file name
+    let taxman = dead_track(1, "Taxman", "C:\\Music\\The Beatles\\Revolver\\01 Taxman.mp3", 1000, 159);
+    assert_eq!(find_candidates(&taxman, &index, &options), vec![
+        Candidate { path: moved.clone(), same_size: true, same_name: true, same_duration: None },
+    ]);
+    assert_eq!(find_candidates(&taxman, &index, &options)[0].score(), 5);
+
+    // Renamed: the size is the only clue
+    let yesterday = dead_track(2, "Yesterday", "C:\\Music\\Yesterday.mp3", 2000, 125);
+    let candidates = find_candidates(&yesterday, &index, &options);
+    assert_eq!(candidates, vec![Candidate { path: renamed.clone(), same_size: true, same_name: false, same_duration: None }]);
+    assert_eq!(candidates[0].score(), 3);
+
+    // Without a location, the name of the track is compared with file names (without track numbers)
+    let blue_in_green = dead_track(3, "Blue in Green", "", 0, 337);
+    assert_eq!(find_candidates(&blue_in_green, &index, &options), vec![
+        Candidate { path: by_name.clone(), same_size: false, same_name: true, same_duration: None },
+    ]);
+
+    let help = dead_track(4, "Help!", "C:\\Music\\Help!.mp3", 3000, 138);
+    let lost = dead_track(5, "Lost", "", 5000, 100);
+    let plan = plan_relink(&[&taxman, &yesterday, &blue_in_green, &help, &lost], &index, &options);
+    assert_eq!(plan.relinks, vec![
+        Relink { track: 1, name: "Taxman".to_string(), new_location: moved },
+        Relink { track: 2, name: "Yesterday".to_string(), new_location: renamed },
+        Relink { track: 3, name: "Blue in Green".to_string(), new_location: by_name },
+    ]);
+    assert_eq!(plan.ambiguous, vec![(4, ambiguous.to_vec())]);
+    assert_eq!(plan.not_found, vec![5]);
+}
+
+#[test]
+fn durations_break_ties() {
+    let folder = TempDir::new("relocate-durations");
+    let right = folder.file("A/Help!.mp3", 3000);
+    let wrong = folder.file("B/Help!.mp3", 3000);
+    let index = FileIndex::scan(&[folder.path()], &MEDIA_EXTENSIONS).unwrap();
+
+    let first_folder = folder.path().join("A");
+    let probe = move |path: &Path| Some(if path.starts_with(&first_folder) { 139 } else { 180 });
+    let options = MatchOptions { duration_probe: Some(&probe), ..MatchOptions::default() };
+    let help = dead_track(1, "Help!", "C:\\Music\\Help!.mp3", 3000, 138);
+
+    // Files whose duration differs are not candidates
+    let candidates = find_candidates(&help, &index, &options);
+    assert_eq!(candidates, vec![Candidate { path: right.clone(), same_size: true, same_name: true, same_duration: Some(true) }]);
+    assert_eq!(candidates[0].score(), 6);
+    assert!(!candidates.iter().any(|c| c.path == wrong));
+    assert_eq!(plan_relink(&[&help], &index, &options).relinks[0].new_location, right);
+}
@@ -0,0 +1,187 @@
+//! Rewrites track locations, and reads back undo logs
+
+mod common;
+
+use std::io::ErrorKind;
+
+use itunes_com::wrappers::library::TrackSnapshot;
+use itunes_com::wrappers::remap::{plan_remap, LocationChange, PathRemapper, RemapPlan, RemapRule, UndoLog};
+
+use common::TempDir;
+
+fn change(track: u64, old: &str, new: &str) -> LocationChange {
+    LocationChange { track, old: old.to_string(), new: new.to_string() }
+}
+
+#[test]
+fn unc_to_drive() {
+    let rule = RemapRule::prefix(r"\\old\music", r"M:\Music");
+    assert_eq!(rule.apply(r"\\old\music\Beatles\Help.mp3").as_deref(), Some(r"M:\Music\Beatles\Help.mp3"));
+    assert_eq!(rule.apply(r"\\OLD\Music\Help.mp3").as_deref(), Some(r"M:\Music\Help.mp3"));
+    assert_eq!(rule.apply(r"\\old\music").as_deref(), Some(r"M:\Music"));
+    assert_eq!(rule.apply(r"\\old\musicals\Cats.mp3"), None);
+    assert_eq!(rule.apply(r"\\other\music\Help.mp3"), None);
+
+    // Long path prefixes
+    assert_eq!(rule.apply(r"\\?\UNC\old\music\Help.mp3").as_deref(), Some(r"M:\Music\Help.mp3"));
+    let local = RemapRule::prefix(r"C:\Music", r"D:\Music");
+    assert_eq!(local.apply(r"\\?\C:\Music\Help.mp3").as_deref(), Some(r"D:\Music\Help.mp3"));
+    assert_eq!(local.apply(r"\\?\UNC\C:\Music\Help.mp3"), None);
+}
+
+#[test]
+fn prefixes_match_whole_components() {
+    let rule = RemapRule::prefix(r"C:\Music", r"D:\Music");
+    assert_eq!(rule.apply(r"C:\Music\Help.mp3").as_deref(), Some(r"D:\Music\Help.mp3"));
+    assert_eq!(rule.apply(r"c:\MUSIC\Help.mp3").as_deref(), Some(r"D:\Music\Help.mp3"));
+    assert_eq!(rule.apply(r"C:\Musicals\Cats.mp3"), None);
+    assert_eq!(rule.apply(r"C:\Mus"), None);
+    assert_eq!(rule.apply(r"D:\Music\Help.mp3"), None);
+
+    // Trailing separators do not matter
+    let rule = RemapRule::prefix(r"C:\Music\", r"D:\Music\");
+    assert_eq!(rule.apply(r"C:\Music\Help.mp3").as_deref(), Some(r"D:\Music\Help.mp3"));
+    assert_eq!(rule.apply(r"C:\Musicals\Cats.mp3"), None);
+}
+
+#[test]
+fn drive_roots() {
+    let rule = RemapRule::prefix(r"M:\", r"N:\");
+    assert_eq!(rule.apply(r"M:\Music\Help.mp3").as_deref(), Some(r"N:\Music\Help.mp3"));
+    assert_eq!(rule.apply(r"m:\Help.mp3").as_deref(), Some(r"N:\Help.mp3"));
+    assert_eq!(rule.apply(r"M:\").as_deref(), Some(r"N:\"));
+    assert_eq!(rule.apply(r"MM:\Help.mp3"), None);
+
+    let rule = RemapRule::prefix(r"M:\", r"\\nas\share\");
+    assert_eq!(rule.apply(r"M:\Music\Help.mp3").as_deref(), Some(r"\\nas\share\Music\Help.mp3"));
+
+    let rule = RemapRule::prefix(r"\\nas\share", r"M:\");
+    assert_eq!(rule.apply(r"\\nas\share\Help.mp3").as_deref(), Some(r"M:\Help.mp3"));
+}
+
+#[test]
+fn separators() {
+    let rule = RemapRule::prefix("//old/music/", "M:/Music");
+    assert_eq!(rule.apply(r"\\old\music\Help.mp3").as_deref(), Some(r"M:\Music\Help.mp3"));
+    assert_eq!(rule.apply("//old/music//Beatles/Help.mp3").as_deref(), Some(r"M:\Music\Beatles\Help.mp3"));
+    assert_eq!(rule.apply(r"\\old/music\\Beatles/Help.mp3").as_deref(), Some(r"M:\Music\Beatles\Help.mp3"));
+}
+
+#[cfg(feature = "remap_regex")]
+#[test]
+fn regular_expressions() {
+    let rule = RemapRule::regex(r"\.m4a$", ".mp4").unwrap();
+    assert_eq!(rule.apply(r"C:/Music/Help.M4A").as_deref(), Some(r"C:\Music\Help.mp4"));
+    assert_eq!(rule.apply(r"C:\Music\Help.mp3"), None);
+
+    let rule = RemapRule::regex(r"^([a-z]):\\music\\(?P<artist>[^\\]+)", r"M:\Artists\${artist}").unwrap();
+    assert_eq!(rule.apply(r"D:\Music\Beatles\Help.mp3").as_deref(), Some(r"M:\Artists\Beatles\Help.mp3"));
+    assert!(RemapRule::regex("(", "").is_err());
+}
+
+#[test]
+fn remapper() {
+    let remapper = PathRemapper::new(vec![
+        RemapRule::prefix(r"C:\Music\Beatles", r"E:\Beatles"),
+        RemapRule::prefix(r"C:\Music", r"D:\Music"),
+    ]);
+    // The first matching rule wins
+    assert_eq!(remapper.remap(r"C:\Music\Beatles\Help.mp3").as_deref(), Some(r"E:\Beatles\Help.mp3"));
+    assert_eq!(remapper.remap(r"C:\Music\Miles Davis\So What.mp3").as_deref(), Some(r"D:\Music\Miles Davis\So What.mp3"));
+    assert_eq!(remapper.remap(r"F:\Help.mp3"), None);
+    assert_eq!(PathRemapper::default().remap(r"C:\Music\Help.mp3"), None);
+}
+
+#[test]
+fn locations_that_only_differ_in_form_are_not_changed() {
+    let remapper = PathRemapper::new(vec![RemapRule::prefix(r"C:\Music", r"c:\MUSIC")]);
+    assert_eq!(remapper.remap(r"C:\Music\Help.mp3"), None);
+    assert_eq!(remapper.remap("c:/music//Help.mp3"), None);
+    assert_eq!(remapper.remap(r"\\?\C:\Music\Help.mp3"), None);
+
+    // Other rules still apply to the canonical form
+    let mut remapper = PathRemapper::new(vec![RemapRule::prefix(r"C:\Music", r"C:\Music")]);
+    remapper.push(RemapRule::prefix(r"C:\", r"D:\"));
+    assert_eq!(remapper.remap(r"C:\Music\Help.mp3"), None);
+    assert_eq!(remapper.remap(r"C:\Other\Help.mp3").as_deref(), Some(r"D:\Other\Help.mp3"));
+    assert_eq!(remapper.rules().len(), 2);
+}
+
+#[test]
+fn plans() {
+    let track = |persistent_id: u64, location: Option<&str>| TrackSnapshot {
+        persistent_id,
+        location: location.map(str::to_string),
+        ..TrackSnapshot::default()
+    };
+    let tracks = [
+        track(1, Some(r"\\old\music\Help.mp3")),
+        track(2, None),
+        track(3, Some("")),
+        track(4, Some(r"M:\Music\Yesterday.mp3")),
+        track(5, Some(r"\\OLD\MUSIC\Taxman.mp3")),
+    ];
+    let remapper = PathRemapper::new(vec![RemapRule::prefix(r"\\old\music", r"M:\Music")]);
+
+    let plan = plan_remap(&tracks, &remapper);
+    assert_eq!(plan.changes, vec![
+        change(1, r"\\old\music\Help.mp3", r"M:\Music\Help.mp3"),
+        change(5, r"\\OLD\MUSIC\Taxman.mp3", r"M:\Music\Taxman.mp3"),
+    ]);
+    assert_eq!(plan.to_string(), format!(
+        "2 location(s) to change\n  [0000000000000001] {}\n      -> {}\n  [0000000000000005] {}\n      -> {}\n",
+        r"\\old\music\Help.mp3", r"M:\Music\Help.mp3", r"\\OLD\MUSIC\Taxman.mp3", r"M:\Music\Taxman.mp3",
+    ));
+}
+
+#[test]
+fn undo_logs() {
+    let changes = [
+        change(0x10, r"\\old\music\Taxman.mp3", r"M:\Music\Taxman.mp3"),
+        change(0xABCDEF0123456789, r"\\old\music\Help.mp3", r"M:\Music\Help.mp3"),
+    ];
+    let mut log = UndoLog::new(Vec::new());
+    for change in &changes {
+        log.record(change).unwrap();
+    }
+    let bytes = log.into_inner();
+    assert_eq!(String::from_utf8(bytes.clone()).unwrap(), format!(
+        "0000000000000010\t{}\t{}\nABCDEF0123456789\t{}\t{}\n",
+        r"\\old\music\Taxman.mp3", r"M:\Music\Taxman.mp3", r"\\old\music\Help.mp3", r"M:\Music\Help.mp3",
+    ));
+
+    // Undoing reverts the latest change first
+    let undo = RemapPlan::from_undo_log(bytes.as_slice()).unwrap();
+    assert_eq!(undo.changes, vec![
+        change(0xABCDEF0123456789, r"M:\Music\Help.mp3", r"\\old\music\Help.mp3"),
+        change(0x10, r"M:\Music\Taxman.mp3", r"\\old\music\Taxman.mp3"),
+    ]);
+
+    // Comments and empty lines are skipped
+    let text = "# comment\n\n0000000000000010\tA\tB\n";
+    assert_eq!(RemapPlan::from_undo_log(text.as_bytes()).unwrap().changes, vec![change(0x10, "B", "A")]);
+    assert!(RemapPlan::from_undo_log("".as_bytes()).unwrap().changes.is_empty());
+
+    for line in ["0000000000000010\tA", "0000000000000010\tA\tB\tC", "not an ID\tA\tB", "0000000000000010 A B"] {
+        let error = RemapPlan::from_undo_log(format!("0000000000000020\tA\tB\n{}\n", line).as_bytes()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData, "{:?}", line);
+        assert_eq!(error.to_string(), format!("Invalid undo log line: {}", line));
+    }
+}
+
+#[test]
+fn undo_files() {
+    let dir = TempDir::new("remap");
+    let path = dir.path().join("undo.tsv");
+    let mut log = UndoLog::create(&path).unwrap();
+    log.record(&change(0x10, r"C:\Music\Taxman.mp3", r"D:\Music\Taxman.mp3")).unwrap();
+    log.flush().unwrap();
+    drop(log);
+
+    // A previous log is never overwritten
+    assert_eq!(UndoLog::create(&path).err().map(|err| err.kind()), Some(ErrorKind::AlreadyExists));
+
+    let undo = RemapPlan::load_undo_file(&path).unwrap();
+    assert_eq!(undo.changes, vec![change(0x10, r"D:\Music\Taxman.mp3", r"C:\Music\Taxman.mp3")]);
+    assert_eq!(RemapPlan::load_undo_file(dir.path().join("missing.tsv")).unwrap_err().kind(), ErrorKind::NotFound);
+}
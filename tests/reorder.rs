@@ -0,0 +1,77 @@
+//! Computes the orders playlists are sorted, shuffled and reordered into
+
+mod common;
+
+use itunes_com::wrappers::library::{TrackField, TrackSnapshot};
+use itunes_com::wrappers::query::SortKey;
+use itunes_com::wrappers::reorder::{moved_order, shuffled_order, sorted_order};
+use itunes_com::wrappers::types::PersistentId;
+
+use common::{library, BLUE_IN_GREEN, HELP, TAXMAN, YESTERDAY};
+
+fn key(field: TrackField, descending: bool) -> SortKey {
+    SortKey { field, descending }
+}
+
+/// The tracks of the library, in this order
+fn tracks(order: &[PersistentId]) -> Vec<TrackSnapshot> {
+    let library = library();
+    order.iter().map(|id| library.tracks[id].clone()).collect()
+}
+
+#[test]
+fn sorted_orders() {
+    let tracks = tracks(&[HELP, BLUE_IN_GREEN, TAXMAN, YESTERDAY]);
+    assert_eq!(sorted_order(&tracks, &[key(TrackField::Duration, false)]), vec![YESTERDAY, HELP, TAXMAN, BLUE_IN_GREEN]);
+    assert_eq!(sorted_order(&tracks, &[key(TrackField::Duration, true)]), vec![BLUE_IN_GREEN, TAXMAN, HELP, YESTERDAY]);
+    assert_eq!(sorted_order(&tracks, &[key(TrackField::Genre, false), key(TrackField::Duration, true)]), vec![BLUE_IN_GREEN, TAXMAN, HELP, YESTERDAY]);
+    assert!(sorted_order(&[], &[key(TrackField::Name, false)]).is_empty());
+}
+
+#[test]
+fn ties_keep_their_order() {
+    let tracks = tracks(&[HELP, BLUE_IN_GREEN, TAXMAN, YESTERDAY]);
+    assert_eq!(sorted_order(&tracks, &[key(TrackField::Genre, false)]), vec![BLUE_IN_GREEN, HELP, TAXMAN, YESTERDAY]);
+    assert_eq!(sorted_order(&tracks, &[key(TrackField::Genre, true)]), vec![HELP, TAXMAN, YESTERDAY, BLUE_IN_GREEN]);
+    assert_eq!(sorted_order(&tracks, &[]), vec![HELP, BLUE_IN_GREEN, TAXMAN, YESTERDAY]);
+
+    let tracks = self::tracks(&[YESTERDAY, TAXMAN, BLUE_IN_GREEN, HELP]);
+    assert_eq!(sorted_order(&tracks, &[key(TrackField::Genre, false)]), vec![BLUE_IN_GREEN, YESTERDAY, TAXMAN, HELP]);
+}
+
+#[test]
+fn shuffled_orders() {
+    let tracks: Vec<PersistentId> = (1..=50).collect();
+    let shuffled = shuffled_order(&tracks, 42);
+
+    // The same seed gives the same order, other seeds give other orders
+    assert_eq!(shuffled_order(&tracks, 42), shuffled);
+    assert_ne!(shuffled, tracks);
+    assert_ne!(shuffled_order(&tracks, 43), shuffled);
+
+    // Every track is still there, once
+    let mut sorted = shuffled.clone();
+    sorted.sort();
+    assert_eq!(sorted, tracks);
+
+    // Repeated tracks as well
+    let mut repeated = shuffled_order(&[1, 1, 2, 3, 3, 3], 7);
+    repeated.sort();
+    assert_eq!(repeated, vec![1, 1, 2, 3, 3, 3]);
+
+    assert!(shuffled_order(&[], 42).is_empty());
+    assert_eq!(shuffled_order(&[1], 42), vec![1]);
+}
+
+#[test]
+fn moved_orders() {
+    let tracks = [1, 2, 3, 4];
+    assert_eq!(moved_order(&tracks, 0, 3), Some(vec![2, 3, 4, 1]));
+    assert_eq!(moved_order(&tracks, 3, 0), Some(vec![4, 1, 2, 3]));
+    assert_eq!(moved_order(&tracks, 1, 2), Some(vec![1, 3, 2, 4]));
+    assert_eq!(moved_order(&tracks, 2, 2), Some(vec![1, 2, 3, 4]));
+
+    assert_eq!(moved_order(&tracks, 4, 0), None);
+    assert_eq!(moved_order(&tracks, 0, 4), None);
+    assert_eq!(moved_order(&[], 0, 0), None);
+}
@@ -0,0 +1,154 @@
+//! Runs a JSON-RPC server against a library snapshot, and talks to it over loopback
+
+mod common;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use itunes_com::wrappers::rpc::{handle_message, CallError, Client, Server, SnapshotBackend, Transport};
+use itunes_com::wrappers::rpc::{INVALID_PARAMS, INVALID_REQUEST, METHOD_NOT_FOUND, NOT_FOUND, PARSE_ERROR};
+
+use common::{library, BLUE_IN_GREEN, FAVORITES, HELP, TAXMAN, YESTERDAY};
+
+/// Serve `backend` on the current thread, while `client` runs on another one. Returns what `client` returns
+fn with_server<F, T>(backend: &mut SnapshotBackend, transport: Transport, client: F) -> T
+where
+    F: FnOnce(SocketAddr) -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let server = Server::new();
+    let address = server.listen("127.0.0.1:0", transport).unwrap();
+    let client = std::thread::spawn(move || client(address));
+    server.run(backend, Duration::from_millis(10), || !client.is_finished());
+    client.join().unwrap()
+}
+
+fn rpc_code(err: CallError) -> i64 {
+    match err {
+        CallError::Rpc(err) => err.code,
+        CallError::Io(err) => panic!("Unexpected I/O error: {}", err),
+    }
+}
+
+#[test]
+fn tcp_calls() {
+    let mut backend = SnapshotBackend::new(library());
+    let (state, found, playlists, tracks) = with_server(&mut backend, Transport::Tcp, |address| {
+        let mut client = Client::connect_tcp(address).unwrap();
+        client.play().unwrap();
+        client.set_volume(40).unwrap();
+        client.set_rating(TAXMAN, 80).unwrap();
+        let state = client.state().unwrap();
+        let found = client.search("artist:miles").unwrap();
+        let playlists = client.playlists().unwrap();
+        let tracks = client.playlist_tracks(FAVORITES).unwrap();
+        (state, found, playlists, tracks)
+    });
+
+    assert!(state.playing);
+    assert_eq!(state.volume, 40);
+    assert_eq!(state.track.map(|t| t.persistent_id), Some(TAXMAN));
+    assert_eq!(found.iter().map(|t| t.persistent_id).collect::<Vec<_>>(), vec![BLUE_IN_GREEN]);
+    assert_eq!(playlists.len(), 1);
+    assert_eq!(playlists[0].name, "Favorites");
+    assert_eq!(playlists[0].track_count, 2);
+    assert_eq!(tracks.iter().map(|t| t.persistent_id).collect::<Vec<_>>(), vec![HELP, TAXMAN]);
+
+    // The server has written to the backend
+    assert_eq!(backend.library.track(TAXMAN).unwrap().rating, 80);
+    assert_eq!(backend.volume, 40);
+}
+
+#[test]
+fn errors() {
+    let mut backend = SnapshotBackend::new(library());
+    let codes = with_server(&mut backend, Transport::Tcp, |address| {
+        let mut client = Client::connect_tcp(address).unwrap();
+        vec![
+            rpc_code(client.call::<Value>("player.dance", Value::Null).unwrap_err()),
+            rpc_code(client.set_volume(101).unwrap_err()),
+            rpc_code(client.call::<Value>("player.setVolume", json!({ "level": 10 })).unwrap_err()),
+            rpc_code(client.call::<Value>("playlist.tracks", json!(["not an ID"])).unwrap_err()),
+            rpc_code(client.search("rating>=many").unwrap_err()),
+            rpc_code(client.playlist_tracks(0x1234).unwrap_err()),
+        ]
+    });
+    assert_eq!(codes, vec![METHOD_NOT_FOUND, INVALID_PARAMS, INVALID_PARAMS, INVALID_PARAMS, INVALID_PARAMS, NOT_FOUND]);
+    // Failed calls have not changed anything
+    assert_eq!(backend.volume, 100);
+}
+
+#[test]
+fn notifications_get_no_response() {
+    let mut backend = SnapshotBackend::new(library());
+    let response = with_server(&mut backend, Transport::Tcp, |address| {
+        let mut stream = TcpStream::connect(address).unwrap();
+        writeln!(stream, r#"{{"jsonrpc":"2.0","method":"player.play"}}"#).unwrap();
+        writeln!(stream, r#"{{"jsonrpc":"2.0","id":7,"method":"player.getState"}}"#).unwrap();
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).unwrap();
+        line
+    });
+
+    // The first response is the one of the request, and the notification has been executed before it
+    let response: Value = serde_json::from_str(&response).unwrap();
+    assert_eq!(response["id"], json!(7));
+    assert_eq!(response["result"]["playing"], json!(true));
+    assert!(backend.playing);
+}
+
+#[test]
+fn invalid_messages() {
+    let mut backend = SnapshotBackend::new(library());
+    let error_code = |response: Option<String>| -> Value {
+        let response: Value = serde_json::from_str(&response.unwrap()).unwrap();
+        response["error"]["code"].clone()
+    };
+
+    assert_eq!(error_code(handle_message(&mut backend, "{not json")), json!(PARSE_ERROR));
+    assert_eq!(error_code(handle_message(&mut backend, "[]")), json!(INVALID_REQUEST));
+    assert_eq!(error_code(handle_message(&mut backend, r#"{"jsonrpc":"1.0","id":1,"method":"player.play"}"#)), json!(INVALID_REQUEST));
+    assert_eq!(error_code(handle_message(&mut backend, r#"{"jsonrpc":"2.0","id":1,"method":"player.play","params":3}"#)), json!(INVALID_REQUEST));
+    // A batch of notifications gets no response at all
+    assert_eq!(handle_message(&mut backend, r#"[{"jsonrpc":"2.0","method":"player.play"},{"jsonrpc":"2.0","method":"player.next"}]"#), None);
+    assert!(backend.playing);
+    assert_eq!(backend.current, Some(YESTERDAY));
+}
+
+#[test]
+fn batches() {
+    let mut backend = SnapshotBackend::new(library());
+    let response = handle_message(&mut backend, r#"[
+        {"jsonrpc":"2.0","id":"a","method":"player.setVolume","params":[25]},
+        {"jsonrpc":"2.0","method":"player.play"},
+        {"jsonrpc":"2.0","id":"b","method":"player.getState"}
+    ]"#).unwrap();
+    let response: Value = serde_json::from_str(&response).unwrap();
+    let responses = response.as_array().unwrap();
+    assert_eq!(responses.len(), 2);
+    assert_eq!(responses[0]["id"], json!("a"));
+    assert_eq!(responses[1]["id"], json!("b"));
+    assert_eq!(responses[1]["result"]["volume"], json!(25));
+    assert_eq!(responses[1]["result"]["playing"], json!(true));
+}
+
+#[test]
+fn websocket_calls() {
+    let mut backend = SnapshotBackend::new(library());
+    let (state, error) = with_server(&mut backend, Transport::WebSocket, |address| {
+        let mut client = Client::connect_websocket(&format!("ws://{}", address)).unwrap();
+        client.next_track().unwrap();
+        client.next_track().unwrap();
+        client.play_pause().unwrap();
+        let state = client.state().unwrap();
+        let error = rpc_code(client.set_rating(0x1234, 20).unwrap_err());
+        (state, error)
+    });
+
+    assert!(state.playing);
+    assert_eq!(state.track.map(|t| t.name), Some("Yesterday".to_string()));
+    assert_eq!(error, NOT_FOUND);
+}
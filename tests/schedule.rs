@@ -0,0 +1,256 @@
+//! Runs scheduled tasks against a fake player, on a manual clock
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use itunes_com::wrappers::player::{PlayerSample, PlayingTrack};
+use itunes_com::wrappers::schedule::{AfterFade, Alarm, Clock, FadeCurve, ManualClock, PlayerControl, ScheduleEvent, Scheduler, SleepTimer, Task};
+use itunes_com::wrappers::types::PersistentId;
+
+const WAKE_UP: PersistentId = 0x1000;
+
+/// A player that records what it is told to do
+#[derive(Debug)]
+struct FakePlayer {
+    playing: bool,
+    /// The current track, and its duration (in seconds)
+    track: Option<(PersistentId, i32)>,
+    position: i32,
+    volume: i32,
+    /// The playlists that have been started, and the volume they started at
+    started_playlists: Vec<(PersistentId, i32)>,
+    pauses: usize,
+    stops: usize,
+}
+
+impl FakePlayer {
+    fn new(volume: i32) -> Self {
+        Self { playing: false, track: None, position: 0, volume, started_playlists: Vec::new(), pauses: 0, stops: 0 }
+    }
+
+    fn playing(track: PersistentId, duration: i32, position: i32, volume: i32) -> Self {
+        Self { playing: true, track: Some((track, duration)), position, ..Self::new(volume) }
+    }
+}
+
+impl PlayerControl for FakePlayer {
+    fn sample(&mut self) -> windows::core::Result<PlayerSample> {
+        Ok(PlayerSample {
+            time: UNIX_EPOCH,
+            playing: self.playing,
+            track: self.track.map(|(persistent_id, duration)| PlayingTrack {
+                persistent_id,
+                name: String::new(),
+                artist: String::new(),
+                album: String::new(),
+                track_number: 0,
+                duration,
+            }),
+            position: self.position,
+        })
+    }
+
+    fn volume(&mut self) -> windows::core::Result<i32> {
+        Ok(self.volume)
+    }
+
+    fn set_volume(&mut self, volume: i32) -> windows::core::Result<()> {
+        self.volume = volume;
+        Ok(())
+    }
+
+    fn play_playlist(&mut self, playlist: PersistentId) -> windows::core::Result<()> {
+        if playlist != WAKE_UP {
+            return Err(windows::core::Error::from(windows::Win32::Foundation::E_FAIL));
+        }
+        self.started_playlists.push((playlist, self.volume));
+        self.playing = true;
+        Ok(())
+    }
+
+    fn pause(&mut self) -> windows::core::Result<()> {
+        self.pauses += 1;
+        self.playing = false;
+        Ok(())
+    }
+
+    fn stop(&mut self) -> windows::core::Result<()> {
+        self.stops += 1;
+        self.playing = false;
+        Ok(())
+    }
+}
+
+fn seconds(seconds: u64) -> Duration {
+    Duration::from_secs(seconds)
+}
+
+#[test]
+fn fade_curves() {
+    let linear = FadeCurve::Linear;
+    assert_eq!(linear.volume(100, 0, 0.0), 100);
+    assert_eq!(linear.volume(100, 0, 0.5), 50);
+    assert_eq!(linear.volume(20, 60, 0.25), 30);
+    assert_eq!(linear.volume(100, 0, 1.0), 0);
+    assert_eq!(linear.volume(100, 0, 2.0), 0);
+    assert_eq!(linear.volume(100, 0, -1.0), 100);
+
+    // -20 dB halfway from 0 dB to -40 dB
+    let logarithmic = FadeCurve::Logarithmic;
+    assert_eq!(logarithmic.volume(100, 0, 0.0), 100);
+    assert_eq!(logarithmic.volume(100, 0, 0.25), 32);
+    assert_eq!(logarithmic.volume(100, 0, 0.5), 10);
+    assert_eq!(logarithmic.volume(100, 0, 0.75), 3);
+    assert_eq!(logarithmic.volume(100, 0, 1.0), 0);
+    assert_eq!(logarithmic.volume(0, 100, 0.5), 10);
+    assert_eq!(logarithmic.volume(10, 100, 0.5), 32);
+}
+
+#[test]
+fn fades_restore_the_volume_after_pausing_or_stopping() {
+    for then in [AfterFade::Pause, AfterFade::Stop] {
+        let clock = ManualClock::new(UNIX_EPOCH);
+        let mut scheduler = Scheduler::new(&clock);
+        let mut player = FakePlayer::playing(1, 300, 0, 80);
+        let id = scheduler.add(Task::Fade { to: 0, duration: seconds(10), curve: FadeCurve::Linear, then });
+
+        assert_eq!(scheduler.tick(&mut player).unwrap(), vec![]);
+        assert_eq!(player.volume, 80);
+        clock.advance(seconds(5));
+        scheduler.tick(&mut player).unwrap();
+        assert_eq!(player.volume, 40);
+        assert!(player.playing);
+
+        clock.advance(seconds(5));
+        assert_eq!(scheduler.tick(&mut player).unwrap(), vec![ScheduleEvent::FadeFinished(id)]);
+        assert!(!player.playing);
+        assert_eq!((player.pauses, player.stops), if then == AfterFade::Pause { (1, 0) } else { (0, 1) });
+        assert_eq!(player.volume, 80);
+        assert!(scheduler.is_empty());
+    }
+}
+
+#[test]
+fn fades_without_action_keep_the_volume() {
+    let clock = ManualClock::new(UNIX_EPOCH);
+    let mut scheduler = Scheduler::new(&clock);
+    let mut player = FakePlayer::playing(1, 300, 0, 80);
+    scheduler.add(Task::Fade { to: 20, duration: seconds(10), curve: FadeCurve::Linear, then: AfterFade::Nothing });
+
+    scheduler.run(&mut player, seconds(1), |_| (), || true).unwrap();
+    assert_eq!(player.volume, 20);
+    assert!(player.playing);
+    assert_eq!(clock.now(), UNIX_EPOCH + seconds(10));
+}
+
+#[test]
+fn sleep_timers_finish_the_track() {
+    let clock = ManualClock::new(UNIX_EPOCH);
+    let mut scheduler = Scheduler::new(&clock);
+    let mut player = FakePlayer::playing(1, 300, 100, 80);
+    let id = scheduler.add(Task::SleepTimer(SleepTimer::after(seconds(60)).finish_track()));
+
+    clock.advance(seconds(59));
+    assert_eq!(scheduler.tick(&mut player).unwrap(), vec![]);
+    // Time is up, but the track goes on
+    clock.advance(seconds(1));
+    player.position = 160;
+    assert_eq!(scheduler.tick(&mut player).unwrap(), vec![]);
+    clock.advance(seconds(100));
+    player.position = 260;
+    assert_eq!(scheduler.tick(&mut player).unwrap(), vec![]);
+    assert_eq!(player.stops, 0);
+
+    // iTunes has gone on with the next track
+    clock.advance(seconds(50));
+    player.track = Some((2, 200));
+    player.position = 10;
+    assert_eq!(scheduler.tick(&mut player).unwrap(), vec![ScheduleEvent::SleepTimerExpired(id)]);
+    assert_eq!(player.stops, 1);
+    assert_eq!(player.volume, 80);
+    assert!(scheduler.is_empty());
+}
+
+#[test]
+fn sleep_timers_fade_out_before_the_end() {
+    let clock = ManualClock::new(UNIX_EPOCH);
+    let mut scheduler = Scheduler::new(&clock);
+    let mut player = FakePlayer::playing(1, 200, 150, 80);
+    let id = scheduler.add(Task::SleepTimer(SleepTimer::end_of_track().fade_out(seconds(20), FadeCurve::Linear)));
+
+    assert_eq!(scheduler.tick(&mut player).unwrap(), vec![]);
+    player.position = 179;
+    assert_eq!(scheduler.tick(&mut player).unwrap(), vec![]);
+    assert_eq!(player.volume, 80);
+
+    // The fade starts 20 seconds before the end of the track
+    player.position = 180;
+    assert_eq!(scheduler.tick(&mut player).unwrap(), vec![ScheduleEvent::SleepFadeStarted(id)]);
+    assert_eq!(player.volume, 80);
+    player.position = 190;
+    assert_eq!(scheduler.tick(&mut player).unwrap(), vec![]);
+    assert_eq!(player.volume, 40);
+    player.position = 195;
+    scheduler.tick(&mut player).unwrap();
+    assert_eq!(player.volume, 20);
+
+    player.position = 200;
+    assert_eq!(scheduler.tick(&mut player).unwrap(), vec![ScheduleEvent::SleepTimerExpired(id)]);
+    assert_eq!(player.stops, 1);
+    assert_eq!(player.volume, 80);
+}
+
+#[test]
+fn sleep_timers_without_track_stop_on_time() {
+    let clock = ManualClock::new(UNIX_EPOCH);
+    let mut scheduler = Scheduler::new(&clock);
+    let mut player = FakePlayer::playing(1, 3600, 0, 50);
+    let id = scheduler.add(Task::SleepTimer(SleepTimer::after(seconds(60)).fade_out(seconds(10), FadeCurve::Linear)));
+
+    clock.advance(seconds(55));
+    assert_eq!(scheduler.tick(&mut player).unwrap(), vec![ScheduleEvent::SleepFadeStarted(id)]);
+    assert_eq!(player.volume, 25);
+    clock.advance(seconds(5));
+    assert_eq!(scheduler.tick(&mut player).unwrap(), vec![ScheduleEvent::SleepTimerExpired(id)]);
+    assert_eq!(player.stops, 1);
+    assert_eq!(player.volume, 50);
+}
+
+#[test]
+fn alarms_start_on_time_and_ramp_up() {
+    let clock = ManualClock::new(UNIX_EPOCH);
+    let mut scheduler = Scheduler::new(&clock);
+    let mut player = FakePlayer::new(100);
+    let alarm = Alarm { at: UNIX_EPOCH + seconds(3600), playlist: WAKE_UP, volume: 60, ramp: seconds(10), curve: FadeCurve::Linear };
+    let id = scheduler.add(Task::Alarm(alarm));
+
+    clock.advance(seconds(3599));
+    assert_eq!(scheduler.tick(&mut player).unwrap(), vec![]);
+    assert!(player.started_playlists.is_empty());
+    assert_eq!(player.volume, 100);
+
+    clock.advance(seconds(1));
+    assert_eq!(scheduler.tick(&mut player).unwrap(), vec![ScheduleEvent::AlarmStarted(id)]);
+    // The playlist starts silently
+    assert_eq!(player.started_playlists, vec![(WAKE_UP, 0)]);
+    assert_eq!(player.volume, 0);
+    clock.advance(seconds(5));
+    assert_eq!(scheduler.tick(&mut player).unwrap(), vec![]);
+    assert_eq!(player.volume, 30);
+    clock.advance(seconds(5));
+    assert_eq!(scheduler.tick(&mut player).unwrap(), vec![ScheduleEvent::AlarmRampFinished(id)]);
+    assert_eq!(player.volume, 60);
+    assert!(scheduler.is_empty());
+}
+
+#[test]
+fn alarms_restore_the_volume_if_the_playlist_fails() {
+    let clock = ManualClock::new(UNIX_EPOCH + seconds(60));
+    let mut scheduler = Scheduler::new(&clock);
+    let mut player = FakePlayer::new(70);
+    let alarm = Alarm { at: UNIX_EPOCH, playlist: 0xDEAD, volume: 60, ramp: seconds(10), curve: FadeCurve::Logarithmic };
+    scheduler.add(Task::Alarm(alarm));
+
+    assert!(scheduler.tick(&mut player).is_err());
+    assert_eq!(player.volume, 70);
+    assert!(!player.playing);
+}
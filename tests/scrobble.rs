@@ -0,0 +1,167 @@
+//! Feeds synthetic timelines to a scrobbler
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use itunes_com::wrappers::player::{PlayerEvent, PlayerSample, PlayingTrack};
+use itunes_com::wrappers::scrobble::{Scrobble, ScrobbleStatus, Scrobbler};
+use itunes_com::wrappers::types::PersistentId;
+
+fn at(seconds: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(seconds)
+}
+
+fn track(persistent_id: PersistentId, duration: i32) -> PlayingTrack {
+    PlayingTrack {
+        persistent_id,
+        name: format!("Track {}", persistent_id),
+        artist: "Artist".to_string(),
+        album: "Album".to_string(),
+        track_number: 1,
+        duration,
+    }
+}
+
+fn playing(time: u64, track: &PlayingTrack, position: i32) -> PlayerSample {
+    PlayerSample { time: at(time), playing: true, track: Some(track.clone()), position }
+}
+
+fn paused(time: u64, track: &PlayingTrack, position: i32) -> PlayerSample {
+    PlayerSample { playing: false, ..playing(time, track, position) }
+}
+
+/// Feed samples, and return the scrobbles they have produced
+fn feed<I: IntoIterator<Item = PlayerSample>>(scrobbler: &mut Scrobbler, samples: I) -> Vec<Scrobble> {
+    samples.into_iter().filter_map(|sample| scrobbler.feed(sample)).collect()
+}
+
+/// A sample every 10 seconds from `from` to `to` (included), while the track plays normally from `position`
+fn play(track: &PlayingTrack, from: u64, to: u64, position: i32) -> impl Iterator<Item = PlayerSample> + '_ {
+    (from..=to).step_by(10).map(move |time| playing(time, track, position + (time - from) as i32))
+}
+
+#[test]
+fn plays_that_cross_the_threshold_are_listened() {
+    let (first, second) = (track(1, 200), track(2, 300));
+    let mut scrobbler = Scrobbler::default();
+
+    assert_eq!(feed(&mut scrobbler, play(&first, 0, 90, 0)), vec![]);
+    assert_eq!(feed(&mut scrobbler, play(&first, 100, 110, 100)), vec![]);
+    let scrobbles = feed(&mut scrobbler, play(&second, 120, 120, 0));
+    assert_eq!(scrobbles, vec![Scrobble { track: first, started: at(0), played: Duration::from_secs(110), status: ScrobbleStatus::Listened }]);
+    assert_eq!(scrobbles[0].timestamp(), 0);
+
+    // Long tracks only need four minutes
+    let scrobble = feed(&mut scrobbler, play(&second, 130, 360, 10)).into_iter().chain(scrobbler.finish()).collect::<Vec<_>>();
+    assert_eq!(scrobble.len(), 1);
+    assert_eq!(scrobble[0].started, at(120));
+    assert_eq!(scrobble[0].status, ScrobbleStatus::Listened);
+}
+
+#[test]
+fn plays_under_the_threshold_are_skipped() {
+    let (first, second) = (track(1, 200), track(2, 300));
+    let mut scrobbler = Scrobbler::default();
+
+    assert_eq!(feed(&mut scrobbler, play(&first, 0, 90, 0)), vec![]);
+    let scrobbles = feed(&mut scrobbler, play(&second, 100, 100, 0));
+    assert_eq!(scrobbles, vec![Scrobble { track: first, started: at(0), played: Duration::from_secs(90), status: ScrobbleStatus::Skipped }]);
+
+    // Tracks too short to count are not reported at all
+    let short = track(3, 20);
+    assert_eq!(feed(&mut scrobbler, play(&short, 110, 110, 0)).len(), 1);
+    assert_eq!(feed(&mut scrobbler, play(&short, 120, 130, 10)), vec![]);
+    assert_eq!(scrobbler.finish(), None);
+}
+
+#[test]
+fn tracks_replayed_back_to_back_are_scrobbled_twice() {
+    let song = track(1, 60);
+    let mut scrobbler = Scrobbler::default();
+
+    assert_eq!(feed(&mut scrobbler, play(&song, 0, 60, 0)), vec![]);
+    // "Repeat one": the track has ended, and has started over between two samples
+    let scrobbles = feed(&mut scrobbler, play(&song, 70, 100, 5));
+    assert_eq!(scrobbles, vec![Scrobble { track: song.clone(), started: at(0), played: Duration::from_secs(60), status: ScrobbleStatus::Listened }]);
+
+    let last = scrobbler.finish().unwrap();
+    assert_eq!(last.started, at(70));
+    assert_eq!(last.played, Duration::from_secs(30));
+    assert_eq!(last.status, ScrobbleStatus::Listened);
+}
+
+#[test]
+fn seeks_do_not_count_as_listened_time() {
+    let song = track(1, 200);
+    let mut scrobbler = Scrobbler::default();
+
+    assert_eq!(feed(&mut scrobbler, play(&song, 0, 40, 0)), vec![]);
+    // Seeking back within the track is not a new play
+    assert_eq!(feed(&mut scrobbler, play(&song, 50, 100, 10)), vec![]);
+    let scrobble = scrobbler.finish().unwrap();
+    assert_eq!(scrobble.started, at(0));
+    assert_eq!(scrobble.played, Duration::from_secs(90));
+    assert_eq!(scrobble.status, ScrobbleStatus::Skipped);
+
+    // Neither does seeking forward
+    assert_eq!(feed(&mut scrobbler, play(&song, 200, 220, 0)), vec![]);
+    assert_eq!(feed(&mut scrobbler, play(&song, 230, 240, 150)), vec![]);
+    assert_eq!(scrobbler.finish().unwrap().played, Duration::from_secs(30));
+}
+
+#[test]
+fn pauses_do_not_count_as_listened_time() {
+    let song = track(1, 200);
+    let mut scrobbler = Scrobbler::default();
+
+    assert_eq!(feed(&mut scrobbler, play(&song, 0, 50, 0)), vec![]);
+    assert_eq!(feed(&mut scrobbler, [paused(60, &song, 50), paused(600, &song, 50)]), vec![]);
+    assert_eq!(feed(&mut scrobbler, play(&song, 610, 630, 50)), vec![]);
+
+    let scrobble = scrobbler.finish().unwrap();
+    assert_eq!(scrobble.started, at(0));
+    assert_eq!(scrobble.played, Duration::from_secs(70));
+    assert_eq!(scrobble.status, ScrobbleStatus::Skipped);
+}
+
+#[test]
+fn player_events() {
+    let (first, second) = (track(1, 200), track(2, 300));
+    let stopped = PlayerSample { time: at(0), playing: false, track: None, position: 0 };
+    let timeline = [
+        playing(10, &first, 0),
+        playing(20, &first, 10),
+        paused(30, &first, 20),
+        playing(40, &first, 20),
+        playing(50, &second, 0),
+        paused(60, &first, 0),
+        stopped.clone(),
+    ];
+
+    let events: Vec<Vec<PlayerEvent>> = std::iter::once(&stopped).chain(&timeline)
+        .zip(&timeline)
+        .map(|(previous, sample)| sample.events_since(previous))
+        .collect();
+    assert_eq!(events, vec![
+        vec![PlayerEvent::PlayingTrackChanged(Some(first.clone())), PlayerEvent::Play(first.clone())],
+        vec![],
+        vec![PlayerEvent::Stop],
+        vec![PlayerEvent::Play(first.clone())],
+        vec![PlayerEvent::Stop, PlayerEvent::PlayingTrackChanged(Some(second.clone())), PlayerEvent::Play(second.clone())],
+        vec![PlayerEvent::Stop, PlayerEvent::PlayingTrackChanged(Some(first.clone()))],
+        vec![PlayerEvent::PlayingTrackChanged(None)],
+    ]);
+}
+
+#[test]
+fn plays_start_when_paused_tracks_are_resumed() {
+    let song = track(1, 200);
+    let mut scrobbler = Scrobbler::default();
+
+    // The track is loaded, but not playing yet
+    assert_eq!(feed(&mut scrobbler, [paused(0, &song, 0), paused(100, &song, 0)]), vec![]);
+    assert_eq!(feed(&mut scrobbler, play(&song, 110, 230, 0)), vec![]);
+    let scrobble = scrobbler.finish().unwrap();
+    assert_eq!(scrobble.started, at(110));
+    assert_eq!(scrobble.played, Duration::from_secs(120));
+    assert_eq!(scrobble.status, ScrobbleStatus::Listened);
+}
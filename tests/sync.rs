@@ -0,0 +1,208 @@
+//! Matches and merges the tracks of two library snapshots
+
+mod common;
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use itunes_com::wrappers::library::{FieldChange, FieldValue, LibrarySnapshot, TrackField, TrackSnapshot};
+use itunes_com::wrappers::sync::{match_tracks, merge_tracks, plan, MatchKind, MergePolicy, SyncPolicies, TrackMatch};
+use itunes_com::wrappers::types::PersistentId;
+
+use common::{library, BLUE_IN_GREEN, HELP, TAXMAN, YESTERDAY};
+
+fn day(day: u64) -> Option<SystemTime> {
+    Some(UNIX_EPOCH + Duration::from_secs(day * 86400))
+}
+
+/// The same library, as imported again on another machine: every track has another persistent ID
+fn reimported(library: &LibrarySnapshot) -> LibrarySnapshot {
+    let mut reimported = LibrarySnapshot::default();
+    for track in library.tracks.values() {
+        let track = TrackSnapshot { persistent_id: track.persistent_id + 0x1000, ..track.clone() };
+        reimported.tracks.insert(track.persistent_id, track);
+    }
+    reimported
+}
+
+fn matched(source: PersistentId, target: PersistentId, kind: MatchKind) -> TrackMatch {
+    TrackMatch { source, target, kind }
+}
+
+/// A track that has been played 10 times and skipped 2 times, last played on day 10 and last skipped on day 5
+fn played() -> TrackSnapshot {
+    TrackSnapshot {
+        persistent_id: TAXMAN,
+        played_count: 10,
+        played_date: day(10),
+        skipped_count: 2,
+        skipped_date: day(5),
+        rating: 60,
+        album_rating: 80,
+        enabled: true,
+        ..TrackSnapshot::default()
+    }
+}
+
+fn changes(source: &TrackSnapshot, target: &TrackSnapshot, policy: MergePolicy) -> Vec<(TrackField, FieldValue)> {
+    merge_tracks(source, target, &SyncPolicies::all(policy)).into_iter()
+        .map(|FieldChange { field, new, .. }| (field, new))
+        .collect()
+}
+
+#[test]
+fn persistent_id_matches() {
+    let mut target = library();
+    target.tracks.remove(&BLUE_IN_GREEN);
+    // Tracks with the same persistent ID match, whatever their metadata
+    target.tracks.get_mut(&HELP).unwrap().name = "Help".to_string();
+
+    let matching = match_tracks(&library(), &target, 2);
+    assert_eq!(matching.matches, vec![
+        matched(TAXMAN, TAXMAN, MatchKind::PersistentId),
+        matched(YESTERDAY, YESTERDAY, MatchKind::PersistentId),
+        matched(HELP, HELP, MatchKind::PersistentId),
+    ]);
+    assert_eq!(matching.unmatched, vec![BLUE_IN_GREEN]);
+    assert!(matching.ambiguous.is_empty());
+}
+
+#[test]
+fn fingerprint_matches() {
+    let source = library();
+    let mut target = reimported(&source);
+    // Case and whitespace do not matter
+    target.tracks.get_mut(&(TAXMAN + 0x1000)).unwrap().artist = "the  BEATLES".to_string();
+    // 2 seconds is within the tolerance, 3 seconds is not
+    target.tracks.get_mut(&(YESTERDAY + 0x1000)).unwrap().duration += 2;
+    target.tracks.get_mut(&(HELP + 0x1000)).unwrap().duration -= 3;
+    target.tracks.get_mut(&(BLUE_IN_GREEN + 0x1000)).unwrap().track_number = 3;
+
+    let matching = match_tracks(&source, &target, 2);
+    assert_eq!(matching.matches, vec![
+        matched(TAXMAN, TAXMAN + 0x1000, MatchKind::Fingerprint),
+        matched(YESTERDAY, YESTERDAY + 0x1000, MatchKind::Fingerprint),
+    ]);
+    assert_eq!(matching.unmatched, vec![HELP, BLUE_IN_GREEN]);
+    assert!(matching.ambiguous.is_empty());
+
+    assert_eq!(match_tracks(&source, &target, 3).unmatched, vec![BLUE_IN_GREEN]);
+}
+
+#[test]
+fn ambiguous_fingerprints() {
+    let source = library();
+    let mut target = reimported(&source);
+    // Two copies of Taxman in the target library
+    let copy = TrackSnapshot { persistent_id: 0x2000, ..target.tracks[&(TAXMAN + 0x1000)].clone() };
+    target.tracks.insert(copy.persistent_id, copy);
+    // Two copies of Help! in the source library
+    let mut source_with_copy = source.clone();
+    let copy = TrackSnapshot { persistent_id: 0x3000, ..source.tracks[&HELP].clone() };
+    source_with_copy.tracks.insert(copy.persistent_id, copy);
+
+    let matching = match_tracks(&source_with_copy, &target, 2);
+    assert_eq!(matching.matches, vec![
+        matched(YESTERDAY, YESTERDAY + 0x1000, MatchKind::Fingerprint),
+        matched(BLUE_IN_GREEN, BLUE_IN_GREEN + 0x1000, MatchKind::Fingerprint),
+    ]);
+    assert_eq!(matching.ambiguous, vec![TAXMAN, HELP, 0x3000]);
+    assert!(matching.unmatched.is_empty());
+
+    // Target tracks that are matched by persistent ID are not candidates anymore
+    let mut target = library();
+    target.tracks.remove(&HELP);
+    let matching = match_tracks(&source_with_copy, &target, 2);
+    assert_eq!(matching.unmatched, vec![HELP, 0x3000]);
+}
+
+#[test]
+fn keep_target_and_use_source() {
+    let target = played();
+    let source = TrackSnapshot { played_count: 3, played_date: day(20), rating: 100, enabled: false, ..played() };
+    assert!(changes(&source, &target, MergePolicy::KeepTarget).is_empty());
+    assert_eq!(changes(&source, &target, MergePolicy::UseSource), vec![
+        (TrackField::PlayedCount, FieldValue::Integer(3)),
+        (TrackField::Rating, FieldValue::Integer(100)),
+        (TrackField::PlayedDate, FieldValue::Date(day(20))),
+        (TrackField::Enabled, FieldValue::Bool(false)),
+    ]);
+}
+
+#[test]
+fn max() {
+    let target = TrackSnapshot { played_date: None, enabled: false, ..played() };
+    let source = TrackSnapshot { played_count: 12, skipped_count: 1, rating: 40, album_rating: 100, played_date: day(3), ..played() };
+    assert_eq!(changes(&source, &target, MergePolicy::Max), vec![
+        (TrackField::PlayedCount, FieldValue::Integer(12)),
+        (TrackField::AlbumRating, FieldValue::Integer(100)),
+        (TrackField::PlayedDate, FieldValue::Date(day(3))),
+        (TrackField::Enabled, FieldValue::Bool(true)),
+    ]);
+    assert_eq!(changes(&target, &source, MergePolicy::Max), vec![
+        (TrackField::SkippedCount, FieldValue::Integer(2)),
+        (TrackField::Rating, FieldValue::Integer(60)),
+    ]);
+}
+
+#[test]
+fn sum_only_adds_counts() {
+    let target = played();
+    let source = TrackSnapshot { played_count: 5, skipped_count: 1, rating: 80, album_rating: 20, played_date: day(3), ..played() };
+    assert_eq!(changes(&source, &target, MergePolicy::Sum), vec![
+        (TrackField::PlayedCount, FieldValue::Integer(15)),
+        (TrackField::SkippedCount, FieldValue::Integer(3)),
+        // Other fields behave as with `Max`
+        (TrackField::Rating, FieldValue::Integer(80)),
+    ]);
+}
+
+#[test]
+fn newest_wins() {
+    let target = played();
+    // Played more recently, but skipped less recently
+    let source = TrackSnapshot { played_count: 4, played_date: day(11), skipped_count: 7, skipped_date: day(4), rating: 20, enabled: false, ..played() };
+    assert_eq!(changes(&source, &target, MergePolicy::NewestWins), vec![
+        (TrackField::PlayedCount, FieldValue::Integer(4)),
+        (TrackField::Rating, FieldValue::Integer(20)),
+        (TrackField::PlayedDate, FieldValue::Date(day(11))),
+        (TrackField::Enabled, FieldValue::Bool(false)),
+    ]);
+
+    // Skipped more recently, but played less recently
+    let source = TrackSnapshot { played_count: 4, played_date: day(9), skipped_count: 7, skipped_date: day(6), rating: 20, ..played() };
+    assert_eq!(changes(&source, &target, MergePolicy::NewestWins), vec![(TrackField::SkippedCount, FieldValue::Integer(7))]);
+
+    // The target is kept when there are no dates to compare
+    let undated = TrackSnapshot { played_date: None, skipped_date: None, rating: 20, ..played() };
+    assert!(changes(&undated, &target, MergePolicy::NewestWins).is_empty());
+    // The source wins when only it has a date
+    let target = TrackSnapshot { played_date: None, ..played() };
+    assert_eq!(changes(&source, &target, MergePolicy::NewestWins)[0], (TrackField::PlayedCount, FieldValue::Integer(4)));
+}
+
+#[test]
+fn plans_applied_to_snapshots() {
+    let mut source = library();
+    source.tracks.get_mut(&TAXMAN).unwrap().played_count = 5;
+    source.tracks.get_mut(&YESTERDAY).unwrap().rating = 80;
+    source.tracks.get_mut(&YESTERDAY).unwrap().played_date = day(1);
+    let mut target = reimported(&library());
+    target.tracks.remove(&(BLUE_IN_GREEN + 0x1000));
+    target.tracks.get_mut(&(TAXMAN + 0x1000)).unwrap().played_count = 3;
+
+    let policies = SyncPolicies::default();
+    let sync = plan(&source, &target, &policies, 2);
+    let changed: Vec<(PersistentId, PersistentId, usize)> = sync.tracks.iter().map(|t| (t.source, t.target, t.changes.len())).collect();
+    assert_eq!(changed, vec![(TAXMAN, TAXMAN + 0x1000, 1), (YESTERDAY, YESTERDAY + 0x1000, 2)]);
+    assert!(sync.tracks.iter().all(|t| t.matched_by == MatchKind::Fingerprint));
+    assert_eq!(sync.unmatched, vec![BLUE_IN_GREEN]);
+    assert_eq!(sync.tracks[0].changes, vec![FieldChange { field: TrackField::PlayedCount, old: FieldValue::Integer(3), new: FieldValue::Integer(5) }]);
+
+    sync.apply_to_snapshot(&mut target);
+    assert_eq!(target.tracks[&(TAXMAN + 0x1000)].played_count, 5);
+    assert_eq!(target.tracks[&(YESTERDAY + 0x1000)].rating, 80);
+    assert_eq!(target.tracks[&(YESTERDAY + 0x1000)].played_date, day(1));
+    assert_eq!(target.tracks[&(HELP + 0x1000)], reimported(&library()).tracks[&(HELP + 0x1000)]);
+    // Nothing is left to synchronize
+    assert!(plan(&source, &target, &policies, 2).tracks.is_empty());
+}